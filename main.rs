@@ -24,7 +24,7 @@ fn main(){
         scp.init().unwrap();
         scp.push_directory("testdir",0o755).unwrap();
         let buf=b"blabla\n".to_vec();
-        scp.push_file("test file",buf.len(),0o644).unwrap();
+        scp.push_file("test file",buf.len() as u64,0o644).unwrap();
         scp.write(&buf).unwrap();
     }
     /*
@@ -32,7 +32,7 @@ fn main(){
         let mut scp=session.scp_new(WRITE,"/tmp").unwrap();
         scp.init().unwrap();
         let buf=b"blabla blibli\n".to_vec();
-        scp.push_file("blublu",buf.len(),0o644).unwrap();
+        scp.push_file("blublu",buf.len() as u64,0o644).unwrap();
         scp.write(&buf).unwrap();
     }
     {
@@ -0,0 +1,158 @@
+//! A session running on a thread of its own, driven by requests sent from any thread: a handle model for multithreaded applications that don't use the `nonblocking` module.
+
+use std::net::TcpStream;
+use std::path::{Path,PathBuf};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use super::{Error,Output,Permissions,Session};
+use super::sftp::Metadata;
+
+type Request=Box<dyn FnOnce(&mut Session)+Send>;
+
+enum Message {
+    Run(Request),
+    Close(mpsc::Sender<Result<(),Error>>)
+}
+
+/// A handle to a session running on its own thread, which processes the requests sent through the handle one at a time, in order. Handles can be cloned and sent to other threads; the session is closed when the last handle is dropped, or by `close`.
+///
+///```no_run
+/// use ssh::*;
+///
+/// let mut session=Session::new().unwrap();
+/// session.set_host("pijul.org").unwrap();
+/// session.parse_config(None).unwrap();
+/// session.connect().unwrap();
+/// session.userauth_publickey_auto(None).unwrap();
+/// let handle=SessionHandle::spawn(session);
+/// let workers:Vec<_>=(0..4).map(|i| {
+///     let handle=handle.clone();
+///     std::thread::spawn(move || handle.exec(&format!("echo {}",i)).wait())
+/// }).collect();
+/// for w in workers {
+///     println!("{:?}",w.join().unwrap().unwrap().stdout)
+/// }
+/// handle.close().wait().unwrap();
+///```
+#[derive(Clone)]
+pub struct SessionHandle {
+    tx:mpsc::Sender<Message>
+}
+
+impl std::fmt::Debug for SessionHandle {
+    fn fmt(&self,f:&mut std::fmt::Formatter)->std::fmt::Result {
+        f.write_str("SessionHandle{ .. }")
+    }
+}
+
+/// The result of a request sent to a `SessionHandle`, available once the session thread has processed it.
+#[derive(Debug)]
+pub struct Pending<T> {
+    rx:mpsc::Receiver<Result<T,Error>>
+}
+
+fn stopped()->Error {
+    Error::Ssh("The session thread has stopped".to_string())
+}
+
+impl<T> Pending<T> {
+    /// Wait for the result.
+    pub fn wait(self)->Result<T,Error> {
+        self.rx.recv().unwrap_or_else(|_| Err(stopped()))
+    }
+    /// Wait for the result for at most `timeout`. Returns `None` if it is not available yet.
+    pub fn wait_timeout(&self,timeout:Duration)->Option<Result<T,Error>> {
+        match self.rx.recv_timeout(timeout) {
+            Ok(r)=>Some(r),
+            Err(mpsc::RecvTimeoutError::Timeout)=>None,
+            Err(mpsc::RecvTimeoutError::Disconnected)=>Some(Err(stopped()))
+        }
+    }
+    /// The result, if it is available.
+    pub fn try_get(&self)->Option<Result<T,Error>> {
+        match self.rx.try_recv() {
+            Ok(r)=>Some(r),
+            Err(mpsc::TryRecvError::Empty)=>None,
+            Err(mpsc::TryRecvError::Disconnected)=>Some(Err(stopped()))
+        }
+    }
+}
+
+impl SessionHandle {
+    /// Move `session`, usually connected and authenticated, to a new thread.
+    pub fn spawn(mut session:Session)->SessionHandle {
+        let (tx,rx)=mpsc::channel::<Message>();
+        thread::spawn(move || {
+            for m in rx.iter() {
+                match m {
+                    Message::Run(f)=>f(&mut session),
+                    Message::Close(reply)=>{
+                        let _=reply.send(session.close());
+                        return
+                    }
+                }
+            }
+            // All handles were dropped.
+            let _=session.close();
+        });
+        SessionHandle { tx }
+    }
+    /// Run `f` on the session thread. The session can't be borrowed by the result, so channels and SFTP sessions must be used and closed within `f`.
+    pub fn run<T,F>(&self,f:F)->Pending<T>
+        where T:Send+'static,F:FnOnce(&mut Session)->Result<T,Error>+Send+'static {
+        let (reply,rx)=mpsc::channel();
+        let _=self.tx.send(Message::Run(Box::new(move |session:&mut Session| {
+            let _=reply.send(f(session));
+        })));
+        Pending { rx }
+    }
+    /// Run `cmd`, as `Session::exec`.
+    pub fn exec(&self,cmd:&str)->Pending<Output> {
+        let cmd=cmd.to_string();
+        self.run(move |s| s.exec(&cmd))
+    }
+    /// The contents of a remote file, over SFTP.
+    pub fn read_file<P:AsRef<Path>>(&self,path:P)->Pending<Vec<u8>> {
+        let path=path.as_ref().to_path_buf();
+        self.run(move |s| {
+            let sftp=s.sftp_new()?;
+            let mut contents=Vec::new();
+            std::io::Read::read_to_end(&mut sftp.open(&path)?,&mut contents)?;
+            Ok(contents)
+        })
+    }
+    /// Create (or truncate) a remote file with permissions `mode`, and write `contents` to it, over SFTP.
+    pub fn write_file<P:AsRef<Path>,M:Into<Permissions>>(&self,path:P,contents:Vec<u8>,mode:M)->Pending<()> {
+        let (path,mode)=(path.as_ref().to_path_buf(),mode.into());
+        self.run(move |s| {
+            let sftp=s.sftp_new()?;
+            let mut f=sftp.create(&path,mode)?;
+            std::io::Write::write_all(&mut f,&contents)?;
+            f.close()
+        })
+    }
+    /// The entries of a remote directory, over SFTP.
+    pub fn read_dir<P:AsRef<Path>>(&self,path:P)->Pending<Vec<Metadata>> {
+        let path:PathBuf=path.as_ref().to_path_buf();
+        self.run(move |s| s.sftp_new()?.read_dir(&path))
+    }
+    /// Forward `local` to `remote_host:remote_port`, as seen from the server, until either side closes the connection. Returns the numbers of bytes sent and received. The session processes no other request while the connection lasts.
+    pub fn forward(&self,local:TcpStream,remote_host:&str,remote_port:u16)->Pending<(u64,u64)> {
+        let remote_host=remote_host.to_string();
+        self.run(move |s| {
+            let peer=local.peer_addr()?;
+            let mut channel=s.channel_new()?;
+            channel.open_forward(&remote_host,remote_port,&peer.ip().to_string(),peer.port())?;
+            let (sent,received)=channel.stream().tunnel(&local)?;
+            Ok((sent,received))
+        })
+    }
+    /// Close the session once the requests sent before are processed. Requests sent afterwards, from other handles, fail.
+    pub fn close(self)->Pending<()> {
+        let (reply,rx)=mpsc::channel();
+        let _=self.tx.send(Message::Close(reply));
+        Pending { rx }
+    }
+}
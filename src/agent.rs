@@ -0,0 +1,273 @@
+//! A minimal client for ssh-agent, to list and remove the identities it holds.
+//!
+//! libssh can authenticate with all the keys of an agent (`Session::userauth_agent`), but does not tell which keys these are. This module talks to the agent directly, so that applications can show the available keys, and check which of them a server accepts (`Session::userauth_try_publickey`).
+//!
+//!```no_run
+//! use ssh::agent::Agent;
+//! use ssh::HashType;
+//!
+//! let mut agent=Agent::connect().unwrap();
+//! for id in agent.identities().unwrap() {
+//!     println!("{} {} {}",id.key.key_type(),id.key.fingerprint(HashType::Sha256).unwrap(),id.comment)
+//! }
+//!```
+
+use std::fs::{self,DirBuilder};
+use std::io::{Read,Write};
+use std::os::unix::fs::DirBuilderExt;
+use std::os::unix::net::{UnixListener,UnixStream};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize,Ordering};
+use std::thread;
+
+use super::{Error,PublicKey,Session,SshOptions,ssh_options_set,err,SSH_OK};
+use super::auth::{AuthMethod,SSH_AUTH_PARTIAL,SSH_AUTH_SUCCESS,ssh_userauth_agent};
+use super::libc::c_int;
+
+const SSH_AGENT_FAILURE:u8=5;
+const SSH_AGENT_SUCCESS:u8=6;
+const SSH2_AGENTC_REQUEST_IDENTITIES:u8=11;
+const SSH2_AGENT_IDENTITIES_ANSWER:u8=12;
+const SSH2_AGENTC_REMOVE_IDENTITY:u8=18;
+const SSH2_AGENTC_REMOVE_ALL_IDENTITIES:u8=19;
+
+/// Agents don't send messages larger than this.
+const MAX_MESSAGE_LEN:usize=256*1024;
+
+/// A key held by the agent.
+#[derive(Debug)]
+pub struct Identity {
+    pub key:PublicKey,
+    /// The comment given when the key was added, usually the path of the key file.
+    pub comment:String
+}
+
+/// A key in the wire format, and its comment, as sent by the agent.
+type RawIdentity=(Vec<u8>,Vec<u8>);
+
+/// A connection to an ssh-agent.
+#[derive(Debug)]
+pub struct Agent {
+    stream:UnixStream
+}
+
+fn protocol_error(msg:&str)->Error {
+    Error::Ssh(format!("ssh-agent protocol error: {}",msg))
+}
+
+/// Read an SSH string (a length followed by bytes) at the beginning of `buf`, and advance `buf`.
+fn read_string<'a>(buf:&mut &'a [u8])->Result<&'a [u8],Error> {
+    if buf.len()<4 {
+        return Err(protocol_error("truncated message"))
+    }
+    let len=u32::from_be_bytes([buf[0],buf[1],buf[2],buf[3]]) as usize;
+    if buf.len()<4+len {
+        return Err(protocol_error("truncated message"))
+    }
+    let s=&buf[4..4+len];
+    *buf=&buf[4+len..];
+    Ok(s)
+}
+
+/// Append an SSH string to `buf`.
+fn put_string(buf:&mut Vec<u8>,s:&[u8]) {
+    buf.extend_from_slice(&(s.len() as u32).to_be_bytes());
+    buf.extend_from_slice(s)
+}
+
+/// Send a message (a type followed by its contents).
+fn write_message<W:Write>(w:&mut W,msg_type:u8,contents:&[u8])->Result<(),Error> {
+    let mut msg=Vec::with_capacity(5+contents.len());
+    msg.extend_from_slice(&(1+contents.len() as u32).to_be_bytes());
+    msg.push(msg_type);
+    msg.extend_from_slice(contents);
+    Ok(w.write_all(&msg)?)
+}
+
+/// Receive a message, and return its type and contents.
+fn read_message<R:Read>(r:&mut R)->Result<(u8,Vec<u8>),Error> {
+    let mut len=[0;4];
+    r.read_exact(&mut len)?;
+    let len=u32::from_be_bytes(len) as usize;
+    if len==0 || len>MAX_MESSAGE_LEN {
+        return Err(protocol_error("invalid message length"))
+    }
+    let mut msg=vec![0;len];
+    r.read_exact(&mut msg)?;
+    let t=msg.remove(0);
+    Ok((t,msg))
+}
+
+impl Agent {
+    /// Connect to the agent whose socket is given by the `SSH_AUTH_SOCK` environment variable.
+    pub fn connect()->Result<Agent,Error> {
+        match std::env::var_os("SSH_AUTH_SOCK") {
+            Some(path)=>Agent::connect_to(path),
+            None=>Err(Error::Ssh("SSH_AUTH_SOCK is not set".to_string()))
+        }
+    }
+    /// Connect to the agent listening on the Unix socket at `path`.
+    pub fn connect_to<P:AsRef<Path>>(path:P)->Result<Agent,Error> {
+        Ok(Agent { stream:UnixStream::connect(path)? })
+    }
+
+    /// Send a request (a message type followed by its contents), and return the type and contents of the answer.
+    fn request(&mut self,msg_type:u8,contents:&[u8])->Result<(u8,Vec<u8>),Error> {
+        write_message(&mut self.stream,msg_type,contents)?;
+        read_message(&mut self.stream)
+    }
+
+    fn expect_success(&mut self,msg_type:u8,contents:&[u8])->Result<(),Error> {
+        match self.request(msg_type,contents)? {
+            (SSH_AGENT_SUCCESS,_)=>Ok(()),
+            (SSH_AGENT_FAILURE,_)=>Err(Error::RequestDenied("the agent refused the request".to_string())),
+            _=>Err(protocol_error("unexpected answer"))
+        }
+    }
+
+    /// The keys held by the agent, in the wire format, with their comments.
+    fn raw_identities(&mut self)->Result<Vec<RawIdentity>,Error> {
+        let (t,answer)=self.request(SSH2_AGENTC_REQUEST_IDENTITIES,&[])?;
+        if t!=SSH2_AGENT_IDENTITIES_ANSWER {
+            return Err(protocol_error("unexpected answer"))
+        }
+        let mut buf=&answer[..];
+        if buf.len()<4 {
+            return Err(protocol_error("truncated message"))
+        }
+        let n=u32::from_be_bytes([buf[0],buf[1],buf[2],buf[3]]);
+        buf=&buf[4..];
+        let mut ids=Vec::new();
+        for _ in 0..n {
+            let blob=read_string(&mut buf)?;
+            let comment=read_string(&mut buf)?;
+            ids.push((blob.to_vec(),comment.to_vec()))
+        }
+        Ok(ids)
+    }
+
+    /// List the keys held by the agent.
+    pub fn identities(&mut self)->Result<Vec<Identity>,Error> {
+        self.raw_identities()?.into_iter().map(|(blob,comment)| Ok(Identity {
+            key:PublicKey::from_blob(&blob)?,
+            comment:String::from_utf8_lossy(&comment).into_owned()
+        })).collect()
+    }
+
+    /// Whether the agent holds `key`. Unlike `identities`, this works even if the agent holds keys of types that libssh cannot parse.
+    pub fn holds(&mut self,key:&PublicKey)->Result<bool,Error> {
+        let blob=key.to_blob()?;
+        Ok(self.raw_identities()?.iter().any(|id| id.0==blob))
+    }
+
+    /// Remove a key from the agent (as `ssh-add -d`).
+    pub fn remove_identity(&mut self,key:&PublicKey)->Result<(),Error> {
+        let blob=key.to_blob()?;
+        let mut contents=Vec::with_capacity(4+blob.len());
+        contents.extend_from_slice(&(blob.len() as u32).to_be_bytes());
+        contents.extend_from_slice(&blob);
+        self.expect_success(SSH2_AGENTC_REMOVE_IDENTITY,&contents)
+    }
+
+    /// Remove all keys from the agent (as `ssh-add -D`).
+    pub fn remove_all_identities(&mut self)->Result<(),Error> {
+        self.expect_success(SSH2_AGENTC_REMOVE_ALL_IDENTITIES,&[])
+    }
+
+    /// Answer the requests of a client on `stream` until it disconnects, listing only the identity `id`, and passing the other requests (signatures) to this agent.
+    fn serve_filtered(mut self,mut stream:UnixStream,id:&RawIdentity)->Result<(),Error> {
+        loop {
+            let (t,contents)=match read_message(&mut stream) {
+                Ok(msg)=>msg,
+                Err(Error::IO(ref e)) if e.kind()==std::io::ErrorKind::UnexpectedEof=>return Ok(()),
+                Err(e)=>return Err(e)
+            };
+            let (t,answer)=if t==SSH2_AGENTC_REQUEST_IDENTITIES {
+                let mut answer=1u32.to_be_bytes().to_vec();
+                put_string(&mut answer,&id.0);
+                put_string(&mut answer,&id.1);
+                (SSH2_AGENT_IDENTITIES_ANSWER,answer)
+            } else {
+                self.request(t,&contents)?
+            };
+            write_message(&mut stream,t,&answer)?
+        }
+    }
+}
+
+/// Distinguishes the filtering agent sockets of a process.
+static FILTER_COUNTER:AtomicUsize=AtomicUsize::new(0);
+
+impl Session {
+    /// Connect to the agent this session authenticates with: the one given to `set_agent_socket` (or by `IdentityAgent` in the configuration), else the one of `SSH_AUTH_SOCK`.
+    pub(crate) fn agent(&self)->Result<Agent,Error> {
+        match self.get_option(SshOptions::IDENTITY_AGENT) {
+            Some(path)=>Agent::connect_to(path),
+            None=>Agent::connect()
+        }
+    }
+    /// Authenticate with the key `key` of the agent only, instead of trying each of its keys as `userauth_agent` does (servers may lock accounts after a few refused keys). libssh is given a private socket where the agent seems to hold `key` alone, and whose signature requests are passed to the agent.
+    ///
+    /// Returns `Ok(false)` if the key was accepted, but the server requires more authentication, with another method.
+    ///
+    /// libssh keeps its connection to the agent once authenticated, so this must come before any other agent authentication on the session.
+    ///
+    ///```no_run
+    /// use ssh::*;
+    /// use ssh::agent::Agent;
+    ///
+    /// let mut agent=Agent::connect().unwrap();
+    /// let id=agent.identities().unwrap().into_iter().find(|id| id.comment=="deploy").unwrap();
+    /// let mut session=Session::new().unwrap();
+    /// session.set_host("pijul.org").unwrap();
+    /// session.parse_config(None).unwrap();
+    /// session.connect().unwrap();
+    /// assert!(session.userauth_agent_identity(&id.key).unwrap());
+    ///```
+    pub fn userauth_agent_identity(&mut self,key:&PublicKey)->Result<bool,Error> {
+        let e=self.agent_identity(key)?;
+        self.record_auth(AuthMethod::PublicKey,e);
+        match e {
+            SSH_AUTH_SUCCESS=>Ok(true),
+            SSH_AUTH_PARTIAL=>Ok(false),
+            _=>Err(err(self))
+        }
+    }
+    /// Authenticate with the key `key` of the agent only, and return the answer of libssh, without recording it.
+    pub(crate) fn agent_identity(&mut self,key:&PublicKey)->Result<c_int,Error> {
+        let mut agent=self.agent()?;
+        let blob=key.to_blob()?;
+        let id=match agent.raw_identities()?.into_iter().find(|id| id.0==blob) {
+            Some(id)=>id,
+            None=>return Err(Error::RequestDenied("the agent doesn't hold this key".to_string()))
+        };
+        // A directory only we can enter, so that nobody else can use the socket.
+        let dir=std::env::temp_dir().join(format!("ssh-agent-{}-{}",std::process::id(),FILTER_COUNTER.fetch_add(1,Ordering::SeqCst)));
+        DirBuilder::new().mode(0o700).create(&dir)?;
+        let path=dir.join("agent.sock");
+        let result=UnixListener::bind(&path).map_err(Error::from).and_then(|listener| {
+            thread::spawn(move || {
+                if let Ok((stream,_))=listener.accept() {
+                    if let Err(e)=agent.serve_filtered(stream,&id) {
+                        debug!("filtering agent: {}",e)
+                    }
+                }
+            });
+            let previous=self.get_option(SshOptions::IDENTITY_AGENT);
+            let result=self.set_agent_socket(&path).map(|_| unsafe { ssh_userauth_agent(self.session,std::ptr::null()) });
+            // Stop the thread if libssh didn't connect.
+            let _=UnixStream::connect(&path);
+            // Without a previous socket, clear the option, so that `SSH_AUTH_SOCK` is used again.
+            match previous {
+                Some(previous)=>self.set_agent_socket(previous)?,
+                None=>if unsafe { ssh_options_set(self.session,SshOptions::IDENTITY_AGENT as c_int,std::ptr::null()) }!=SSH_OK {
+                    return Err(err(self))
+                }
+            }
+            result
+        });
+        let _=fs::remove_file(&path);
+        let _=fs::remove_dir(&dir);
+        result
+    }
+}
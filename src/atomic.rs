@@ -0,0 +1,80 @@
+//! Uploads that replace files atomically: readers of the destination see either the old file or the complete new one, never a partial upload.
+
+use std::io::Read;
+use std::path::{Path,PathBuf};
+
+use super::{Error,Permissions,Session};
+use super::exec::shell_quote;
+
+/// The temporary name of an upload to `remote`: a hidden file in the same directory, so that renaming it doesn't cross file systems.
+fn temporary_name(remote:&Path)->Result<PathBuf,Error> {
+    let name=match remote.file_name() {
+        Some(n)=>n.to_string_lossy(),
+        None=>return Err(Error::Ssh(format!("{:?} is not a file name",remote)))
+    };
+    let mut random=[0u8;6];
+    getrandom::getrandom(&mut random).map_err(|e| Error::IO(std::io::Error::other(e.to_string())))?;
+    let suffix:String=random.iter().map(|b| format!("{:02x}",b)).collect();
+    Ok(remote.with_file_name(format!(".{}.{}.tmp",name,suffix)))
+}
+
+impl Session {
+    /// Upload `source` to `remote` atomically: the data is written over SFTP to a temporary file next to `remote`, which is then renamed to `remote`, replacing it. If `fsync` is set, the temporary file is written to disk before being renamed (this needs the `fsync@openssh.com` extension of OpenSSH), so that a crash of the server cannot leave an empty file behind either. Returns the number of bytes uploaded.
+    ///
+    /// The temporary file replaces `remote` with `posix-rename@openssh.com` on servers supporting it, such as OpenSSH. Other SFTP servers usually refuse to rename over an existing file: the file is then moved with `mv -f` on the server, which is atomic too. Only if the server runs no commands is `remote` removed before the rename, leaving a short window without the file. The temporary file is removed if the upload fails.
+    ///
+    ///```no_run
+    /// use ssh::*;
+    ///
+    /// let mut session=Session::new().unwrap();
+    /// session.set_host("pijul.org").unwrap();
+    /// session.parse_config(None).unwrap();
+    /// session.connect().unwrap();
+    /// session.userauth_publickey_auto(None).unwrap();
+    /// let mut artifact=std::fs::File::open("target/release/server").unwrap();
+    /// session.upload_atomic(&mut artifact,"/srv/app/server",0o755,true).unwrap();
+    ///```
+    pub fn upload_atomic<P:AsRef<Path>,M:Into<Permissions>>(&mut self,source:&mut dyn Read,remote:P,mode:M,fsync:bool)->Result<u64,Error> {
+        let remote=remote.as_ref();
+        let tmp=temporary_name(remote)?;
+        let r=self.upload_then_rename(source,remote,&tmp,mode.into(),fsync);
+        if r.is_err() {
+            if let Ok(sftp)=self.sftp_new() {
+                let _=sftp.remove_file(&tmp);
+            }
+        }
+        r
+    }
+
+    fn upload_then_rename(&mut self,source:&mut dyn Read,remote:&Path,tmp:&Path,mode:Permissions,fsync:bool)->Result<u64,Error> {
+        let n={
+            let sftp=self.sftp_new()?;
+            let mut f=sftp.open_with(tmp,super::libc::O_WRONLY|super::libc::O_CREAT|super::libc::O_EXCL,mode)?;
+            let n=std::io::copy(source,&mut f)?;
+            if fsync {
+                f.sync_all()?
+            }
+            f.close()?;
+            // The mode given to `open` is filtered by the umask of the server.
+            sftp.set_permissions(tmp,mode)?;
+            if sftp.rename(tmp,remote).is_ok() {
+                return Ok(n)
+            }
+            n
+        };
+        let cmd=format!("mv -f -- {} {}",shell_quote(&tmp.to_string_lossy()),shell_quote(&remote.to_string_lossy()));
+        match self.exec(&cmd) {
+            Ok(ref out) if out.exit_status==Some(0)=>return Ok(n),
+            Ok(out)=>debug!("{} failed: {}",cmd,String::from_utf8_lossy(&out.stderr).trim()),
+            Err(Error::RequestDenied(_))=>debug!("exec refused, replacing {:?} non-atomically",remote),
+            Err(e)=>return Err(e)
+        }
+        let sftp=self.sftp_new()?;
+        match sftp.remove_file(remote) {
+            Err(Error::IO(ref e)) if e.kind()==std::io::ErrorKind::NotFound=>(),
+            r=>r?
+        }
+        sftp.rename(tmp,remote)?;
+        Ok(n)
+    }
+}
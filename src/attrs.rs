@@ -0,0 +1,147 @@
+//! Ownership and extended attributes of remote files, for provisioning system images: numeric ids of remote users and groups, and the `user.` extended attributes, read and written with `getfattr` and `setfattr` on the server.
+
+use std::path::Path;
+
+use super::{Error,Session};
+use super::exec::shell_quote;
+
+/// Extended attributes carried by transfers are those of this namespace, which unprivileged users can set.
+const NAMESPACE:&str="user.";
+
+/// An extended attribute: its name, including the namespace (`user.origin`), and its value.
+pub type Xattr=(String,Vec<u8>);
+
+fn hex_decode(s:&str)->Option<Vec<u8>> {
+    if s.len()&1==1 {
+        return None
+    }
+    (0..s.len()).step_by(2).map(|i| u8::from_str_radix(s.get(i..i+2)?,16).ok()).collect()
+}
+
+/// Parse the output of `getfattr -d -e hex`: comments, and `name=0x…` lines.
+fn parse_getfattr(out:&str)->Vec<Xattr> {
+    out.lines()
+        .filter(|l| !l.starts_with('#') && !l.is_empty())
+        .filter_map(|l| {
+            let (name,value)=match l.find('=') {
+                Some(i)=>(&l[..i],&l[i+1..]),
+                None=>(l,"")
+            };
+            let value=match value.strip_prefix("0x") {
+                Some(h)=>hex_decode(h)?,
+                None=>value.trim_matches('"').as_bytes().to_vec()
+            };
+            Some((name.to_string(),value))
+        })
+        .collect()
+}
+
+impl Session {
+    /// Run `cmd` with `sh`, and return its standard output, failing if it exits with a non-zero status.
+    fn run_checked(&mut self,cmd:&str)->Result<String,Error> {
+        let out=self.exec(cmd)?;
+        if out.exit_status==Some(0) {
+            Ok(String::from_utf8_lossy(&out.stdout).into_owned())
+        } else {
+            Err(Error::Ssh(format!("{} exited with status {:?}: {}",cmd,out.exit_status,String::from_utf8_lossy(&out.stderr).trim())))
+        }
+    }
+    /// The numeric ids of the remote user `user` and of `group`, or of the primary group of `user` if `group` is `None`, for `Sftp::chown`. This runs `id` and `getent` on the server.
+    ///
+    ///```no_run
+    /// use ssh::*;
+    ///
+    /// let mut session=Session::new().unwrap();
+    /// session.set_host("pijul.org").unwrap();
+    /// session.parse_config(None).unwrap();
+    /// session.connect().unwrap();
+    /// session.userauth_publickey_auto(None).unwrap();
+    /// let (uid,gid)=session.lookup_owner("www-data",None).unwrap();
+    /// session.sftp_new().unwrap().chown("/srv/www/index.html",uid,gid).unwrap();
+    ///```
+    pub fn lookup_owner(&mut self,user:&str,group:Option<&str>)->Result<(u32,u32),Error> {
+        let parse=|out:&str,what:&str|->Result<u32,Error> {
+            out.trim().parse().map_err(|_| Error::Ssh(format!("Could not find the id of {} {:?}",what,out.trim())))
+        };
+        let uid=parse(&self.run_checked(&format!("id -u -- {}",shell_quote(user)))?,"user")?;
+        let gid=match group {
+            Some(g)=>{
+                // "name:password:gid:members"
+                let line=self.run_checked(&format!("getent group -- {}",shell_quote(g)))?;
+                parse(line.split(':').nth(2).unwrap_or(""),"group")?
+            },
+            None=>parse(&self.run_checked(&format!("id -g -- {}",shell_quote(user)))?,"group")?
+        };
+        Ok((uid,gid))
+    }
+    /// The `user.` extended attributes of the remote file `path`. The server needs `getfattr` (from the `attr` package).
+    pub fn xattrs<P:AsRef<Path>>(&mut self,path:P)->Result<Vec<Xattr>,Error> {
+        let out=self.run_checked(&format!("getfattr -d -e hex --absolute-names -- {}",shell_quote(&path.as_ref().to_string_lossy())))?;
+        Ok(parse_getfattr(&out))
+    }
+    /// Set extended attributes of the remote file `path`, keeping the others. Attributes outside of the `user.` namespace are rejected. The server needs `setfattr` (from the `attr` package).
+    pub fn set_xattrs<P:AsRef<Path>>(&mut self,path:P,xattrs:&[Xattr])->Result<(),Error> {
+        if xattrs.is_empty() {
+            return Ok(())
+        }
+        let path=shell_quote(&path.as_ref().to_string_lossy());
+        let mut cmd=Vec::with_capacity(xattrs.len());
+        for (name,value) in xattrs {
+            if !name.starts_with(NAMESPACE) {
+                return Err(Error::Ssh(format!("Extended attribute {:?} is not in the {} namespace",name,NAMESPACE)))
+            }
+            let value:String=value.iter().map(|b| format!("{:02x}",b)).collect();
+            cmd.push(format!("setfattr -n {} -v 0x{} -- {}",shell_quote(name),value,path))
+        }
+        self.run_checked(&cmd.join(" && "))?;
+        Ok(())
+    }
+}
+
+/// The `user.` extended attributes of the local file `path`.
+#[cfg(target_os="linux")]
+pub fn local_xattrs<P:AsRef<Path>>(path:P)->Result<Vec<Xattr>,Error> {
+    use super::libc::{c_char,c_void,getxattr,listxattr};
+    let p=super::path_as_ptr(path.as_ref())?;
+    let os_err=|| Error::IO(std::io::Error::last_os_error());
+    let len=unsafe { listxattr(p.as_ptr(),std::ptr::null_mut(),0) };
+    if len<0 {
+        return Err(os_err())
+    }
+    let mut names=vec![0u8;len as usize];
+    let len=unsafe { listxattr(p.as_ptr(),names.as_mut_ptr() as *mut c_char,names.len()) };
+    if len<0 {
+        return Err(os_err())
+    }
+    names.truncate(len as usize);
+    let mut xattrs=Vec::new();
+    for name in names.split(|&c| c==0).filter(|n| n.starts_with(NAMESPACE.as_bytes())) {
+        let cname=std::ffi::CString::new(name)?;
+        let len=unsafe { getxattr(p.as_ptr(),cname.as_ptr(),std::ptr::null_mut(),0) };
+        if len<0 {
+            return Err(os_err())
+        }
+        let mut value=vec![0u8;len as usize];
+        let len=unsafe { getxattr(p.as_ptr(),cname.as_ptr(),value.as_mut_ptr() as *mut c_void,value.len()) };
+        if len<0 {
+            return Err(os_err())
+        }
+        value.truncate(len as usize);
+        xattrs.push((String::from_utf8_lossy(name).into_owned(),value))
+    }
+    Ok(xattrs)
+}
+
+/// Set extended attributes of the local file `path`, for instance those returned by `Session::xattrs`.
+#[cfg(target_os="linux")]
+pub fn set_local_xattrs<P:AsRef<Path>>(path:P,xattrs:&[Xattr])->Result<(),Error> {
+    use super::libc::{c_void,setxattr};
+    let p=super::path_as_ptr(path.as_ref())?;
+    for (name,value) in xattrs {
+        let name=std::ffi::CString::new(name.as_bytes())?;
+        if unsafe { setxattr(p.as_ptr(),name.as_ptr(),value.as_ptr() as *const c_void,value.len(),0) }<0 {
+            return Err(Error::IO(std::io::Error::last_os_error()))
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,218 @@
+//! An audit log of the remote commands run through `RemoteCommand` (and so `Session::exec`, `exec_to`, `exec_login`, `Batch` and `fleet`): who ran what, where, when, and how it ended.
+//!
+//! The log is tamper-evident: each record contains the hash of the previous one, and its own hash, so that modifying, inserting or removing a record breaks the chain, which `verify` detects. With a secret `key`, the hashes are HMACs, which can't be recomputed without the key after editing the file.
+//!
+//! Each record is a line of tab-separated fields: sequence number, start and end times (seconds since the Unix epoch), user, host, port, exit status, error, command line, hash of the previous record and hash of the record. Tabs, newlines and backslashes in the fields are escaped with backslashes.
+//!
+//!```no_run
+//! use ssh::*;
+//! use ssh::audit::AuditLog;
+//!
+//! let log=AuditLog::open("/var/log/ssh-audit.log").unwrap().key(b"secret");
+//! let mut session=Session::new().unwrap();
+//! session.set_host("pijul.org").unwrap();
+//! session.set_audit_log(Some(log.clone()));
+//! session.parse_config(None).unwrap();
+//! session.connect().unwrap();
+//! session.userauth_publickey_auto(None).unwrap();
+//! session.exec("uname -a").unwrap();
+//!
+//! let f=std::io::BufReader::new(std::fs::File::open("/var/log/ssh-audit.log").unwrap());
+//! println!("{} records", ssh::audit::verify(f,Some(b"secret")).unwrap());
+//!```
+
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::{BufRead,BufReader,Write};
+use std::path::Path;
+use std::sync::{Arc,Mutex};
+use std::time::{SystemTime,UNIX_EPOCH};
+
+use hmac::{Hmac,Mac};
+use sha1::{Digest,Sha1};
+
+use super::{Error,Session};
+
+/// The previous hash of the first record of a log.
+const GENESIS:&str="0000000000000000000000000000000000000000";
+
+/// A command, as recorded in the log.
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub struct Record {
+    /// Position in the log, starting at 0.
+    pub seq:u64,
+    pub start:SystemTime,
+    pub end:SystemTime,
+    pub user:Option<String>,
+    pub host:Option<String>,
+    pub port:u16,
+    /// The command line sent to the server.
+    pub command:String,
+    /// `None` if the command failed to run, or finished without an exit status.
+    pub exit_status:Option<i32>,
+    /// Why the command failed to run, or its output could not be read.
+    pub error:Option<String>,
+    /// Hash of the previous record.
+    pub prev:String,
+    pub hash:String
+}
+
+fn escape(s:&str)->String {
+    s.replace('\\',"\\\\").replace('\t',"\\t").replace('\n',"\\n").replace('\r',"\\r")
+}
+
+fn time(t:SystemTime)->String {
+    let d=t.duration_since(UNIX_EPOCH).unwrap_or_default();
+    format!("{}.{:09}",d.as_secs(),d.subsec_nanos())
+}
+
+impl Record {
+    /// The line of the record, without its hash.
+    fn body(&self)->String {
+        format!("{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                self.seq,time(self.start),time(self.end),
+                escape(self.user.as_deref().unwrap_or("")),escape(self.host.as_deref().unwrap_or("")),self.port,
+                self.exit_status.map(|e| e.to_string()).unwrap_or_default(),
+                escape(self.error.as_deref().unwrap_or("")),escape(&self.command),self.prev)
+    }
+}
+
+impl fmt::Display for Record {
+    /// The line of the record in the log.
+    fn fmt(&self,f:&mut fmt::Formatter)->fmt::Result {
+        write!(f,"{}\t{}",self.body(),self.hash)
+    }
+}
+
+fn hash(key:Option<&[u8]>,body:&str)->String {
+    let digest=match key {
+        Some(key)=>{
+            let mut mac=<Hmac<Sha1> as Mac>::new_from_slice(key).expect("HMAC accepts keys of any length");
+            mac.update(body.as_bytes());
+            mac.finalize().into_bytes().to_vec()
+        },
+        None=>Sha1::digest(body.as_bytes()).to_vec()
+    };
+    digest.iter().map(|b| format!("{:02x}",b)).collect()
+}
+
+type Hook=Box<dyn FnMut(&Record)+Send>;
+
+struct Chain {
+    out:Box<dyn Write+Send>,
+    key:Option<Vec<u8>>,
+    seq:u64,
+    last:String,
+    on_record:Option<Hook>
+}
+
+/// Where the commands are recorded. Clones share the same chain, so that a single log can be given to several sessions, used from several threads.
+#[derive(Clone)]
+pub struct AuditLog {
+    chain:Arc<Mutex<Chain>>
+}
+
+impl fmt::Debug for AuditLog {
+    fn fmt(&self,f:&mut fmt::Formatter)->fmt::Result {
+        f.write_str("AuditLog{ .. }")
+    }
+}
+
+impl AuditLog {
+    /// A new log, written to `out`.
+    pub fn new<W:Write+Send+'static>(out:W)->AuditLog {
+        AuditLog { chain:Arc::new(Mutex::new(Chain { out:Box::new(out),key:None,seq:0,last:GENESIS.to_string(),on_record:None })) }
+    }
+    /// Append to the log file at `path`, continuing its chain, or create it. The existing records are not checked, see `verify`.
+    pub fn open<P:AsRef<Path>>(path:P)->Result<AuditLog,Error> {
+        let mut f=OpenOptions::new().read(true).append(true).create(true).open(path)?;
+        let mut last=None;
+        for line in BufReader::new(&mut f).lines() {
+            let line=line?;
+            if !line.is_empty() {
+                last=Some(line)
+            }
+        }
+        let log=AuditLog::new(f);
+        if let Some(line)=last {
+            let fields:Vec<&str>=line.split('\t').collect();
+            let seq:u64=fields[0].parse().map_err(|_| invalid(0,"malformed last record"))?;
+            let mut chain=log.chain.lock().unwrap();
+            chain.seq=seq+1;
+            chain.last=fields[fields.len()-1].to_string();
+        }
+        Ok(log)
+    }
+    /// Compute the hashes as HMACs with `key`.
+    pub fn key(self,key:&[u8])->AuditLog {
+        self.chain.lock().unwrap().key=Some(key.to_vec());
+        self
+    }
+    /// Call `f` with each record, once written, for instance to forward it to a remote collector.
+    pub fn on_record<F:FnMut(&Record)+Send+'static>(self,f:F)->AuditLog {
+        self.chain.lock().unwrap().on_record=Some(Box::new(f));
+        self
+    }
+    /// Record a command run on `session`.
+    pub(crate) fn append(&self,session:&Session,command:&str,start:SystemTime,result:&Result<Option<i32>,Error>)->Result<Record,Error> {
+        let mut chain=self.chain.lock().unwrap();
+        let mut record=Record {
+            seq:chain.seq,
+            start,
+            end:SystemTime::now(),
+            user:session.username(),
+            host:session.host(),
+            port:session.port(),
+            command:command.to_string(),
+            exit_status:result.as_ref().ok().and_then(|e| *e),
+            error:result.as_ref().err().map(|e| e.to_string()),
+            prev:chain.last.clone(),
+            hash:String::new()
+        };
+        record.hash=hash(chain.key.as_deref(),&record.body());
+        writeln!(chain.out,"{}",record)?;
+        chain.out.flush()?;
+        chain.seq+=1;
+        chain.last=record.hash.clone();
+        if let Some(ref mut f)=chain.on_record {
+            f(&record)
+        }
+        Ok(record)
+    }
+}
+
+fn invalid(line:u64,msg:&str)->Error {
+    Error::IO(std::io::Error::new(std::io::ErrorKind::InvalidData,format!("audit log, line {}: {}",line,msg)))
+}
+
+/// Check the chain of the records of `log`, with the `key` the log was written with, and return the number of records. A broken chain (including records missing at the start) is reported as an `InvalidData` I/O error, giving the first line that doesn't match.
+pub fn verify<R:BufRead>(log:R,key:Option<&[u8]>)->Result<u64,Error> {
+    let mut last=GENESIS.to_string();
+    let mut n=0;
+    for (i,line) in log.lines().enumerate() {
+        let line=line?;
+        let i=i as u64+1;
+        if line.is_empty() {
+            continue
+        }
+        let (body,h)=line.rsplit_once('\t').ok_or_else(|| invalid(i,"malformed record"))?;
+        let (rest,prev)=body.rsplit_once('\t').ok_or_else(|| invalid(i,"malformed record"))?;
+        let seq=rest.split('\t').next().and_then(|s| s.parse::<u64>().ok()).ok_or_else(|| invalid(i,"malformed record"))?;
+        if prev!=last || seq!=n {
+            return Err(invalid(i,"the chain is broken"))
+        }
+        if hash(key,body)!=h {
+            return Err(invalid(i,"the hash doesn't match"))
+        }
+        last=h.to_string();
+        n+=1;
+    }
+    Ok(n)
+}
+
+impl Session {
+    /// Record the commands run on this session with `RemoteCommand` in `log`, or stop recording them. If a record can't be written, the command's result is replaced by the error.
+    pub fn set_audit_log(&mut self,log:Option<AuditLog>) {
+        self.audit=log
+    }
+}
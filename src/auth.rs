@@ -0,0 +1,440 @@
+//! Authentication methods beyond the basic ones of `Session`.
+
+use std::ffi::{CStr,CString};
+use std::fmt;
+use std::path::{Path,PathBuf};
+
+use super::libc::{c_char,c_int,c_uint,c_void,size_t};
+use super::{Error,PublicKey,Session,Session_,err,path_as_ptr,ssh_string_free_char,ssh_userauth_kbdint};
+use super::{SSH_EOF,SSH_OK};
+use super::key::Key_;
+
+extern "C" {
+    fn ssh_userauth_kbdint_getname(s:*mut Session_)->*const c_char;
+    fn ssh_userauth_kbdint_getinstruction(s:*mut Session_)->*const c_char;
+    fn ssh_userauth_kbdint_getnprompts(s:*mut Session_)->c_int;
+    fn ssh_userauth_kbdint_getprompt(s:*mut Session_,i:c_uint,echo:*mut c_char)->*const c_char;
+    fn ssh_userauth_kbdint_setanswer(s:*mut Session_,i:c_uint,answer:*const c_char)->c_int;
+    pub(crate) fn ssh_userauth_agent(s:*mut Session_,user:*const c_char)->c_int;
+    fn ssh_userauth_try_publickey(s:*mut Session_,user:*const c_char,key:*const Key_)->c_int;
+    fn ssh_userauth_none(s:*mut Session_,user:*const c_char)->c_int;
+    fn ssh_get_issue_banner(s:*mut Session_)->*mut c_char;
+    fn ssh_pki_import_privkey_file(path:*const c_char,passphrase:*const c_char,auth_fn:Option<AuthCallback>,auth_data:*mut c_void,k:*mut *mut Key_)->c_int;
+    fn ssh_pki_export_privkey_to_pubkey(private:*const Key_,public:*mut *mut Key_)->c_int;
+    fn ssh_userauth_publickey(s:*mut Session_,user:*const c_char,private:*const Key_)->c_int;
+    fn ssh_key_free(k:*mut Key_);
+}
+
+type AuthCallback=extern "C" fn(prompt:*const c_char,buf:*mut c_char,len:size_t,echo:c_int,verify:c_int,userdata:*mut c_void)->c_int;
+
+pub(crate) const SSH_AUTH_SUCCESS:c_int=0;
+pub(crate) const SSH_AUTH_DENIED:c_int=1;
+pub(crate) const SSH_AUTH_PARTIAL:c_int=2;
+pub(crate) const SSH_AUTH_INFO:c_int=3;
+
+/// An authentication method, as named in the protocol.
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Hash)]
+pub enum AuthMethod {
+    None,
+    Password,
+    KeyboardInteractive,
+    /// Public keys, including those of an agent.
+    PublicKey
+}
+
+impl AuthMethod {
+    /// The name of the method in the protocol, such as "keyboard-interactive".
+    pub fn name(&self)->&'static str {
+        match *self {
+            AuthMethod::None=>"none",
+            AuthMethod::Password=>"password",
+            AuthMethod::KeyboardInteractive=>"keyboard-interactive",
+            AuthMethod::PublicKey=>"publickey"
+        }
+    }
+}
+
+impl std::fmt::Display for AuthMethod {
+    fn fmt(&self,f:&mut std::fmt::Formatter)->std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// How a session was authenticated, returned by `Session::auth_info`.
+#[derive(Debug,Clone,Default,PartialEq,Eq)]
+pub struct AuthInfo {
+    /// The method that authenticated the session, `None` while it is not authenticated.
+    pub method:Option<AuthMethod>,
+    /// Number of authentication requests answered by the server, successful or not. Errors, such as a lost connection, are not counted.
+    pub attempts:u32,
+    /// The methods refused by the server (or only partially successful), in the order they were tried.
+    pub refused:Vec<AuthMethod>
+}
+
+/// One question asked by the server during keyboard-interactive authentication.
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub struct KbdintPrompt {
+    pub text:String,
+    /// Whether the answer may be displayed while it is typed (false for passwords and codes).
+    pub echo:bool
+}
+
+/// A round of questions sent by the server during keyboard-interactive authentication.
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub struct KbdintChallenge {
+    /// Name of the challenge, identifying what is being asked (may be empty).
+    pub name:String,
+    /// Instructions to display before the prompts, such as "Enter your RSA token code" (may be empty).
+    pub instruction:String,
+    /// The questions. The server may send rounds without any question, which are answered automatically.
+    pub prompts:Vec<KbdintPrompt>
+}
+
+fn string(s:*const c_char)->String {
+    if s.is_null() {
+        String::new()
+    } else {
+        unsafe { CStr::from_ptr(s) }.to_string_lossy().into_owned()
+    }
+}
+
+/// Why an identity given to `Session::userauth_identities` did not authenticate the session.
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub enum IdentityFailure {
+    /// The key file does not exist, or is not readable.
+    NotFound,
+    /// The key is encrypted, and no passphrase was given, or a wrong one.
+    Encrypted,
+    /// The file is not a private key, or its type is not supported by libssh.
+    Unsupported,
+    /// The key was refused, by the server or because its algorithm is not allowed by the configuration of the session, as explained by the message.
+    Denied(String),
+    /// The key was accepted, but the server requires more authentication, with another method.
+    Partial,
+    /// A FIDO security key that libssh cannot use directly, and that the agent does not hold: add it to the agent with `ssh-add`.
+    NotInAgent
+}
+
+impl fmt::Display for IdentityFailure {
+    fn fmt(&self,f:&mut fmt::Formatter)->fmt::Result {
+        match *self {
+            IdentityFailure::NotFound=>write!(f,"not found"),
+            IdentityFailure::Encrypted=>write!(f,"encrypted, and no valid passphrase given"),
+            IdentityFailure::Unsupported=>write!(f,"not a supported private key"),
+            IdentityFailure::Denied(ref msg)=>write!(f,"denied ({})",msg),
+            IdentityFailure::Partial=>write!(f,"accepted, but more authentication is needed"),
+            IdentityFailure::NotInAgent=>write!(f,"security key not held by the agent")
+        }
+    }
+}
+
+/// A private key, freed when dropped.
+struct PrivateKey(*mut Key_);
+
+impl Drop for PrivateKey {
+    fn drop(&mut self) {
+        unsafe { ssh_key_free(self.0) }
+    }
+}
+
+/// Called by libssh when a key needs a passphrase that was not given: note that it is encrypted, and give up.
+extern "C" fn encrypted(_:*const c_char,_:*mut c_char,_:size_t,_:c_int,_:c_int,asked:*mut c_void)->c_int {
+    unsafe { *(asked as *mut bool)=true };
+    -1
+}
+
+/// Read the private key in `path`.
+fn import_private_key(path:&Path,passphrase:Option<&CString>)->Result<PrivateKey,IdentityFailure> {
+    // A path containing a NUL byte cannot exist.
+    let path=path_as_ptr(path).map_err(|_| IdentityFailure::NotFound)?;
+    // The return code of libssh, and whether it asked for a passphrase.
+    let import=|passphrase:Option<&CString>| {
+        let mut key=std::ptr::null_mut();
+        let mut asked=false;
+        let e=unsafe {
+            ssh_pki_import_privkey_file(path.as_ptr(),passphrase.map(|p| p.as_ptr()).unwrap_or(std::ptr::null()),
+                                        Some(encrypted),&mut asked as *mut bool as *mut c_void,&mut key)
+        };
+        if e==SSH_OK { Ok(PrivateKey(key)) } else { Err((e,asked)) }
+    };
+    match import(passphrase) {
+        Ok(key)=>Ok(key),
+        Err((SSH_EOF,_))=>Err(IdentityFailure::NotFound),
+        Err((_,true))=>Err(IdentityFailure::Encrypted),
+        // A wrong passphrase and an invalid file fail alike: look again without the passphrase.
+        Err(_) if passphrase.is_some()=>match import(None) {
+            Err((_,true))=>Err(IdentityFailure::Encrypted),
+            _=>Err(IdentityFailure::Unsupported)
+        },
+        Err(_)=>Err(IdentityFailure::Unsupported)
+    }
+}
+
+impl Session {
+    /// Record the answer `e` of the server to an authentication request.
+    pub(crate) fn record_auth(&self,method:AuthMethod,e:c_int) {
+        self.touch();
+        let mut info=self.auth.borrow_mut();
+        match e {
+            SSH_AUTH_SUCCESS=>{
+                count!("ssh_auth_attempts_total",1,"method"=>method.name(),"result"=>"success");
+                info.attempts+=1;
+                info.method=Some(method);
+                let refused=info.refused.clone();
+                drop(info);
+                self.report_auth_downgrade(method,&refused)
+            },
+            SSH_AUTH_DENIED|SSH_AUTH_PARTIAL=>{
+                count!("ssh_auth_attempts_total",1,"method"=>method.name(),"result"=>if e==SSH_AUTH_PARTIAL { "partial" } else { "denied" });
+                info.attempts+=1;
+                info.refused.push(method)
+            },
+            _=>()
+        }
+    }
+    /// Authenticate with the private keys in `identities`, tried in order until one is accepted, and return the path of that key. `passphrase` is used for encrypted keys.
+    ///
+    /// Unlike `userauth_publickey_auto`, which fails with one error whatever happened to each key, this fails with `Error::Identities`, telling why each key was not used.
+    ///
+    /// FIDO security keys (`id_ed25519_sk`, `id_ecdsa_sk`) are used directly if libssh supports them. Else, if the public key next to the file (`id_ed25519_sk.pub`) is held by the agent of the session (given to `set_agent_socket`, else by `SSH_AUTH_SOCK`), the agent is used instead, which asks for the touch on the key. Only that key of the agent is offered, as with `userauth_agent_identity`.
+    ///
+    ///```no_run
+    /// use ssh::*;
+    ///
+    /// let mut session=Session::new().unwrap();
+    /// session.set_host("pijul.org").unwrap();
+    /// session.parse_config(None).unwrap();
+    /// session.connect().unwrap();
+    /// let ssh=std::path::Path::new(&std::env::var("HOME").unwrap()).join(".ssh");
+    /// match session.userauth_identities(&[ssh.join("id_ed25519"),ssh.join("id_rsa")],None) {
+    ///     Ok(key)=>println!("authenticated with {}",key.display()),
+    ///     Err(Error::Identities(failures))=>{
+    ///         for (key,failure) in failures {
+    ///             eprintln!("{}: {}",key.display(),failure)
+    ///         }
+    ///     },
+    ///     Err(e)=>panic!("{}",e)
+    /// }
+    ///```
+    pub fn userauth_identities<P:AsRef<Path>>(&mut self,identities:&[P],passphrase:Option<&str>)->Result<PathBuf,Error> {
+        let passphrase=match passphrase { Some(p)=>Some(CString::new(p)?), None=>None };
+        traced!("ssh.auth",{session=self.label(),method="publickey"},{
+            let mut failures=Vec::new();
+            for path in identities {
+                let path=path.as_ref();
+                match self.userauth_identity(path,passphrase.as_ref())? {
+                    None=>return Ok(path.to_path_buf()),
+                    Some(failure)=>{
+                        debug!("identity {:?} not used: {}",path,failure);
+                        let partial=failure==IdentityFailure::Partial;
+                        failures.push((path.to_path_buf(),failure));
+                        if partial {
+                            break
+                        }
+                    }
+                }
+            }
+            Err(Error::Identities(failures))
+        })
+    }
+    /// Try the key in `path`, returning why it was not used, or `None` if the session is now authenticated.
+    fn userauth_identity(&mut self,path:&Path,passphrase:Option<&CString>)->Result<Option<IdentityFailure>,Error> {
+        let private=match import_private_key(path,passphrase) {
+            Ok(key)=>key,
+            Err(IdentityFailure::Unsupported)=>{
+                let mut public=path.as_os_str().to_owned();
+                public.push(".pub");
+                return match PublicKey::from_file(public) {
+                    Ok(ref key) if key.is_security_key()=>self.userauth_agent_key(key),
+                    _=>Ok(Some(IdentityFailure::Unsupported))
+                }
+            },
+            Err(failure)=>return Ok(Some(failure))
+        };
+        let mut public=PublicKey { key:std::ptr::null_mut() };
+        if unsafe { ssh_pki_export_privkey_to_pubkey(private.0,&mut public.key) }!=SSH_OK {
+            return Ok(Some(IdentityFailure::Unsupported))
+        }
+        // Ask first, so that keys that would be refused are not used to sign anything.
+        let e=unsafe { ssh_userauth_try_publickey(self.session,std::ptr::null(),public.key) };
+        let e=if e==SSH_AUTH_SUCCESS { unsafe { ssh_userauth_publickey(self.session,std::ptr::null(),private.0) } } else { e };
+        self.identity_result(e)
+    }
+    /// Authenticate with the agent, if it holds `key`.
+    #[cfg(unix)]
+    fn userauth_agent_key(&mut self,key:&PublicKey)->Result<Option<IdentityFailure>,Error> {
+        let held=match self.agent() {
+            Ok(mut agent)=>agent.holds(key)?,
+            Err(_)=>false
+        };
+        if !held {
+            return Ok(Some(IdentityFailure::NotInAgent))
+        }
+        let e=self.agent_identity(key)?;
+        self.identity_result(e)
+    }
+    #[cfg(not(unix))]
+    fn userauth_agent_key(&mut self,_:&PublicKey)->Result<Option<IdentityFailure>,Error> {
+        Ok(Some(IdentityFailure::NotInAgent))
+    }
+    /// Record the answer `e` to a public key authentication, and tell why it failed.
+    fn identity_result(&self,e:c_int)->Result<Option<IdentityFailure>,Error> {
+        self.record_auth(AuthMethod::PublicKey,e);
+        match e {
+            SSH_AUTH_SUCCESS=>Ok(None),
+            SSH_AUTH_PARTIAL=>Ok(Some(IdentityFailure::Partial)),
+            SSH_AUTH_DENIED=>match err(self) {
+                Error::RequestDenied(msg)|Error::Ssh(msg)=>Ok(Some(IdentityFailure::Denied(msg))),
+                e=>Err(e)
+            },
+            _=>Err(err(self))
+        }
+    }
+    /// Which method authenticated this session, and how many attempts it took, for logs and metrics.
+    ///
+    ///```no_run
+    /// use ssh::*;
+    ///
+    /// let mut session=Session::new().unwrap();
+    /// session.set_host("pijul.org").unwrap();
+    /// session.parse_config(None).unwrap();
+    /// session.connect().unwrap();
+    /// if session.userauth_publickey_auto(None).is_err() {
+    ///     session.userauth_password("hunter2").unwrap();
+    /// }
+    /// let info=session.auth_info();
+    /// println!("authenticated with {} after {} attempts",info.method.unwrap(),info.attempts);
+    ///```
+    pub fn auth_info(&self)->AuthInfo {
+        self.auth.borrow().clone()
+    }
+}
+
+impl Session {
+    /// Authenticate with the keyboard-interactive method, calling `answer` with each round of questions sent by the server. `answer` must return one answer per prompt, in order.
+    ///
+    ///```no_run
+    /// use ssh::*;
+    /// use std::io::BufRead;
+    ///
+    /// let mut session=Session::new().unwrap();
+    /// session.set_host("pijul.org").unwrap();
+    /// session.parse_config(None).unwrap();
+    /// session.connect().unwrap();
+    /// session.userauth_kbdint_with(None,|challenge| {
+    ///     if !challenge.instruction.is_empty() {
+    ///         println!("{}",challenge.instruction)
+    ///     }
+    ///     let stdin=std::io::stdin();
+    ///     let mut answers=Vec::new();
+    ///     for p in challenge.prompts.iter() {
+    ///         println!("{}",p.text);
+    ///         let mut line=String::new();
+    ///         stdin.lock().read_line(&mut line)?;
+    ///         answers.push(line.trim_end().to_string())
+    ///     }
+    ///     Ok(answers)
+    /// }).unwrap();
+    ///```
+    pub fn userauth_kbdint_with<F>(&mut self,user:Option<&str>,mut answer:F)->Result<(),Error>
+        where F:FnMut(&KbdintChallenge)->Result<Vec<String>,Error> {
+        traced!("ssh.auth",{session=self.label(),method="keyboard-interactive"},{
+            let e=self.kbdint_rounds(user,&mut answer)?;
+            if e==SSH_AUTH_SUCCESS { Ok(()) } else { Err(err(self)) }
+        })
+    }
+    /// Run keyboard-interactive authentication, answering the questions with `answer`, and return the final answer of the server (after recording it).
+    pub(crate) fn kbdint_rounds(&mut self,user:Option<&str>,answer:&mut dyn FnMut(&KbdintChallenge)->Result<Vec<String>,Error>)->Result<c_int,Error> {
+        let user=match user { Some(u)=>Some(CString::new(u)?), None=>None };
+        let user_ptr=user.as_ref().map(|u| u.as_ptr()).unwrap_or(std::ptr::null());
+        let mut e=unsafe { ssh_userauth_kbdint(self.session,user_ptr,std::ptr::null()) };
+        while e==SSH_AUTH_INFO {
+            let challenge=unsafe {
+                let n=std::cmp::max(ssh_userauth_kbdint_getnprompts(self.session),0) as c_uint;
+                KbdintChallenge {
+                    name:string(ssh_userauth_kbdint_getname(self.session)),
+                    instruction:string(ssh_userauth_kbdint_getinstruction(self.session)),
+                    prompts:(0..n).map(|i| {
+                        let mut echo=0;
+                        let text=string(ssh_userauth_kbdint_getprompt(self.session,i,&mut echo));
+                        KbdintPrompt { text, echo:echo!=0 }
+                    }).collect()
+                }
+            };
+            let answers=if challenge.prompts.is_empty() { Vec::new() } else { answer(&challenge)? };
+            if answers.len()!=challenge.prompts.len() {
+                return Err(Error::Ssh(format!("{} answers given to {} prompts",answers.len(),challenge.prompts.len())))
+            }
+            for (i,a) in answers.iter().enumerate() {
+                let a=CString::new(a.as_str())?;
+                if unsafe { ssh_userauth_kbdint_setanswer(self.session,i as c_uint,a.as_ptr()) }<0 {
+                    return Err(err(self))
+                }
+            }
+            e=unsafe { ssh_userauth_kbdint(self.session,user_ptr,std::ptr::null()) };
+        }
+        self.record_auth(AuthMethod::KeyboardInteractive,e);
+        Ok(e)
+    }
+}
+
+impl Session {
+    /// Authenticate with the keys of the ssh-agent given by `SSH_AUTH_SOCK`, trying each of them in turn.
+    pub fn userauth_agent(&mut self)->Result<(),Error> {
+        traced!("ssh.auth",{session=self.label(),method="agent"},{
+            let e=unsafe { ssh_userauth_agent(self.session,std::ptr::null()) };
+            self.record_auth(AuthMethod::PublicKey,e);
+            if e==SSH_AUTH_SUCCESS { Ok(()) } else { Err(err(self)) }
+        })
+    }
+    /// Ask the server whether it would accept authentication with `key`, without proving that we own the private key. Returns `Ok(false)` if the key is refused.
+    pub fn userauth_try_publickey(&mut self,key:&PublicKey)->Result<bool,Error> {
+        let e=unsafe { ssh_userauth_try_publickey(self.session,std::ptr::null(),key.key) };
+        match e {
+            SSH_AUTH_SUCCESS=>Ok(true),
+            SSH_AUTH_DENIED|SSH_AUTH_PARTIAL=>Ok(false),
+            _=>Err(err(self))
+        }
+    }
+    /// Try the "none" authentication method, which very few servers accept. Returns `Ok(false)` if it is refused. Servers usually send their issue banner in reply to this first request.
+    pub fn userauth_none(&mut self)->Result<bool,Error> {
+        let e=unsafe { ssh_userauth_none(self.session,std::ptr::null()) };
+        self.record_auth(AuthMethod::None,e);
+        match e {
+            SSH_AUTH_SUCCESS=>Ok(true),
+            SSH_AUTH_DENIED|SSH_AUTH_PARTIAL=>Ok(false),
+            _=>Err(err(self))
+        }
+    }
+    /// The banner sent by the server before authentication (legal notices, typically), if any. It is only received after a first authentication request, so call `userauth_none` first, or use `userauth_none_with_banner`.
+    pub fn get_issue_banner(&self)->Option<String> {
+        let b=unsafe { ssh_get_issue_banner(self.session) };
+        if b.is_null() {
+            None
+        } else {
+            let s=string(b);
+            unsafe { ssh_string_free_char(b) };
+            Some(s)
+        }
+    }
+    /// Call `userauth_none`, then `show` with the issue banner if the server sent one, before any other authentication method prompts the user (this is what OpenSSH does).
+    ///
+    ///```no_run
+    /// use ssh::*;
+    ///
+    /// let mut session=Session::new().unwrap();
+    /// session.set_host("pijul.org").unwrap();
+    /// session.parse_config(None).unwrap();
+    /// session.connect().unwrap();
+    /// if !session.userauth_none_with_banner(|banner| eprint!("{}",banner)).unwrap() {
+    ///     session.userauth_publickey_auto(None).unwrap();
+    /// }
+    ///```
+    pub fn userauth_none_with_banner<F:FnOnce(&str)>(&mut self,show:F)->Result<bool,Error> {
+        let authenticated=self.userauth_none()?;
+        if let Some(b)=self.get_issue_banner() {
+            show(&b)
+        }
+        Ok(authenticated)
+    }
+}
@@ -0,0 +1,83 @@
+//! What the command-line tools have in common: options, destinations and connections.
+
+use ssh::*;
+use std::path::PathBuf;
+
+/// Options shared by all the tools.
+#[derive(Default)]
+pub struct Options {
+    pub identities:Vec<PathBuf>,
+    pub port:Option<u16>,
+    /// Accept (and remember) the host keys of unknown hosts, as OpenSSH's `StrictHostKeyChecking=accept-new`.
+    pub accept_new:bool,
+    /// The other arguments, in order.
+    pub args:Vec<String>
+}
+
+pub fn fail<E:std::fmt::Display>(tool:&str,e:E)->! {
+    eprintln!("{}: {}",tool,e);
+    std::process::exit(255)
+}
+
+/// Parse `-i IDENTITY`, `-p PORT` and `--accept-new`, and keep the other arguments.
+pub fn options(tool:&str,usage:&str)->Options {
+    let mut options=Options::default();
+    let mut args=std::env::args().skip(1);
+    while let Some(a)=args.next() {
+        match &a[..] {
+            "-i"=>options.identities.push(args.next().unwrap_or_else(|| fail(tool,usage)).into()),
+            "-p"=>options.port=Some(args.next().and_then(|p| p.parse().ok()).unwrap_or_else(|| fail(tool,usage))),
+            "--accept-new"=>options.accept_new=true,
+            "-h"|"--help"=>{
+                println!("usage: {}",usage);
+                std::process::exit(0)
+            },
+            _=>{
+                // Everything after the destination belongs to the tool, even if it looks like an option.
+                options.args.push(a);
+                options.args.extend(args);
+                break
+            }
+        }
+    }
+    options
+}
+
+/// Connect to `destination` (`[user@]host`), check its host key as OpenSSH does, and authenticate with the agent and the keys.
+// ssh-copy connects through `ssh::copy` instead.
+#[allow(dead_code)]
+pub fn connect(tool:&str,destination:&str,options:&Options)->Session {
+    let (user,host)=match destination.rfind('@') {
+        Some(i)=>(Some(&destination[..i]),&destination[i+1..]),
+        None=>(None,destination)
+    };
+    let mut session=Session::new().unwrap_or_else(|()| fail(tool,"could not allocate a session"));
+    let r=(|| {
+        session.set_host(host)?;
+        if let Some(p)=options.port {
+            session.set_port(p as usize)?
+        }
+        if let Some(u)=user {
+            session.set_username(u)?
+        }
+        session.parse_config(None)?;
+        for id in options.identities.iter() {
+            session.add_identity(id)?
+        }
+        session.connect()?;
+        let check=session.check_known_host()?;
+        match check.status {
+            ServerKnown::Known=>(),
+            ServerKnown::NotKnown|ServerKnown::FileNotFound if options.accept_new=>session.write_knownhost()?,
+            _=>{
+                eprint!("{}",check.warning().unwrap_or_default());
+                return Err(Error::Ssh(format!("host key verification failed for {}",host)))
+            }
+        }
+        session.userauth_publickey_auto(None)
+    })();
+    if let Err(e)=r {
+        fail(tool,e)
+    }
+    session
+}
@@ -0,0 +1,29 @@
+//! Copy a file to or from a server: `ssh-copy [-i IDENTITY] [--accept-new] FROM TO`, where one of `FROM` and `TO` is an `sftp://` or `scp://` URL, such as `sftp://user@host/path` or `scp://host:2222/~/file`, and the other one a local path.
+//!
+//! The password is read from the `SSH_PASSWORD` environment variable if public key authentication fails.
+
+extern crate ssh;
+
+mod common;
+
+use ssh::*;
+
+const USAGE:&str="ssh-copy [-i IDENTITY] [--accept-new] FROM TO";
+
+fn main() {
+    let options=common::options("ssh-copy",USAGE);
+    if options.args.len()!=2 {
+        common::fail("ssh-copy",USAGE)
+    }
+    let mut copy_options=CopyOptions::default();
+    copy_options.config.identities=options.identities.clone();
+    copy_options.config.port=options.port;
+    if options.accept_new {
+        copy_options.config.host_key_policy=HostKeyPolicy::AcceptNew
+    }
+    copy_options.password=std::env::var("SSH_PASSWORD").ok();
+    match copy(&options.args[0],&options.args[1],&copy_options) {
+        Ok(n)=>eprintln!("{} bytes copied",n),
+        Err(e)=>common::fail("ssh-copy",e)
+    }
+}
@@ -0,0 +1,23 @@
+//! Run a command on a server, as `ssh host command`: `ssh-exec [-i IDENTITY] [-p PORT] [--accept-new] [USER@]HOST COMMAND...`.
+//!
+//! The output of the command is streamed to the standard output and error, and its exit status is the exit status of `ssh-exec` (255 for connection errors).
+
+extern crate ssh;
+
+mod common;
+
+const USAGE:&str="ssh-exec [-i IDENTITY] [-p PORT] [--accept-new] [USER@]HOST COMMAND...";
+
+fn main() {
+    let options=common::options("ssh-exec",USAGE);
+    if options.args.len()<2 {
+        common::fail("ssh-exec",USAGE)
+    }
+    let mut session=common::connect("ssh-exec",&options.args[0],&options);
+    let command=options.args[1..].join(" ");
+    let status=session.exec_to(&command,&mut std::io::stdout(),&mut std::io::stderr()).unwrap_or_else(|e| common::fail("ssh-exec",e));
+    if let Err(e)=session.close() {
+        common::fail("ssh-exec",e)
+    }
+    std::process::exit(status.unwrap_or(255))
+}
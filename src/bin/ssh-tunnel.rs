@@ -0,0 +1,51 @@
+//! Forward a local port through a server, as `ssh -N -L`: `ssh-tunnel [-i IDENTITY] [-p PORT] [--accept-new] [USER@]HOST LOCAL_PORT:TARGET_HOST:TARGET_PORT`.
+//!
+//! Connections to `LOCAL_PORT` (on the loopback interface) are forwarded to `TARGET_HOST:TARGET_PORT`, as seen from the server. A session can only be used from one thread, so connections are served one at a time.
+
+extern crate ssh;
+
+mod common;
+
+use std::net::TcpListener;
+
+const USAGE:&str="ssh-tunnel [-i IDENTITY] [-p PORT] [--accept-new] [USER@]HOST LOCAL_PORT:TARGET_HOST:TARGET_PORT";
+
+/// Parse `LOCAL_PORT:TARGET_HOST:TARGET_PORT`, where `TARGET_HOST` may be an IPv6 address in brackets.
+fn parse_forward(s:&str)->Option<(u16,String,u16)> {
+    let (local,rest)=s.split_at(s.find(':')?);
+    let rest=&rest[1..];
+    let (host,port)=rest.split_at(rest.rfind(':')?);
+    let host=host.trim_start_matches('[').trim_end_matches(']');
+    Some((local.parse().ok()?,host.to_string(),port[1..].parse().ok()?))
+}
+
+fn main() {
+    let options=common::options("ssh-tunnel",USAGE);
+    if options.args.len()!=2 {
+        common::fail("ssh-tunnel",USAGE)
+    }
+    let (local_port,target_host,target_port)=parse_forward(&options.args[1]).unwrap_or_else(|| common::fail("ssh-tunnel",USAGE));
+    let mut session=common::connect("ssh-tunnel",&options.args[0],&options);
+    let listener=TcpListener::bind(("127.0.0.1",local_port)).unwrap_or_else(|e| common::fail("ssh-tunnel",e));
+    eprintln!("forwarding 127.0.0.1:{} to {}:{}",local_port,target_host,target_port);
+    for local in listener.incoming() {
+        let local=match local {
+            Ok(l)=>l,
+            Err(e)=>{
+                eprintln!("ssh-tunnel: {}",e);
+                continue
+            }
+        };
+        let peer=local.peer_addr().unwrap_or_else(|e| common::fail("ssh-tunnel",e));
+        let r=session.channel_new().and_then(|mut channel| {
+            channel.open_forward(&target_host,target_port,&peer.ip().to_string(),peer.port())?;
+            channel.stream().tunnel(&local)
+        });
+        match r {
+            Ok((sent,received))=>eprintln!("{}: {} bytes sent, {} received",peer,sent,received),
+            // The session is still usable if the server only refused this forwarding.
+            Err(ref e) if session.is_connected()=>eprintln!("ssh-tunnel: {}: {}",peer,e),
+            Err(e)=>common::fail("ssh-tunnel",e)
+        }
+    }
+}
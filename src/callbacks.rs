@@ -0,0 +1,189 @@
+//! Callbacks called by libssh during the life of a session, to follow the protocol instead of treating the session as a black box.
+//!
+//! libssh logging is process-wide rather than attached to sessions: see the `capture` module, and the `log` crate.
+
+use std::cell::RefCell;
+use std::ffi::CStr;
+use std::panic::{AssertUnwindSafe,catch_unwind};
+
+use super::libc::{c_char,c_int,c_void,size_t};
+use super::{Channel_,Error,SecurityEvent,Session,Session_,err,ssh_channel_new,SSH_OK};
+
+#[allow(missing_copy_implementations)]
+enum Message_ {}
+
+type AuthCallback=extern "C" fn(prompt:*const c_char,buf:*mut c_char,len:size_t,echo:c_int,verify:c_int,userdata:*mut c_void)->c_int;
+type ConnectStatusCallback=extern "C" fn(userdata:*mut c_void,status:f32);
+type GlobalRequestCallback=extern "C" fn(session:*mut Session_,msg:*mut Message_,userdata:*mut c_void);
+type AuthAgentCallback=extern "C" fn(session:*mut Session_,userdata:*mut c_void)->*mut Channel_;
+
+/// The beginning of libssh's `struct ssh_callbacks_struct`. libssh reads `size` to know which fields are present, so the remaining ones can be omitted.
+#[repr(C)]
+struct SshCallbacks {
+    size:size_t,
+    userdata:*mut c_void,
+    auth_function:Option<AuthCallback>,
+    log_function:*const c_void,
+    connect_status_function:Option<ConnectStatusCallback>,
+    global_request_function:Option<GlobalRequestCallback>,
+    channel_open_request_x11_function:*const c_void,
+    channel_open_request_auth_agent_function:Option<AuthAgentCallback>
+}
+
+extern "C" {
+    fn ssh_set_callbacks(s:*mut Session_,cb:*mut SshCallbacks)->c_int;
+    fn ssh_message_subtype(msg:*mut Message_)->c_int;
+    fn ssh_message_reply_default(msg:*mut Message_)->c_int;
+}
+
+/// Global requests sent by the server, as recognised by libssh.
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum GlobalRequest {
+    /// A request unknown to libssh. libssh refuses those itself, without calling `global_request`: in particular, the host keys a server announces with `hostkeys-00@openssh.com` are never seen by the application.
+    Unknown,
+    TcpipForward,
+    CancelTcpipForward,
+    /// `keepalive@openssh.com`, sent by servers to check that the client is still alive.
+    Keepalive,
+    /// `no-more-sessions@openssh.com`.
+    NoMoreSessions
+}
+
+/// Handlers for the events of a session. All methods do nothing by default.
+pub trait SessionCallbacks:Send {
+    /// Progress of `connect`, between 0 and 1.
+    fn connect_status(&mut self,_progress:f32) {}
+    /// libssh needs a passphrase to decrypt a private key. Return `None` to cancel.
+    fn auth_prompt(&mut self,_prompt:&str,_echo:bool,_verify:bool)->Option<String> {
+        None
+    }
+    /// The server sent a global request. libssh answers it with a failure if the server wants a reply.
+    fn global_request(&mut self,_request:GlobalRequest) {}
+    /// Something happened that matters to security monitoring, such as a changed host key.
+    fn security_event(&mut self,_event:&SecurityEvent) {}
+}
+
+/// Callbacks registered to libssh, which must live as long as the session.
+pub(crate) struct Registered {
+    callbacks:SshCallbacks,
+    handler:Box<dyn SessionCallbacks>,
+    /// Agent channels opened by the server, not yet returned by `accept_incoming`.
+    pub(crate) agent_channels:RefCell<Vec<*mut Channel_>>
+}
+
+impl Registered {
+    pub(crate) fn security_event(&self,event:&SecurityEvent) {
+        handler(self.callbacks.userdata).security_event(event)
+    }
+}
+
+struct NoCallbacks;
+impl SessionCallbacks for NoCallbacks {}
+
+fn handler<'a>(userdata:*mut c_void)->&'a mut Box<dyn SessionCallbacks> {
+    unsafe { &mut (*(userdata as *mut Registered)).handler }
+}
+
+extern "C" fn auth_function(prompt:*const c_char,buf:*mut c_char,len:size_t,echo:c_int,verify:c_int,userdata:*mut c_void)->c_int {
+    if prompt.is_null() || buf.is_null() || len==0 {
+        return -1
+    }
+    let prompt=unsafe { CStr::from_ptr(prompt) }.to_string_lossy();
+    // Never unwind into libssh.
+    let answer=catch_unwind(AssertUnwindSafe(|| handler(userdata).auth_prompt(&prompt,echo!=0,verify!=0)));
+    match answer {
+        Ok(Some(a))=>{
+            if a.len()>=len || a.as_bytes().contains(&0) {
+                return -1
+            }
+            unsafe {
+                std::ptr::copy_nonoverlapping(a.as_ptr(),buf as *mut u8,a.len());
+                *buf.add(a.len())=0
+            }
+            0
+        },
+        _=>-1
+    }
+}
+
+extern "C" fn connect_status_function(userdata:*mut c_void,status:f32) {
+    let _=catch_unwind(AssertUnwindSafe(|| handler(userdata).connect_status(status)));
+}
+
+extern "C" fn global_request_function(_:*mut Session_,msg:*mut Message_,userdata:*mut c_void) {
+    let request=match unsafe { ssh_message_subtype(msg) } {
+        1=>GlobalRequest::TcpipForward,
+        2=>GlobalRequest::CancelTcpipForward,
+        3=>GlobalRequest::Keepalive,
+        4=>GlobalRequest::NoMoreSessions,
+        _=>GlobalRequest::Unknown
+    };
+    let _=catch_unwind(AssertUnwindSafe(|| handler(userdata).global_request(request)));
+    unsafe { ssh_message_reply_default(msg) };
+}
+
+extern "C" fn auth_agent_function(session:*mut Session_,userdata:*mut c_void)->*mut Channel_ {
+    let c=unsafe { ssh_channel_new(session) };
+    if !c.is_null() {
+        let r=unsafe { &*(userdata as *const Registered) };
+        r.agent_channels.borrow_mut().push(c)
+    }
+    c
+}
+
+impl Session {
+    /// Install `handler` to be called on the events of this session, replacing any previous one. This must be done before `connect`.
+    ///
+    ///```no_run
+    /// use ssh::*;
+    ///
+    /// struct Progress;
+    /// impl SessionCallbacks for Progress {
+    ///     fn connect_status(&mut self,progress:f32) {
+    ///         println!("connecting: {}%",(progress*100.) as u32)
+    ///     }
+    /// }
+    ///
+    /// let mut session=Session::new().unwrap();
+    /// session.set_host("pijul.org").unwrap();
+    /// session.set_callbacks(Progress).unwrap();
+    /// session.parse_config(None).unwrap();
+    /// session.connect().unwrap();
+    ///```
+    pub fn set_callbacks<C:SessionCallbacks+'static>(&mut self,handler:C)->Result<(),Error> {
+        let mut r=Box::new(Registered {
+            callbacks:SshCallbacks {
+                size:std::mem::size_of::<SshCallbacks>(),
+                userdata:std::ptr::null_mut(),
+                auth_function:Some(auth_function),
+                log_function:std::ptr::null(),
+                connect_status_function:Some(connect_status_function),
+                global_request_function:Some(global_request_function),
+                channel_open_request_x11_function:std::ptr::null(),
+                channel_open_request_auth_agent_function:Some(auth_agent_function)
+            },
+            handler:Box::new(handler),
+            agent_channels:RefCell::new(Vec::new())
+        });
+        if let Some(ref mut old)=self.callbacks {
+            r.agent_channels=RefCell::new(old.agent_channels.take())
+        }
+        r.callbacks.userdata=&mut *r as *mut Registered as *mut c_void;
+        let e=unsafe { ssh_set_callbacks(self.session,&mut r.callbacks) };
+        if e==SSH_OK {
+            // The previous callbacks, if any, are no longer referenced by libssh.
+            self.callbacks=Some(r);
+            Ok(())
+        } else {
+            Err(err(self))
+        }
+    }
+    /// Make sure libssh has callbacks for this session, which are needed to accept agent channels.
+    pub(crate) fn ensure_callbacks(&mut self)->Result<(),Error> {
+        if self.callbacks.is_none() {
+            self.set_callbacks(NoCallbacks)
+        } else {
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,189 @@
+//! Machine-readable capture of the SSH packets exchanged by libssh, for debugging interoperability problems (similar to `ssh -vvv`).
+//!
+//! Only the type and length of each packet are recorded, never its payload, so captures contain no secrets. libssh logging is process-wide: while a capture is running, the packets of all sessions are reported. Other libssh log messages are forwarded to the `log` crate.
+//!
+//!```
+//! use ssh::capture;
+//!
+//! capture::start(|p:&capture::Packet| println!("{:?} {} ({:?} bytes)",p.direction,p.name(),p.len));
+//! // … connect and use sessions …
+//! capture::stop();
+//!```
+
+use std::sync::{Arc,Mutex};
+use std::time::SystemTime;
+use std::ffi::CStr;
+
+use super::libc::{c_char,c_int,c_void};
+
+type LoggingCallback=extern "C" fn(priority:c_int,function:*const c_char,buffer:*const c_char,userdata:*mut c_void);
+
+extern "C" {
+    fn ssh_set_log_callback(cb:Option<LoggingCallback>)->c_int;
+    fn ssh_get_log_callback()->Option<LoggingCallback>;
+    fn ssh_get_log_level()->c_int;
+    fn ssh_set_log_level(level:c_int)->c_int;
+}
+
+const SSH_LOG_WARN:c_int=1;
+const SSH_LOG_INFO:c_int=2;
+const SSH_LOG_DEBUG:c_int=3;
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum Direction {
+    Sent,
+    Received
+}
+
+/// A packet sent or received by libssh.
+#[derive(Debug,Clone)]
+pub struct Packet {
+    pub direction:Direction,
+    /// The SSH message number (for instance 20 for `SSH_MSG_KEXINIT`).
+    pub msg_type:u8,
+    /// Length of the packet, if libssh reported it.
+    pub len:Option<u32>,
+    pub time:SystemTime
+}
+
+impl Packet {
+    /// Name of the message type, as in RFC 4250.
+    pub fn name(&self)->&'static str {
+        match self.msg_type {
+            1=>"SSH_MSG_DISCONNECT",
+            2=>"SSH_MSG_IGNORE",
+            3=>"SSH_MSG_UNIMPLEMENTED",
+            4=>"SSH_MSG_DEBUG",
+            5=>"SSH_MSG_SERVICE_REQUEST",
+            6=>"SSH_MSG_SERVICE_ACCEPT",
+            7=>"SSH_MSG_EXT_INFO",
+            20=>"SSH_MSG_KEXINIT",
+            21=>"SSH_MSG_NEWKEYS",
+            30=>"SSH_MSG_KEX_ECDH_INIT",
+            31=>"SSH_MSG_KEX_ECDH_REPLY",
+            50=>"SSH_MSG_USERAUTH_REQUEST",
+            51=>"SSH_MSG_USERAUTH_FAILURE",
+            52=>"SSH_MSG_USERAUTH_SUCCESS",
+            53=>"SSH_MSG_USERAUTH_BANNER",
+            60=>"SSH_MSG_USERAUTH_INFO_REQUEST",
+            61=>"SSH_MSG_USERAUTH_INFO_RESPONSE",
+            80=>"SSH_MSG_GLOBAL_REQUEST",
+            81=>"SSH_MSG_REQUEST_SUCCESS",
+            82=>"SSH_MSG_REQUEST_FAILURE",
+            90=>"SSH_MSG_CHANNEL_OPEN",
+            91=>"SSH_MSG_CHANNEL_OPEN_CONFIRMATION",
+            92=>"SSH_MSG_CHANNEL_OPEN_FAILURE",
+            93=>"SSH_MSG_CHANNEL_WINDOW_ADJUST",
+            94=>"SSH_MSG_CHANNEL_DATA",
+            95=>"SSH_MSG_CHANNEL_EXTENDED_DATA",
+            96=>"SSH_MSG_CHANNEL_EOF",
+            97=>"SSH_MSG_CHANNEL_CLOSE",
+            98=>"SSH_MSG_CHANNEL_REQUEST",
+            99=>"SSH_MSG_CHANNEL_SUCCESS",
+            100=>"SSH_MSG_CHANNEL_FAILURE",
+            _=>"unknown"
+        }
+    }
+}
+
+/// Shared, so that it can be called without holding the lock on `CAPTURE` (the sink may start or stop captures).
+type Sink=Arc<dyn Fn(&Packet)+Send+Sync>;
+
+struct Capture {
+    sink:Sink,
+    previous_level:c_int,
+    previous_callback:Option<LoggingCallback>
+}
+
+static CAPTURE:Mutex<Option<Capture>>=Mutex::new(None);
+
+/// Start capturing packets, calling `sink` for each of them. This replaces any capture already running.
+pub fn start<F:Fn(&Packet)+Send+Sync+'static>(sink:F) {
+    let mut capture=CAPTURE.lock().unwrap_or_else(|e| e.into_inner());
+    let (previous_level,previous_callback)=match capture.take() {
+        Some(c)=>(c.previous_level,c.previous_callback),
+        None=>unsafe { (ssh_get_log_level(),ssh_get_log_callback()) }
+    };
+    *capture=Some(Capture { sink:Arc::new(sink), previous_level, previous_callback });
+    unsafe {
+        ssh_set_log_callback(Some(log_callback));
+        ssh_set_log_level(SSH_LOG_DEBUG);
+    }
+}
+
+/// Stop the current capture, and restore the previous log level and log callback of libssh.
+pub fn stop() {
+    let mut capture=CAPTURE.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(c)=capture.take() {
+        unsafe {
+            ssh_set_log_callback(c.previous_callback);
+            ssh_set_log_level(c.previous_level);
+        }
+    }
+}
+
+/// Parse the integer following `key` in `msg`.
+fn field(msg:&str,key:&str)->Option<u32> {
+    let start=msg.find(key)?+key.len();
+    let digits:String=msg[start..].chars().take_while(|c| c.is_ascii_digit()).collect();
+    digits.parse().ok()
+}
+
+/// Recognise libssh's packet log lines ("packet: read type 94 [len=…" and "packet: wrote [type=94, len=…").
+fn parse_packet(msg:&str)->Option<Packet> {
+    let (direction,msg_type)=if msg.contains("packet: read type ") {
+        (Direction::Received,field(msg,"packet: read type ")?)
+    } else if msg.contains("packet: wrote [") {
+        (Direction::Sent,field(msg,"type=")?)
+    } else {
+        return None
+    };
+    Some(Packet {
+        direction,
+        msg_type:msg_type as u8,
+        len:field(msg,"len="),
+        time:SystemTime::now()
+    })
+}
+
+extern "C" fn log_callback(priority:c_int,_function:*const c_char,buffer:*const c_char,_userdata:*mut c_void) {
+    if buffer.is_null() {
+        return
+    }
+    let msg=unsafe { CStr::from_ptr(buffer) }.to_string_lossy();
+    // Never unwind into libssh.
+    let _=std::panic::catch_unwind(|| {
+        if let Some(p)=parse_packet(&msg) {
+            let sink=match CAPTURE.lock() {
+                Ok(capture)=>capture.as_ref().map(|c| c.sink.clone()),
+                Err(_)=>None
+            };
+            if let Some(sink)=sink {
+                sink(&p)
+            }
+        } else if priority<=SSH_LOG_WARN {
+            warn!("{}",msg)
+        } else if priority==SSH_LOG_INFO {
+            info!("{}",msg)
+        } else {
+            trace!("{}",msg)
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Direction,parse_packet};
+
+    #[test]
+    fn parse_libssh_lines() {
+        let p=parse_packet("ssh_packet_socket_callback: packet: read type 94 [len=44,padding=10,comp=33,payload=33]").unwrap();
+        assert_eq!((p.direction,p.msg_type,p.len),(Direction::Received,94,Some(44)));
+        assert_eq!(p.name(),"SSH_MSG_CHANNEL_DATA");
+        let p=parse_packet("ssh_packet_send2: packet: wrote [type=20, len=1244, padding_size=7, comp=1236, payload=1236]").unwrap();
+        assert_eq!((p.direction,p.msg_type,p.len),(Direction::Sent,20,Some(1244)));
+        assert_eq!(p.name(),"SSH_MSG_KEXINIT");
+        assert!(parse_packet("ssh_connect: libssh 0.10.6 (c) 2003-2023 Aris Adamantiadis").is_none());
+        assert!(parse_packet("ssh_packet_socket_callback: packet: read type [len=44]").is_none());
+    }
+}
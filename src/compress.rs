@@ -0,0 +1,256 @@
+//! Transfers compressed on the fly by `gzip` or `zstd` running on the server, for text-heavy files on connections without SSH compression.
+
+use std::io::{Read,Write};
+use std::path::Path;
+
+use super::{Error,Permissions,Session};
+use super::exec::shell_quote;
+use super::transfer::Transfer;
+
+/// At most this much of the standard error of the remote command is kept, for error messages.
+const MAX_STDERR:usize=4096;
+
+/// A compression format, and the remote command that handles it. The command must be installed on the server.
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum Compression {
+    /// `gzip`, available almost everywhere.
+    #[cfg(feature="gzip")]
+    Gzip,
+    /// `zstd`, faster and usually smaller.
+    #[cfg(feature="zstd")]
+    Zstd
+}
+
+impl Compression {
+    fn program(&self)->&'static str {
+        match *self {
+            #[cfg(feature="gzip")]
+            Compression::Gzip=>"gzip",
+            #[cfg(feature="zstd")]
+            Compression::Zstd=>"zstd -q"
+        }
+    }
+    fn decoder<W:Write>(&self,w:W)->Result<Decoder<W>,Error> {
+        Ok(match *self {
+            #[cfg(feature="gzip")]
+            Compression::Gzip=>Decoder::Gzip(flate2::write::GzDecoder::new(w)),
+            #[cfg(feature="zstd")]
+            Compression::Zstd=>Decoder::Zstd(zstd::stream::write::Decoder::new(w)?)
+        })
+    }
+    fn encoder<'a,R:Read+'a>(&self,r:R)->Result<Box<dyn Read+'a>,Error> {
+        Ok(match *self {
+            #[cfg(feature="gzip")]
+            Compression::Gzip=>Box::new(flate2::read::GzEncoder::new(r,flate2::Compression::default())),
+            #[cfg(feature="zstd")]
+            Compression::Zstd=>Box::new(zstd::stream::read::Encoder::new(r,0)?)
+        })
+    }
+}
+
+/// Whether to compress a transfer made with `Session::upload_advised` or `download_advised`.
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum CompressionAdvice {
+    /// Compress with this format.
+    Always(Compression),
+    /// Copy the data as it is, over SFTP.
+    Never,
+    /// Compress with this format, unless the data is already compressed (judging by the file name, and for uploads by the first bytes of the data), or the connection is (see `Session::compression`), which would only waste CPU.
+    Auto(Compression)
+}
+
+/// Extensions of file formats that are already compressed.
+const COMPRESSED_EXTENSIONS:[&str;30]=[
+    "gz","tgz","zst","xz","txz","bz2","tbz2","lz4","lzma","7z","zip","rar","jar","apk","deb","rpm",
+    "jpg","jpeg","png","gif","webp","heic","mp3","mp4","mkv","webm","mov","ogg","flac","woff2"
+];
+
+/// Magic numbers of compressed formats.
+const COMPRESSED_MAGIC:[&[u8];9]=[
+    b"\x1f\x8b",b"\x28\xb5\x2f\xfd",b"\xfd7zXZ\x00",b"BZh",b"PK\x03\x04",b"7z\xbc\xaf\x27\x1c",
+    b"\x89PNG",b"\xff\xd8\xff",b"GIF8"
+];
+
+/// Whether the file `name`, starting with `head`, is already compressed.
+fn is_compressed(name:&Path,head:&[u8])->bool {
+    let by_name=name.extension().and_then(|e| e.to_str())
+        .is_some_and(|e| COMPRESSED_EXTENSIONS.iter().any(|c| c.eq_ignore_ascii_case(e)));
+    by_name || COMPRESSED_MAGIC.iter().any(|m| head.starts_with(m))
+}
+
+enum Decoder<W:Write> {
+    #[cfg(feature="gzip")]
+    Gzip(flate2::write::GzDecoder<W>),
+    #[cfg(feature="zstd")]
+    Zstd(zstd::stream::write::Decoder<'static,W>)
+}
+
+impl<W:Write> Decoder<W> {
+    fn write_all(&mut self,data:&[u8])->Result<(),std::io::Error> {
+        match *self {
+            #[cfg(feature="gzip")]
+            Decoder::Gzip(ref mut d)=>d.write_all(data),
+            #[cfg(feature="zstd")]
+            Decoder::Zstd(ref mut d)=>d.write_all(data)
+        }
+    }
+    /// Check that the stream is complete, and write the end of the data.
+    fn finish(self)->Result<(),std::io::Error> {
+        match self {
+            #[cfg(feature="gzip")]
+            Decoder::Gzip(d)=>d.finish().map(|_| ()),
+            #[cfg(feature="zstd")]
+            Decoder::Zstd(mut d)=>d.flush()
+        }
+    }
+}
+
+/// Counts the bytes going through a reader or a writer.
+struct Counted<T> {
+    inner:T,
+    count:u64
+}
+
+impl<T:Read> Read for Counted<T> {
+    fn read(&mut self,buf:&mut [u8])->Result<usize,std::io::Error> {
+        let n=self.inner.read(buf)?;
+        self.count+=n as u64;
+        Ok(n)
+    }
+}
+
+impl<T:Write> Write for Counted<T> {
+    fn write(&mut self,buf:&[u8])->Result<usize,std::io::Error> {
+        let n=self.inner.write(buf)?;
+        self.count+=n as u64;
+        Ok(n)
+    }
+    fn flush(&mut self)->Result<(),std::io::Error> {
+        self.inner.flush()
+    }
+}
+
+fn command_failed(cmd:&str,status:Option<i32>,stderr:&[u8])->Error {
+    match status {
+        Some(status)=>Error::Ssh(format!("{} exited with status {}: {}",cmd,status,String::from_utf8_lossy(stderr).trim())),
+        None=>Error::Ssh(format!("{} exited without a status: {}",cmd,String::from_utf8_lossy(stderr).trim()))
+    }
+}
+
+impl Session {
+    /// Copy the remote file `remote` to `dest`, compressed by `compression` on the server and decompressed here, and return the number of (uncompressed) bytes copied. The server needs a POSIX shell and the compression program.
+    ///
+    ///```no_run
+    /// use ssh::*;
+    ///
+    /// let mut session=Session::new().unwrap();
+    /// session.set_host("pijul.org").unwrap();
+    /// session.parse_config(None).unwrap();
+    /// session.connect().unwrap();
+    /// session.userauth_publickey_auto(None).unwrap();
+    /// let mut log=std::fs::File::create("/tmp/access.log").unwrap();
+    /// session.download_compressed("/var/log/nginx/access.log",&mut log,Compression::Gzip).unwrap();
+    ///```
+    pub fn download_compressed<P:AsRef<Path>>(&mut self,remote:P,dest:&mut dyn Write,compression:Compression)->Result<u64,Error> {
+        let cmd=format!("{} -c < {}",compression.program(),shell_quote(&remote.as_ref().to_string_lossy()));
+        let mut channel=self.channel_new()?;
+        channel.open_session()?;
+        channel.request_exec(&cmd)?;
+        channel.send_eof()?;
+        let mut out=Counted { inner:dest,count:0 };
+        let mut stderr=Vec::new();
+        {
+            let mut decoder=compression.decoder(&mut out)?;
+            channel.read_both(None,|is_stderr,data| {
+                if !is_stderr {
+                    decoder.write_all(data)?
+                } else if stderr.len()<MAX_STDERR {
+                    stderr.extend_from_slice(data)
+                }
+                Ok(())
+            })?;
+            match channel.command_status()? {
+                Some(0)=>decoder.finish()?,
+                status=>return Err(command_failed(&cmd,status,&stderr))
+            }
+        }
+        channel.close()?;
+        Ok(out.count)
+    }
+    /// Copy `source` to the remote file `remote`, compressed here and decompressed by `compression` on the server, and return the number of (uncompressed) bytes copied. The file is created (or truncated) with permissions `mode`. The server needs a POSIX shell and the compression program.
+    pub fn upload_compressed<P:AsRef<Path>,M:Into<Permissions>>(&mut self,source:&mut dyn Read,remote:P,mode:M,compression:Compression)->Result<u64,Error> {
+        let remote=shell_quote(&remote.as_ref().to_string_lossy());
+        let cmd=format!("{} -dc > {} && chmod {:o} {}",compression.program(),remote,u32::from(mode.into()),remote);
+        let mut channel=self.channel_new()?;
+        channel.open_session()?;
+        channel.request_exec(&cmd)?;
+        let mut input=Counted { inner:source,count:0 };
+        let mut stderr=Vec::new();
+        {
+            let mut encoder=compression.encoder(&mut input)?;
+            channel.read_both(Some(&mut encoder),|is_stderr,data| {
+                if is_stderr && stderr.len()<MAX_STDERR {
+                    stderr.extend_from_slice(data)
+                }
+                Ok(())
+            })?;
+        }
+        match channel.command_status()? {
+            Some(0)=>(),
+            status=>return Err(command_failed(&cmd,status,&stderr))
+        }
+        channel.close()?;
+        Ok(input.count)
+    }
+    /// The format to compress a transfer of `remote` with, following `advice`, or `None`.
+    fn advised(&self,advice:CompressionAdvice,remote:&Path,head:&[u8])->Option<Compression> {
+        match advice {
+            CompressionAdvice::Always(c)=>Some(c),
+            CompressionAdvice::Never=>None,
+            CompressionAdvice::Auto(c)=>{
+                if self.compression().is_some() || is_compressed(remote,head) {
+                    debug!("not compressing the transfer of {:?}",remote);
+                    None
+                } else {
+                    Some(c)
+                }
+            }
+        }
+    }
+    /// Copy the remote file `remote` to `dest`, compressed as `advice` says (see `download_compressed`), or over SFTP, and return the number of bytes copied.
+    ///
+    ///```no_run
+    /// use ssh::*;
+    ///
+    /// let mut session=Session::new().unwrap();
+    /// session.set_host("pijul.org").unwrap();
+    /// session.parse_config(None).unwrap();
+    /// session.connect().unwrap();
+    /// session.userauth_publickey_auto(None).unwrap();
+    /// for name in ["access.log","access.log.2.gz"] {
+    ///     let mut f=std::fs::File::create(name).unwrap();
+    ///     // Only the first file is compressed.
+    ///     session.download_advised(format!("/var/log/nginx/{}",name),&mut f,CompressionAdvice::Auto(Compression::Gzip)).unwrap();
+    /// }
+    ///```
+    pub fn download_advised<P:AsRef<Path>>(&mut self,remote:P,dest:&mut dyn Write,advice:CompressionAdvice)->Result<u64,Error> {
+        let remote=remote.as_ref();
+        match self.advised(advice,remote,&[]) {
+            Some(c)=>self.download_compressed(remote,dest,c),
+            None=>self.sftp_new()?.download(remote,dest)
+        }
+    }
+    /// Copy `source` to the remote file `remote`, compressed as `advice` says (see `upload_compressed`), or over SFTP, and return the number of bytes copied. The file is created (or truncated) with permissions `mode`.
+    pub fn upload_advised<P:AsRef<Path>,M:Into<Permissions>>(&mut self,source:&mut dyn Read,remote:P,mode:M,advice:CompressionAdvice)->Result<u64,Error> {
+        let (remote,mode)=(remote.as_ref(),mode.into());
+        let mut head=Vec::with_capacity(16);
+        if let CompressionAdvice::Auto(_)=advice {
+            source.take(16).read_to_end(&mut head)?;
+        }
+        let mut source=(&head[..]).chain(source);
+        match self.advised(advice,remote,&head) {
+            Some(c)=>self.upload_compressed(&mut source,remote,mode,c),
+            None=>self.sftp_new()?.upload_stream(&mut source,remote,mode)
+        }
+    }
+}
@@ -0,0 +1,242 @@
+//! Connection settings as plain data, to be loaded from configuration files (with the `serde` feature) and applied to sessions.
+
+use std::ffi::CString;
+use std::path::{Path,PathBuf};
+use std::time::Duration;
+
+#[cfg(feature="serde")]
+use serde::{Deserialize,Serialize};
+
+use super::libc::{c_int,c_void};
+use super::{Error,ServerKnown,Session,SshOptions,err,path_as_ptr,ssh_options_set,SSH_OK};
+
+/// What to do with host keys that are not in the known hosts file, as OpenSSH's `StrictHostKeyChecking`.
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Default)]
+#[cfg_attr(feature="serde",derive(Serialize,Deserialize))]
+#[cfg_attr(feature="serde",serde(rename_all="kebab-case"))]
+pub enum HostKeyPolicy {
+    /// Refuse unknown keys (the default).
+    #[default]
+    Strict,
+    /// Add unknown keys to the known hosts file, but refuse keys that changed.
+    AcceptNew,
+    /// Don't check host keys. Only for tests and throwaway hosts.
+    Off
+}
+
+/// The settings of a connection. Empty fields leave the libssh defaults.
+///
+/// With the `serde` feature, durations are written in seconds, and all fields except `host` are optional:
+///
+///```text
+/// host = "build.example.com"
+/// user = "ci"
+/// identities = ["~/.ssh/id_ed25519"]
+/// timeout = 5
+/// ciphers = ["chacha20-poly1305@openssh.com", "aes256-gcm@openssh.com"]
+/// host_key_policy = "accept-new"
+///```
+#[derive(Debug,Clone,PartialEq,Default)]
+#[cfg_attr(feature="serde",derive(Serialize,Deserialize))]
+#[cfg_attr(feature="serde",serde(default,deny_unknown_fields))]
+pub struct SessionConfig {
+    pub host:String,
+    pub port:Option<u16>,
+    pub user:Option<String>,
+    /// Private keys tried for public key authentication, in order.
+    pub identities:Vec<PathBuf>,
+    #[cfg_attr(feature="serde",serde(with="secs"))]
+    pub timeout:Option<Duration>,
+    #[cfg_attr(feature="serde",serde(with="secs"))]
+    pub rekey_time:Option<Duration>,
+    pub key_exchange:Vec<String>,
+    pub host_key_types:Vec<String>,
+    pub ciphers:Vec<String>,
+    pub macs:Vec<String>,
+    pub known_hosts:Option<PathBuf>,
+    pub host_key_policy:HostKeyPolicy
+}
+
+#[cfg(feature="serde")]
+mod secs {
+    use std::time::Duration;
+    use serde::{Deserialize,Deserializer,Serializer};
+
+    pub fn serialize<S:Serializer>(d:&Option<Duration>,s:S)->Result<S::Ok,S::Error> {
+        match *d {
+            Some(d)=>s.serialize_some(&d.as_secs_f64()),
+            None=>s.serialize_none()
+        }
+    }
+    pub fn deserialize<'de,D:Deserializer<'de>>(d:D)->Result<Option<Duration>,D::Error> {
+        match Option::<f64>::deserialize(d)? {
+            Some(s) if s.is_finite() && s>=0.=>Ok(Some(Duration::from_secs_f64(s))),
+            Some(s)=>Err(serde::de::Error::custom(format!("invalid duration: {}",s))),
+            None=>Ok(None)
+        }
+    }
+}
+
+impl SessionConfig {
+    pub fn new<S:Into<String>>(host:S)->SessionConfig {
+        SessionConfig { host:host.into(),..SessionConfig::default() }
+    }
+    /// A new session with these settings, not connected yet.
+    pub fn session(&self)->Result<Session,Error> {
+        let mut session=Session::new().map_err(|_| Error::Ssh("Could not allocate a session".to_string()))?;
+        session.set_host(&self.host)?;
+        if let Some(port)=self.port {
+            session.set_port(port as usize)?
+        }
+        if let Some(ref user)=self.user {
+            session.set_username(user)?
+        }
+        for id in &self.identities {
+            session.add_identity(id)?
+        }
+        if let Some(t)=self.timeout {
+            session.set_timeout(t)?
+        }
+        if let Some(t)=self.rekey_time {
+            session.set_rekey_time(t)?
+        }
+        if !self.key_exchange.is_empty() {
+            session.set_key_exchange(&self.key_exchange)?
+        }
+        if !self.host_key_types.is_empty() {
+            session.set_host_key_types(&self.host_key_types)?
+        }
+        if !self.ciphers.is_empty() {
+            session.set_ciphers(&self.ciphers)?
+        }
+        if !self.macs.is_empty() {
+            session.set_macs(&self.macs)?
+        }
+        if let Some(ref k)=self.known_hosts {
+            session.set_knownhosts(k)?
+        }
+        Ok(session)
+    }
+    /// The settings of the SSH configuration file at `path` (`~/.ssh/config` if `None`) for `host`, as parsed by libssh: `host` is replaced by `HostName` if the file sets one, and only the other fields that the file sets are filled (identities are not reported).
+    pub fn from_ssh_config<S:Into<String>>(host:S,path:Option<&Path>)->Result<SessionConfig,Error> {
+        let host=host.into();
+        let default=SessionConfig::new(host.as_str()).session()?;
+        let mut parsed=SessionConfig::new(host.as_str()).session()?;
+        parsed.parse_config(path)?;
+        let changed=|opt:SshOptions| {
+            let v=parsed.get_option(opt);
+            if v!=default.get_option(opt) { v } else { None }
+        };
+        let list=|opt:SshOptions| changed(opt).map(|v| v.split(',').map(|s| s.to_string()).collect()).unwrap_or_default();
+        Ok(SessionConfig {
+            host:parsed.host().unwrap_or(host),
+            port:if parsed.port()!=default.port() { Some(parsed.port()) } else { None },
+            user:changed(SshOptions::USER),
+            key_exchange:list(SshOptions::KEY_EXCHANGE),
+            host_key_types:list(SshOptions::HOSTKEYS),
+            ciphers:list(SshOptions::CIPHERS_C_S),
+            macs:list(SshOptions::HMAC_C_S),
+            known_hosts:changed(SshOptions::KNOWNHOSTS).map(PathBuf::from),
+            ..SessionConfig::default()
+        })
+    }
+    /// These settings, overridden by those set in `other`: fields of `other` that are not empty (or, for `host_key_policy`, not the default) replace those of `self`. Merging layers from the lowest precedence to the highest gives OpenSSH's order, for instance:
+    ///
+    ///```no_run
+    /// use ssh::*;
+    ///
+    /// let from_file=SessionConfig::from_ssh_config("build",None).unwrap();
+    /// let from_code=SessionConfig { ciphers:vec!["aes256-gcm@openssh.com".to_string()],..SessionConfig::default() };
+    /// let from_command_line=SessionConfig { user:Some("ci".to_string()),..SessionConfig::default() };
+    /// let config=from_file.merge(&from_code).merge(&from_command_line);
+    /// let session=config.connect().unwrap();
+    ///```
+    pub fn merge(&self,other:&SessionConfig)->SessionConfig {
+        fn vec<T:Clone>(a:&[T],b:&[T])->Vec<T> {
+            if b.is_empty() { a.to_vec() } else { b.to_vec() }
+        }
+        SessionConfig {
+            host:if other.host.is_empty() { self.host.clone() } else { other.host.clone() },
+            port:other.port.or(self.port),
+            user:other.user.clone().or_else(|| self.user.clone()),
+            identities:vec(&self.identities,&other.identities),
+            timeout:other.timeout.or(self.timeout),
+            rekey_time:other.rekey_time.or(self.rekey_time),
+            key_exchange:vec(&self.key_exchange,&other.key_exchange),
+            host_key_types:vec(&self.host_key_types,&other.host_key_types),
+            ciphers:vec(&self.ciphers,&other.ciphers),
+            macs:vec(&self.macs,&other.macs),
+            known_hosts:other.known_hosts.clone().or_else(|| self.known_hosts.clone()),
+            host_key_policy:if other.host_key_policy!=HostKeyPolicy::default() { other.host_key_policy } else { self.host_key_policy }
+        }
+    }
+    /// Open a session, connect, and check the host key according to `host_key_policy`. Authentication is left to the caller.
+    pub fn connect(&self)->Result<Session,Error> {
+        self.connect_session(self.session()?)
+    }
+    /// Connect `session`, created by `session` and possibly configured further, and check the host key.
+    pub(crate) fn connect_session(&self,mut session:Session)->Result<Session,Error> {
+        session.connect()?;
+        if self.host_key_policy==HostKeyPolicy::Off {
+            return Ok(session)
+        }
+        verify_host_key(&mut session,&self.host,self.host_key_policy==HostKeyPolicy::AcceptNew)?;
+        Ok(session)
+    }
+}
+
+/// Check the host key of `session`, connected to `host`, against the known hosts files (unless it has expected host keys), adding it to them if it is not known and `accept_new` is set.
+pub(crate) fn verify_host_key(session:&mut Session,host:&str,accept_new:bool)->Result<(),Error> {
+    // Checked by `connect`.
+    if session.has_expected_host_keys() {
+        return Ok(())
+    }
+    match session.is_server_known()? {
+        ServerKnown::Known=>Ok(()),
+        ServerKnown::NotKnown|ServerKnown::FileNotFound if accept_new=>session.write_knownhost(),
+        ServerKnown::Changed|ServerKnown::FoundOther=>Err(Error::Ssh(format!("Host key of {} has changed",host))),
+        known=>Err(Error::Ssh(format!("Host key of {} is not known: {:?}",host,known)))
+    }
+}
+
+impl Session {
+    pub(crate) fn set_list<S:AsRef<str>>(&mut self,option:SshOptions,algorithms:&[S])->Result<(),Error> {
+        let v=CString::new(algorithms.iter().map(|a| a.as_ref()).collect::<Vec<_>>().join(","))?;
+        let e=unsafe { ssh_options_set(self.session,option as c_int,v.as_ptr() as *const c_void) };
+        if e==SSH_OK { Ok(()) } else { Err(err(self)) }
+    }
+    /// Add a private key to the ones tried by `userauth_publickey_auto`, after those already set.
+    pub fn add_identity<P:AsRef<Path>>(&mut self,v:P)->Result<(),Error> {
+        let e=unsafe { ssh_options_set(self.session,SshOptions::ADD_IDENTITY as c_int,path_as_ptr(v.as_ref())?.as_ptr() as *const c_void) };
+        if e==SSH_OK { Ok(()) } else { Err(err(self)) }
+    }
+    /// Key exchange algorithms allowed for this session, in order of preference.
+    pub fn set_key_exchange<S:AsRef<str>>(&mut self,algorithms:&[S])->Result<(),Error> {
+        self.set_list(SshOptions::KEY_EXCHANGE,algorithms)
+    }
+    /// Host key types accepted from the server, in order of preference.
+    pub fn set_host_key_types<S:AsRef<str>>(&mut self,algorithms:&[S])->Result<(),Error> {
+        self.set_list(SshOptions::HOSTKEYS,algorithms)
+    }
+    /// Ciphers allowed in both directions, in order of preference.
+    pub fn set_ciphers<S:AsRef<str>>(&mut self,algorithms:&[S])->Result<(),Error> {
+        self.set_list(SshOptions::CIPHERS_C_S,algorithms)?;
+        self.set_list(SshOptions::CIPHERS_S_C,algorithms)
+    }
+    /// MAC algorithms allowed in both directions, in order of preference.
+    pub fn set_macs<S:AsRef<str>>(&mut self,algorithms:&[S])->Result<(),Error> {
+        self.set_list(SshOptions::HMAC_C_S,algorithms)?;
+        self.set_list(SshOptions::HMAC_S_C,algorithms)
+    }
+    /// Compress all the traffic of the connection with zlib, as `Compression yes` in the OpenSSH configuration. This must be done before `connect`.
+    pub fn set_compression(&mut self,enabled:bool)->Result<(),Error> {
+        let v=CString::new(if enabled { "yes" } else { "no" })?;
+        let e=unsafe { ssh_options_set(self.session,SshOptions::COMPRESSION as c_int,v.as_ptr() as *const c_void) };
+        if e==SSH_OK { Ok(()) } else { Err(err(self)) }
+    }
+    /// The compression method of the connection, such as `zlib@openssh.com`, or `None` if it isn't compressed. libssh doesn't report the method negotiated, so this is the one preferred by this session (set by `set_compression` or the configuration file), which OpenSSH servers accept unless configured with `Compression no`.
+    pub fn compression(&self)->Option<String> {
+        let methods=self.get_option(SshOptions::COMPRESSION_C_S)?;
+        methods.split(',').next().filter(|m| *m!="none" && !m.is_empty()).map(|m| m.to_string())
+    }
+}
@@ -0,0 +1,291 @@
+//! Connecting over TCP ourselves, instead of letting libssh do it: libssh only tries the first address of a host, which hangs when that address is unreachable (typically an IPv6 address on an IPv4-only network).
+
+use std::io::{Read,Write};
+use std::net::{Shutdown,SocketAddr,TcpStream,ToSocketAddrs};
+use std::os::unix::io::{AsRawFd,FromRawFd,IntoRawFd,OwnedFd};
+use std::os::unix::net::UnixStream;
+use std::process::Command;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use super::libc::{self,c_int,c_void};
+use super::{Error,Session,SshOptions,ssh_options_set,err,SSH_OK};
+
+/// Delay before starting a connection attempt to the next address while the previous ones are still pending (RFC 8305 recommends 250ms).
+const ATTEMPT_DELAY:Duration=Duration::from_millis(250);
+
+/// Which IP versions to use when connecting, as the `-4` and `-6` options of OpenSSH.
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Default)]
+pub enum AddressFamily {
+    #[default]
+    Any,
+    Inet,
+    Inet6
+}
+
+impl AddressFamily {
+    fn accepts(&self,addr:&SocketAddr)->bool {
+        match *self {
+            AddressFamily::Any=>true,
+            AddressFamily::Inet=>addr.is_ipv4(),
+            AddressFamily::Inet6=>addr.is_ipv6()
+        }
+    }
+}
+
+/// Interleave the address families, starting with the first one returned by the resolver (RFC 8305, section 4).
+fn interleave(addrs:Vec<SocketAddr>)->Vec<SocketAddr> {
+    let first_v6=addrs.first().map(|a| a.is_ipv6()).unwrap_or(false);
+    let (mut a,mut b):(Vec<_>,Vec<_>)=addrs.into_iter().partition(|a| a.is_ipv6()==first_v6);
+    a.reverse();
+    b.reverse();
+    let mut v=Vec::with_capacity(a.len()+b.len());
+    loop {
+        match (a.pop(),b.pop()) {
+            (None,None)=>return v,
+            (x,y)=>{
+                v.extend(x);
+                v.extend(y)
+            }
+        }
+    }
+}
+
+/// Try `addrs` in order, starting the next attempt when the previous one fails or takes longer than `ATTEMPT_DELAY`, and return the first connection established.
+fn connect_tcp(addrs:&[SocketAddr],timeout:Duration)->std::io::Result<TcpStream> {
+    let (tx,rx)=mpsc::channel();
+    let mut addrs=addrs.iter();
+    let mut pending=0;
+    let mut last_error=None;
+    loop {
+        if let Some(&addr)=addrs.next() {
+            let tx=tx.clone();
+            thread::spawn(move || {
+                // Late connections are just dropped if another attempt won.
+                tx.send(TcpStream::connect_timeout(&addr,timeout)).unwrap_or(())
+            });
+            pending+=1
+        } else if pending==0 {
+            break
+        }
+        let r=if addrs.len()>0 {
+            match rx.recv_timeout(ATTEMPT_DELAY) {
+                Ok(r)=>r,
+                Err(_)=>continue
+            }
+        } else {
+            rx.recv().expect("sender still alive")
+        };
+        pending-=1;
+        match r {
+            Ok(s)=>return Ok(s),
+            Err(e)=>last_error=Some(e)
+        }
+    }
+    Err(last_error.unwrap_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound,"no address to connect to")))
+}
+
+/// A connection to the server that is not a socket libssh can use directly, such as a WebSocket tunnel, a TLS wrapper or a serial link, given to `Session::set_transport`. It must be split into a reading and a writing half, used from two threads.
+pub trait Transport:Send+'static {
+    type Reader:Read+Send+'static;
+    type Writer:Write+Send+'static;
+    fn split(self)->std::io::Result<(Self::Reader,Self::Writer)>;
+}
+
+/// A reader and a writer, for instance the standard output and input of a child process.
+impl<R:Read+Send+'static,W:Write+Send+'static> Transport for (R,W) {
+    type Reader=R;
+    type Writer=W;
+    fn split(self)->std::io::Result<(R,W)> {
+        Ok(self)
+    }
+}
+
+impl Transport for TcpStream {
+    type Reader=TcpStream;
+    type Writer=TcpStream;
+    fn split(self)->std::io::Result<(TcpStream,TcpStream)> {
+        Ok((self.try_clone()?,self))
+    }
+}
+
+impl Transport for UnixStream {
+    type Reader=UnixStream;
+    type Writer=UnixStream;
+    fn split(self)->std::io::Result<(UnixStream,UnixStream)> {
+        Ok((self.try_clone()?,self))
+    }
+}
+
+/// Receive a file descriptor sent over `sock` with `SCM_RIGHTS`.
+fn receive_fd(sock:&UnixStream)->std::io::Result<c_int> {
+    let mut byte=[0u8;1];
+    let mut iov=libc::iovec { iov_base:byte.as_mut_ptr() as *mut c_void,iov_len:1 };
+    // Aligned for cmsghdr, and large enough for one descriptor.
+    let mut control=[0u64;8];
+    let mut msg:libc::msghdr=unsafe { std::mem::zeroed() };
+    msg.msg_iov=&mut iov;
+    msg.msg_iovlen=1;
+    msg.msg_control=control.as_mut_ptr() as *mut c_void;
+    msg.msg_controllen=std::mem::size_of_val(&control) as _;
+    let n=unsafe { libc::recvmsg(sock.as_raw_fd(),&mut msg,0) };
+    if n<0 {
+        return Err(std::io::Error::last_os_error())
+    }
+    unsafe {
+        let c=libc::CMSG_FIRSTHDR(&msg);
+        if c.is_null() || (*c).cmsg_level!=libc::SOL_SOCKET || (*c).cmsg_type!=libc::SCM_RIGHTS {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData,"the proxy command did not pass a file descriptor"))
+        }
+        Ok(std::ptr::read_unaligned(libc::CMSG_DATA(c) as *const c_int))
+    }
+}
+
+/// `command` with the `%h`, `%p`, `%r` and `%%` tokens of OpenSSH replaced.
+fn expand_tokens(command:&str,host:&str,port:u16,user:&str)->String {
+    let mut expanded=String::new();
+    let mut chars=command.chars();
+    while let Some(c)=chars.next() {
+        match (c,chars.clone().next()) {
+            ('%',Some('h'))=>expanded.push_str(host),
+            ('%',Some('p'))=>expanded.push_str(&port.to_string()),
+            ('%',Some('r'))=>expanded.push_str(user),
+            ('%',Some('%'))=>expanded.push('%'),
+            _=>{
+                expanded.push(c);
+                continue
+            }
+        }
+        chars.next();
+    }
+    expanded
+}
+
+impl Session {
+    /// Use only IPv4 or IPv6 addresses in `connect_happy_eyeballs` (default `AddressFamily::Any`).
+    pub fn set_address_family(&mut self,family:AddressFamily) {
+        self.family=family
+    }
+    /// Connect to the host, trying all its addresses as in RFC 8305 ("Happy Eyeballs"): IPv6 and IPv4 addresses are alternated, and a new attempt is started every 250ms until one succeeds. Each attempt is abandoned after `timeout`.
+    ///
+    /// Unlike `connect`, this ignores the `ProxyCommand` of the configuration file.
+    pub fn connect_happy_eyeballs(&mut self,timeout:Duration)->Result<(),Error> {
+        self.connect_with_resolver(|host,port| Ok((host,port).to_socket_addrs()?.collect()),timeout)
+    }
+    /// Same as `connect_happy_eyeballs`, but resolving the host name and port with `resolve` instead of the system resolver (for service discovery, or split-horizon DNS).
+    pub fn connect_with_resolver<F:FnOnce(&str,u16)->std::io::Result<Vec<SocketAddr>>>(&mut self,resolve:F,timeout:Duration)->Result<(),Error> {
+        let host=match self.host() {
+            Some(h)=>h,
+            None=>return Err(Error::Ssh("No host set".to_string()))
+        };
+        let family=self.family;
+        let addrs:Vec<_>=resolve(&host,self.port())?.into_iter().filter(|a| family.accepts(a)).collect();
+        self.connect_to(&interleave(addrs),timeout)
+    }
+    /// Connect to the server at one of `addrs`, tried in order as in `connect_happy_eyeballs`, for applications that resolve host names themselves. The host key is still checked under the host name given to `set_host`.
+    ///
+    ///```no_run
+    /// use ssh::*;
+    /// use std::time::Duration;
+    ///
+    /// let mut session=Session::new().unwrap();
+    /// session.set_host("pijul.org").unwrap();
+    /// session.connect_to(&["10.0.0.12:22".parse().unwrap()],Duration::from_secs(10)).unwrap();
+    /// assert!(session.is_server_known().unwrap().is_known());
+    ///```
+    pub fn connect_to(&mut self,addrs:&[SocketAddr],timeout:Duration)->Result<(),Error> {
+        let stream=connect_tcp(addrs,timeout)?;
+        self.set_stream(stream)?;
+        self.connect()
+    }
+    /// Use `stream` as the connection to the server: `connect` then runs the SSH protocol over it, without opening a connection itself. This works with any socket, for instance one end of `UnixStream::pair()`, the other end being served by `sshd -i`, which makes tests independent of the network. This crate has no server API, so there is no in-process server to put at the other end: a real `sshd` is still needed.
+    ///
+    /// libssh takes ownership of the socket, and closes it on disconnection.
+    ///
+    ///```no_run
+    /// use ssh::*;
+    /// use std::os::fd::OwnedFd;
+    /// use std::os::unix::net::UnixStream;
+    /// use std::process::Command;
+    ///
+    /// let (client,server)=UnixStream::pair().unwrap();
+    /// let _sshd=Command::new("/usr/sbin/sshd").args(["-i","-f","/path/to/sshd_config"])
+    ///     .stdin(OwnedFd::from(server.try_clone().unwrap()))
+    ///     .stdout(OwnedFd::from(server))
+    ///     .spawn().unwrap();
+    ///
+    /// let mut session=Session::new().unwrap();
+    /// session.set_host("localhost").unwrap();
+    /// session.set_stream(client).unwrap();
+    /// session.connect().unwrap();
+    ///```
+    pub fn set_stream<S:IntoRawFd>(&mut self,stream:S)->Result<(),Error> {
+        let fd:[c_int;1]=[stream.into_raw_fd()];
+        let e=unsafe { ssh_options_set(self.session,SshOptions::FD as c_int,fd.as_ptr() as *const c_void) };
+        if e==SSH_OK {
+            Ok(())
+        } else {
+            unsafe { super::libc::close(fd[0]) };
+            Err(err(self))
+        }
+    }
+    /// Use `transport` as the connection to the server, as `set_stream` does with sockets. libssh is given one end of a socket pair, and two threads copy the data between the other end and the halves of `transport`, until either side closes the connection.
+    ///
+    ///```no_run
+    /// use ssh::*;
+    /// use std::process::{Command,Stdio};
+    ///
+    /// // A serial console, or any other link reached through a program.
+    /// let mut child=Command::new("socat").args(["-","/dev/ttyUSB0,raw,b115200"])
+    ///     .stdin(Stdio::piped()).stdout(Stdio::piped())
+    ///     .spawn().unwrap();
+    /// let mut session=Session::new().unwrap();
+    /// session.set_host("device").unwrap();
+    /// session.set_transport((child.stdout.take().unwrap(),child.stdin.take().unwrap())).unwrap();
+    /// session.connect().unwrap();
+    ///```
+    pub fn set_transport<T:Transport>(&mut self,transport:T)->Result<(),Error> {
+        let (mut reader,mut writer)=transport.split()?;
+        let (ours,theirs)=UnixStream::pair()?;
+        let (mut to_libssh,mut from_libssh)=(theirs.try_clone()?,theirs);
+        self.set_stream(ours)?;
+        thread::spawn(move || {
+            if let Err(e)=std::io::copy(&mut reader,&mut to_libssh) {
+                debug!("transport: {}",e)
+            }
+            let _=to_libssh.shutdown(Shutdown::Write);
+        });
+        thread::spawn(move || {
+            if let Err(e)=std::io::copy(&mut from_libssh,&mut writer).and_then(|_| writer.flush()) {
+                debug!("transport: {}",e)
+            }
+            let _=from_libssh.shutdown(Shutdown::Read);
+        });
+        Ok(())
+    }
+    /// Run `command` with `sh -c`, and use the connected socket it passes back on its standard output (with `SCM_RIGHTS`) as the connection to the server, as OpenSSH does with `ProxyCommand` and `ProxyUseFdpass yes`. The tokens `%h`, `%p` and `%r` in `command` are replaced by the host, port and user of the session. `connect` must then be called.
+    ///
+    /// libssh runs `ProxyCommand` lines of configuration files itself, but ignores `ProxyUseFdpass`: this must be called instead.
+    ///
+    ///```no_run
+    /// use ssh::*;
+    ///
+    /// let mut session=Session::new().unwrap();
+    /// session.set_host("db.internal").unwrap();
+    /// session.set_proxy_fdpass("connection-broker --fdpass %h %p").unwrap();
+    /// session.connect().unwrap();
+    ///```
+    pub fn set_proxy_fdpass(&mut self,command:&str)->Result<(),Error> {
+        let command=expand_tokens(command,&self.host().unwrap_or_default(),self.port(),&self.username().unwrap_or_default());
+        let (ours,theirs)=UnixStream::pair()?;
+        let mut child=Command::new("sh").arg("-c").arg(&command)
+            .stdin(OwnedFd::from(theirs.try_clone()?))
+            .stdout(OwnedFd::from(theirs))
+            .spawn()?;
+        let fd=receive_fd(&ours);
+        let status=child.wait()?;
+        let fd=fd.map_err(|e| Error::IO(std::io::Error::new(e.kind(),format!("{} (proxy command {:?} exited with {})",e,command,status))))?;
+        self.set_stream(unsafe { UnixStream::from_raw_fd(fd) })
+    }
+}
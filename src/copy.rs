@@ -0,0 +1,225 @@
+//! One-shot copies between a local file and an `sftp://` or `scp://` URL, for scripts.
+
+use std::fs::File;
+use std::path::{Path,PathBuf};
+
+use super::{Error,Permissions,Session,SessionConfig};
+use super::exec::shell_quote;
+use super::transfer::{ScpTransfer,Transfer};
+
+/// Settings of `copy`.
+#[derive(Debug,Clone,Default)]
+pub struct CopyOptions {
+    /// Settings of the connection. `host` is replaced by the host of the URL, as are `port` and `user` if the URL has them. The OpenSSH configuration of the host is read too.
+    pub config:SessionConfig,
+    /// Password, tried if public key authentication (with the agent and the default keys) fails.
+    pub password:Option<String>,
+    /// Permissions of uploaded files. Defaults to those of the local file.
+    pub mode:Option<Permissions>,
+    /// Owner of uploaded files on the server, as `user` or `user:group` (see `Session::lookup_owner`). The remote user must be allowed to change it, which usually means being root.
+    pub owner:Option<String>,
+    /// Copy the `user.` extended attributes of the file too (see the `attrs` module). Only on Linux.
+    pub xattrs:bool
+}
+
+/// The parts of an `scp://` or `sftp://` URL (as in draft-ietf-secsh-scp-sftp-ssh-uri).
+#[derive(Debug,Clone,PartialEq,Eq)]
+struct Url {
+    sftp:bool,
+    user:Option<String>,
+    host:String,
+    port:Option<u16>,
+    /// Relative paths are relative to the home directory of the user.
+    path:PathBuf
+}
+
+/// Decode `%XX` escapes.
+fn percent_decode(s:&str)->Result<String,Error> {
+    let invalid=|| Error::Ssh(format!("Invalid escape in URL component {:?}",s));
+    let mut bytes=Vec::with_capacity(s.len());
+    let mut it=s.bytes();
+    while let Some(b)=it.next() {
+        if b==b'%' {
+            let hex=[it.next().ok_or_else(invalid)?,it.next().ok_or_else(invalid)?];
+            let hex=std::str::from_utf8(&hex).map_err(|_| invalid())?;
+            bytes.push(u8::from_str_radix(hex,16).map_err(|_| invalid())?)
+        } else {
+            bytes.push(b)
+        }
+    }
+    String::from_utf8(bytes).map_err(|_| invalid())
+}
+
+impl Url {
+    /// Parse `s`, or return `None` if it is not an `scp://` or `sftp://` URL.
+    fn parse(s:&str)->Result<Option<Url>,Error> {
+        let (sftp,rest)=if let Some(rest)=s.strip_prefix("sftp://") {
+            (true,rest)
+        } else if let Some(rest)=s.strip_prefix("scp://") {
+            (false,rest)
+        } else {
+            return Ok(None)
+        };
+        let invalid=|| Error::Ssh(format!("Invalid URL {:?}",s));
+        let (authority,path)=match rest.find('/') {
+            Some(i)=>(&rest[..i],&rest[i..]),
+            None=>return Err(invalid())
+        };
+        let (user,hostport)=match authority.rfind('@') {
+            // Connection parameters (";fingerprint=…") are not supported.
+            Some(i)=>(Some(percent_decode(authority[..i].split(';').next().unwrap_or(""))?),&authority[i+1..]),
+            None=>(None,authority)
+        };
+        let (host,port)=if let Some(h)=hostport.strip_prefix('[') {
+            // An IPv6 address.
+            let end=h.find(']').ok_or_else(invalid)?;
+            (&h[..end],h[end+1..].strip_prefix(':'))
+        } else {
+            match hostport.rfind(':') {
+                Some(i)=>(&hostport[..i],Some(&hostport[i+1..])),
+                None=>(hostport,None)
+            }
+        };
+        if host.is_empty() {
+            return Err(invalid())
+        }
+        let port=match port {
+            Some(p)=>Some(p.parse().map_err(|_| invalid())?),
+            None=>None
+        };
+        let path=percent_decode(path)?;
+        // "/~/file" is relative to the home directory.
+        let path=match path.strip_prefix("/~/") {
+            Some(p)=>PathBuf::from(p),
+            None=>PathBuf::from(path)
+        };
+        Ok(Some(Url { sftp,user,host:host.to_string(),port,path }))
+    }
+
+    fn connect(&self,options:&CopyOptions)->Result<Session,Error> {
+        let mut config=options.config.clone();
+        config.host=self.host.clone();
+        if self.port.is_some() {
+            config.port=self.port
+        }
+        if self.user.is_some() {
+            config.user=self.user.clone()
+        }
+        let mut session=config.session()?;
+        session.parse_config(None)?;
+        let mut session=config.connect_session(session)?;
+        if let Err(e)=session.userauth_publickey_auto(None) {
+            match options.password {
+                Some(ref p)=>session.userauth_password(p)?,
+                None=>return Err(e)
+            }
+        }
+        Ok(session)
+    }
+}
+
+/// Copy a file between this machine and a server: one of `from` and `to` is a local path, the other one an `sftp://` or `scp://` URL, such as `sftp://user@host/path/to/file` or `scp://host:2222/~/relative/to/home`. This connects, authenticates with the agent, the default keys or `options.password`, copies the file over the protocol of the URL, and disconnects. Returns the number of bytes copied.
+///
+/// Host keys are checked according to `options.config.host_key_policy`. If the server can't run SCP, `scp://` URLs are copied over SFTP.
+///
+///```no_run
+/// use ssh::*;
+///
+/// ssh::copy("sftp://pijul.org/tmp/blublu","/tmp/blublu",&CopyOptions::default()).unwrap();
+/// ssh::copy("/tmp/blublu","scp://me@pijul.org/~/blublu",&CopyOptions::default()).unwrap();
+///```
+pub fn copy(from:&str,to:&str,options:&CopyOptions)->Result<u64,Error> {
+    match (Url::parse(from)?,Url::parse(to)?) {
+        (Some(_),Some(_))=>Err(Error::Ssh("Copies between two servers are not supported".to_string())),
+        (None,None)=>Err(Error::Ssh(format!("Neither {:?} nor {:?} is an sftp:// or scp:// URL",from,to))),
+        (Some(url),None)=>{
+            let mut session=url.connect(options)?;
+            let n={
+                let mut dest=File::create(to)?;
+                let n=with_transfer(&mut session,url.sftp,|t| t.download(&url.path,&mut dest))?;
+                dest.sync_all()?;
+                n
+            };
+            if options.xattrs {
+                copy_xattrs_from(&mut session,&url.path,Path::new(to))?
+            }
+            session.close()?;
+            Ok(n)
+        },
+        (None,Some(url))=>{
+            let mut source=File::open(from)?;
+            let meta=source.metadata()?;
+            let mode=options.mode.unwrap_or_else(|| local_mode(&meta));
+            let mut session=url.connect(options)?;
+            with_transfer(&mut session,url.sftp,|t| t.upload(&mut source,meta.len(),&url.path,mode))?;
+            if let Some(ref owner)=options.owner {
+                let (user,group)=match owner.find(':') {
+                    Some(i)=>(&owner[..i],Some(&owner[i+1..])),
+                    None=>(&owner[..],None)
+                };
+                let (uid,gid)=session.lookup_owner(user,group)?;
+                if url.sftp {
+                    session.sftp_new()?.chown(&url.path,uid,gid)?
+                } else {
+                    let out=session.exec(&format!("chown {}:{} -- {}",uid,gid,shell_quote(&url.path.to_string_lossy())))?;
+                    if out.exit_status!=Some(0) {
+                        return Err(Error::Ssh(format!("chown failed: {}",String::from_utf8_lossy(&out.stderr).trim())))
+                    }
+                }
+            }
+            if options.xattrs {
+                copy_xattrs_to(&mut session,Path::new(from),&url.path)?
+            }
+            session.close()?;
+            Ok(meta.len())
+        }
+    }
+}
+
+#[cfg(target_os="linux")]
+fn copy_xattrs_from(session:&mut Session,remote:&Path,local:&Path)->Result<(),Error> {
+    let xattrs=session.xattrs(remote)?;
+    super::attrs::set_local_xattrs(local,&xattrs)
+}
+
+#[cfg(target_os="linux")]
+fn copy_xattrs_to(session:&mut Session,local:&Path,remote:&Path)->Result<(),Error> {
+    session.set_xattrs(remote,&super::attrs::local_xattrs(local)?)
+}
+
+#[cfg(not(target_os="linux"))]
+fn copy_xattrs_from(_:&mut Session,_:&Path,_:&Path)->Result<(),Error> {
+    Err(Error::Ssh("Extended attributes are only supported on Linux".to_string()))
+}
+
+#[cfg(not(target_os="linux"))]
+fn copy_xattrs_to(_:&mut Session,_:&Path,_:&Path)->Result<(),Error> {
+    Err(Error::Ssh("Extended attributes are only supported on Linux".to_string()))
+}
+
+/// Run `f` with SFTP or SCP, falling back to SFTP if the server can't run SCP (`f` fails before reading or writing anything then).
+fn with_transfer<T,F:FnMut(&mut dyn Transfer)->Result<T,Error>>(session:&mut Session,sftp:bool,mut f:F)->Result<T,Error> {
+    if !sftp {
+        match f(&mut ScpTransfer::new(session)) {
+            Err(Error::ScpUnavailable(msg))=>debug!("SCP unavailable ({}), falling back to SFTP",msg),
+            r=>return r
+        }
+    }
+    f(&mut session.sftp_new()?)
+}
+
+#[cfg(unix)]
+pub(crate) fn local_mode(meta:&std::fs::Metadata)->Permissions {
+    use std::os::unix::fs::PermissionsExt;
+    Permissions::from_mode(meta.permissions().mode()&0o777)
+}
+
+#[cfg(not(unix))]
+pub(crate) fn local_mode(_:&std::fs::Metadata)->Permissions {
+    Permissions::from_mode(0o644)
+}
+
+/// Whether `s` is a URL that `copy` understands.
+pub fn is_copy_url(s:&str)->bool {
+    s.starts_with("sftp://") || s.starts_with("scp://")
+}
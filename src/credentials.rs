@@ -0,0 +1,58 @@
+//! Credentials asked for while authenticating, from wherever the application keeps them: a GUI dialog, the system keychain, a vault… The crate itself never prompts.
+
+use std::path::Path;
+
+/// Gives passwords, passphrases and one-time codes to an `AuthPipeline` (see `AuthPipeline::credentials`). All methods return `None` by default, meaning that the credential is not available.
+///
+///```no_run
+/// use ssh::*;
+/// use std::path::Path;
+///
+/// struct Vault;
+/// impl CredentialsProvider for Vault {
+///     fn get_password(&mut self,host:&str,user:&str)->Option<String> {
+///         std::env::var(format!("PASSWORD_{}_{}",user,host)).ok()
+///     }
+///     fn get_key_passphrase(&mut self,key:&Path)->Option<String> {
+///         std::fs::read_to_string(key.with_extension("passphrase")).ok()
+///     }
+/// }
+///
+/// let mut session=Session::new().unwrap();
+/// session.set_host("pijul.org").unwrap();
+/// session.parse_config(None).unwrap();
+/// session.connect().unwrap();
+/// let home=std::env::var("HOME").unwrap();
+/// AuthPipeline::new()
+///     .credentials(Vault)
+///     .identities([Path::new(&home).join(".ssh/id_ed25519")],None)
+///     .provided_password()
+///     .run(&mut session)
+///     .unwrap();
+///```
+pub trait CredentialsProvider {
+    /// The password of `user` on `host`.
+    fn get_password(&mut self,_host:&str,_user:&str)->Option<String> {
+        None
+    }
+    /// The passphrase of the encrypted private key in the file `key`.
+    fn get_key_passphrase(&mut self,_key:&Path)->Option<String> {
+        None
+    }
+    /// The answer to a keyboard-interactive `prompt` of the server, such as "Verification code: ".
+    fn get_otp(&mut self,_prompt:&str)->Option<String> {
+        None
+    }
+}
+
+impl<C:CredentialsProvider+?Sized> CredentialsProvider for &mut C {
+    fn get_password(&mut self,host:&str,user:&str)->Option<String> {
+        (**self).get_password(host,user)
+    }
+    fn get_key_passphrase(&mut self,key:&Path)->Option<String> {
+        (**self).get_key_passphrase(key)
+    }
+    fn get_otp(&mut self,prompt:&str)->Option<String> {
+        (**self).get_otp(prompt)
+    }
+}
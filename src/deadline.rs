@@ -0,0 +1,181 @@
+//! A wall-clock deadline for everything a session does, for batch jobs giving each host a fixed budget.
+
+use std::sync::{Arc,Mutex};
+use std::sync::atomic::{AtomicBool,Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration,Instant};
+
+#[cfg(unix)]
+use super::libc;
+use super::libc::c_int;
+use super::{Error,Session,ssh_disconnect,ssh_get_fd};
+#[cfg(unix)]
+use super::interrupt::Interrupter;
+
+/// The socket of a session, shared with the threads that may shut it down.
+pub(crate) struct Socket {
+    /// The socket, with its identity, or `None` when the session is not connected. Locked while shutting the socket down.
+    state:Mutex<Option<(i32,Identity)>>
+}
+
+#[cfg(unix)]
+type Identity=(libc::dev_t,libc::ino_t);
+#[cfg(not(unix))]
+type Identity=();
+
+/// The device and inode numbers of the file open as `fd`, which tell it apart from a file opened later with the same number.
+#[cfg(unix)]
+fn identity(fd:i32)->Option<Identity> {
+    let mut st:libc::stat=unsafe { std::mem::zeroed() };
+    if unsafe { libc::fstat(fd,&mut st) }==0 { Some((st.st_dev,st.st_ino)) } else { None }
+}
+#[cfg(not(unix))]
+fn identity(_:i32)->Option<Identity> {
+    None
+}
+
+impl Socket {
+    /// Record the socket of the connected session, or -1 once it is disconnected.
+    pub(crate) fn set(&self,fd:i32) {
+        let mut state=self.state.lock().unwrap_or_else(|e| e.into_inner());
+        *state=if fd>=0 { identity(fd).map(|id| (fd,id)) } else { None }
+    }
+    pub(crate) fn shutdown(&self) {
+        let state=self.state.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some((fd,id))=*state {
+            // libssh closes the socket itself when the connection is lost, and its number may have been reused since.
+            if identity(fd)==Some(id) {
+                #[cfg(unix)]
+                unsafe { libc::shutdown(fd,libc::SHUT_RDWR) };
+            }
+        }
+    }
+}
+
+/// Shuts the socket of a session down when its deadline passes, making the blocking libssh call in progress (and all later ones) fail.
+pub(crate) struct Watchdog {
+    pub(crate) socket:Arc<Socket>,
+    deadline:Option<Instant>,
+    /// Whether the deadline has passed.
+    expired:Arc<AtomicBool>,
+    /// Stops the thread waiting for the deadline, when dropped.
+    cancel:Option<mpsc::Sender<()>>,
+    /// Whether an `Interrupter` was used.
+    pub(crate) interrupted:Arc<AtomicBool>,
+    /// Created by `Session::interrupter`.
+    #[cfg(unix)]
+    pub(crate) interrupter:Option<Interrupter>
+}
+
+impl Default for Watchdog {
+    fn default()->Watchdog {
+        Watchdog {
+            socket:Arc::new(Socket { state:Mutex::new(None) }),
+            deadline:None,
+            expired:Arc::new(AtomicBool::new(false)),
+            cancel:None,
+            interrupted:Arc::new(AtomicBool::new(false)),
+            #[cfg(unix)]
+            interrupter:None
+        }
+    }
+}
+
+impl Watchdog {
+    pub(crate) fn expired(&self)->bool {
+        self.expired.load(Ordering::SeqCst)
+    }
+    /// Record the socket of the connected session (shutting it down if the deadline has already passed), or -1 once it is disconnected.
+    pub(crate) fn set_fd(&self,fd:i32) {
+        self.socket.set(fd);
+        if self.expired() || self.interrupted.load(Ordering::SeqCst) {
+            self.socket.shutdown()
+        }
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        // Stop the thread waiting for interruptions.
+        #[cfg(unix)]
+        if let Some(ref i)=self.interrupter {
+            i.quit()
+        }
+    }
+}
+
+impl Session {
+    /// Make all operations of this session (connecting, authenticating, opening channels, reading, writing, SFTP…) fail with `Error::DeadlineExceeded` once `deadline` has passed, or remove the deadline with `None`. An operation in progress at the deadline is interrupted by shutting the connection down, so the session can't be used afterwards; the deadline also limits the timeout of `connect`.
+    ///
+    ///```no_run
+    /// use ssh::*;
+    /// use std::time::{Duration,Instant};
+    ///
+    /// let mut session=Session::new().unwrap();
+    /// session.set_host("pijul.org").unwrap();
+    /// session.set_deadline(Some(Instant::now()+Duration::from_secs(60)));
+    /// session.parse_config(None).unwrap();
+    /// session.connect().unwrap();
+    /// session.userauth_publickey_auto(None).unwrap();
+    /// session.exec("make check").unwrap();
+    ///```
+    pub fn set_deadline(&mut self,deadline:Option<Instant>) {
+        let watchdog=&mut self.watchdog;
+        watchdog.cancel=None;
+        watchdog.deadline=deadline;
+        watchdog.expired=Arc::new(AtomicBool::new(false));
+        if let Some(deadline)=deadline {
+            let (tx,rx)=mpsc::channel::<()>();
+            let (socket,expired)=(watchdog.socket.clone(),watchdog.expired.clone());
+            thread::spawn(move || {
+                let left=deadline.saturating_duration_since(Instant::now());
+                if let Err(mpsc::RecvTimeoutError::Timeout)=rx.recv_timeout(left) {
+                    debug!("session deadline passed");
+                    expired.store(true,Ordering::SeqCst);
+                    socket.shutdown()
+                }
+            });
+            watchdog.cancel=Some(tx)
+        }
+    }
+    pub fn deadline(&self)->Option<Instant> {
+        self.watchdog.deadline
+    }
+    /// Fail if the deadline has passed or the session was interrupted, else return the time left before it, if there is one.
+    pub(crate) fn check_deadline(&self)->Result<Option<Duration>,Error> {
+        self.check_interrupted()?;
+        match self.watchdog.deadline {
+            Some(d) if self.watchdog.expired() || Instant::now()>=d=>Err(Error::DeadlineExceeded),
+            Some(d)=>Ok(Some(d.saturating_duration_since(Instant::now()))),
+            None=>Ok(None)
+        }
+    }
+    /// The error of a libssh call that failed after the deadline, or after an interruption.
+    pub(crate) fn deadline_error(&self)->Option<Error> {
+        if let Err(e)=self.check_interrupted() {
+            Some(e)
+        } else if self.watchdog.expired() {
+            Some(Error::DeadlineExceeded)
+        } else {
+            None
+        }
+    }
+    /// Watch the socket of the session, once connected.
+    pub(crate) fn watch_socket(&self) {
+        self.watchdog.set_fd(unsafe { ssh_get_fd(self.session) })
+    }
+    /// Disconnect, after forgetting the socket, which libssh closes.
+    pub(crate) fn drop_connection(&self)->c_int {
+        self.watchdog.set_fd(-1);
+        unsafe { ssh_disconnect(self.session) }
+    }
+    /// Whether the session was interrupted by an `Interrupter`.
+    pub fn is_interrupted(&self)->bool {
+        self.watchdog.interrupted.load(Ordering::SeqCst)
+    }
+    /// Fail if the session was interrupted.
+    pub(crate) fn check_interrupted(&self)->Result<(),Error> {
+        if self.is_interrupted() { Err(Error::Interrupted) } else { Ok(()) }
+    }
+}
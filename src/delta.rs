@@ -0,0 +1,178 @@
+//! Updating large files by sending only the blocks that changed, as a simpler, fixed-block `rsync`.
+//!
+//! The file is cut into blocks of a fixed size, the server computes the SHA-1 of each of its blocks (with `split --filter=sha1sum`, or `dd` and `sha1sum` if `split` doesn't support it), and only the blocks whose hashes differ are copied, over SFTP. Unlike `rsync`, insertions shift all the following blocks, so this is for files modified in place, such as disk images or databases.
+
+use std::fs::{File,OpenOptions};
+use std::io::{ErrorKind,Read,Seek,SeekFrom};
+use std::path::Path;
+
+use sha1::{Digest,Sha1};
+
+use super::{Error,Session};
+use super::copy::local_mode;
+use super::exec::shell_quote;
+
+/// Exit status of the checksum command when the file doesn't exist.
+const MISSING:i32=3;
+
+/// Size of the buffer through which local blocks are hashed.
+const HASH_BUFFER_LEN:usize=64*1024;
+
+/// What a delta transfer did.
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Default)]
+pub struct DeltaStats {
+    /// Number of blocks of the source file.
+    pub blocks:u64,
+    /// Number of blocks copied, because they were different or missing on the destination.
+    pub changed:u64,
+    /// Number of bytes copied.
+    pub bytes:u64
+}
+
+fn hex(digest:&[u8])->String {
+    digest.iter().map(|b| format!("{:02x}",b)).collect()
+}
+
+/// The hashes of the blocks of the local file `path`, empty if it doesn't exist.
+fn local_blocks(path:&Path,block_size:u64)->Result<Vec<String>,Error> {
+    let mut f=match File::open(path) {
+        Ok(f)=>f,
+        Err(ref e) if e.kind()==ErrorKind::NotFound=>return Ok(Vec::new()),
+        Err(e)=>return Err(e.into())
+    };
+    let mut hashes=Vec::new();
+    // Blocks are hashed as they are read, so that huge block sizes don't need as much memory.
+    let mut buf=vec![0;HASH_BUFFER_LEN];
+    loop {
+        let mut hasher=Sha1::new();
+        let mut block=(&mut f).take(block_size);
+        let mut len=0;
+        loop {
+            let n=match block.read(&mut buf) {
+                Ok(n)=>n,
+                Err(ref e) if e.kind()==ErrorKind::Interrupted=>continue,
+                Err(e)=>return Err(e.into())
+            };
+            if n==0 {
+                break
+            }
+            hasher.update(&buf[..n]);
+            len+=n
+        }
+        if len==0 {
+            return Ok(hashes)
+        }
+        hashes.push(hex(&hasher.finalize()))
+    }
+}
+
+/// Copy `len` bytes at `offset` from `source` to `dest`.
+fn copy_block<R:Read+Seek,W:std::io::Write+Seek>(source:&mut R,dest:&mut W,offset:u64,len:u64)->Result<u64,Error> {
+    source.seek(SeekFrom::Start(offset))?;
+    dest.seek(SeekFrom::Start(offset))?;
+    Ok(std::io::copy(&mut source.take(len),dest)?)
+}
+
+impl Session {
+    /// The hashes of the blocks of the remote file `path`, or `None` if it doesn't exist.
+    fn remote_blocks(&mut self,path:&Path,block_size:u64)->Result<Option<Vec<String>>,Error> {
+        let cmd=format!(
+            "f={f}; test -f \"$f\" || exit {missing}; split -b {bs} --filter=sha1sum -- \"$f\" 2>/dev/null || {{ i=0; n=$(( ($(wc -c < \"$f\") + {bs} - 1) / {bs} )); while [ $i -lt $n ]; do dd if=\"$f\" bs={bs} skip=$i count=1 2>/dev/null | sha1sum || exit 1; i=$((i+1)); done; }}",
+            f=shell_quote(&path.to_string_lossy()),missing=MISSING,bs=block_size);
+        let out=self.exec(&cmd)?;
+        match out.exit_status {
+            Some(0)=>Ok(Some(String::from_utf8_lossy(&out.stdout).lines()
+                             .filter_map(|l| l.split_whitespace().next().map(|h| h.to_string()))
+                             .collect())),
+            Some(MISSING)=>Ok(None),
+            status=>Err(Error::Ssh(format!("computing the block hashes of {:?} failed ({:?}): {}",path,status,String::from_utf8_lossy(&out.stderr).trim())))
+        }
+    }
+    /// Update the remote file `remote` to match the local file `local`, sending only the blocks of `block_size` bytes that differ. The remote file is created if it doesn't exist, and truncated if it is longer. The server needs a POSIX shell, `sha1sum` and SFTP.
+    ///
+    ///```no_run
+    /// use ssh::*;
+    ///
+    /// let mut session=Session::new().unwrap();
+    /// session.set_host("pijul.org").unwrap();
+    /// session.parse_config(None).unwrap();
+    /// session.connect().unwrap();
+    /// session.userauth_publickey_auto(None).unwrap();
+    /// let stats=session.upload_delta("/var/lib/vm/disk.img","/srv/backups/disk.img",1<<20).unwrap();
+    /// println!("{} of {} blocks sent",stats.changed,stats.blocks);
+    ///```
+    pub fn upload_delta<P:AsRef<Path>,Q:AsRef<Path>>(&mut self,local:P,remote:Q,block_size:u64)->Result<DeltaStats,Error> {
+        let (local,remote)=(local.as_ref(),remote.as_ref());
+        let block_size=std::cmp::max(block_size,1);
+        let meta=std::fs::metadata(local)?;
+        let ours=local_blocks(local,block_size)?;
+        let theirs=self.remote_blocks(remote,block_size)?.unwrap_or_default();
+        let mut stats=DeltaStats { blocks:ours.len() as u64,..DeltaStats::default() };
+        let remote_size={
+            let sftp=self.sftp_new()?;
+            let mut dest=sftp.open_with(remote,super::libc::O_WRONLY|super::libc::O_CREAT,local_mode(&meta))?;
+            let mut source=File::open(local)?;
+            for (i,h) in ours.iter().enumerate() {
+                if theirs.get(i)!=Some(h) {
+                    stats.changed+=1;
+                    stats.bytes+=copy_block(&mut source,&mut dest,i as u64*block_size,block_size)?
+                }
+            }
+            dest.close()?;
+            sftp.stat(remote)?.size.unwrap_or(0)
+        };
+        if remote_size>meta.len() {
+            let out=self.exec(&format!("truncate -s {} -- {}",meta.len(),shell_quote(&remote.to_string_lossy())))?;
+            if out.exit_status!=Some(0) {
+                return Err(Error::Ssh(format!("truncating {:?} failed: {}",remote,String::from_utf8_lossy(&out.stderr).trim())))
+            }
+        }
+        trace_event!(blocks=stats.blocks,changed=stats.changed,bytes=stats.bytes,"delta upload finished");
+        Ok(stats)
+    }
+    /// Update the local file `local` to match the remote file `remote`, fetching only the blocks of `block_size` bytes that differ. The local file is created if it doesn't exist, and truncated if it is longer. The server needs a POSIX shell, `sha1sum` and SFTP.
+    pub fn download_delta<P:AsRef<Path>,Q:AsRef<Path>>(&mut self,remote:P,local:Q,block_size:u64)->Result<DeltaStats,Error> {
+        let (remote,local)=(remote.as_ref(),local.as_ref());
+        let block_size=std::cmp::max(block_size,1);
+        let theirs=match self.remote_blocks(remote,block_size)? {
+            Some(h)=>h,
+            None=>return Err(Error::IO(std::io::Error::new(ErrorKind::NotFound,format!("{:?} does not exist",remote))))
+        };
+        let ours=local_blocks(local,block_size)?;
+        let mut stats=DeltaStats { blocks:theirs.len() as u64,..DeltaStats::default() };
+        let sftp=self.sftp_new()?;
+        let mut source=sftp.open(remote)?;
+        let size=source.metadata()?.size.unwrap_or(0);
+        let mut dest=OpenOptions::new().write(true).create(true).truncate(false).open(local)?;
+        for (i,h) in theirs.iter().enumerate() {
+            if ours.get(i)!=Some(h) {
+                stats.changed+=1;
+                stats.bytes+=copy_block(&mut source,&mut dest,i as u64*block_size,block_size)?
+            }
+        }
+        dest.set_len(size)?;
+        dest.sync_all()?;
+        trace_event!(blocks=stats.blocks,changed=stats.changed,bytes=stats.bytes,"delta download finished");
+        Ok(stats)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sha1::{Digest,Sha1};
+    use super::{hex,local_blocks,HASH_BUFFER_LEN};
+
+    #[test]
+    fn local_blocks_streamed() {
+        let path=std::env::temp_dir().join(format!("ssh-delta-{}",std::process::id()));
+        let data:Vec<u8>=(0..3*HASH_BUFFER_LEN+100).map(|i| (i%251) as u8).collect();
+        std::fs::write(&path,&data).unwrap();
+        let block=HASH_BUFFER_LEN+7;
+        let expected:Vec<String>=data.chunks(block).map(|c| hex(&Sha1::digest(c))).collect();
+        assert_eq!(local_blocks(&path,block as u64).unwrap(),expected);
+        // A block size larger than memory is not allocated.
+        assert_eq!(local_blocks(&path,u64::MAX).unwrap(),vec![hex(&Sha1::digest(&data))]);
+        std::fs::remove_file(&path).unwrap();
+        assert!(local_blocks(&path,1024).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,333 @@
+//! Running a remote command and collecting its output.
+
+use std::io::{Read,Write};
+use std::fmt;
+use std::time::{Duration,Instant,SystemTime};
+
+use super::libc::{c_char,c_int,size_t};
+use super::{Channel,Error,Session,err,SSH_ERROR,SSH_EOF};
+use super::{ssh_channel_poll,ssh_channel_poll_timeout,ssh_channel_read};
+use super::tap::Direction;
+
+/// The output of a finished remote command.
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub struct Output {
+    pub stdout:Vec<u8>,
+    /// Empty if standard error was merged or discarded.
+    pub stderr:Vec<u8>,
+    /// The exit status sent by the server, or `None` if the channel was closed without one (for instance when the command was killed by a signal). If the connection is lost before the end of the command, the result is `Error::ConnectionLost` instead.
+    pub exit_status:Option<i32>
+}
+
+/// What to do with the standard error of a remote command.
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum Stderr {
+    /// Collect it separately (the default).
+    Capture,
+    /// Interleave it with standard output, in the order it arrives (like `2>&1`, but without involving the remote shell).
+    Merge,
+    /// Read it, and throw it away.
+    Discard
+}
+
+/// Quote `s` for a POSIX shell, so that it is passed as a single word.
+pub(crate) fn shell_quote(s:&str)->String {
+    format!("'{}'",s.replace('\'',"'\\''"))
+}
+
+/// How long to wait for new data when neither stream has anything to read, in milliseconds.
+const POLL_INTERVAL:c_int=50;
+
+impl<'c> Channel<'c> {
+    /// Run `cmd` with `shell -c`, whatever the login shell of the remote user is: for instance, with `shell` set to `"sh"`, a POSIX script runs the same for users whose shell is `fish` or `tcsh`. `cmd` is quoted and passed as a single argument, and must not contain NUL bytes.
+    pub fn request_exec_shell(&mut self,shell:&str,cmd:&str)->Result<(),Error> {
+        self.request_exec(format!("{} -c {}",shell,shell_quote(cmd)))
+    }
+    /// The exit status of the finished command: `Some` if the server sent one, `None` if the channel was closed without one, and `Error::ConnectionLost` if the connection was lost before the command finished.
+    pub fn command_status(&self)->Result<Option<i32>,Error> {
+        match self.get_exit_status() {
+            Some(status)=>Ok(Some(status)),
+            None if self.session.connection_lost()=>Err(Error::ConnectionLost("the channel was closed without an exit status".to_string())),
+            None=>Ok(None)
+        }
+    }
+    /// The error of a failed read: `ConnectionLost` if the connection is gone.
+    fn read_err(&self)->Error {
+        let e=err(self.session);
+        if self.session.connection_lost() { Error::ConnectionLost(e.to_string()) } else { e }
+    }
+    /// Read standard output and standard error until EOF, calling `f` with each piece of data as it arrives, along with `true` if it comes from standard error. Both streams are read concurrently, so that a command filling its standard error cannot block while we wait on its standard output.
+    ///
+    /// If `input` is given, it is copied to the standard input of the command at the same time (at the pace allowed by the remote window), followed by an EOF. If the command stops accepting input before the end, the rest of `input` is dropped.
+    pub(crate) fn read_both<F:FnMut(bool,&[u8])->Result<(),Error>>(&mut self,mut input:Option<&mut dyn Read>,mut f:F)->Result<(),Error> {
+        let mut buf=[0;8192];
+        let mut inbuf=vec![0;8192];
+        let (mut in_start,mut in_end)=(0,0);
+        loop {
+            let mut eof=true;
+            let mut progress=false;
+            if let Some(ref mut r)=input {
+                if in_start==in_end {
+                    in_start=0;
+                    in_end=r.read(&mut inbuf)?;
+                }
+            }
+            if in_end==0 && input.is_some() {
+                self.send_eof()?;
+                input=None
+            } else if in_start<in_end {
+                match self.try_write(&inbuf[in_start..in_end]) {
+                    Ok(n)=>{ in_start+=n; progress=true },
+                    Err(ref e) if e.kind()==std::io::ErrorKind::WouldBlock=>(),
+                    Err(ref e) if e.kind()==std::io::ErrorKind::BrokenPipe=>{
+                        // The command exited or closed its standard input without reading everything: keep its output and exit status, as a shell pipeline would.
+                        trace_event!(unsent_bytes=in_end-in_start,"the command stopped reading its input");
+                        in_start=in_end;
+                        input=None
+                    },
+                    Err(e)=>return Err(e.into())
+                }
+            }
+            for &is_stderr in &[0,1] {
+                let n=unsafe { ssh_channel_poll(self.channel,is_stderr) };
+                if n==SSH_ERROR {
+                    return Err(self.read_err())
+                } else if n==SSH_EOF {
+                    continue
+                }
+                eof=false;
+                if n>0 {
+                    let len=std::cmp::min(n as usize,buf.len());
+                    let r=unsafe { ssh_channel_read(self.channel,buf.as_mut_ptr() as *mut c_char,len as size_t,is_stderr) };
+                    if r<0 {
+                        return Err(self.read_err())
+                    }
+                    self.session.tap(self.channel,Direction::Received,is_stderr==1,&buf[..r as usize]);
+                    f(is_stderr==1,&buf[..r as usize])?;
+                    progress=true
+                }
+            }
+            if eof {
+                return Ok(())
+            }
+            if !progress && unsafe { ssh_channel_poll_timeout(self.channel,POLL_INTERVAL,0) }==SSH_ERROR {
+                return Err(self.read_err())
+            }
+        }
+    }
+}
+
+/// A builder for running remote commands, in the style of `std::process::Command`.
+///
+///```no_run
+/// use ssh::*;
+///
+/// let mut session=Session::new().unwrap();
+/// session.set_host("pijul.org").unwrap();
+/// session.parse_config(None).unwrap();
+/// session.connect().unwrap();
+/// session.userauth_publickey_auto(None).unwrap();
+/// let out=RemoteCommand::new("ls -l /")
+///     .stderr(Stderr::Merge)
+///     .max_output(1<<20)
+///     .output(&mut session)
+///     .unwrap();
+/// println!("{}",String::from_utf8_lossy(&out.stdout));
+///```
+pub struct RemoteCommand<'a> {
+    command:String,
+    stderr:Stderr,
+    max_output:Option<usize>,
+    stdin:Option<Box<dyn Read+'a>>,
+    login_shell:Option<String>,
+    shell:Option<String>
+}
+
+impl<'a> fmt::Debug for RemoteCommand<'a> {
+    fn fmt(&self,f:&mut fmt::Formatter)->fmt::Result {
+        write!(f,"RemoteCommand{{ command:{:?}, stderr:{:?}, max_output:{:?}, stdin:{}, login_shell:{:?}, shell:{:?} }}",
+               self.command,self.stderr,self.max_output,if self.stdin.is_some() { "Some(..)" } else { "None" },self.login_shell,self.shell)
+    }
+}
+
+impl<'a> RemoteCommand<'a> {
+    pub fn new<S:Into<String>>(command:S)->RemoteCommand<'a> {
+        RemoteCommand {
+            command:command.into(),
+            stderr:Stderr::Capture,
+            max_output:None,
+            stdin:None,
+            login_shell:None,
+            shell:None
+        }
+    }
+    /// Feed `input` to the standard input of the command, followed by an EOF, while its output is being read (as in `cat archive.tar | ssh host tar -x`). Without this, the command gets an empty standard input. The input is consumed by the first run of the command.
+    pub fn stdin<R:Read+'a>(mut self,input:R)->RemoteCommand<'a> {
+        self.stdin=Some(Box::new(input));
+        self
+    }
+    /// Choose what to do with standard error.
+    pub fn stderr(mut self,stderr:Stderr)->RemoteCommand<'a> {
+        self.stderr=stderr;
+        self
+    }
+    /// Fail with `Error::OutputTooLarge` (and close the channel) if the command outputs more than `bytes` bytes, standard output and captured standard error combined. This applies to `stream` as well as to `output`.
+    pub fn max_output(mut self,bytes:usize)->RemoteCommand<'a> {
+        self.max_output=Some(bytes);
+        self
+    }
+    /// Run the command in a login shell (as `bash -lc 'command'` with `shell` set to `"bash"`), so that it sees the `PATH` and environment set up by the profile files of the remote user, like in an interactive session. `shell` must accept the `-l` and `-c` options, as `sh`, `bash` and `zsh` do.
+    pub fn login_shell<S:Into<String>>(mut self,shell:S)->RemoteCommand<'a> {
+        self.login_shell=Some(shell.into());
+        self
+    }
+    /// Run the command with `shell -c` (as `sh -c 'command'` with `shell` set to `"sh"`) instead of the shell of the remote user, without reading the profile files. `login_shell` takes precedence over this.
+    pub fn shell<S:Into<String>>(mut self,shell:S)->RemoteCommand<'a> {
+        self.shell=Some(shell.into());
+        self
+    }
+    /// The command line sent to the server.
+    pub fn command_line(&self)->String {
+        match (&self.login_shell,&self.shell) {
+            (Some(shell),_)=>format!("{} -lc {}",shell,shell_quote(&self.command)),
+            (None,Some(shell))=>format!("{} -c {}",shell,shell_quote(&self.command)),
+            (None,None)=>self.command.clone()
+        }
+    }
+    /// Run the command on a new channel of `session`, and wait for it to finish.
+    pub fn output(&mut self,session:&mut Session)->Result<Output,Error> {
+        let mut stdout=Vec::new();
+        let mut stderr=Vec::new();
+        let exit_status=self.stream(session,&mut stdout,&mut stderr)?;
+        Ok(Output { stdout, stderr, exit_status })
+    }
+    /// Run the command on a new channel of `session`, writing its output to `stdout` and `stderr` as it arrives instead of keeping it in memory, and return its exit status (see `Channel::command_status`). With `Stderr::Merge`, everything goes to `stdout`.
+    pub fn stream<O:Write,E:Write>(&mut self,session:&mut Session,stdout:&mut O,stderr:&mut E)->Result<Option<i32>,Error> {
+        let start=SystemTime::now();
+        let result=timed!("ssh_command_duration_seconds","ssh_commands_total",self.run(session,stdout,stderr));
+        if let Some(log)=session.audit.clone() {
+            log.append(session,&self.command_line(),start,&result)?;
+        }
+        result
+    }
+    fn run<O:Write,E:Write>(&mut self,session:&mut Session,stdout:&mut O,stderr:&mut E)->Result<Option<i32>,Error> {
+        let mut channel=session.channel_new()?;
+        channel.open_session()?;
+        channel.request_exec(self.command_line())?;
+        let mut input=self.stdin.take();
+        if input.is_none() {
+            channel.send_eof()?;
+        }
+        let mut total=0;
+        let (keep_stderr,max_output)=(self.stderr,self.max_output);
+        channel.read_both(input.as_mut().map(|r| &mut **r as &mut dyn Read),|is_stderr,data| {
+            if is_stderr && keep_stderr==Stderr::Discard {
+                return Ok(())
+            }
+            total+=data.len();
+            if let Some(max)=max_output {
+                if total>max {
+                    return Err(Error::OutputTooLarge(max))
+                }
+            }
+            if is_stderr && keep_stderr==Stderr::Capture {
+                stderr.write_all(data)?
+            } else {
+                stdout.write_all(data)?
+            }
+            Ok(())
+        })?;
+        let exit_status=channel.command_status()?;
+        trace_event!(output_bytes=total,exit_status=?exit_status,"command finished");
+        channel.close()?;
+        Ok(exit_status)
+    }
+}
+
+impl Session {
+    /// Run `cmd` on a new channel, wait for it to finish, and return its output. See `RemoteCommand` for more options.
+    pub fn exec(&mut self,cmd:&str)->Result<Output,Error> {
+        RemoteCommand::new(cmd).output(self)
+    }
+    /// Run `cmd`, streaming its standard output and standard error to `stdout` and `stderr`, and return its exit status.
+    pub fn exec_to<O:Write,E:Write>(&mut self,cmd:&str,stdout:&mut O,stderr:&mut E)->Result<Option<i32>,Error> {
+        RemoteCommand::new(cmd).stream(self,stdout,stderr)
+    }
+    /// Run `cmd` in a `bash` login shell, so that it behaves as it would when typed by the remote user in an interactive session. See `RemoteCommand::login_shell` to use another shell.
+    pub fn exec_login(&mut self,cmd:&str)->Result<Output,Error> {
+        RemoteCommand::new(cmd).login_shell("bash").output(self)
+    }
+}
+
+/// The result of one command of a `Batch`.
+#[derive(Debug)]
+pub struct BatchResult {
+    pub command:String,
+    pub result:Result<Output,Error>,
+    pub duration:Duration
+}
+
+impl BatchResult {
+    /// Whether the command ran and exited with status 0.
+    pub fn success(&self)->bool {
+        match self.result {
+            Ok(ref out)=>out.exit_status==Some(0),
+            Err(_)=>false
+        }
+    }
+}
+
+/// A sequence of commands run one after the other on the same session, each on its own channel, without reconnecting or authenticating again.
+///
+///```no_run
+/// use ssh::*;
+///
+/// let mut session=Session::new().unwrap();
+/// session.set_host("pijul.org").unwrap();
+/// session.parse_config(None).unwrap();
+/// session.connect().unwrap();
+/// session.userauth_publickey_auto(None).unwrap();
+/// let results=Batch::new()
+///     .command("mkdir -p /tmp/deploy")
+///     .command("ls /tmp/deploy")
+///     .stop_on_failure(true)
+///     .run(&mut session);
+/// for r in results {
+///     println!("{}: {} ({:?})",r.command,r.success(),r.duration)
+/// }
+///```
+#[derive(Debug,Clone,Default)]
+pub struct Batch {
+    commands:Vec<String>,
+    stop_on_failure:bool
+}
+
+impl Batch {
+    pub fn new()->Batch {
+        Batch::default()
+    }
+    pub fn command<S:Into<String>>(mut self,cmd:S)->Batch {
+        self.commands.push(cmd.into());
+        self
+    }
+    /// Don't run the remaining commands after a command fails to run, or exits with a non-zero status.
+    pub fn stop_on_failure(mut self,stop:bool)->Batch {
+        self.stop_on_failure=stop;
+        self
+    }
+    /// Run the commands in order, and return one result for each command that was run.
+    pub fn run(&self,session:&mut Session)->Vec<BatchResult> {
+        let mut results=Vec::with_capacity(self.commands.len());
+        for cmd in &self.commands {
+            let start=Instant::now();
+            let result=session.exec(cmd);
+            let r=BatchResult { command:cmd.clone(), result, duration:start.elapsed() };
+            let failed=!r.success();
+            results.push(r);
+            if failed && self.stop_on_failure {
+                break
+            }
+        }
+        results
+    }
+}
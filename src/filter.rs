@@ -0,0 +1,128 @@
+//! Transformations of transferred data, such as encryption, compression or hashing, applied as the data streams through uploads and downloads of any `Transfer`.
+
+use std::fs::{File,OpenOptions};
+use std::io::{Read,Seek,SeekFrom,Write};
+use std::path::Path;
+
+use super::{Error,Permissions};
+use super::sftp::Metadata;
+use super::transfer::Transfer;
+
+/// A transformation of the data of uploads and downloads.
+///
+/// For instance, a filter encrypting uploads wraps the source in a reader returning encrypted data, and decrypts downloads by wrapping the destination in a writer that decrypts what it is given.
+pub trait Filter {
+    /// Wrap the data of an upload, as read from the local source.
+    fn upload<'a>(&mut self,source:Box<dyn Read+'a>)->Result<Box<dyn Read+'a>,Error>;
+    /// Wrap the local destination of a download. The writer is flushed, then dropped, at the end of the download: writers that add data at the end (such as encoders) must write it then.
+    fn download<'a>(&mut self,dest:Box<dyn Write+'a>)->Result<Box<dyn Write+'a>,Error>;
+}
+
+/// A `Transfer` whose uploads and downloads go through filters. With several filters, uploads go through them in the order they were added, and downloads in the reverse order, so that for instance a filter compressing uploads, followed by one encrypting them, give downloads that are decrypted, then decompressed.
+///
+///```no_run
+/// use ssh::*;
+/// use std::io::{Read,Write};
+/// use std::path::Path;
+///
+/// /// Counts the bytes uploaded.
+/// struct Count(std::sync::Arc<std::sync::atomic::AtomicU64>);
+/// struct Counting<'a>(Box<dyn Read+'a>,std::sync::Arc<std::sync::atomic::AtomicU64>);
+/// impl<'a> Read for Counting<'a> {
+///     fn read(&mut self,buf:&mut [u8])->std::io::Result<usize> {
+///         let n=self.0.read(buf)?;
+///         self.1.fetch_add(n as u64,std::sync::atomic::Ordering::Relaxed);
+///         Ok(n)
+///     }
+/// }
+/// impl Filter for Count {
+///     fn upload<'a>(&mut self,source:Box<dyn Read+'a>)->Result<Box<dyn Read+'a>,Error> {
+///         Ok(Box::new(Counting(source,self.0.clone())))
+///     }
+///     fn download<'a>(&mut self,dest:Box<dyn Write+'a>)->Result<Box<dyn Write+'a>,Error> {
+///         Ok(dest)
+///     }
+/// }
+///
+/// let mut session=Session::new().unwrap();
+/// session.set_host("pijul.org").unwrap();
+/// session.parse_config(None).unwrap();
+/// session.connect().unwrap();
+/// session.userauth_publickey_auto(None).unwrap();
+/// let count=std::sync::Arc::new(std::sync::atomic::AtomicU64::new(0));
+/// let mut t=Filtered::new(session.transfer().unwrap()).filter(Count(count.clone()));
+/// let data=b"blabla\n";
+/// t.upload(&mut &data[..],data.len() as u64,Path::new("/tmp/blublu"),0o644.into()).unwrap();
+///```
+pub struct Filtered<'f,T> {
+    inner:T,
+    filters:Vec<Box<dyn Filter+'f>>
+}
+
+impl<'f,T:Transfer> Filtered<'f,T> {
+    pub fn new(inner:T)->Filtered<'f,T> {
+        Filtered { inner,filters:Vec::new() }
+    }
+    pub fn filter<F:Filter+'f>(mut self,filter:F)->Filtered<'f,T> {
+        self.filters.push(Box::new(filter));
+        self
+    }
+    pub fn into_inner(self)->T {
+        self.inner
+    }
+}
+
+impl<'f,T:Transfer> Transfer for Filtered<'f,T> {
+    /// Upload `size` bytes of `source`, filtered. The size of the filtered data is usually not known in advance: over SCP, which needs it, the data is first written to a temporary local file (see `Transfer::upload_stream`).
+    fn upload(&mut self,source:&mut dyn Read,size:u64,remote:&Path,mode:Permissions)->Result<(),Error> {
+        let mut filtered:Box<dyn Read+'_>=Box::new(source.take(size));
+        for f in self.filters.iter_mut() {
+            filtered=f.upload(filtered)?
+        }
+        self.inner.upload_stream(&mut filtered,remote,mode)?;
+        Ok(())
+    }
+    /// Download `remote` through the filters, and return the number of bytes downloaded (before filtering).
+    fn download(&mut self,remote:&Path,dest:&mut dyn Write)->Result<u64,Error> {
+        let mut filtered:Box<dyn Write+'_>=Box::new(dest);
+        for f in self.filters.iter_mut().rev() {
+            filtered=f.download(filtered)?
+        }
+        let n=self.inner.download(remote,&mut filtered)?;
+        filtered.flush()?;
+        Ok(n)
+    }
+    fn list(&mut self,remote:&Path)->Result<Vec<Metadata>,Error> {
+        self.inner.list(remote)
+    }
+    fn stat(&mut self,remote:&Path)->Result<Metadata,Error> {
+        self.inner.stat(remote)
+    }
+    fn remove(&mut self,remote:&Path)->Result<(),Error> {
+        self.inner.remove(remote)
+    }
+    fn create_dir(&mut self,remote:&Path,mode:Permissions)->Result<(),Error> {
+        self.inner.create_dir(remote,mode)
+    }
+}
+
+/// An anonymous temporary file: on Unix, it is removed as soon as it is open.
+pub(crate) fn spool_file()->Result<File,Error> {
+    let mut random=[0u8;8];
+    getrandom::getrandom(&mut random).map_err(|e| Error::IO(std::io::Error::other(e.to_string())))?;
+    let name:String=random.iter().map(|b| format!("{:02x}",b)).collect();
+    let path=std::env::temp_dir().join(format!("ssh-spool-{}",name));
+    let f=OpenOptions::new().read(true).write(true).create_new(true).open(&path)?;
+    if cfg!(unix) {
+        std::fs::remove_file(&path)?
+    }
+    Ok(f)
+}
+
+/// Copy `source` to a temporary file, and return it, rewound, with its size.
+pub(crate) fn spool(source:&mut dyn Read)->Result<(File,u64),Error> {
+    let mut f=spool_file()?;
+    let size=std::io::copy(source,&mut f)?;
+    f.seek(SeekFrom::Start(0))?;
+    Ok((f,size))
+}
@@ -0,0 +1,222 @@
+//! Running the same job on many hosts in parallel.
+//!
+//!```no_run
+//! use ssh::fleet::Fleet;
+//!
+//! let fleet=Fleet::new(vec!["web1.example.com","web2.example.com"])
+//!     .parallelism(8)
+//!     .max_startups(4)
+//!     .connect_rate(20.);
+//! for r in fleet.exec("uptime") {
+//!     match r.result {
+//!         Ok(out)=>println!("{} ({:?}): {}",r.host,r.duration,String::from_utf8_lossy(&out.stdout)),
+//!         Err(e)=>println!("{}: {}",r.host,e)
+//!     }
+//! }
+//!```
+
+use std::collections::{HashMap,VecDeque};
+use std::sync::{Condvar,Mutex};
+use std::thread;
+use std::time::{Duration,Instant};
+
+use super::{Error,Output,Session};
+
+/// The result of a job on one host.
+#[derive(Debug)]
+pub struct HostResult<T> {
+    pub host:String,
+    pub result:Result<T,Error>,
+    /// Time spent on this host, including connection and authentication.
+    pub duration:Duration
+}
+
+type Connect=dyn Fn(&str)->Result<Session,Error>+Sync;
+
+/// A set of hosts, and the parameters used to run jobs on them.
+pub struct Fleet {
+    hosts:Vec<String>,
+    parallelism:usize,
+    per_host:usize,
+    max_startups:usize,
+    connect_interval:Duration,
+    connect:Box<Connect>
+}
+
+impl std::fmt::Debug for Fleet {
+    fn fmt(&self,f:&mut std::fmt::Formatter)->std::fmt::Result {
+        write!(f,"Fleet{{ hosts:{:?}, parallelism:{}, per_host:{}, max_startups:{}, connect_interval:{:?} }}",
+               self.hosts,self.parallelism,self.per_host,self.max_startups,self.connect_interval)
+    }
+}
+
+/// The state shared by the workers of `Fleet::run`.
+struct Schedule {
+    /// Indices of the hosts not started yet.
+    pending:VecDeque<usize>,
+    /// Number of jobs running on each host.
+    running:HashMap<String,usize>,
+    /// Number of connections being opened and authenticated.
+    connecting:usize,
+    /// Earliest time at which the next connection may be opened.
+    next_connect:Instant
+}
+
+struct Scheduler<'a> {
+    fleet:&'a Fleet,
+    state:Mutex<Schedule>,
+    changed:Condvar
+}
+
+impl<'a> Scheduler<'a> {
+    /// The next host to handle, waiting if all the remaining hosts are at their limit. `None` when all hosts have been started.
+    fn next_host(&self)->Option<usize> {
+        let mut state=self.state.lock().unwrap();
+        loop {
+            if state.pending.is_empty() {
+                return None
+            }
+            let per_host=self.fleet.per_host;
+            let running=&state.running;
+            let pos=state.pending.iter().position(|&i| running.get(&self.fleet.hosts[i]).cloned().unwrap_or(0)<per_host);
+            if let Some(pos)=pos {
+                let i=state.pending.remove(pos).unwrap();
+                *state.running.entry(self.fleet.hosts[i].clone()).or_insert(0)+=1;
+                return Some(i)
+            }
+            state=self.changed.wait(state).unwrap();
+        }
+    }
+    fn host_done(&self,i:usize) {
+        let mut state=self.state.lock().unwrap();
+        if let Some(n)=state.running.get_mut(&self.fleet.hosts[i]) {
+            *n-=1
+        }
+        self.changed.notify_all()
+    }
+    /// Wait until a new connection may be opened.
+    fn start_connect(&self) {
+        let wait={
+            let mut state=self.state.lock().unwrap();
+            while state.connecting>=self.fleet.max_startups {
+                state=self.changed.wait(state).unwrap();
+            }
+            state.connecting+=1;
+            let now=Instant::now();
+            let at=std::cmp::max(now,state.next_connect);
+            state.next_connect=at+self.fleet.connect_interval;
+            at-now
+        };
+        if wait>Duration::from_secs(0) {
+            thread::sleep(wait)
+        }
+    }
+    fn connect_done(&self) {
+        self.state.lock().unwrap().connecting-=1;
+        self.changed.notify_all()
+    }
+}
+
+/// The default way of opening a session to a host: read `~/.ssh/config`, connect, check that the host key is known, and authenticate with public keys.
+pub fn connect(host:&str)->Result<Session,Error> {
+    let mut session=Session::new().map_err(|_| Error::Ssh("Could not allocate a session".to_string()))?;
+    session.set_host(host)?;
+    session.parse_config(None)?;
+    session.connect()?;
+    let known=session.is_server_known()?;
+    if !known.is_known() {
+        return Err(Error::Ssh(format!("Host key of {} is not known: {:?}",host,known)))
+    }
+    session.userauth_publickey_auto(None)?;
+    Ok(session)
+}
+
+impl Fleet {
+    pub fn new<I:IntoIterator<Item=S>,S:Into<String>>(hosts:I)->Fleet {
+        Fleet {
+            hosts:hosts.into_iter().map(|h| h.into()).collect(),
+            parallelism:4,
+            per_host:usize::MAX,
+            max_startups:usize::MAX,
+            connect_interval:Duration::from_secs(0),
+            connect:Box::new(connect)
+        }
+    }
+    /// Maximal number of hosts handled at the same time (at least 1, default 4).
+    pub fn parallelism(mut self,n:usize)->Fleet {
+        self.parallelism=std::cmp::max(n,1);
+        self
+    }
+    /// Maximal number of jobs running at the same time on the same host, when a host appears several times in the list (at least 1, unlimited by default).
+    pub fn per_host(mut self,n:usize)->Fleet {
+        self.per_host=std::cmp::max(n,1);
+        self
+    }
+    /// Maximal number of connections being opened and authenticated at the same time (at least 1, unlimited by default). Servers and bastions drop unauthenticated connections beyond their `MaxStartups` setting (10 by default in OpenSSH).
+    pub fn max_startups(mut self,n:usize)->Fleet {
+        self.max_startups=std::cmp::max(n,1);
+        self
+    }
+    /// Open at most `per_second` connections per second, spreading them evenly. Zero or less means no limit (the default).
+    pub fn connect_rate(mut self,per_second:f64)->Fleet {
+        self.connect_interval=if per_second>0. {
+            Duration::from_secs_f64(1./per_second)
+        } else {
+            Duration::from_secs(0)
+        };
+        self
+    }
+    /// Replace the default `connect` function, for instance to use passwords or custom host key checks.
+    pub fn connect_with<F:Fn(&str)->Result<Session,Error>+Sync+'static>(mut self,f:F)->Fleet {
+        self.connect=Box::new(f);
+        self
+    }
+    pub fn hosts(&self)->&[String] {
+        &self.hosts
+    }
+
+    /// Connect to each host, and call `f` with the session. The results are returned in the order of the hosts.
+    pub fn run<T,F>(&self,f:F)->Vec<HostResult<T>>
+        where T:Send, F:Fn(&str,&mut Session)->Result<T,Error>+Sync {
+        let scheduler=Scheduler {
+            fleet:self,
+            state:Mutex::new(Schedule {
+                pending:(0..self.hosts.len()).collect(),
+                running:HashMap::new(),
+                connecting:0,
+                next_connect:Instant::now()
+            }),
+            changed:Condvar::new()
+        };
+        let results:Vec<Mutex<Option<HostResult<T>>>>=self.hosts.iter().map(|_| Mutex::new(None)).collect();
+        let workers=std::cmp::min(self.parallelism,self.hosts.len());
+        thread::scope(|scope| {
+            for _ in 0..workers {
+                scope.spawn(|| while let Some(i)=scheduler.next_host() {
+                    let host=&self.hosts[i];
+                    let start=Instant::now();
+                    scheduler.start_connect();
+                    let session=(self.connect)(host);
+                    scheduler.connect_done();
+                    let result=session.and_then(|mut session| {
+                        let r=f(host,&mut session);
+                        let _=session.close();
+                        r
+                    });
+                    scheduler.host_done(i);
+                    *results[i].lock().unwrap()=Some(HostResult {
+                        host:host.clone(),
+                        result,
+                        duration:start.elapsed()
+                    })
+                });
+            }
+        });
+        results.into_iter().map(|r| r.into_inner().unwrap().unwrap()).collect()
+    }
+
+    /// Run `cmd` on all hosts.
+    pub fn exec(&self,cmd:&str)->Vec<HostResult<Output>> {
+        self.run(|_,session| session.exec(cmd))
+    }
+}
@@ -0,0 +1,237 @@
+//! Expanding glob patterns such as `/var/log/*.gz` against remote directories, over SFTP: names are matched here, so no remote shell sees them, and names containing spaces, quotes or newlines are handled like any other.
+
+use std::path::{Component,Path,PathBuf};
+
+use super::Error;
+use super::sftp::{FileType,Metadata,Sftp};
+
+fn is_pattern(s:&str)->bool {
+    s.contains(['*','?','[','\\'])
+}
+
+/// Match the character class starting after `[` at `p[0]` against `c`. Returns whether it matches, and the length of the class after the `[`, or `None` if the class is not closed (and `[` is then an ordinary character).
+fn match_class(p:&[char],c:char)->Option<(bool,usize)> {
+    let mut i=0;
+    let negated=matches!(p.first(),Some('!')|Some('^'));
+    if negated {
+        i+=1
+    }
+    let mut matched=false;
+    let mut first=true;
+    while i<p.len() {
+        // A `]` right after the opening is part of the class.
+        if p[i]==']' && !first {
+            return Some((matched!=negated,i+1))
+        }
+        first=false;
+        let lo=p[i];
+        if i+2<p.len() && p[i+1]=='-' && p[i+2]!=']' {
+            matched|=lo<=c && c<=p[i+2];
+            i+=3
+        } else {
+            matched|=lo==c;
+            i+=1
+        }
+    }
+    None
+}
+
+/// Whether `name` matches the pattern `p`, as in `fnmatch(3)` with `FNM_PERIOD`: `*` and `?` don't match a leading `.`.
+fn matches(p:&[char],name:&[char])->bool {
+    if name.first()==Some(&'.') && !matches!(p.first(),Some('.')|Some('\\')) {
+        return false
+    }
+    match_from(p,name)
+}
+
+fn match_from(p:&[char],name:&[char])->bool {
+    let (mut pi,mut ni)=(0,0);
+    // Where to resume after the last `*`: the position of the pattern after it, and of the name.
+    let mut star:Option<(usize,usize)>=None;
+    while ni<name.len() {
+        let step=match p.get(pi) {
+            Some('*')=>{
+                star=Some((pi+1,ni));
+                pi+=1;
+                continue
+            },
+            Some('?')=>Some(1),
+            Some('[')=>match match_class(&p[pi+1..],name[ni]) {
+                Some((true,len))=>Some(len+1),
+                Some((false,_))=>None,
+                None=>if name[ni]=='[' { Some(1) } else { None }
+            },
+            Some('\\') if pi+1<p.len()=>if p[pi+1]==name[ni] { Some(2) } else { None },
+            Some(&c)=>if c==name[ni] { Some(1) } else { None },
+            None=>None
+        };
+        match step {
+            Some(len)=>{
+                pi+=len;
+                ni+=1
+            },
+            None=>match star {
+                // Let the last `*` match one more character.
+                Some((sp,sn))=>{
+                    pi=sp;
+                    ni=sn+1;
+                    star=Some((sp,sn+1))
+                },
+                None=>return false
+            }
+        }
+    }
+    p[pi..].iter().all(|&c| c=='*')
+}
+
+impl<'b> Sftp<'b> {
+    /// The remote files matching `pattern`, sorted by path, with their attributes. Each component of the pattern may contain `*`, `?` and character classes (`[a-z]`, `[!0-9]`), and `\` escapes the next character; as in shells, `*` and `?` don't match names starting with a dot, and `/` is never matched. Components without wildcards are not listed, so the pattern can go through directories that are not readable.
+    ///
+    /// Nothing is run on the server: directories are listed over SFTP, and names matched locally.
+    ///
+    ///```no_run
+    /// use ssh::*;
+    ///
+    /// let mut session=Session::new().unwrap();
+    /// session.set_host("pijul.org").unwrap();
+    /// session.parse_config(None).unwrap();
+    /// session.connect().unwrap();
+    /// session.userauth_publickey_auto(None).unwrap();
+    /// let sftp=session.sftp_new().unwrap();
+    /// for (path,meta) in sftp.glob("/var/log/nginx/*.gz").unwrap() {
+    ///     let mut local=std::fs::File::create(std::path::Path::new("/tmp").join(&meta.name)).unwrap();
+    ///     std::io::copy(&mut sftp.open(&path).unwrap(),&mut local).unwrap();
+    /// }
+    ///```
+    pub fn glob<P:AsRef<Path>>(&self,pattern:P)->Result<Vec<(PathBuf,Metadata)>,Error> {
+        let pattern=pattern.as_ref();
+        // Candidates so far, with their attributes when they come from a listing.
+        let mut found:Vec<(PathBuf,Option<Metadata>)>=vec![(PathBuf::new(),None)];
+        for component in pattern.components() {
+            let c=match component {
+                Component::Normal(c)=>c.to_string_lossy(),
+                other=>{
+                    for f in found.iter_mut() {
+                        f.0.push(other.as_os_str())
+                    }
+                    continue
+                }
+            };
+            if !is_pattern(&c) {
+                for f in found.iter_mut() {
+                    f.0.push(&*c);
+                    f.1=None
+                }
+                continue
+            }
+            let p:Vec<char>=c.chars().collect();
+            let mut next=Vec::new();
+            for (dir,meta) in found {
+                if let Some(ref m)=meta {
+                    if m.file_type!=FileType::Directory && m.file_type!=FileType::Symlink {
+                        continue
+                    }
+                }
+                let list=if dir.as_os_str().is_empty() { Path::new(".") } else { &dir };
+                let entries=match self.read_dir(list) {
+                    Ok(e)=>e,
+                    // Not a directory, or not readable: nothing matches below it.
+                    Err(Error::IO(_))=>continue,
+                    Err(e)=>return Err(e)
+                };
+                for e in entries {
+                    let name:Vec<char>=e.name.chars().collect();
+                    if matches(&p,&name) {
+                        next.push((dir.join(e.name_os()),Some(e)))
+                    }
+                }
+            }
+            found=next
+        }
+        let mut result=Vec::with_capacity(found.len());
+        for (path,meta) in found {
+            match meta {
+                Some(m)=>result.push((path,m)),
+                // A literal last component: check that it exists.
+                None=>match self.lstat(&path) {
+                    Ok(m)=>{
+                        let name=path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+                        result.push((path,Metadata { raw_name:name.as_bytes().to_vec(),name,..m }))
+                    },
+                    Err(Error::IO(_))=>(),
+                    Err(e)=>return Err(e)
+                }
+            }
+        }
+        result.sort_by(|a,b| a.0.cmp(&b.0));
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::matches;
+
+    fn m(pattern:&str,name:&str)->bool {
+        let p:Vec<char>=pattern.chars().collect();
+        let n:Vec<char>=name.chars().collect();
+        matches(&p,&n)
+    }
+
+    #[test]
+    fn leading_dot() {
+        assert!(!m("*",".profile"));
+        assert!(!m("?profile",".profile"));
+        assert!(!m("[.]profile",".profile"));
+        assert!(m(".*",".profile"));
+        assert!(m("\\.profile",".profile"));
+        assert!(m("*.gz","a.b.gz"));
+    }
+
+    #[test]
+    fn classes() {
+        assert!(m("[a-c]x","bx"));
+        assert!(!m("[a-c]x","dx"));
+        assert!(m("[!0-9]","a"));
+        assert!(!m("[!0-9]","5"));
+        assert!(m("[^0-9]","a"));
+        // `]` first in a class, possibly after `!`, is an ordinary character.
+        assert!(m("[]a]","]"));
+        assert!(m("[]a]","a"));
+        assert!(!m("[!]]","]"));
+        assert!(m("[!]]","b"));
+        // A `-` last in the class is an ordinary character.
+        assert!(m("[a-]","-"));
+    }
+
+    #[test]
+    fn unclosed_class() {
+        assert!(m("[ab","[ab"));
+        assert!(!m("[ab","a"));
+        assert!(m("x[*","x[yz"));
+    }
+
+    #[test]
+    fn escapes() {
+        assert!(m("\\*","*"));
+        assert!(!m("\\*","a"));
+        assert!(m("a\\?","a?"));
+        assert!(!m("a\\?","ab"));
+        assert!(m("\\[a]","[a]"));
+        // A trailing `\` matches itself.
+        assert!(m("a\\","a\\"));
+    }
+
+    #[test]
+    fn star_backtracking() {
+        assert!(m("*.tar.gz","a.tar.tar.gz"));
+        assert!(m("a*b*c","aXbYbZc"));
+        assert!(!m("a*b*c","aXbYbZ"));
+        assert!(m("*","x"));
+        assert!(m("**a","bba"));
+        assert!(m("a*","a"));
+        assert!(m("*a?","ab"));
+        assert!(!m("*a?","ba"));
+        assert!(m("*a?","xxaab"));
+    }
+}
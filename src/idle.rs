@@ -0,0 +1,81 @@
+//! Closing sessions left idle, for services (such as those behind bastions) that must not hold connections open indefinitely.
+
+use std::cell::Cell;
+use std::time::{Duration,Instant};
+
+use super::{Error,Session};
+
+/// The idle state of a session.
+pub(crate) struct Idle {
+    /// When the session was last used.
+    last:Cell<Instant>,
+    timeout:Option<Duration>,
+    notify:Option<Box<dyn FnMut(Duration)+Send>>
+}
+
+impl Default for Idle {
+    fn default()->Idle {
+        Idle { last:Cell::new(Instant::now()),timeout:None,notify:None }
+    }
+}
+
+impl Session {
+    /// Record a use of the session, restarting the idle time.
+    pub(crate) fn touch(&self) {
+        self.idle.last.set(Instant::now())
+    }
+    /// Record the end of a channel, SCP transfer or SFTP session borrowing this session.
+    pub(crate) fn release_child(&self) {
+        self.children.set(self.children.get()-1);
+        self.touch()
+    }
+    /// Disconnect the session once it has been idle for `timeout` (or never, with `None`, the default): see `check_idle`.
+    pub fn set_idle_timeout(&mut self,timeout:Option<Duration>) {
+        self.idle.timeout=timeout
+    }
+    /// Call `f` with the idle time when `check_idle` disconnects the session.
+    pub fn on_idle<F:FnMut(Duration)+Send+'static>(&mut self,f:F) {
+        self.idle.notify=Some(Box::new(f))
+    }
+    /// How long the session has been idle: since it was connected or authenticated, or since its last channel, SCP transfer or SFTP session was closed.
+    pub fn idle_time(&self)->Duration {
+        self.idle.last.get().elapsed()
+    }
+    /// If the session has been idle for longer than the timeout given to `set_idle_timeout`, disconnect it cleanly (telling the server), call the function given to `on_idle`, and return `true`. Applications holding idle sessions call this periodically, or before reusing a session.
+    ///
+    ///```no_run
+    /// use ssh::*;
+    /// use std::time::Duration;
+    ///
+    /// let mut session=Session::new().unwrap();
+    /// session.set_host("pijul.org").unwrap();
+    /// session.parse_config(None).unwrap();
+    /// session.connect().unwrap();
+    /// session.userauth_publickey_auto(None).unwrap();
+    /// session.set_idle_timeout(Some(Duration::from_secs(15*60)));
+    /// session.on_idle(|idle| eprintln!("disconnected after {:?} without activity",idle));
+    /// loop {
+    ///     std::thread::sleep(Duration::from_secs(60));
+    ///     if session.check_idle().unwrap() {
+    ///         break
+    ///     }
+    /// }
+    ///```
+    pub fn check_idle(&mut self)->Result<bool,Error> {
+        let timeout=match self.idle.timeout {
+            Some(t)=>t,
+            None=>return Ok(false)
+        };
+        let idle=self.idle_time();
+        if idle<timeout || !self.is_connected() {
+            return Ok(false)
+        }
+        debug!("session idle for {:?}, disconnecting",idle);
+        let _=self.blocking_flush(super::FLUSH_TIMEOUT);
+        self.disconnect()?;
+        if let Some(ref mut f)=self.idle.notify {
+            f(idle)
+        }
+        Ok(true)
+    }
+}
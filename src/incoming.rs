@@ -0,0 +1,85 @@
+//! Channels opened by the server: X11 connections, agent connections, and connections to remotely forwarded ports.
+
+use std::time::{Duration,Instant};
+
+use super::libc::c_int;
+use super::{Channel,Channel_,Error,Session,err,SSH_OK};
+
+extern "C" {
+    fn ssh_channel_accept_x11(s:*mut Channel_,timeout_ms:c_int)->*mut Channel_;
+    fn ssh_channel_accept_forward(s:*mut super::Session_,timeout_ms:c_int,port:*mut c_int)->*mut Channel_;
+    fn ssh_channel_request_auth_agent(c:*mut Channel_)->c_int;
+}
+
+/// The kind of an incoming channel.
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum IncomingKind {
+    /// An X11 connection, after X11 forwarding was requested on a session channel.
+    X11,
+    /// A connection to the local ssh-agent, after `Channel::request_auth_agent`.
+    AuthAgent,
+    /// A connection to a port forwarded from the server, with the number of that port.
+    ForwardedTcpip(u16)
+}
+
+/// A channel opened by the server, returned by `accept_incoming`.
+pub struct IncomingChannel<'b> {
+    pub kind:IncomingKind,
+    pub channel:Channel<'b>
+}
+
+/// Wait up to `timeout` for a channel opened by the server. X11 channels are only accepted if `x11` is the session channel on which X11 forwarding was requested.
+fn accept<'b>(session:&'b Session,x11:Option<*mut Channel_>,timeout:Duration)->Result<Option<IncomingChannel<'b>>,Error> {
+    let deadline=Instant::now()+timeout;
+    loop {
+        let agent=session.callbacks.as_ref().and_then(|r| r.agent_channels.borrow_mut().pop());
+        // libssh waits up to 50ms for packets in each of these calls.
+        let (kind,c)=if let Some(c)=agent {
+            (IncomingKind::AuthAgent,c)
+        } else {
+            let mut port=0;
+            let c=unsafe { ssh_channel_accept_forward(session.session,0,&mut port) };
+            if !c.is_null() {
+                (IncomingKind::ForwardedTcpip(port as u16),c)
+            } else {
+                (IncomingKind::X11,match x11 {
+                    Some(x11)=>unsafe { ssh_channel_accept_x11(x11,0) },
+                    None=>std::ptr::null_mut()
+                })
+            }
+        };
+        if !c.is_null() {
+            session.children.set(session.children.get()+1);
+            return Ok(Some(IncomingChannel { kind,channel:Channel { session,channel:c } }))
+        }
+        if !session.is_connected() {
+            return Err(err(session))
+        }
+        if Instant::now()>=deadline {
+            return Ok(None)
+        }
+    }
+}
+
+impl<'b> Channel<'b> {
+    /// Ask the server to forward connections to the agent (as `ssh -A`). The agent channels opened by the server are then returned by `accept_incoming`. `Session::enable_agent_forwarding` must have been called before connecting.
+    pub fn request_auth_agent(&mut self)->Result<(),Error> {
+        let e=unsafe { ssh_channel_request_auth_agent(self.channel) };
+        if e==SSH_OK { Ok(()) } else { Err(err(self.session)) }
+    }
+    /// Wait up to `timeout` for the server to open a channel (X11 connection for this channel, agent connection, or connection to a forwarded port), and return it. Returns `Ok(None)` on timeout.
+    pub fn accept_incoming(&mut self,timeout:Duration)->Result<Option<IncomingChannel<'b>>,Error> {
+        accept(self.session,Some(self.channel),timeout)
+    }
+}
+
+impl Session {
+    /// Install what libssh needs to accept agent channels from the server. This must be called before `connect` if `Channel::request_auth_agent` is to be used.
+    pub fn enable_agent_forwarding(&mut self)->Result<(),Error> {
+        self.ensure_callbacks()
+    }
+    /// Wait up to `timeout` for the server to open a channel (agent connection, or connection to a forwarded port), and return it. Returns `Ok(None)` on timeout. X11 channels are accepted by `Channel::accept_incoming`, on the channel that requested X11 forwarding.
+    pub fn accept_incoming<'b>(&'b mut self,timeout:Duration)->Result<Option<IncomingChannel<'b>>,Error> {
+        accept(self,None,timeout)
+    }
+}
@@ -0,0 +1,85 @@
+//! Interrupting the blocking calls of a session from another thread or from a signal handler, for instance to abort cleanly on Ctrl-C instead of waiting for a timeout.
+//!
+//! An `Interrupter` wakes a thread of the session up by writing a byte to a socket pair (the self-pipe trick); that thread then shuts the connection down, which makes the libssh call in progress return.
+
+use std::io::Read;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::UnixStream;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::thread;
+
+use super::libc::{self,c_void};
+use super::{Error,Session};
+
+const INTERRUPT:u8=b'i';
+const QUIT:u8=b'q';
+
+/// Interrupts a session. Clones interrupt the same session.
+///
+///```no_run
+/// use ssh::*;
+///
+/// let mut session=Session::new().unwrap();
+/// session.set_host("pijul.org").unwrap();
+/// let interrupter=session.interrupter().unwrap();
+/// std::thread::spawn(move || {
+///     std::thread::sleep(std::time::Duration::from_secs(10));
+///     interrupter.interrupt()
+/// });
+/// session.parse_config(None).unwrap();
+/// session.connect().unwrap();
+/// session.userauth_publickey_auto(None).unwrap();
+/// match session.exec("sleep 60") {
+///     Err(Error::Interrupted)=>println!("interrupted"),
+///     r=>println!("{:?}",r)
+/// }
+///```
+#[derive(Debug,Clone)]
+pub struct Interrupter {
+    tx:Arc<UnixStream>
+}
+
+impl Interrupter {
+    /// Make the blocking call in progress on the session, if any, and all later ones, fail with `Error::Interrupted`. The connection is shut down, so the session can't be used afterwards.
+    ///
+    /// This only calls `send`, which is async-signal-safe, so it can be called from a signal handler (with the interrupter stored in a static beforehand). It does nothing once the session is dropped.
+    pub fn interrupt(&self) {
+        self.send(INTERRUPT)
+    }
+    pub(crate) fn quit(&self) {
+        self.send(QUIT)
+    }
+    fn send(&self,b:u8) {
+        unsafe {
+            libc::send(self.tx.as_raw_fd(),&b as *const u8 as *const c_void,1,libc::MSG_NOSIGNAL|libc::MSG_DONTWAIT);
+        }
+    }
+}
+
+impl Session {
+    /// An interrupter for the blocking calls of this session. It can be created before connecting.
+    pub fn interrupter(&mut self)->Result<Interrupter,Error> {
+        if let Some(ref i)=self.watchdog.interrupter {
+            return Ok(i.clone())
+        }
+        let (tx,mut rx)=UnixStream::pair()?;
+        let (socket,interrupted)=(self.watchdog.socket.clone(),self.watchdog.interrupted.clone());
+        thread::spawn(move || {
+            let mut b=[0];
+            while let Ok(1)=rx.read(&mut b) {
+                match b[0] {
+                    INTERRUPT=>{
+                        debug!("session interrupted");
+                        interrupted.store(true,Ordering::SeqCst);
+                        socket.shutdown()
+                    },
+                    _=>break
+                }
+            }
+        });
+        let i=Interrupter { tx:Arc::new(tx) };
+        self.watchdog.interrupter=Some(i.clone());
+        Ok(i)
+    }
+}
@@ -0,0 +1,250 @@
+//! Public keys, as handled by libssh.
+
+use std::ffi::{CStr,CString};
+use std::fmt;
+
+use super::libc::{c_char,c_int,c_void,size_t};
+use super::{Error,SecurityEvent,Session,Session_,err,ssh_clean_pubkey_hash,ssh_string_free_char};
+
+#[allow(missing_copy_implementations)]
+pub(crate) enum Key_ {}
+#[allow(missing_copy_implementations)]
+pub(crate) enum String_ {}
+
+extern "C" {
+    fn ssh_key_free(k:*mut Key_);
+    fn ssh_key_type(k:*const Key_)->c_int;
+    fn ssh_key_type_to_char(t:c_int)->*const c_char;
+    fn ssh_key_type_from_name(name:*const c_char)->c_int;
+    fn ssh_key_cmp(k1:*const Key_,k2:*const Key_,what:c_int)->c_int;
+    fn ssh_pki_import_pubkey_blob(blob:*const String_,k:*mut *mut Key_)->c_int;
+    fn ssh_pki_import_pubkey_base64(b64:*const c_char,t:c_int,k:*mut *mut Key_)->c_int;
+    fn ssh_pki_export_pubkey_blob(k:*const Key_,blob:*mut *mut String_)->c_int;
+    fn ssh_pki_export_pubkey_base64(k:*const Key_,b64:*mut *mut c_char)->c_int;
+    fn ssh_get_publickey_hash(k:*const Key_,t:c_int,hash:*mut *mut u8,len:*mut size_t)->c_int;
+    fn ssh_get_fingerprint_hash(t:c_int,hash:*mut u8,len:size_t)->*mut c_char;
+    fn ssh_string_new(len:size_t)->*mut String_;
+    fn ssh_string_fill(s:*mut String_,data:*const c_void,len:size_t)->c_int;
+    fn ssh_string_free(s:*mut String_);
+    fn ssh_string_len(s:*const String_)->size_t;
+    fn ssh_string_data(s:*const String_)->*const c_void;
+    fn ssh_get_server_publickey(s:*mut Session_,k:*mut *mut Key_)->c_int;
+}
+
+const SSH_OK:c_int=0;
+const SSH_KEY_CMP_PUBLIC:c_int=0;
+
+/// Hash functions used for fingerprints.
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum HashType {
+    Sha1=0,
+    Md5=1,
+    Sha256=2
+}
+
+/// A public key (of a server, or of an identity).
+pub struct PublicKey {
+    pub(crate) key:*mut Key_
+}
+
+unsafe impl Send for PublicKey {}
+
+impl PublicKey {
+    /// Parse a key in the SSH wire format (as sent by servers and agents).
+    pub fn from_blob(blob:&[u8])->Result<PublicKey,Error> {
+        unsafe {
+            let s=ssh_string_new(blob.len() as size_t);
+            if s.is_null() {
+                return Err(Error::Ssh("Could not allocate a string".to_string()))
+            }
+            ssh_string_fill(s,blob.as_ptr() as *const c_void,blob.len() as size_t);
+            let mut key=std::ptr::null_mut();
+            let e=ssh_pki_import_pubkey_blob(s,&mut key);
+            ssh_string_free(s);
+            if e==SSH_OK && !key.is_null() {
+                Ok(PublicKey { key })
+            } else {
+                Err(Error::Ssh("Could not parse the public key".to_string()))
+            }
+        }
+    }
+    /// Parse a key given by its type name and base64 encoding, as in `authorized_keys` and `known_hosts` files (for instance `"ssh-ed25519"` and `"AAAAC3Nza…"`).
+    pub fn from_base64(key_type:&str,base64:&str)->Result<PublicKey,Error> {
+        let t=CString::new(key_type)?;
+        let b=CString::new(base64)?;
+        let mut key=std::ptr::null_mut();
+        let e=unsafe { ssh_pki_import_pubkey_base64(b.as_ptr(),ssh_key_type_from_name(t.as_ptr()),&mut key) };
+        if e==SSH_OK && !key.is_null() {
+            Ok(PublicKey { key })
+        } else {
+            Err(Error::Ssh(format!("Could not parse the {} public key",key_type)))
+        }
+    }
+    /// Name of the key type, such as `"ssh-ed25519"` or `"ssh-rsa"`.
+    pub fn key_type(&self)->&'static str {
+        unsafe {
+            let t=ssh_key_type_to_char(ssh_key_type(self.key));
+            if t.is_null() {
+                "unknown"
+            } else {
+                CStr::from_ptr(t).to_str().unwrap_or("unknown")
+            }
+        }
+    }
+    /// Whether `fingerprint`, formatted as by OpenSSH, is that of this key. SHA256 fingerprints may omit the `SHA256:` prefix and the final `=` padding.
+    pub fn matches_fingerprint(&self,fingerprint:&str)->bool {
+        let fingerprint=fingerprint.trim();
+        if let Some(md5)=fingerprint.strip_prefix("MD5:") {
+            return self.fingerprint(HashType::Md5).map(|f| f.trim_start_matches("MD5:").eq_ignore_ascii_case(md5)).unwrap_or(false)
+        }
+        if let Some(sha1)=fingerprint.strip_prefix("SHA1:") {
+            return self.fingerprint(HashType::Sha1).map(|f| f.trim_start_matches("SHA1:").trim_end_matches('=')==sha1.trim_end_matches('=')).unwrap_or(false)
+        }
+        let sha256=fingerprint.trim_start_matches("SHA256:").trim_end_matches('=');
+        self.fingerprint(HashType::Sha256).map(|f| f.trim_start_matches("SHA256:").trim_end_matches('=')==sha256).unwrap_or(false)
+    }
+    /// Whether this is the key of a FIDO security key (`sk-ecdsa-sha2-nistp256@openssh.com` or `sk-ssh-ed25519@openssh.com`), whose private part never leaves the hardware. libssh knows these types from version 0.10.
+    pub fn is_security_key(&self)->bool {
+        self.key_type().starts_with("sk-")
+    }
+    /// Read a public key file, in the format of `id_ed25519.pub` and `authorized_keys` lines: the key type, the key in base64, and an optional comment.
+    pub fn from_file<P:AsRef<std::path::Path>>(path:P)->Result<PublicKey,Error> {
+        let s=std::fs::read_to_string(path.as_ref())?;
+        let mut fields=s.split_whitespace();
+        match (fields.next(),fields.next()) {
+            (Some(t),Some(b64))=>PublicKey::from_base64(t,b64),
+            _=>Err(Error::Ssh(format!("{} is not a public key file",path.as_ref().display())))
+        }
+    }
+    /// The key in the SSH wire format.
+    pub fn to_blob(&self)->Result<Vec<u8>,Error> {
+        unsafe {
+            let mut s=std::ptr::null_mut();
+            if ssh_pki_export_pubkey_blob(self.key,&mut s)!=SSH_OK || s.is_null() {
+                return Err(Error::Ssh("Could not export the public key".to_string()))
+            }
+            let v=std::slice::from_raw_parts(ssh_string_data(s) as *const u8,ssh_string_len(s)).to_vec();
+            ssh_string_free(s);
+            Ok(v)
+        }
+    }
+    /// The base64 encoding of the key, as in `authorized_keys` files (without the type name).
+    pub fn to_base64(&self)->Result<String,Error> {
+        unsafe {
+            let mut b=std::ptr::null_mut();
+            if ssh_pki_export_pubkey_base64(self.key,&mut b)!=SSH_OK || b.is_null() {
+                return Err(Error::Ssh("Could not export the public key".to_string()))
+            }
+            let s=CStr::from_ptr(b).to_string_lossy().into_owned();
+            ssh_string_free_char(b);
+            Ok(s)
+        }
+    }
+    /// Raw hash of the key.
+    pub fn hash(&self,t:HashType)->Result<Vec<u8>,Error> {
+        unsafe {
+            let mut h=std::ptr::null_mut();
+            let mut len=0;
+            if ssh_get_publickey_hash(self.key,t as c_int,&mut h,&mut len)!=SSH_OK || h.is_null() {
+                return Err(Error::Ssh("Could not hash the public key".to_string()))
+            }
+            let v=std::slice::from_raw_parts(h,len).to_vec();
+            ssh_clean_pubkey_hash(&mut h);
+            Ok(v)
+        }
+    }
+    /// The fingerprint of the key, formatted as by OpenSSH (for instance `"SHA256:uNiVztksCsDhcc0u9e8BujQXVUpKZIDTMczCvj3tD2s"`).
+    pub fn fingerprint(&self,t:HashType)->Result<String,Error> {
+        unsafe {
+            let mut h=std::ptr::null_mut();
+            let mut len=0;
+            if ssh_get_publickey_hash(self.key,t as c_int,&mut h,&mut len)!=SSH_OK || h.is_null() {
+                return Err(Error::Ssh("Could not hash the public key".to_string()))
+            }
+            let f=ssh_get_fingerprint_hash(t as c_int,h,len);
+            ssh_clean_pubkey_hash(&mut h);
+            if f.is_null() {
+                return Err(Error::Ssh("Could not format the fingerprint".to_string()))
+            }
+            let s=CStr::from_ptr(f).to_string_lossy().into_owned();
+            ssh_string_free_char(f);
+            Ok(s)
+        }
+    }
+}
+
+impl PartialEq for PublicKey {
+    fn eq(&self,other:&PublicKey)->bool {
+        unsafe { ssh_key_cmp(self.key,other.key,SSH_KEY_CMP_PUBLIC)==0 }
+    }
+}
+
+impl fmt::Debug for PublicKey {
+    fn fmt(&self,f:&mut fmt::Formatter)->fmt::Result {
+        match self.fingerprint(HashType::Sha256) {
+            Ok(fp)=>write!(f,"PublicKey({} {})",self.key_type(),fp),
+            Err(_)=>write!(f,"PublicKey({})",self.key_type())
+        }
+    }
+}
+
+impl Drop for PublicKey {
+    fn drop(&mut self) {
+        unsafe { ssh_key_free(self.key) }
+    }
+}
+
+impl Session {
+    /// The host key presented by the server, once connected.
+    pub fn server_public_key(&mut self)->Result<PublicKey,Error> {
+        self.get_server_public_key()
+    }
+    pub(crate) fn get_server_public_key(&self)->Result<PublicKey,Error> {
+        let mut key=std::ptr::null_mut();
+        let e=unsafe { ssh_get_server_publickey(self.session,&mut key) };
+        if e==SSH_OK && !key.is_null() {
+            Ok(PublicKey { key })
+        } else {
+            Err(err(self))
+        }
+    }
+    /// Only accept servers whose host key has this fingerprint, or one of the other fingerprints given by previous calls: `connect` fails with `Error::HostKeyMismatch` for other keys, and the known hosts files are not used. This is for appliances that publish the fingerprints of their keys.
+    ///
+    /// Fingerprints are written as by OpenSSH, such as `"SHA256:uNiVztksCsDhcc0u9e8BujQXVUpKZIDTMczCvj3tD2s"` or `"MD5:16:27:ac:a5:76:28:2d:36:63:1b:56:4d:eb:df:a6:48"`.
+    ///
+    ///```no_run
+    /// use ssh::*;
+    ///
+    /// let mut session=Session::new().unwrap();
+    /// session.set_host("pijul.org").unwrap();
+    /// session.expect_host_key("SHA256:uNiVztksCsDhcc0u9e8BujQXVUpKZIDTMczCvj3tD2s");
+    /// session.connect().unwrap();
+    /// session.userauth_publickey_auto(None).unwrap();
+    ///```
+    pub fn expect_host_key(&mut self,fingerprint:&str) {
+        self.expected_host_keys.push(fingerprint.to_string())
+    }
+    /// Whether host keys are checked against fingerprints given to `expect_host_key`, instead of known hosts files.
+    pub fn has_expected_host_keys(&self)->bool {
+        !self.expected_host_keys.is_empty()
+    }
+    /// After connecting, disconnect unless the host key has one of the expected fingerprints.
+    pub(crate) fn check_expected_host_key(&self)->Result<(),Error> {
+        if self.expected_host_keys.is_empty() {
+            return Ok(())
+        }
+        let key=self.get_server_public_key()?;
+        if self.expected_host_keys.iter().any(|f| key.matches_fingerprint(f)) {
+            return Ok(())
+        }
+        let fingerprint=key.fingerprint(HashType::Sha256)?;
+        self.security_event(SecurityEvent::HostKeyChanged {
+            host:self.host().unwrap_or_default(),
+            port:self.port(),
+            old:self.expected_host_keys.clone(),
+            new:fingerprint.clone()
+        });
+        self.drop_connection();
+        Err(Error::HostKeyMismatch(fingerprint))
+    }
+}
@@ -0,0 +1,900 @@
+//! Reading and editing `known_hosts` files, in the format of OpenSSH.
+//!
+//! This is what `ssh-keygen -R` and `ssh-keygen -H` do: remove the keys of a reprovisioned host, add keys obtained out-of-band, or hash host names so that the file doesn't reveal which hosts were visited. Comments and unrecognised lines are preserved.
+//!
+//!```no_run
+//! use ssh::known_hosts::KnownHosts;
+//!
+//! let mut kh=KnownHosts::open("/home/me/.ssh/known_hosts").unwrap();
+//! kh.remove_host("server.example.com",22);
+//! kh.hash().unwrap();
+//! kh.save().unwrap();
+//!```
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io::Write;
+use std::path::{Path,PathBuf};
+use std::sync::atomic::{AtomicUsize,Ordering};
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use hmac::{Hmac,Mac};
+use sha1::Sha1;
+
+use super::{Error,HashType,HostKeyPolicy,PublicKey,ServerKnown,Session,SshOptions,ssh_options_set,err,SSH_OK};
+use super::libc::{c_int,c_void};
+use super::config::verify_host_key;
+
+/// One host key line of a known_hosts file.
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub struct Entry {
+    /// `@cert-authority` or `@revoked`, if present.
+    pub marker:Option<String>,
+    /// Host patterns: names, `[name]:port`, wildcards, negations (`!name`), or hashed names (`|1|salt|hash`).
+    pub hosts:Vec<String>,
+    pub key_type:String,
+    /// Base64 encoding of the key.
+    pub key:String,
+    pub comment:Option<String>
+}
+
+#[derive(Debug,Clone,PartialEq,Eq)]
+enum Line {
+    Entry(Entry),
+    /// Comments, blank lines, and lines we don't understand.
+    Other(String)
+}
+
+/// The contents of a known_hosts file.
+#[derive(Debug,Clone,Default)]
+pub struct KnownHosts {
+    path:Option<PathBuf>,
+    lines:Vec<Line>
+}
+
+/// The host name as written in known_hosts files: `host`, or `[host]:port` for non-standard ports.
+pub fn host_pattern(host:&str,port:u16)->String {
+    if port==22 {
+        host.to_string()
+    } else {
+        format!("[{}]:{}",host,port)
+    }
+}
+
+fn hmac_sha1(salt:&[u8],name:&str)->Vec<u8> {
+    let mut mac=<Hmac<Sha1> as Mac>::new_from_slice(salt).expect("HMAC accepts keys of any length");
+    mac.update(name.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Hash a host name (as returned by `host_pattern`) the way `HashKnownHosts` does.
+pub fn hash_host(name:&str)->Result<String,Error> {
+    let mut salt=[0;20];
+    getrandom::getrandom(&mut salt).map_err(|e| Error::IO(std::io::Error::other(e.to_string())))?;
+    Ok(format!("|1|{}|{}",BASE64.encode(salt),BASE64.encode(hmac_sha1(&salt,name))))
+}
+
+/// Match `name` against a pattern with `*` and `?` wildcards.
+fn wildcard_match(pattern:&[u8],name:&[u8])->bool {
+    match pattern.split_first() {
+        None=>name.is_empty(),
+        Some((&b'*',rest))=>(0..=name.len()).any(|i| wildcard_match(rest,&name[i..])),
+        Some((&b'?',rest))=>!name.is_empty() && wildcard_match(rest,&name[1..]),
+        Some((&c,rest))=>match name.split_first() {
+            Some((&d,name_rest))=>c.eq_ignore_ascii_case(&d) && wildcard_match(rest,name_rest),
+            None=>false
+        }
+    }
+}
+
+/// Whether a single (non-negated) pattern matches `name`.
+fn pattern_matches(pattern:&str,name:&str)->bool {
+    if let Some(hashed)=pattern.strip_prefix("|1|") {
+        let mut parts=hashed.splitn(2,'|');
+        match (parts.next().map(|s| BASE64.decode(s)),parts.next().map(|s| BASE64.decode(s))) {
+            (Some(Ok(salt)),Some(Ok(hash)))=>hmac_sha1(&salt,name)==hash,
+            _=>false
+        }
+    } else {
+        wildcard_match(pattern.as_bytes(),name.as_bytes())
+    }
+}
+
+impl Entry {
+    /// Whether this entry applies to `host` on `port`. As in OpenSSH, a matching negated pattern excludes the host even if another pattern matches.
+    pub fn matches(&self,host:&str,port:u16)->bool {
+        let name=host_pattern(host,port);
+        let mut matched=false;
+        for p in self.hosts.iter() {
+            if let Some(p)=p.strip_prefix('!') {
+                if pattern_matches(p,&name) {
+                    return false
+                }
+            } else if pattern_matches(p,&name) {
+                matched=true
+            }
+        }
+        matched
+    }
+    /// Parse the key of this entry.
+    pub fn public_key(&self)->Result<PublicKey,Error> {
+        PublicKey::from_base64(&self.key_type,&self.key)
+    }
+    fn parse(line:&str)->Option<Entry> {
+        let mut fields=line.split_whitespace();
+        let mut first=fields.next()?;
+        let marker=if first.starts_with('@') {
+            let m=first.to_string();
+            first=fields.next()?;
+            Some(m)
+        } else {
+            None
+        };
+        let key_type=fields.next()?.to_string();
+        let key=fields.next()?.to_string();
+        let comment:Vec<&str>=fields.collect();
+        Some(Entry {
+            marker,
+            hosts:first.split(',').map(|h| h.to_string()).collect(),
+            key_type,
+            key,
+            comment:if comment.is_empty() { None } else { Some(comment.join(" ")) }
+        })
+    }
+}
+
+impl fmt::Display for Entry {
+    fn fmt(&self,f:&mut fmt::Formatter)->fmt::Result {
+        if let Some(ref m)=self.marker {
+            write!(f,"{} ",m)?
+        }
+        write!(f,"{} {} {}",self.hosts.join(","),self.key_type,self.key)?;
+        if let Some(ref c)=self.comment {
+            write!(f," {}",c)?
+        }
+        Ok(())
+    }
+}
+
+impl KnownHosts {
+    /// An empty file, not associated to any path.
+    pub fn new()->KnownHosts {
+        KnownHosts::default()
+    }
+    /// Read the file at `path`. A missing file is treated as empty, and will be created by `save`.
+    pub fn open<P:AsRef<Path>>(path:P)->Result<KnownHosts,Error> {
+        let path=path.as_ref();
+        let contents=match fs::read_to_string(path) {
+            Ok(s)=>s,
+            Err(ref e) if e.kind()==std::io::ErrorKind::NotFound=>String::new(),
+            Err(e)=>return Err(e.into())
+        };
+        let mut kh=KnownHosts::parse(&contents);
+        kh.path=Some(path.to_path_buf());
+        Ok(kh)
+    }
+    /// Parse the contents of a known_hosts file.
+    pub fn parse(contents:&str)->KnownHosts {
+        KnownHosts {
+            path:None,
+            lines:contents.lines().map(|l| {
+                let t=l.trim();
+                if t.is_empty() || t.starts_with('#') {
+                    Line::Other(l.to_string())
+                } else {
+                    match Entry::parse(t) {
+                        Some(e)=>Line::Entry(e),
+                        None=>Line::Other(l.to_string())
+                    }
+                }
+            }).collect()
+        }
+    }
+    /// All the entries of the file.
+    pub fn entries(&self)->impl Iterator<Item=&Entry> {
+        self.lines.iter().filter_map(|l| match *l { Line::Entry(ref e)=>Some(e), _=>None })
+    }
+    /// The entries that apply to `host` on `port`.
+    pub fn lookup<'a>(&'a self,host:&'a str,port:u16)->impl Iterator<Item=&'a Entry>+'a {
+        self.entries().filter(move |e| e.matches(host,port))
+    }
+    /// Same as `lookup`, with the line numbers (starting at 1) of the entries.
+    pub fn lookup_lines<'a>(&'a self,host:&'a str,port:u16)->impl Iterator<Item=(usize,&'a Entry)>+'a {
+        self.lines.iter().enumerate().filter_map(move |(i,l)| match *l {
+            Line::Entry(ref e) if e.matches(host,port)=>Some((i+1,e)),
+            _=>None
+        })
+    }
+    /// Add an entry for `host` on `port`, with the given key type and base64-encoded key. If `hash` is true, the host name is hashed.
+    pub fn add(&mut self,host:&str,port:u16,key_type:&str,key:&str,hash:bool)->Result<(),Error> {
+        let name=host_pattern(host,port);
+        let name=if hash { hash_host(&name)? } else { name };
+        self.lines.push(Line::Entry(Entry {
+            marker:None,
+            hosts:vec![name],
+            key_type:key_type.to_string(),
+            key:key.to_string(),
+            comment:None
+        }));
+        Ok(())
+    }
+    /// Add an entry for `host` on `port` with `key`.
+    pub fn add_key(&mut self,host:&str,port:u16,key:&PublicKey,hash:bool)->Result<(),Error> {
+        let b64=key.to_base64()?;
+        self.add(host,port,key.key_type(),&b64,hash)
+    }
+    /// Remove `host` from the file, as `ssh-keygen -R`: entries listing only this host are deleted, and the host is removed from entries listing several hosts. Returns the number of entries changed or deleted.
+    pub fn remove_host(&mut self,host:&str,port:u16)->usize {
+        let name=host_pattern(host,port);
+        let mut changed=0;
+        self.lines.retain_mut(|l| match *l {
+            Line::Entry(ref mut e)=>{
+                let before=e.hosts.len();
+                e.hosts.retain(|p| p.starts_with('!') || !pattern_matches(p,&name));
+                if e.hosts.len()<before {
+                    changed+=1
+                }
+                e.hosts.iter().any(|p| !p.starts_with('!'))
+            },
+            _=>true
+        });
+        changed
+    }
+    /// Remove every entry with this key, for whatever host. Returns the number of entries deleted.
+    pub fn remove_key(&mut self,key_type:&str,key:&str)->usize {
+        let before=self.lines.len();
+        self.lines.retain(|l| match *l {
+            Line::Entry(ref e)=>!(e.key_type==key_type && e.key==key),
+            _=>true
+        });
+        before-self.lines.len()
+    }
+    /// Hash all the host names of the file (as `ssh-keygen -H`). A hashed entry holds a single host, so entries listing several hosts are split into one entry per host, with the same marker, key and comment. Entries with wildcards or negations are left untouched, since they cannot be hashed.
+    pub fn hash(&mut self)->Result<(),Error> {
+        let mut lines=Vec::with_capacity(self.lines.len());
+        for l in self.lines.drain(..) {
+            match l {
+                Line::Entry(ref e) if !e.hosts.iter().any(|p| p.contains(['*','?','!']))=>{
+                    for p in e.hosts.iter() {
+                        let host=if p.starts_with("|1|") { p.clone() } else { hash_host(p)? };
+                        lines.push(Line::Entry(Entry { hosts:vec![host],..e.clone() }))
+                    }
+                },
+                l=>lines.push(l)
+            }
+        }
+        self.lines=lines;
+        Ok(())
+    }
+    /// Write the file back to the path it was opened from, atomically (through a temporary file in the same directory).
+    pub fn save(&self)->Result<(),Error> {
+        match self.path {
+            Some(ref p)=>self.save_to(p),
+            None=>Err(Error::Ssh("This known_hosts file has no path".to_string()))
+        }
+    }
+    /// Write the file to `path`, atomically.
+    pub fn save_to<P:AsRef<Path>>(&self,path:P)->Result<(),Error> {
+        let path=path.as_ref();
+        let mut tmp=path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let tmp=PathBuf::from(tmp);
+        {
+            let mut f=fs::File::create(&tmp)?;
+            write!(f,"{}",self)?;
+            f.sync_all()?;
+        }
+        fs::rename(&tmp,path)?;
+        Ok(())
+    }
+}
+
+impl fmt::Display for KnownHosts {
+    fn fmt(&self,f:&mut fmt::Formatter)->fmt::Result {
+        for l in self.lines.iter() {
+            match *l {
+                Line::Entry(ref e)=>writeln!(f,"{}",e)?,
+                Line::Other(ref s)=>writeln!(f,"{}",s)?
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Host key algorithms asked for by `keyscan` when no key type is given.
+pub const DEFAULT_KEYSCAN_TYPES:&[&str]=&["ssh-ed25519","ecdsa-sha2-nistp256","ecdsa-sha2-nistp384","ecdsa-sha2-nistp521","rsa-sha2-512"];
+
+/// Fetch the host keys of a server without authenticating, as `ssh-keyscan` does, for instance to populate a known_hosts file ahead of time. One connection is made per host key algorithm in `key_types` (`DEFAULT_KEYSCAN_TYPES` if empty); algorithms the server doesn't support are skipped.
+///
+/// The keys are *not* verified in any way: they should only be trusted if the network between this host and the server is.
+///
+///```no_run
+/// use ssh::known_hosts::{KnownHosts,keyscan};
+///
+/// let mut kh=KnownHosts::new();
+/// for key in keyscan("pijul.org",22,&[]).unwrap() {
+///     kh.add_key("pijul.org",22,&key,false).unwrap()
+/// }
+/// print!("{}",kh);
+///```
+pub fn keyscan(host:&str,port:u16,key_types:&[&str])->Result<Vec<PublicKey>,Error> {
+    let key_types=if key_types.is_empty() { DEFAULT_KEYSCAN_TYPES } else { key_types };
+    let mut keys:Vec<PublicKey>=Vec::new();
+    let mut last_error=None;
+    for t in key_types {
+        let mut session=Session::new().map_err(|_| Error::Ssh("Could not allocate a session".to_string()))?;
+        session.set_host(host)?;
+        session.set_port(port as usize)?;
+        let t=std::ffi::CString::new(*t)?;
+        let e=unsafe { ssh_options_set(session.session,SshOptions::HOSTKEYS as c_int,t.as_ptr() as *const c_void) };
+        if e!=SSH_OK {
+            return Err(err(&session))
+        }
+        match session.connect().and_then(|_| session.server_public_key()) {
+            Ok(key)=>if !keys.contains(&key) {
+                keys.push(key)
+            },
+            Err(e)=>last_error=Some(e)
+        }
+        session.close()?
+    }
+    match last_error {
+        Some(e) if keys.is_empty()=>Err(e),
+        _=>Ok(keys)
+    }
+}
+
+/// A source of trusted host keys, for applications that keep them elsewhere than in known_hosts files (a database, configuration management…). See `Session::check_host_key`.
+pub trait HostKeyStore {
+    /// The keys trusted for `host` on `port`.
+    fn host_keys(&self,host:&str,port:u16)->Result<Vec<PublicKey>,Error>;
+}
+
+impl HostKeyStore for KnownHosts {
+    fn host_keys(&self,host:&str,port:u16)->Result<Vec<PublicKey>,Error> {
+        self.lookup(host,port)
+            .filter(|e| e.marker.is_none())
+            .map(|e| e.public_key())
+            .collect()
+    }
+}
+
+/// A host key store held in memory.
+#[derive(Debug,Default)]
+pub struct MemoryStore {
+    keys:HashMap<String,Vec<PublicKey>>
+}
+
+impl MemoryStore {
+    pub fn new()->MemoryStore {
+        MemoryStore::default()
+    }
+    /// Trust `key` for `host` on `port`.
+    pub fn insert(&mut self,host:&str,port:u16,key:PublicKey) {
+        self.keys.entry(host_pattern(host,port)).or_default().push(key)
+    }
+    /// Forget all the keys of `host` on `port`.
+    pub fn remove(&mut self,host:&str,port:u16)->Vec<PublicKey> {
+        self.keys.remove(&host_pattern(host,port)).unwrap_or_default()
+    }
+}
+
+impl HostKeyStore for MemoryStore {
+    fn host_keys(&self,host:&str,port:u16)->Result<Vec<PublicKey>,Error> {
+        Ok(match self.keys.get(&host_pattern(host,port)) {
+            Some(keys)=>keys.iter().map(|k| PublicKey::from_blob(&k.to_blob()?)).collect::<Result<_,Error>>()?,
+            None=>Vec::new()
+        })
+    }
+}
+
+/// A host key store that also records trust decisions, such as accepting the key of a new host, or a changed key after asking the user. Applications can implement it to keep these decisions in their own database; `FileStore` keeps them in a known hosts file.
+pub trait TrustStore:HostKeyStore {
+    /// Trust `key` for `host` on `port`, replacing the trusted key of the same type, if any.
+    fn trust(&mut self,host:&str,port:u16,key:&PublicKey)->Result<(),Error>;
+    /// Forget all the keys of `host` on `port`.
+    fn forget(&mut self,host:&str,port:u16)->Result<(),Error>;
+}
+
+impl TrustStore for MemoryStore {
+    fn trust(&mut self,host:&str,port:u16,key:&PublicKey)->Result<(),Error> {
+        let keys=self.keys.entry(host_pattern(host,port)).or_default();
+        keys.retain(|k| k.key_type()!=key.key_type());
+        keys.push(PublicKey::from_blob(&key.to_blob()?)?);
+        Ok(())
+    }
+    fn forget(&mut self,host:&str,port:u16)->Result<(),Error> {
+        self.remove(host,port);
+        Ok(())
+    }
+}
+
+impl TrustStore for KnownHosts {
+    fn trust(&mut self,host:&str,port:u16,key:&PublicKey)->Result<(),Error> {
+        // Only this host's own patterns are removed: lines shared with other hosts, and wildcard or negated patterns, still apply to the other hosts.
+        let key_type=key.key_type();
+        let name=host_pattern(host,port);
+        self.lines.retain_mut(|l| match *l {
+            Line::Entry(ref mut e) if e.marker.is_none() && e.matches(host,port) && e.public_key().map(|k| k.key_type()==key_type).unwrap_or(false)=>{
+                e.hosts.retain(|p| p.starts_with('!') || p.contains(['*','?']) || !pattern_matches(p,&name));
+                e.hosts.iter().any(|p| !p.starts_with('!'))
+            },
+            _=>true
+        });
+        self.add_key(host,port,key,false)
+    }
+    fn forget(&mut self,host:&str,port:u16)->Result<(),Error> {
+        self.remove_host(host,port);
+        Ok(())
+    }
+}
+
+/// A known hosts file used as a `TrustStore`: the file is read at each lookup, so that changes made by other programs are seen, and written back (atomically) at each decision.
+#[derive(Debug,Clone)]
+pub struct FileStore {
+    path:PathBuf,
+    hash:bool
+}
+
+impl FileStore {
+    /// The known hosts file at `path`, which is created at the first decision if it doesn't exist.
+    pub fn new<P:AsRef<Path>>(path:P)->FileStore {
+        FileStore { path:path.as_ref().to_path_buf(),hash:false }
+    }
+    /// Hash the host names of the keys added (off by default).
+    pub fn hash(mut self,hash:bool)->FileStore {
+        self.hash=hash;
+        self
+    }
+    pub fn path(&self)->&Path {
+        &self.path
+    }
+    fn open(&self)->Result<KnownHosts,Error> {
+        match KnownHosts::open(&self.path) {
+            Err(Error::IO(ref e)) if e.kind()==std::io::ErrorKind::NotFound=>Ok(KnownHosts { path:Some(self.path.clone()),lines:Vec::new() }),
+            r=>r
+        }
+    }
+}
+
+impl HostKeyStore for FileStore {
+    fn host_keys(&self,host:&str,port:u16)->Result<Vec<PublicKey>,Error> {
+        self.open()?.host_keys(host,port)
+    }
+}
+
+impl TrustStore for FileStore {
+    fn trust(&mut self,host:&str,port:u16,key:&PublicKey)->Result<(),Error> {
+        let mut kh=self.open()?;
+        kh.trust(host,port,key)?;
+        if self.hash {
+            kh.hash()?
+        }
+        kh.save()
+    }
+    fn forget(&mut self,host:&str,port:u16)->Result<(),Error> {
+        let mut kh=self.open()?;
+        if kh.remove_host(host,port)>0 {
+            kh.save()?
+        }
+        Ok(())
+    }
+}
+
+/// A view of a store restricted to a profile (such as a user account of a GUI client), so that the decisions made in one profile don't apply to the others. Hosts are stored as `profile/host` in the underlying store.
+#[derive(Debug,Clone)]
+pub struct Scoped<S> {
+    profile:String,
+    store:S
+}
+
+impl<S> Scoped<S> {
+    pub fn new<P:Into<String>>(profile:P,store:S)->Scoped<S> {
+        Scoped { profile:profile.into(),store }
+    }
+    pub fn into_inner(self)->S {
+        self.store
+    }
+    fn host(&self,host:&str)->String {
+        format!("{}/{}",self.profile,host)
+    }
+}
+
+impl<S:HostKeyStore> HostKeyStore for Scoped<S> {
+    fn host_keys(&self,host:&str,port:u16)->Result<Vec<PublicKey>,Error> {
+        self.store.host_keys(&self.host(host),port)
+    }
+}
+
+impl<S:TrustStore> TrustStore for Scoped<S> {
+    fn trust(&mut self,host:&str,port:u16,key:&PublicKey)->Result<(),Error> {
+        let host=self.host(host);
+        self.store.trust(&host,port,key)
+    }
+    fn forget(&mut self,host:&str,port:u16)->Result<(),Error> {
+        let host=self.host(host);
+        self.store.forget(&host,port)
+    }
+}
+
+/// A known hosts file of its own, in the temporary directory, deleted when dropped. This is for throwaway connections to freshly provisioned machines, whose host keys are new by definition: their keys are accepted on the first connection, without polluting the user's known hosts file, and then checked on reconnections while this exists.
+///
+///```no_run
+/// use ssh::*;
+/// use ssh::known_hosts::EphemeralKnownHosts;
+///
+/// let known_hosts=EphemeralKnownHosts::new().unwrap();
+/// let mut session=Session::new().unwrap();
+/// session.set_host("pijul.org").unwrap();
+/// known_hosts.apply(&mut session).unwrap();
+/// session.connect().unwrap();
+/// known_hosts.accept(&mut session).unwrap();
+/// session.userauth_publickey_auto(None).unwrap();
+///```
+#[derive(Debug)]
+pub struct EphemeralKnownHosts {
+    path:PathBuf
+}
+
+/// Distinguishes the ephemeral files of a process.
+static EPHEMERAL_COUNTER:AtomicUsize=AtomicUsize::new(0);
+
+impl EphemeralKnownHosts {
+    /// Create an empty file.
+    pub fn new()->Result<EphemeralKnownHosts,Error> {
+        loop {
+            let n=EPHEMERAL_COUNTER.fetch_add(1,Ordering::SeqCst);
+            let path=std::env::temp_dir().join(format!("ssh-known-hosts-{}-{}",std::process::id(),n));
+            match fs::OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(_)=>return Ok(EphemeralKnownHosts { path }),
+                // Left over by another process with the same id.
+                Err(ref e) if e.kind()==std::io::ErrorKind::AlreadyExists=>continue,
+                Err(e)=>return Err(e.into())
+            }
+        }
+    }
+    pub fn path(&self)->&Path {
+        &self.path
+    }
+    /// Make `session` use this file, instead of both the user's and the system-wide known hosts files.
+    pub fn apply(&self,session:&mut Session)->Result<(),Error> {
+        session.set_knownhosts(&self.path)?;
+        session.set_global_knownhosts(&self.path)
+    }
+    /// Check the host key of `session`, connected after `apply`: add it to this file if the host is not in it yet, and fail if it changed.
+    pub fn accept(&self,session:&mut Session)->Result<(),Error> {
+        let host=session.host().unwrap_or_default();
+        verify_host_key(session,&host,true)
+    }
+}
+
+impl Drop for EphemeralKnownHosts {
+    fn drop(&mut self) {
+        let _=fs::remove_file(&self.path);
+    }
+}
+
+/// Compare the server's host key with the keys trusted for `host` on `port`.
+pub fn check_key(store:&dyn HostKeyStore,host:&str,port:u16,key:&PublicKey)->Result<ServerKnown,Error> {
+    let trusted=store.host_keys(host,port)?;
+    if trusted.iter().any(|k| k==key) {
+        Ok(ServerKnown::Known)
+    } else if trusted.iter().any(|k| k.key_type()==key.key_type()) {
+        Ok(ServerKnown::Changed)
+    } else if !trusted.is_empty() {
+        Ok(ServerKnown::FoundOther)
+    } else {
+        Ok(ServerKnown::NotKnown)
+    }
+}
+
+impl Session {
+    /// Check the server's host key against `store` instead of the known hosts files, under the host name and port given to `set_host` and `set_port` (or read from the configuration).
+    ///
+    ///```no_run
+    /// use ssh::*;
+    /// use ssh::known_hosts::MemoryStore;
+    ///
+    /// let mut store=MemoryStore::new();
+    /// store.insert("pijul.org",22,PublicKey::from_base64("ssh-ed25519","AAAAC3NzaC1lZDI1NTE5AAAAIBBKHxmMSJfLxTSJchE6IWc0vCvjGSsvYKUGdTuBNpj1").unwrap());
+    ///
+    /// let mut session=Session::new().unwrap();
+    /// session.set_host("pijul.org").unwrap();
+    /// session.parse_config(None).unwrap();
+    /// session.connect().unwrap();
+    /// if !session.check_host_key(&store).unwrap().is_known() {
+    ///     panic!("unknown host key")
+    /// }
+    ///```
+    pub fn check_host_key(&mut self,store:&dyn HostKeyStore)->Result<ServerKnown,Error> {
+        let host=match self.host() {
+            Some(h)=>h,
+            None=>return Err(Error::Ssh("No host set".to_string()))
+        };
+        let port=self.port();
+        let key=self.server_public_key()?;
+        check_key(store,&host,port,&key)
+    }
+    /// Trust the server's host key from now on, recording the decision in `store` (for instance after the user accepted a key reported by `check_host_key`).
+    pub fn trust_host_key(&mut self,store:&mut dyn TrustStore)->Result<(),Error> {
+        let host=match self.host() {
+            Some(h)=>h,
+            None=>return Err(Error::Ssh("No host set".to_string()))
+        };
+        let port=self.port();
+        let key=self.server_public_key()?;
+        store.trust(&host,port,&key)
+    }
+    /// Check the server's host key against `store`, as `SessionConfig::connect` does with the known hosts files: with `HostKeyPolicy::AcceptNew`, the key of a host not in `store` is trusted and recorded there.
+    ///
+    ///```no_run
+    /// use ssh::*;
+    /// use ssh::known_hosts::{FileStore,Scoped};
+    ///
+    /// let mut store=Scoped::new("work",FileStore::new("/home/me/.config/client/known_hosts"));
+    /// let mut session=Session::new().unwrap();
+    /// session.set_host("pijul.org").unwrap();
+    /// session.parse_config(None).unwrap();
+    /// session.connect().unwrap();
+    /// session.verify_host_key(&mut store,HostKeyPolicy::AcceptNew).unwrap();
+    ///```
+    pub fn verify_host_key(&mut self,store:&mut dyn TrustStore,policy:HostKeyPolicy)->Result<(),Error> {
+        if policy==HostKeyPolicy::Off {
+            return Ok(())
+        }
+        let host=self.host().unwrap_or_default();
+        match self.check_host_key(&*store)? {
+            ServerKnown::Known=>Ok(()),
+            ServerKnown::NotKnown if policy==HostKeyPolicy::AcceptNew=>self.trust_host_key(store),
+            ServerKnown::Changed|ServerKnown::FoundOther=>Err(Error::Ssh(format!("Host key of {} has changed",host))),
+            known=>Err(Error::Ssh(format!("Host key of {} is not known: {:?}",host,known)))
+        }
+    }
+}
+
+/// A key found in a known hosts file.
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub struct KnownKey {
+    pub key_type:String,
+    /// SHA256 fingerprint of the key.
+    pub fingerprint:String,
+    pub file:PathBuf,
+    /// Line of `file` (starting at 1).
+    pub line:usize
+}
+
+/// The result of `Session::check_known_host`: whether the server's host key is known, along with what is needed to explain the situation to the user.
+#[derive(Debug)]
+pub struct HostKeyCheck {
+    pub status:ServerKnown,
+    pub host:String,
+    pub port:u16,
+    /// The key presented by the server.
+    pub server_key:PublicKey,
+    /// The keys known for this host, when they don't match `server_key`.
+    pub known_keys:Vec<KnownKey>
+}
+
+/// Key type names as displayed by OpenSSH ("ED25519", "RSA"…).
+fn display_type(key_type:&str)->String {
+    let t=key_type.trim_start_matches("ssh-");
+    if t.starts_with("ecdsa") {
+        "ECDSA".to_string()
+    } else if t.starts_with("sk-ecdsa") {
+        "ECDSA-SK".to_string()
+    } else if t.starts_with("sk-ssh-ed25519") {
+        "ED25519-SK".to_string()
+    } else {
+        t.to_uppercase()
+    }
+}
+
+impl HostKeyCheck {
+    /// The warning OpenSSH would print in this situation, or `None` if the key is known.
+    pub fn warning(&self)->Option<String> {
+        let name=host_pattern(&self.host,self.port);
+        let key_type=display_type(self.server_key.key_type());
+        let fingerprint=self.server_key.fingerprint(HashType::Sha256).unwrap_or_default();
+        let mut w=String::new();
+        match self.status {
+            ServerKnown::Known=>return None,
+            ServerKnown::Changed=>{
+                w.push_str("@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@\n");
+                w.push_str("@    WARNING: REMOTE HOST IDENTIFICATION HAS CHANGED!     @\n");
+                w.push_str("@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@\n");
+                w.push_str("IT IS POSSIBLE THAT SOMEONE IS DOING SOMETHING NASTY!\n");
+                w.push_str("Someone could be eavesdropping on you right now (man-in-the-middle attack)!\n");
+                w.push_str("It is also possible that a host key has just been changed.\n");
+                w.push_str(&format!("The fingerprint for the {} key sent by the remote host is\n{}.\n",key_type,fingerprint));
+                w.push_str("Please contact your system administrator.\n");
+                for k in self.known_keys.iter() {
+                    w.push_str(&format!("Offending {} key in {}:{}\n",display_type(&k.key_type),k.file.display(),k.line));
+                    w.push_str(&format!("  remove with:\n  ssh-keygen -f \"{}\" -R \"{}\"\n",k.file.display(),name));
+                }
+                w.push_str(&format!("Host key for {} has changed and you have requested strict checking.\n",name));
+                w.push_str("Host key verification failed.\n");
+            },
+            ServerKnown::FoundOther=>{
+                w.push_str(&format!("The authenticity of host '{}' can't be established.\n",name));
+                w.push_str(&format!("{} key fingerprint is {}.\n",key_type,fingerprint));
+                w.push_str("The following keys of other types are already known for this host:\n");
+                for k in self.known_keys.iter() {
+                    w.push_str(&format!("    {}:{}: {} {}\n",k.file.display(),k.line,display_type(&k.key_type),k.fingerprint));
+                }
+                w.push_str("This may mean that the server's keys were changed, or that someone is impersonating it.\n");
+            },
+            ServerKnown::NotKnown|ServerKnown::FileNotFound=>{
+                w.push_str(&format!("The authenticity of host '{}' can't be established.\n",name));
+                w.push_str(&format!("{} key fingerprint is {}.\n",key_type,fingerprint));
+                w.push_str("This key is not known by any other names.\n");
+            }
+        }
+        Some(w)
+    }
+}
+
+impl Session {
+    /// Same as `is_server_known`, but also returns the server's key and, if it doesn't match, the keys known for this host, along with their location in the known hosts files.
+    ///
+    ///```no_run
+    /// use ssh::*;
+    ///
+    /// let mut session=Session::new().unwrap();
+    /// session.set_host("pijul.org").unwrap();
+    /// session.parse_config(None).unwrap();
+    /// session.connect().unwrap();
+    /// let check=session.check_known_host().unwrap();
+    /// if let Some(warning)=check.warning() {
+    ///     eprint!("{}",warning);
+    ///     std::process::exit(1)
+    /// }
+    ///```
+    pub fn check_known_host(&mut self)->Result<HostKeyCheck,Error> {
+        let status=self.is_server_known()?;
+        let server_key=self.server_public_key()?;
+        let host=self.host().unwrap_or_default();
+        let port=self.port();
+        let known_keys=if let ServerKnown::Changed|ServerKnown::FoundOther=status {
+            self.known_keys(&host,port,&server_key)
+        } else {
+            Vec::new()
+        };
+        Ok(HostKeyCheck { status,host,port,server_key,known_keys })
+    }
+    /// The keys of `host` on `port` in the known hosts files, other than `server_key`.
+    pub(crate) fn known_keys(&self,host:&str,port:u16,server_key:&PublicKey)->Vec<KnownKey> {
+        let mut known_keys=Vec::new();
+        let files=[self.get_option(SshOptions::KNOWNHOSTS),self.get_option(SshOptions::GLOBAL_KNOWNHOSTS)];
+        for file in files.iter().flatten() {
+            let kh=match KnownHosts::open(file) {
+                Ok(kh)=>kh,
+                Err(_)=>continue
+            };
+            for (line,e) in kh.lookup_lines(host,port) {
+                if e.marker.is_some() {
+                    continue
+                }
+                if let Ok(k)=e.public_key() {
+                    if k!=*server_key {
+                        known_keys.push(KnownKey {
+                            key_type:e.key_type.clone(),
+                            fingerprint:k.fingerprint(HashType::Sha256).unwrap_or_default(),
+                            file:PathBuf::from(file),
+                            line
+                        })
+                    }
+                }
+            }
+        }
+        known_keys
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use super::{KnownHosts,TrustStore,hash_host,Entry,HostKeyCheck,KnownKey};
+    use super::super::{HashType,PublicKey,ServerKnown};
+    const FILE:&str="# comment\n\n@cert-authority *.example.com ssh-ed25519 AAAAC3Nza ca\na,b,[c]:2222 ssh-ed25519 AAAAC3Nzb user@host\n!d,*.org ssh-rsa AAAAB3Nza\n";
+
+    #[test]
+    fn parse_round_trip() {
+        let k=KnownHosts::parse(FILE);
+        assert_eq!(k.to_string(),FILE);
+        assert_eq!(k.entries().count(),3);
+        let e=k.lookup("c",2222).next().unwrap();
+        assert_eq!(e.hosts,["a","b","[c]:2222"]);
+        assert_eq!(e.comment.as_deref(),Some("user@host"));
+        assert!(k.lookup("c",22).next().is_none());
+        assert!(k.lookup("d.org",22).next().is_some());
+        assert!(k.lookup("d",22).next().is_none());
+    }
+
+    #[test]
+    fn remove_host() {
+        let mut k=KnownHosts::parse(FILE);
+        assert_eq!(k.remove_host("b",22),1);
+        assert_eq!(k.lookup("a",22).next().unwrap().hosts,["a","[c]:2222"]);
+        assert_eq!(k.remove_host("a",22),1);
+        assert_eq!(k.remove_host("c",2222),1);
+        assert_eq!(k.entries().count(),2);
+        assert_eq!(k.remove_host("nothing",22),0);
+    }
+
+    #[test]
+    fn hash_splits_hosts() {
+        let mut k=KnownHosts::parse(FILE);
+        k.hash().unwrap();
+        let entries:Vec<&Entry>=k.entries().collect();
+        assert_eq!(entries.len(),5);
+        for (e,(host,port)) in entries[1..4].iter().zip([("a",22),("b",22),("c",2222)]) {
+            assert_eq!(e.hosts.len(),1);
+            assert!(e.hosts[0].starts_with("|1|"));
+            assert!(e.matches(host,port));
+            assert_eq!((&e.key_type[..],&e.key[..],e.comment.as_deref()),("ssh-ed25519","AAAAC3Nzb",Some("user@host")));
+        }
+        assert_eq!(entries[0].hosts,["*.example.com"]);
+        assert_eq!(entries[4].hosts,["!d","*.org"]);
+        let hashed=k.to_string();
+        k.hash().unwrap();
+        assert_eq!(k.to_string(),hashed);
+    }
+
+    #[test]
+    fn hash_host_matches() {
+        let e=Entry { marker:None,hosts:vec![hash_host("[host]:2222").unwrap()],key_type:"ssh-ed25519".to_string(),key:"AAAA".to_string(),comment:None };
+        assert!(e.matches("host",2222));
+        assert!(!e.matches("host",22));
+        assert!(!e.matches("other",2222));
+        assert_ne!(hash_host("host").unwrap(),hash_host("host").unwrap());
+    }
+
+    #[test]
+    fn trust_keeps_shared_lines() {
+        const KEY:&str="AAAAC3NzaC1lZDI1NTE5AAAAIBBKHxmMSJfLxTSJchE6IWc0vCvjGSsvYKUGdTuBNpj1";
+        let mut k=KnownHosts::parse(&format!("a,b,c ssh-ed25519 {0}\n*.org ssh-ed25519 {0}\nb ssh-ed25519 {0}\n",KEY));
+        let key=PublicKey::from_base64("ssh-ed25519",KEY).unwrap();
+        k.trust("b",22,&key).unwrap();
+        let entries:Vec<&Entry>=k.entries().collect();
+        assert_eq!(entries.len(),3);
+        assert_eq!(entries[0].hosts,["a","c"]);
+        assert_eq!(entries[1].hosts,["*.org"]);
+        assert_eq!(entries[2].hosts,["b"]);
+        assert!(k.lookup("a",22).next().is_some() && k.lookup("c",22).next().is_some());
+    }
+
+    fn check(status:ServerKnown,known_keys:Vec<KnownKey>)->HostKeyCheck {
+        let server_key=PublicKey::from_base64("ssh-ed25519","AAAAC3NzaC1lZDI1NTE5AAAAIBBKHxmMSJfLxTSJchE6IWc0vCvjGSsvYKUGdTuBNpj1").unwrap();
+        HostKeyCheck { status,host:"example.com".to_string(),port:2222,server_key,known_keys }
+    }
+
+    fn known_key(key_type:&str)->KnownKey {
+        KnownKey { key_type:key_type.to_string(),fingerprint:"SHA256:abc".to_string(),file:PathBuf::from("/home/me/.ssh/known_hosts"),line:3 }
+    }
+
+    #[test]
+    fn warning() {
+        assert_eq!(check(ServerKnown::Known,Vec::new()).warning(),None);
+        let c=check(ServerKnown::NotKnown,Vec::new());
+        let fingerprint=c.server_key.fingerprint(HashType::Sha256).unwrap();
+        let w=c.warning().unwrap();
+        assert!(w.starts_with("The authenticity of host '[example.com]:2222' can't be established.\n"));
+        assert!(w.contains(&format!("ED25519 key fingerprint is {}.\n",fingerprint)));
+        assert!(w.ends_with("This key is not known by any other names.\n"));
+        assert_eq!(check(ServerKnown::FileNotFound,Vec::new()).warning().unwrap(),w);
+
+        let w=check(ServerKnown::Changed,vec![known_key("ssh-ed25519")]).warning().unwrap();
+        assert!(w.contains("WARNING: REMOTE HOST IDENTIFICATION HAS CHANGED!"));
+        assert!(w.contains(&format!("The fingerprint for the ED25519 key sent by the remote host is\n{}.\n",fingerprint)));
+        assert!(w.contains("Offending ED25519 key in /home/me/.ssh/known_hosts:3\n"));
+        assert!(w.contains("ssh-keygen -f \"/home/me/.ssh/known_hosts\" -R \"[example.com]:2222\"\n"));
+        assert!(w.ends_with("Host key verification failed.\n"));
+
+        let w=check(ServerKnown::FoundOther,vec![known_key("ecdsa-sha2-nistp256"),known_key("ssh-rsa")]).warning().unwrap();
+        assert!(w.starts_with("The authenticity of host '[example.com]:2222' can't be established.\n"));
+        assert!(w.contains("    /home/me/.ssh/known_hosts:3: ECDSA SHA256:abc\n    /home/me/.ssh/known_hosts:3: RSA SHA256:abc\n"));
+        assert!(w.ends_with("someone is impersonating it.\n"));
+    }
+}
@@ -6,7 +6,7 @@
 //!
 //!# Client examples
 //!
-//! ```
+//! ```no_run
 //! use ssh::*;
 //!
 //! let mut session=Session::new().unwrap();
@@ -19,7 +19,7 @@
 //!
 //!## Running a command on a remote server
 //!
-//!```
+//!```no_run
 //! use ssh::*;
 //! use std::io::Read;
 //!
@@ -42,7 +42,7 @@
 //!
 //!## Creating a remote file
 //!
-//!```
+//!```no_run
 //! use ssh::*;
 //! use std::io::Write;
 //!
@@ -56,14 +56,14 @@
 //!     let mut scp=session.scp_new(WRITE,"/tmp").unwrap();
 //!     scp.init().unwrap();
 //!     let buf=b"blabla blibli\n".to_vec();
-//!     scp.push_file("blublu",buf.len(),0o644).unwrap();
+//!     scp.push_file("blublu",buf.len() as u64,0o644).unwrap();
 //!     scp.write(&buf).unwrap();
 //! }
 //!```
 //!
 //!## Creating a remote directory with a file inside
 //!
-//!```
+//!```no_run
 //! use ssh::*;
 //! use std::io::Write;
 //!
@@ -78,7 +78,7 @@
 //!     scp.init().unwrap();
 //!     scp.push_directory("testdir",0o755).unwrap();
 //!     let buf=b"blabla\n".to_vec();
-//!     scp.push_file("test file",buf.len(),0o644).unwrap();
+//!     scp.push_file("test file",buf.len() as u64,0o644).unwrap();
 //!     scp.write(&buf).unwrap();
 //! }
 //!
@@ -86,7 +86,7 @@
 //!
 //!## Reading a remote file
 //!
-//!```
+//!```no_run
 //! use ssh::*;
 //! use std::io::Read;
 //!
@@ -119,12 +119,126 @@
 //!```
 
 extern crate libc;
-use self::libc::{c_int,c_uint,c_void,c_char,size_t,uint64_t};
+#[cfg(feature="tracing")]
+extern crate tracing;
+extern crate sha1;
+extern crate hmac;
+extern crate base64;
+extern crate getrandom;
+#[cfg(feature="serde")]
+extern crate serde;
+#[cfg(all(unix,feature="tokio"))]
+extern crate tokio;
+#[cfg(all(unix,feature="async-io"))]
+extern crate async_io;
+#[cfg(all(unix,feature="async"))]
+extern crate futures_core;
+#[cfg(feature="mirror")]
+extern crate notify;
+#[cfg(feature="gzip")]
+extern crate flate2;
+#[cfg(feature="zstd")]
+extern crate zstd;
+#[cfg(feature="tar")]
+extern crate tar;
+#[cfg(feature="metrics")]
+extern crate metrics;
+
+#[macro_use]
+mod trace;
+#[macro_use]
+mod meter;
+mod retry;
+pub use retry::Retry;
+mod exec;
+pub use exec::{Batch,BatchResult,Output,RemoteCommand,Stderr};
+pub mod fleet;
+mod auth;
+pub use auth::{AuthInfo,AuthMethod,IdentityFailure,KbdintChallenge,KbdintPrompt};
+mod mfa;
+mod idle;
+mod deadline;
+#[cfg(unix)]
+mod interrupt;
+#[cfg(unix)]
+pub use interrupt::Interrupter;
+mod actor;
+pub use actor::{Pending,SessionHandle};
+pub mod audit;
+pub use mfa::{AuthPipeline,StepResult};
+mod credentials;
+pub use credentials::CredentialsProvider;
+mod key;
+pub use key::{HashType,PublicKey};
+#[cfg(unix)]
+pub mod agent;
+#[cfg(unix)]
+mod connect;
+#[cfg(unix)]
+pub use connect::{AddressFamily,Transport};
+pub mod capture;
+pub mod tap;
+mod callbacks;
+pub use callbacks::{GlobalRequest,SessionCallbacks};
+pub mod security;
+pub use security::SecurityEvent;
+mod incoming;
+pub use incoming::{IncomingChannel,IncomingKind};
+pub mod sftp;
+mod glob;
+pub mod probe;
+mod remote_path;
+pub use remote_path::{PathStyle,RemotePath};
+mod scp_pull;
+pub use scp_pull::{ScpEntries,ScpEntry};
+mod tail;
+pub use tail::Tail;
+mod lines;
+pub use lines::BoundedLines;
+#[cfg(unix)]
+pub mod shell;
+mod pty;
+pub use pty::{PtyOptions,TerminalMode,TerminalModes};
+mod transfer;
+pub use transfer::{ScpTransfer,Transfer};
+mod filter;
+pub use filter::{Filter,Filtered};
+mod copy;
+pub use copy::{CopyOptions,copy,is_copy_url};
+mod parallel;
+pub use parallel::ParallelTransfer;
+mod tree;
+pub use tree::TreeStrategy;
+mod delta;
+pub use delta::DeltaStats;
+mod sparse;
+pub use sparse::SparseStats;
+mod atomic;
+pub mod attrs;
+#[cfg(any(feature="gzip",feature="zstd"))]
+mod compress;
+#[cfg(any(feature="gzip",feature="zstd"))]
+pub use compress::{Compression,CompressionAdvice};
+pub mod known_hosts;
+mod userdata;
+mod stream;
+pub use stream::ChannelStream;
+#[cfg(feature="serde")]
+mod ser;
+mod config;
+pub use config::{HostKeyPolicy,SessionConfig};
+#[cfg(all(unix,feature="async"))]
+pub mod nonblocking;
+#[cfg(feature="mirror")]
+pub mod mirror;
+
+use self::libc::{c_int,c_long,c_uint,c_void,c_char,size_t};
 use std::path::Path;
 use std::ffi::CString;
 use std::io::{Read,Write};
 use std::fmt;
 use std::ptr::copy_nonoverlapping;
+use std::cell::{Cell,RefCell};
 #[macro_use]
 extern crate log;
 
@@ -140,9 +254,20 @@ extern "C" {
     fn ssh_free(s:*mut Session_);
     fn ssh_connect(s:*mut Session_)->c_int;
     fn ssh_disconnect(s:*mut Session_)->c_int;
+    fn ssh_blocking_flush(s:*mut Session_,timeout:c_int)->c_int;
+    fn ssh_get_status(s:*mut Session_)->c_int;
+    fn ssh_is_connected(s:*mut Session_)->c_int;
+    fn ssh_is_blocking(s:*mut Session_)->c_int;
+    fn ssh_set_blocking(s:*mut Session_,blocking:c_int);
     fn ssh_options_set(s:*mut Session_,t:c_int,v:*const c_void)->c_int;
+    fn ssh_options_get(s:*mut Session_,t:c_int,v:*mut *mut c_char)->c_int;
+    fn ssh_options_get_port(s:*mut Session_,port:*mut c_uint)->c_int;
+    #[cfg(unix)]
+    fn ssh_get_fd(s:*mut Session_)->c_int;
+    fn ssh_string_free_char(s:*mut c_char);
     fn ssh_options_parse_config(s:*mut Session_,v:*const c_char)->c_int;
     fn ssh_get_error(s:*const c_void)->*const c_char;
+    fn ssh_get_error_code(s:*const c_void)->c_int;
     fn ssh_userauth_password(s:*mut Session_,user:*const c_char,p:*const c_char)->c_int;
     fn ssh_userauth_kbdint(s:*mut Session_,user:*const c_char,p:*const c_char)->c_int;
     fn ssh_userauth_publickey_auto(s:*mut Session_,user:*const c_char,p:*const c_char)->c_int;
@@ -154,15 +279,48 @@ extern "C" {
 
 
 pub struct Session {
-    session:*mut Session_
+    session:*mut Session_,
+    /// Number of channels and SCP handles currently borrowing this session.
+    children:Cell<usize>,
+    #[cfg(unix)]
+    family:AddressFamily,
+    /// Callbacks given to libssh by `set_callbacks`.
+    callbacks:Option<Box<callbacks::Registered>>,
+    /// What `probe` found out about the remote host.
+    probe:probe::Cache,
+    label:Option<String>,
+    userdata:userdata::UserData,
+    /// What `auth_info` returns.
+    auth:RefCell<auth::AuthInfo>,
+    /// Fingerprints given to `expect_host_key`.
+    expected_host_keys:Vec<String>,
+    /// Given to `set_security_policy`.
+    policy:Option<security::SecurityPolicy>,
+    /// Given to `set_timeout`.
+    timeout:Option<std::time::Duration>,
+    idle:idle::Idle,
+    /// Given to `set_audit_log`.
+    audit:Option<audit::AuditLog>,
+    /// Given to `set_tap`.
+    tap:RefCell<Option<Box<dyn tap::Tap>>>,
+    /// Enforces the deadline given to `set_deadline`.
+    watchdog:deadline::Watchdog
 }
+// libssh sessions are not thread-safe, but a session (with all its channels) can be used from any thread, one thread at a time.
+unsafe impl Send for Session {}
+
 impl std::fmt::Debug for Session {
     fn fmt(&self,f:&mut std::fmt::Formatter)->Result<(),std::fmt::Error> {
-        write!(f,"Session{{..}}")
+        match self.label {
+            Some(ref l)=>write!(f,"Session{{ label:{:?}, .. }}",l),
+            None=>write!(f,"Session{{..}}")
+        }
     }
 }
 
-#[allow(dead_code)]
+// The names of the variants follow libssh's SSH_OPTIONS_* constants.
+#[allow(dead_code,non_camel_case_types,clippy::upper_case_acronyms)]
+#[derive(Clone,Copy)]
 #[repr(C)]
 enum SshOptions {
   HOST,
@@ -194,56 +352,268 @@ enum SshOptions {
   GSSAPI_SERVER_IDENTITY,
   GSSAPI_CLIENT_IDENTITY,
   GSSAPI_DELEGATE_CREDENTIALS,
+  HMAC_C_S,
+  HMAC_S_C,
+  PASSWORD_AUTH,
+  PUBKEY_AUTH,
+  KBDINT_AUTH,
+  GSSAPI_AUTH,
+  GLOBAL_KNOWNHOSTS,
+  NODELAY,
+  PUBLICKEY_ACCEPTED_TYPES,
+  PROCESS_CONFIG,
+  REKEY_DATA,
+  REKEY_TIME,
+  RSA_MIN_SIZE,
+  IDENTITY_AGENT,
+}
+
+/// Milliseconds in `d`, for libssh's timeouts, saturating at `c_int::MAX`.
+fn millis(d:std::time::Duration)->c_int {
+    std::cmp::min(d.as_millis(),c_int::MAX as u128) as c_int
 }
 
-fn path_as_ptr(p:&Path)->CString {
-    let p=p.to_str().unwrap();
-    std::ffi::CString::new(p).unwrap()
+#[cfg(unix)]
+fn path_as_ptr(p:&Path)->Result<CString,Error> {
+    use std::os::unix::ffi::OsStrExt;
+    Ok(std::ffi::CString::new(p.as_os_str().as_bytes())?)
+}
+
+#[cfg(not(unix))]
+fn path_as_ptr(p:&Path)->Result<CString,Error> {
+    match p.to_str() {
+        Some(p)=>Ok(std::ffi::CString::new(p)?),
+        None=>Err(Error::Ssh(format!("Path {:?} is not valid UTF-8",p)))
+    }
 }
 
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum Error {
+    /// An error of libssh that retrying won't fix, or one detected by this crate.
     Ssh(String),
-    IO(std::io::Error)
+    /// A fatal error of the session reported by libssh, such as a network failure or a timeout: the session can't be used anymore, but a new connection may succeed.
+    Fatal(String),
+    /// The server refused a request: authentication failure, channel opening refused, etc. Retrying the same request will not help.
+    RequestDenied(String),
+    IO(std::io::Error),
+    /// An argument (command, path, username…) contained a NUL byte, and cannot be passed to libssh.
+    Nul(std::ffi::NulError),
+    /// A file mode was out of range, or could not be parsed.
+    InvalidPermissions(String),
+    /// The output of a remote command exceeded the given number of bytes.
+    OutputTooLarge(usize),
+    /// A line read by `BoundedLines` exceeded the given number of bytes.
+    LineTooLong(usize),
+    /// The server refused to open a channel, for the given reason.
+    ChannelOpen(ChannelOpenFailure,String),
+    /// The server presented a host key (whose SHA256 fingerprint is given) that does not have any of the fingerprints given to `Session::expect_host_key`.
+    HostKeyMismatch(String),
+    /// An algorithm of the given kind was negotiated, but is not allowed by the security policy of the session.
+    PolicyViolation(security::AlgorithmKind,String),
+    /// No identity given to `Session::userauth_identities` was accepted, for the given reasons.
+    Identities(Vec<(std::path::PathBuf,auth::IdentityFailure)>),
+    /// The connection was lost (or closed by the server) before a remote command finished, so that its exit status is unknown.
+    ConnectionLost(String),
+    /// The server has no space left on its file system, or the quota of the user is exceeded, as reported by SCP or SFTP (or found by `Sftp::check_space` before an upload).
+    NoSpace(String),
+    /// The server can't run SCP: it has no `scp` program (SCP is deprecated since OpenSSH 9, whose clients use SFTP by default), or only allows SFTP. `copy` falls back to SFTP in this case.
+    ScpUnavailable(String),
+    /// The session was interrupted by its `Interrupter`. Retrying won't help: the session stays interrupted.
+    Interrupted,
+    /// The deadline of the session, set by `Session::set_deadline`, has passed.
+    DeadlineExceeded,
+    /// An error, with a description of the operation that caused it (such as "connecting to example.com:22"). The original error is the `source` of this one.
+    Context(String,Box<Error>)
 }
 
+/// Why the server refused to open a channel (RFC 4254, section 5.1).
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum ChannelOpenFailure {
+    /// The server doesn't allow this kind of channel, for instance port forwarding is disabled.
+    AdministrativelyProhibited,
+    /// The server could not connect to the target of a forwarding.
+    ConnectFailed,
+    UnknownChannelType,
+    ResourceShortage,
+    Other(u32)
+}
+
+impl ChannelOpenFailure {
+    fn from_code(code:u32)->ChannelOpenFailure {
+        match code {
+            1=>ChannelOpenFailure::AdministrativelyProhibited,
+            2=>ChannelOpenFailure::ConnectFailed,
+            3=>ChannelOpenFailure::UnknownChannelType,
+            4=>ChannelOpenFailure::ResourceShortage,
+            c=>ChannelOpenFailure::Other(c)
+        }
+    }
+}
+
+/// Error after a failed channel opening. libssh only reports the reason code of the server in its error message, "Channel opening failure: channel N error (CODE) DESCRIPTION".
+fn channel_open_err(session:&Session)->Error {
+    match err(session) {
+        Error::RequestDenied(msg)=>{
+            let code=msg.split("error (").nth(1)
+                .and_then(|s| s.split(')').next())
+                .and_then(|s| s.parse().ok());
+            match code {
+                Some(code)=>Error::ChannelOpen(ChannelOpenFailure::from_code(code),msg),
+                None=>Error::RequestDenied(msg)
+            }
+        },
+        e=>e
+    }
+}
+
+const SSH_REQUEST_DENIED:c_int=1;
+const SSH_FATAL:c_int=2;
+
 fn err(session:&Session)->Error {
-    Error::Ssh(unsafe {
+    if let Some(e)=session.deadline_error() {
+        return e
+    }
+    let (code,msg)=unsafe {
         let err=ssh_get_error(session.session as *const c_void);
-        let slice=std::slice::from_raw_parts(err as *const u8,libc::strlen(err));
-        std::str::from_utf8(slice).unwrap().to_string()
-    })
+        let msg=if err.is_null() {
+            "unknown error".to_string()
+        } else {
+            std::ffi::CStr::from_ptr(err).to_string_lossy().into_owned()
+        };
+        (ssh_get_error_code(session.session as *const c_void),msg)
+    };
+    let msg=match session.label {
+        Some(ref l)=>format!("{}: {}",l,msg),
+        None=>msg
+    };
+    if is_no_space(&msg) {
+        Error::NoSpace(msg)
+    } else if code==SSH_REQUEST_DENIED {
+        Error::RequestDenied(msg)
+    } else if code==SSH_FATAL {
+        Error::Fatal(msg)
+    } else {
+        Error::Ssh(msg)
+    }
 }
 
-impl fmt::Display for Error {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+/// Error after a failed `ssh_scp_init`. When the server can't run `scp`, the command is refused, or exits (with "scp: command not found" on its standard error) before sending the status byte that starts the protocol.
+fn scp_init_err(session:&Session)->Error {
+    match err(session) {
+        Error::Ssh(msg) | Error::Fatal(msg) | Error::RequestDenied(msg) if msg.contains("Error reading status code") || msg.contains("request exec failed")=>Error::ScpUnavailable(msg),
+        e=>e
+    }
+}
+
+/// Whether an error message of the server (from `scp`, or from an SFTP server) means that the disk is full or the quota exceeded.
+fn is_no_space(msg:&str)->bool {
+    let msg=msg.to_lowercase();
+    msg.contains("no space left") || msg.contains("quota exceeded")
+}
+
+impl Error {
+    /// Wrap this error with a description of the operation that failed.
+    pub fn context<C:Into<String>>(self,context:C)->Error {
+        Error::Context(context.into(),Box::new(self))
+    }
+    /// The original error, without its context.
+    pub fn root(&self)->&Error {
+        match *self {
+            Error::Context(_,ref e)=>e.root(),
+            ref e=>e
+        }
+    }
+    /// Whether this error may go away by itself, so that retrying the operation makes sense: network errors and fatal session errors are transient, refused requests, invalid arguments, interruptions, deadlines and errors detected by this crate (such as unknown host keys) are not.
+    pub fn is_transient(&self)->bool {
         match *self {
-            Error::Ssh(ref descr) => write!(f, "SSH error: {}", descr),
-            Error::IO(ref e)=> e.fmt(f)
+            Error::Fatal(_)=>true,
+            Error::ConnectionLost(_)=>true,
+            Error::ChannelOpen(ChannelOpenFailure::ResourceShortage,_)=>true,
+            Error::Context(_,ref e)=>e.is_transient(),
+            Error::IO(ref e)=>{
+                use std::io::ErrorKind::*;
+                matches!(e.kind(),
+                         ConnectionRefused|ConnectionReset|ConnectionAborted|NotConnected
+                         |BrokenPipe|TimedOut|Interrupted|WouldBlock|UnexpectedEof)
+            },
+            _=>false
         }
     }
 }
 
-//pub type Error=&'static str;
-impl std::error::Error for Error {
-    fn description(&self) -> &str {
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
-            Error::Ssh(ref descr)=>descr,
-            Error::IO(ref e)=>e.description()
+            Error::Ssh(ref descr) | Error::Fatal(ref descr) => write!(f, "SSH error: {}", descr),
+            Error::RequestDenied(ref descr) => write!(f, "Request denied: {}", descr),
+            Error::IO(ref e)=> e.fmt(f),
+            Error::Nul(ref e)=> write!(f, "Invalid argument: {}", e),
+            Error::InvalidPermissions(ref p)=> write!(f, "Invalid permissions: {}", p),
+            Error::OutputTooLarge(n)=> write!(f, "Command output exceeds {} bytes", n),
+            Error::LineTooLong(n)=> write!(f, "Line exceeds {} bytes", n),
+            Error::ChannelOpen(ref r,ref descr)=> write!(f, "Channel opening refused ({:?}): {}", r, descr),
+            Error::HostKeyMismatch(ref fingerprint)=> write!(f, "Unexpected host key {}", fingerprint),
+            Error::PolicyViolation(kind,ref name)=> write!(f, "Algorithm {} ({:?}) not allowed by the security policy", name, kind),
+            Error::Identities(ref failures)=>{
+                write!(f, "No identity accepted")?;
+                for (i,(path,failure)) in failures.iter().enumerate() {
+                    write!(f, "{} {}: {}", if i==0 { ":" } else { ";" }, path.display(), failure)?
+                }
+                Ok(())
+            },
+            Error::ConnectionLost(ref descr)=> write!(f, "Connection lost: {}", descr),
+            Error::NoSpace(ref descr)=> write!(f, "No space left on the server: {}", descr),
+            Error::ScpUnavailable(ref descr)=> write!(f, "SCP is not available on the server: {}", descr),
+            Error::Interrupted=> write!(f, "The session was interrupted"),
+            Error::DeadlineExceeded=> write!(f, "The deadline of the session has passed"),
+            Error::Context(ref c,_)=> write!(f, "Error while {}", c)
         }
     }
-    fn cause(&self) -> Option<&std::error::Error> {
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error+'static)> {
         match *self {
-            Error::Ssh(_)=>None,
-            Error::IO(ref e)=>Some(e)
+            Error::IO(ref e)=>Some(e),
+            Error::Nul(ref e)=>Some(e),
+            Error::Context(_,ref e)=>Some(&**e),
+            _=>None
         }
     }
 }
 const SSH_OK:c_int=0;
+const SSH_ERROR:c_int=-1;
+const SSH_AGAIN:c_int=-2;
+const SSH_EOF:c_int=-127;
+
+/// How long closing a session, channel or SCP transfer waits for the data written before to be sent.
+const FLUSH_TIMEOUT:std::time::Duration=std::time::Duration::from_secs(30);
+/// How long dropping a channel or SCP transfer waits for the data written before to be sent, so that drops (and the cancellation of async tasks) don't stall.
+const DROP_FLUSH_TIMEOUT:std::time::Duration=std::time::Duration::from_millis(100);
 
 impl From<std::io::Error> for Error {
     fn from(err: std::io::Error) -> Error {
-        Error::IO(err)
+        // Errors of this crate, converted to `io::Error` by `Read` and `Write` implementations, are unwrapped.
+        match err.downcast::<Error>() {
+            Ok(e)=>e,
+            Err(err)=>Error::IO(err)
+        }
+    }
+}
+
+impl From<Error> for std::io::Error {
+    fn from(err: Error) -> std::io::Error {
+        match err {
+            Error::IO(e)=>e,
+            e=>std::io::Error::other(e)
+        }
+    }
+}
+
+impl From<std::ffi::NulError> for Error {
+    fn from(err: std::ffi::NulError) -> Error {
+        Error::Nul(err)
     }
 }
 
@@ -253,11 +623,11 @@ impl Session {
         if session.is_null() {
             Err(())
         } else {
-            Ok(Session { session:session })
+            Ok(Session { session:session, children:Cell::new(0), #[cfg(unix)] family:AddressFamily::Any, callbacks:None, probe:probe::Cache::default(), label:None, userdata:userdata::UserData::default(), auth:RefCell::default(), expected_host_keys:Vec::new(), policy:None, timeout:None, idle:idle::Idle::default(), audit:None, tap:RefCell::new(None), watchdog:deadline::Watchdog::default() })
         }
     }
     pub fn set_host(&mut self,v:&str)->Result<(),Error> {
-        let v=std::ffi::CString::new(v).unwrap();
+        let v=std::ffi::CString::new(v)?;
         let e = unsafe { ssh_options_set(self.session,SshOptions::HOST as c_int,v.as_ptr() as *const c_void) };
         if e==SSH_OK { Ok(()) }
         else { Err(err(self)) }
@@ -269,29 +639,41 @@ impl Session {
         else { Err(err(self))}
     }
     pub fn set_username(&mut self,v:&str)->Result<(),Error> {
-        let v=std::ffi::CString::new(v).unwrap();
+        let v=std::ffi::CString::new(v)?;
         let e = unsafe { ssh_options_set(self.session,SshOptions::USER as c_int,v.as_ptr() as *const c_void) };
         if e==SSH_OK { Ok(()) }
         else { Err(err(self))}
     }
     /// Set the location of the ".ssh" directory, where the config file and keys can be found (it may include "%s", which will be replaced by the user home directory).
     pub fn set_ssh_dir<P: AsRef<Path>>(&mut self,v:P)->Result<(),Error> {
-        let e = unsafe { ssh_options_set(self.session,SshOptions::USER as c_int, path_as_ptr(v.as_ref()).as_ptr() as *const c_void) };
+        let e = unsafe { ssh_options_set(self.session,SshOptions::USER as c_int, path_as_ptr(v.as_ref())?.as_ptr() as *const c_void) };
         if e==SSH_OK { Ok(()) }
         else { Err(err(self))}
     }
     /// Set the location of the "knownhosts" file (it may include "%s", which will be replaced by the user home directory).
     pub fn set_knownhosts<P: AsRef<Path>>(&mut self,v:P)->Result<(),Error> {
-        let e = unsafe { ssh_options_set(self.session,SshOptions::KNOWNHOSTS as c_int, path_as_ptr(v.as_ref()).as_ptr() as *const c_void) };
+        let e = unsafe { ssh_options_set(self.session,SshOptions::KNOWNHOSTS as c_int, path_as_ptr(v.as_ref())?.as_ptr() as *const c_void) };
+        if e==SSH_OK { Ok(()) }
+        else { Err(err(self))}
+    }
+    /// Set the location of the system-wide "knownhosts" file (default "/etc/ssh/ssh_known_hosts"). As in OpenSSH, host keys are looked up in both this file and the one set by `set_knownhosts`, but `write_knownhost` only writes to the latter.
+    pub fn set_global_knownhosts<P: AsRef<Path>>(&mut self,v:P)->Result<(),Error> {
+        let e = unsafe { ssh_options_set(self.session,SshOptions::GLOBAL_KNOWNHOSTS as c_int, path_as_ptr(v.as_ref())?.as_ptr() as *const c_void) };
         if e==SSH_OK { Ok(()) }
         else { Err(err(self))}
     }
     /// Set the location of the key to be used for authentication (it may include "%s", which will be replaced by the user home directory).
     pub fn set_identity<P: AsRef<Path>>(&mut self,v:P)->Result<(),Error> {
-        let e = unsafe { ssh_options_set(self.session,SshOptions::IDENTITY as c_int, path_as_ptr(v.as_ref()).as_ptr() as *const c_void) };
+        let e = unsafe { ssh_options_set(self.session,SshOptions::IDENTITY as c_int, path_as_ptr(v.as_ref())?.as_ptr() as *const c_void) };
         if e==SSH_OK { Ok(()) }
         else { Err(err(self)) }
     }
+    /// Use the ssh-agent listening on the Unix socket at `path` for this session, instead of the one given by the `SSH_AUTH_SOCK` environment variable.
+    pub fn set_agent_socket<P: AsRef<Path>>(&mut self,v:P)->Result<(),Error> {
+        let e = unsafe { ssh_options_set(self.session,SshOptions::IDENTITY_AGENT as c_int, path_as_ptr(v.as_ref())?.as_ptr() as *const c_void) };
+        if e==SSH_OK { Ok(()) }
+        else { Err(err(self))}
+    }
     /// Allow version 1 of the protocol (default unspecified).
     pub fn set_ssh1(&mut self,v:bool)->Result<(),Error> {
         let v:[c_int;1]=[if v { 1 } else { 0 }];
@@ -306,24 +688,59 @@ impl Session {
         if e==SSH_OK { Ok(()) }
         else { Err(err(self))}
     }
+    /// Set the timeout of connection and of blocking operations (default 10 seconds).
+    pub fn set_timeout(&mut self,timeout:std::time::Duration)->Result<(),Error> {
+        let secs:[c_long;1]=[std::cmp::min(timeout.as_secs(),c_long::MAX as u64) as c_long];
+        let usecs:[c_long;1]=[timeout.subsec_micros() as c_long];
+        let e = unsafe { ssh_options_set(self.session,SshOptions::TIMEOUT as c_int, secs.as_ptr() as *const c_void) };
+        if e!=SSH_OK {
+            return Err(err(self))
+        }
+        let e = unsafe { ssh_options_set(self.session,SshOptions::TIMEOUT_USEC as c_int, usecs.as_ptr() as *const c_void) };
+        if e!=SSH_OK {
+            return Err(err(self))
+        }
+        self.timeout=Some(timeout);
+        Ok(())
+    }
+    /// The timeout given to `set_timeout`, if any.
+    pub fn timeout(&self)->Option<std::time::Duration> {
+        self.timeout
+    }
+    /// Renegotiate the session keys after `interval` (rounded down to seconds; zero disables time-based rekeying).
+    pub fn set_rekey_time(&mut self,interval:std::time::Duration)->Result<(),Error> {
+        let v:[u32;1]=[std::cmp::min(interval.as_secs(),u32::MAX as u64) as u32];
+        let e = unsafe { ssh_options_set(self.session,SshOptions::REKEY_TIME as c_int, v.as_ptr() as *const c_void) };
+        if e==SSH_OK { Ok(()) }
+        else { Err(err(self))}
+    }
     /// Parse configuration file. If the path is `None`, then `~/.ssh/config` is read.
     pub fn parse_config(&mut self,path:Option<&Path>)->Result<(),Error> {
+        let path=match path { Some(p) => Some(path_as_ptr(p)?), None => None };
         let e=unsafe {
             ssh_options_parse_config(self.session,
-                                     match path { Some(p) => path_as_ptr(p).as_ptr() as *const c_char,
+                                     match path { Some(ref p) => p.as_ptr() as *const c_char,
                                                   None => std::ptr::null_mut() })
         };
         if e==SSH_OK { Ok(()) }
         else { Err(err(self))}
     }
 
-    /// Check whether the remote server's key is known.
+    /// Check whether the remote server's key is known, in either the user or the global known hosts file.
     pub fn is_server_known(&mut self)->Result<ServerKnown,Error>{
         let e=unsafe {
             ssh_is_server_known(self.session)
         };
-        if e>=0 { Ok(unsafe { std::mem::transmute(e) }) }
-        else { Err(err(self))}
+        match e {
+            0=>Ok(ServerKnown::NotKnown),
+            1=>Ok(ServerKnown::Known),
+            2|3=>{
+                self.report_host_key_changed();
+                Ok(if e==2 { ServerKnown::Changed } else { ServerKnown::FoundOther })
+            },
+            4=>Ok(ServerKnown::FileNotFound),
+            _=>Err(err(self))
+        }
     }
     /// Accept the remote server's key.
     pub fn write_knownhost(&mut self)->Result<(),Error>{
@@ -337,55 +754,166 @@ impl Session {
     pub fn get_pubkey_hash(&mut self)->Result<Vec<u8>,Error>{
         let mut ptr=std::ptr::null_mut();
         let e=unsafe {
-            ssh_get_pubkey_hash(self.session,std::mem::transmute(&mut ptr))
+            ssh_get_pubkey_hash(self.session,&mut ptr)
         };
-        if e>=0 {
+        if e>=0 && !ptr.is_null() {
             let mut v=vec![0;e as usize];
             unsafe {
                 copy_nonoverlapping(ptr, v.as_mut_ptr(), e as usize);
-                ssh_clean_pubkey_hash(std::mem::transmute(&mut ptr))
+                ssh_clean_pubkey_hash(&mut ptr)
             }
             Ok(v)
         } else {
-            unsafe {
-                ssh_clean_pubkey_hash(std::mem::transmute(&mut ptr))
+            if !ptr.is_null() {
+                unsafe { ssh_clean_pubkey_hash(&mut ptr) }
             }
             Err(err(self))
         }
     }
+    /// Read a string option back from libssh.
+    fn get_option(&self,opt:SshOptions)->Option<String> {
+        let mut v=std::ptr::null_mut();
+        unsafe {
+            if ssh_options_get(self.session,opt as c_int,&mut v)==SSH_OK && !v.is_null() {
+                let s=std::ffi::CStr::from_ptr(v).to_string_lossy().into_owned();
+                ssh_string_free_char(v);
+                Some(s)
+            } else {
+                None
+            }
+        }
+    }
+    /// The host name this session connects to, as set by `set_host` or the configuration file.
+    pub fn host(&self)->Option<String> {
+        self.get_option(SshOptions::HOST)
+    }
+    /// The remote port (22 unless set otherwise).
+    pub fn port(&self)->u16 {
+        let mut port:c_uint=22;
+        unsafe { ssh_options_get_port(self.session,&mut port) };
+        port as u16
+    }
+    /// The user name used to authenticate: the one set by `set_username` or the configuration file, else the local user.
+    pub fn username(&self)->Option<String> {
+        self.get_option(SshOptions::USER)
+    }
+    /// The address of the server, once connected. This is `None` when the connection goes through a `ProxyCommand`, since there is no socket to the server then.
+    #[cfg(unix)]
+    pub fn peer_addr(&self)->Option<std::net::SocketAddr> {
+        self.with_socket(|s| s.peer_addr())
+    }
+    /// The local address of the connection to the server, with the same restrictions as `peer_addr`.
+    #[cfg(unix)]
+    pub fn local_addr(&self)->Option<std::net::SocketAddr> {
+        self.with_socket(|s| s.local_addr())
+    }
+    #[cfg(unix)]
+    fn with_socket<F:FnOnce(&std::net::TcpStream)->std::io::Result<std::net::SocketAddr>>(&self,f:F)->Option<std::net::SocketAddr> {
+        use std::os::unix::io::FromRawFd;
+        let fd=unsafe { ssh_get_fd(self.session) };
+        if fd<0 {
+            return None
+        }
+        // The socket belongs to libssh, and must not be closed here.
+        let s=std::mem::ManuallyDrop::new(unsafe { std::net::TcpStream::from_raw_fd(fd) });
+        f(&s).ok()
+    }
     pub fn connect(&mut self)->Result<(),Error>{
-        let e=unsafe {
-            ssh_connect(self.session)
-        };
-        if e==SSH_OK { Ok(()) }
-        else { Err(err(self))}
+        self.clear_probe();
+        let previous=self.timeout;
+        if let Some(left)=self.check_deadline()? {
+            self.set_timeout(previous.map(|t| t.min(left)).unwrap_or(left))?
+        }
+        traced!("ssh.connect",{session=self.label(),host=?self.get_option(SshOptions::HOST)},{
+            let e=unsafe {
+                ssh_connect(self.session)
+            };
+            if self.deadline().is_some() {
+                self.set_timeout(previous.unwrap_or(std::time::Duration::from_secs(10)))?
+            }
+            let r=if e==SSH_OK {
+                self.watch_socket();
+                self.touch();
+                self.after_connect()
+            }
+            else { Err(err(self).context(format!("connecting to {}:{}",self.host().unwrap_or_default(),self.port()))) };
+            count!("ssh_connections_total",1,"result"=>if r.is_ok() { "ok" } else { "error" });
+            r
+        })
     }
     /// Disconnect the session. The session can be reused later to open a new session.
     pub fn disconnect(&mut self)->Result<(),Error>{
-        let e=unsafe {ssh_disconnect(self.session)};
+        debug_assert_eq!(self.children.get(),0,"disconnecting a session with live channels");
+        let e=self.drop_connection();
         if e==SSH_OK { Ok(()) } else {Err(err(self))}
     }
+    /// Whether the session is currently connected.
+    pub fn is_connected(&self)->bool {
+        unsafe { ssh_is_connected(self.session)!=0 }
+    }
+    /// Whether the connection was closed, by the server or by a network error.
+    pub(crate) fn connection_lost(&self)->bool {
+        // SSH_CLOSED|SSH_CLOSED_ERROR
+        !self.is_connected() || unsafe { ssh_get_status(self.session) }&(0x01|0x04)!=0
+    }
+    /// Wait until the data buffered by libssh has been written to the socket, for at most `timeout`. Returns `false` if some of it is still unsent after `timeout`.
+    ///
+    /// Writes (to channels, SCP or SFTP) return once libssh has accepted the data, which may still be in its output buffer: a program exiting right after a write could lose its end. The `close` methods of `Session`, `Channel` and `Scp`, and `flush` on channels, call this.
+    pub fn blocking_flush(&self,timeout:std::time::Duration)->Result<bool,Error> {
+        let ms=std::cmp::min(timeout.as_millis(),c_int::MAX as u128) as c_int;
+        match unsafe { ssh_blocking_flush(self.session,ms) } {
+            SSH_OK=>Ok(true),
+            SSH_ERROR=>Err(err(self)),
+            // SSH_AGAIN
+            _=>Ok(false)
+        }
+    }
+    /// Send the buffered data before dropping a channel or SCP transfer, waiting briefly, and not at all if the session is non-blocking.
+    fn drop_flush(&self) {
+        if unsafe { ssh_is_blocking(self.session) }!=0 {
+            let _=self.blocking_flush(DROP_FLUSH_TIMEOUT);
+        }
+    }
+    /// Shut the session down: send the buffered data, disconnect if still connected, and free it. Unlike dropping the session, this reports errors that occur while disconnecting, and fails with `TimedOut` if buffered data could not be sent within 30 seconds.
+    pub fn close(mut self)->Result<(),Error>{
+        if self.is_connected() {
+            let flushed=self.blocking_flush(FLUSH_TIMEOUT)?;
+            self.disconnect()?;
+            if flushed { Ok(()) } else {
+                Err(std::io::Error::new(std::io::ErrorKind::TimedOut,"data left unsent when closing the session").into())
+            }
+        } else {
+            Ok(())
+        }
+    }
     /// Authenticate with a password.
     pub fn userauth_password(&mut self,p:&str)->Result<(),Error> {
-        let p=std::ffi::CString::new(p).unwrap();
-        let e = unsafe {ssh_userauth_password(self.session,std::ptr::null_mut(),p.as_ptr() as *const _)};
-        if e==SSH_OK { Ok(()) }
-        else { Err(err(self)) }
+        let p=std::ffi::CString::new(p)?;
+        traced!("ssh.auth",{session=self.label(),method="password"},{
+            let e = unsafe {ssh_userauth_password(self.session,std::ptr::null_mut(),p.as_ptr() as *const _)};
+            self.record_auth(auth::AuthMethod::Password,e);
+            if e==SSH_OK { Ok(()) }
+            else { Err(err(self)) }
+        })
     }
-    /// Print a prompt on the standard output, and then ask the user a password on the standard input. The typed password is not echoed.
+    /// Start keyboard-interactive authentication. This only succeeds if the server asks no questions: use `userauth_kbdint_with` to answer them.
     pub fn userauth_kbdint(&mut self,user:Option<&str>)->Result<(),Error> {
-        let e = match user {
-            None=>unsafe { ssh_userauth_kbdint(self.session,std::ptr::null_mut(),std::ptr::null_mut()) },
-            Some(p)=> {
-                let p=std::ffi::CString::new(p).unwrap();
-                unsafe {ssh_userauth_kbdint(self.session,p.as_ptr() as *const _,std::ptr::null_mut())}
-            }
-        };
-        if e==SSH_OK { Ok(()) }
-        else { Err(err(self)) }
+        traced!("ssh.auth",{session=self.label(),method="keyboard-interactive"},{
+            let e = match user {
+                None=>unsafe { ssh_userauth_kbdint(self.session,std::ptr::null_mut(),std::ptr::null_mut()) },
+                Some(p)=> {
+                    let p=std::ffi::CString::new(p)?;
+                    unsafe {ssh_userauth_kbdint(self.session,p.as_ptr() as *const _,std::ptr::null_mut())}
+                }
+            };
+            self.record_auth(auth::AuthMethod::KeyboardInteractive,e);
+            if e==SSH_OK { Ok(()) }
+            else { Err(err(self)) }
+        })
     }
     /// Print a prompt on the standard output, and then ask the user a password on the standard input. The typed password is not echoed.
     pub fn userauth_publickey_auto(&mut self,p:Option<&str>)->Result<(),Error> {
+        traced!("ssh.auth",{session=self.label(),method="publickey"},{
         let e = match p {
             None=>{
                 unsafe {
@@ -395,27 +923,31 @@ impl Session {
                 }
             },
             Some(p)=> {
-                let p=std::ffi::CString::new(p).unwrap();
+                let p=std::ffi::CString::new(p)?;
                 unsafe {ssh_userauth_publickey_auto(self.session,
                                                     std::ptr::null_mut(),
                                                     p.as_ptr() as *const _) }
             }
         };
+        self.record_auth(auth::AuthMethod::PublicKey,e);
         if e==SSH_OK { Ok(()) }
         else { Err(err(self)) }
+        })
     }
     /// Start an SCP connection.
     pub fn scp_new<'b,P: AsRef<Path>>(&'b mut self,mode:Mode,v:P)->Result<Scp<'b>,Error> {
         let scp= unsafe {
             ssh_scp_new(self.session,
                         mode.bits(),
-                        path_as_ptr(v.as_ref()).as_ptr() as *const _)
+                        path_as_ptr(v.as_ref())?.as_ptr() as *const _)
         };
         if scp.is_null() {
             Err(err(self))
         } else {
+            self.children.set(self.children.get()+1);
             Ok(Scp { session:self,
-                     scp:scp,size:0 })
+                     scp:scp,size:0,
+                     transferred:0,started:std::time::Instant::now() })
         }
     }
     /// Start a channel to issue remote commands.
@@ -424,20 +956,34 @@ impl Session {
         if e.is_null() {
             Err(err(self))
         } else {
+            self.children.set(self.children.get()+1);
             Ok(Channel { session:self,channel:e })
         }
     }
+    /// Check that the server can connect to `host:port`, from its side of the network, by opening a forwarding channel (as `ssh -L` would) and closing it right away. Fails with `Error::ChannelOpen` if the server refuses, giving its reason (`ConnectFailed` if nothing listens there, `AdministrativelyProhibited` if forwarding is disabled), or with a `TimedOut` error if it doesn't answer within `timeout`.
+    pub fn check_remote_port(&mut self,host:&str,port:u16,timeout:std::time::Duration)->Result<(),Error> {
+        let previous=self.timeout;
+        self.set_timeout(timeout)?;
+        let result=self.channel_new().and_then(|mut channel| {
+            channel.open_forward(host,port,"127.0.0.1",0)?;
+            channel.close()
+        });
+        self.set_timeout(previous.unwrap_or(std::time::Duration::from_secs(10)))?;
+        result
+    }
 }
 
 
 impl Drop for Session {
     fn drop(&mut self) {
+        debug_assert_eq!(self.children.get(),0,"session freed before its channels");
         debug!("ssh_free");
+        self.watchdog.set_fd(-1);
         unsafe {ssh_free(self.session)}
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
 #[repr(C)]
 pub enum ServerKnown {
     /// The key is unknown
@@ -470,10 +1016,23 @@ extern "C" {
     fn ssh_channel_close(s:*mut Channel_)->c_int;
     fn ssh_channel_free(s:*mut Channel_);
     fn ssh_channel_open_session(s:*mut Channel_)->c_int;
+    fn ssh_channel_open_forward(s:*mut Channel_,remotehost:*const c_char,remoteport:c_int,sourcehost:*const c_char,localport:c_int)->c_int;
     fn ssh_channel_request_exec(s:*mut Channel_,b:*const c_char)->c_int;
+    fn ssh_channel_request_subsystem(s:*mut Channel_,b:*const c_char)->c_int;
+    fn ssh_channel_write_stderr(s:*mut Channel_,data:*const c_void,len:u32)->c_int;
+    fn ssh_channel_request_pty_size(s:*mut Channel_,term:*const c_char,cols:c_int,rows:c_int)->c_int;
+    fn ssh_channel_change_pty_size(s:*mut Channel_,cols:c_int,rows:c_int)->c_int;
+    fn ssh_channel_request_shell(s:*mut Channel_)->c_int;
+    fn ssh_channel_request_send_signal(s:*mut Channel_,sig:*const c_char)->c_int;
     fn ssh_channel_read(s:*mut Channel_,b:*mut c_char,c:size_t,is_stderr:c_int)->c_int;
+    fn ssh_channel_read_timeout(s:*mut Channel_,b:*mut c_char,c:size_t,is_stderr:c_int,timeout_ms:c_int)->c_int;
+    fn ssh_channel_poll(s:*mut Channel_,is_stderr:c_int)->c_int;
+    fn ssh_channel_poll_timeout(s:*mut Channel_,timeout:c_int,is_stderr:c_int)->c_int;
+    fn ssh_channel_write(s:*mut Channel_,b:*const c_void,c:u32)->c_int;
     fn ssh_channel_send_eof(s:*mut Channel_)->c_int;
     fn ssh_channel_get_exit_status(s:*const Channel_)->c_int;
+    fn ssh_channel_is_open(s:*mut Channel_)->c_int;
+    fn ssh_channel_window_size(s:*mut Channel_)->u32;
 }
 
 pub struct Channel<'b> {
@@ -483,30 +1042,99 @@ pub struct Channel<'b> {
 
 impl <'b> Channel<'b> {
     pub fn open_session(&mut self)->Result<(),Error> {
-        let e= unsafe { ssh_channel_open_session(self.channel) };
-        if e==0 {
-            Ok(())
-        } else {
-            Err(err(self.session))
-        }
+        traced!("ssh.channel_open",{session=self.session.label(),kind="session"},{
+            let e= unsafe { ssh_channel_open_session(self.channel) };
+            if e==0 {
+                Ok(())
+            } else {
+                Err(channel_open_err(self.session))
+            }
+        })
+    }
+    /// Open a channel to `remote_host:remote_port`, through the server (as `ssh -L`). `source_host` and `source_port` are only informative, and tell the server where the connection comes from.
+    pub fn open_forward(&mut self,remote_host:&str,remote_port:u16,source_host:&str,source_port:u16)->Result<(),Error> {
+        let remote=CString::new(remote_host)?;
+        let source=CString::new(source_host)?;
+        traced!("ssh.channel_open",{session=self.session.label(),kind="direct-tcpip"},{
+            let e=unsafe { ssh_channel_open_forward(self.channel,remote.as_ptr(),remote_port as c_int,source.as_ptr(),source_port as c_int) };
+            if e==SSH_OK {
+                Ok(())
+            } else if e==SSH_AGAIN {
+                // The server didn't answer within the timeout of the session.
+                Err(Error::IO(std::io::Error::new(std::io::ErrorKind::TimedOut,format!("no answer to the opening of a channel to {}:{}",remote_host,remote_port))))
+            } else {
+                Err(channel_open_err(self.session))
+            }
+        })
     }
 }
 
+/// One of the two data streams of a channel: the normal data (standard input or output of a command), or the extended data of type 1 (its standard error).
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum Stream {
+    Stdout,
+    Stderr
+}
+
 pub struct ChannelReader<'d,'c:'d> {
     channel:&'d Channel<'c>,
     is_stderr:c_int
 }
 
 impl <'d,'c:'d> Channel<'c> {
-    pub fn request_exec(&mut self,cmd:&[u8])->Result<(),Error> {
-        let str=std::ffi::CString::new(cmd).unwrap();
-        let e = unsafe {ssh_channel_request_exec(self.channel,str.as_ptr() as *const _)};
+    /// Run a command on the remote server. The command is a `&str`, a `String` or bytes (`b"ls"`); libssh takes it as a C string, so a command containing a NUL byte is rejected with `Error::Nul`. See `request_exec_shell` to choose the shell that runs it.
+    pub fn request_exec<C:AsRef<[u8]>>(&mut self,cmd:C)->Result<(),Error> {
+        let cmd=cmd.as_ref();
+        let str=std::ffi::CString::new(cmd)?;
+        traced!("ssh.exec",{session=self.session.label(),command=%String::from_utf8_lossy(cmd)},{
+            let e = unsafe {ssh_channel_request_exec(self.channel,str.as_ptr() as *const _)};
+            if e==SSH_OK {
+                Ok(())
+            } else {
+                Err(err(self.session))
+            }
+        })
+    }
+    /// Run `cmd`, and send `input` verbatim to its standard input, followed by an EOF.
+    ///
+    /// The bytes of `input` travel in the channel data stream and are never interpreted by a shell, so this is the way to feed arbitrary binary data (including NUL bytes) to a remote program. The output can then be read with `stdout` and `stderr`.
+    pub fn exec_bytes(&mut self,cmd:&str,input:&[u8])->Result<(),Error> {
+        self.request_exec(cmd)?;
+        self.write_all(input)?;
+        self.send_eof()
+    }
+    /// Start a subsystem (such as "sftp") instead of a command. This is the alternative to `request_exec` for protocols spoken over the channel data stream.
+    pub fn request_subsystem(&mut self,name:&str)->Result<(),Error> {
+        let name=std::ffi::CString::new(name)?;
+        let e = unsafe {ssh_channel_request_subsystem(self.channel,name.as_ptr())};
         if e==SSH_OK {
             Ok(())
         } else {
             Err(err(self.session))
         }
     }
+    /// Ask for a pseudo-terminal of type `term` (such as "xterm-256color"), of `cols` columns and `rows` rows, before starting a shell or a command. Recent versions of libssh send the modes of the local terminal along; see `request_pty_modes` to choose them.
+    pub fn request_pty(&mut self,term:&str,cols:u32,rows:u32)->Result<(),Error> {
+        let term=CString::new(term)?;
+        let e=unsafe { ssh_channel_request_pty_size(self.channel,term.as_ptr(),cols as c_int,rows as c_int) };
+        if e==SSH_OK { Ok(()) } else { Err(err(self.session)) }
+    }
+    /// Tell the server that the terminal was resized.
+    pub fn change_pty_size(&mut self,cols:u32,rows:u32)->Result<(),Error> {
+        let e=unsafe { ssh_channel_change_pty_size(self.channel,cols as c_int,rows as c_int) };
+        if e==SSH_OK { Ok(()) } else { Err(err(self.session)) }
+    }
+    /// Start the login shell of the user, instead of a command.
+    pub fn request_shell(&mut self)->Result<(),Error> {
+        let e=unsafe { ssh_channel_request_shell(self.channel) };
+        if e==SSH_OK { Ok(()) } else { Err(err(self.session)) }
+    }
+    /// Send a signal to the remote command, named without the `SIG` prefix ("TERM", "HUP", "INT"…). Servers are free to ignore it (OpenSSH supports it since version 8.1).
+    pub fn send_signal(&mut self,signal:&str)->Result<(),Error> {
+        let signal=CString::new(signal)?;
+        let e=unsafe { ssh_channel_request_send_signal(self.channel,signal.as_ptr()) };
+        if e==SSH_OK { Ok(()) } else { Err(err(self.session)) }
+    }
     pub fn send_eof(&mut self)->Result<(),Error> {
         let e=unsafe { ssh_channel_send_eof(self.channel) };
         if e==0 {
@@ -523,21 +1151,154 @@ impl <'d,'c:'d> Channel<'c> {
             Some(e)
         }
     }
+    /// Number of bytes the remote side is currently willing to receive on this channel (its receive window). Writing more than this blocks until the window is adjusted by the remote.
+    ///
+    /// libssh does not expose the remote maximum packet size, but splits writes into packets by itself.
+    pub fn window_size(&self)->u32 {
+        unsafe { ssh_channel_window_size(self.channel) }
+    }
+    /// Write as much of `buf` as the remote window allows, without waiting for the remote to open its window. If the window is closed, this fails with an error of kind `WouldBlock`.
+    pub fn try_write(&mut self,buf:&[u8])->Result<usize,std::io::Error> {
+        if buf.is_empty() {
+            return Ok(0)
+        }
+        // In non-blocking mode, libssh processes the pending window adjustments, and then writes only what fits in the window.
+        let len=std::cmp::min(buf.len(),u32::MAX as usize) as u32;
+        let e=unsafe {
+            let blocking=ssh_is_blocking(self.session.session);
+            ssh_set_blocking(self.session.session,0);
+            let e=ssh_channel_write(self.channel,buf.as_ptr() as *const c_void,len);
+            ssh_set_blocking(self.session.session,blocking);
+            e
+        };
+        if e>0 {
+            self.session.tap(self.channel,tap::Direction::Sent,false,&buf[..e as usize]);
+            Ok(e as usize)
+        } else if e==0 {
+            Err(std::io::ErrorKind::WouldBlock.into())
+        } else {
+            Err(self.write_err())
+        }
+    }
+    /// Wait until the remote window opens, for at most `timeout`. Returns `false` if the window was still closed after `timeout`.
+    ///
+    /// libssh only processes incoming packets here while one of the output streams has no unread data, so interactive applications should keep reading the output of the remote command.
+    pub fn flush_window(&mut self,timeout:std::time::Duration)->Result<bool,Error> {
+        let deadline=std::time::Instant::now()+timeout;
+        loop {
+            if self.window_size()>0 {
+                return Ok(true)
+            }
+            let now=std::time::Instant::now();
+            if now>=deadline {
+                return Ok(false)
+            }
+            let slice=std::cmp::min(deadline-now,std::time::Duration::from_millis(10));
+            // Polling a stream with an empty buffer makes libssh read from the socket, including window adjustments.
+            let empty=[0,1].iter().cloned().find(|&is_stderr| unsafe { ssh_channel_poll(self.channel,is_stderr) }==0);
+            match empty {
+                Some(is_stderr)=>{
+                    if unsafe { ssh_channel_poll_timeout(self.channel,millis(slice),is_stderr) }==SSH_ERROR {
+                        return Err(err(self.session))
+                    }
+                },
+                None=>std::thread::sleep(slice)
+            }
+        }
+    }
+    /// Wait up to `timeout` for data on the standard output (or error, if `is_stderr`), and return the number of bytes available, `Ok(0)` on timeout, and `Ok(None)` at the end of the stream.
+    pub fn poll_timeout(&mut self,timeout:std::time::Duration,is_stderr:bool)->Result<Option<usize>,Error> {
+        let e=unsafe { ssh_channel_poll_timeout(self.channel,millis(timeout),is_stderr as c_int) };
+        if e>=0 {
+            Ok(Some(e as usize))
+        } else if e==SSH_EOF {
+            Ok(None)
+        } else {
+            Err(err(self.session))
+        }
+    }
+    /// Read from the standard output (or error, if `is_stderr`), waiting at most `timeout`. Returns `Ok(0)` on timeout or at the end of the stream.
+    pub fn read_timeout(&mut self,buf:&mut [u8],timeout:std::time::Duration,is_stderr:bool)->Result<usize,Error> {
+        let e=unsafe { ssh_channel_read_timeout(self.channel,buf.as_mut_ptr() as *mut c_char,buf.len() as size_t,is_stderr as c_int,millis(timeout)) };
+        if e>=0 {
+            self.session.tap(self.channel,tap::Direction::Received,is_stderr,&buf[..e as usize]);
+            Ok(e as usize)
+        } else if e==SSH_EOF {
+            Ok(0)
+        } else {
+            Err(err(self.session))
+        }
+    }
+    /// Send `data` on `stream`, and return the number of bytes sent. Sending on `Stream::Stderr` is for servers, or for peers expecting extended data from a client.
+    pub fn write_ext(&mut self,data:&[u8],stream:Stream)->Result<usize,Error> {
+        let len=std::cmp::min(data.len(),u32::MAX as usize) as u32;
+        let e=unsafe {
+            match stream {
+                Stream::Stdout=>ssh_channel_write(self.channel,data.as_ptr() as *const c_void,len),
+                Stream::Stderr=>ssh_channel_write_stderr(self.channel,data.as_ptr() as *const c_void,len)
+            }
+        };
+        if e>=0 {
+            self.session.tap(self.channel,tap::Direction::Sent,stream==Stream::Stderr,&data[..e as usize]);
+            Ok(e as usize)
+        } else {
+            Err(Error::IO(self.write_err()))
+        }
+    }
+    /// Read from `stream`, waiting until data is available. Returns 0 at the end of the stream.
+    pub fn read_ext(&mut self,buf:&mut [u8],stream:Stream)->Result<usize,Error> {
+        let e=unsafe { ssh_channel_read(self.channel,buf.as_mut_ptr() as *mut c_char,buf.len() as size_t,(stream==Stream::Stderr) as c_int) };
+        if e>=0 {
+            self.session.tap(self.channel,tap::Direction::Received,stream==Stream::Stderr,&buf[..e as usize]);
+            Ok(e as usize)
+        } else if e==SSH_EOF {
+            Ok(0)
+        } else {
+            Err(err(self.session))
+        }
+    }
     pub fn stdout(&'d mut self)->ChannelReader<'d,'c> {
         ChannelReader { channel:self, is_stderr: 0 }
     }
     pub fn stderr(&'d mut self)->ChannelReader<'d,'c> {
         ChannelReader { channel:self, is_stderr: 1 }
     }
-    pub fn close(&mut self) {
-        unsafe { ssh_channel_close(self.channel) };
+    /// Close the channel, after sending the data written to it. Fails with `TimedOut` if that data could not be sent within 30 seconds (the channel is closed anyway).
+    pub fn close(&mut self)->Result<(),Error> {
+        let flushed=self.session.blocking_flush(FLUSH_TIMEOUT)?;
+        if unsafe { ssh_channel_close(self.channel) }!=SSH_OK {
+            return Err(err(self.session))
+        }
+        if flushed { Ok(()) } else {
+            Err(std::io::Error::new(std::io::ErrorKind::TimedOut,"data left unsent when closing the channel").into())
+        }
+    }
+    /// The error of a failed write. libssh refuses writes to a channel closed by the remote side (for instance after the remote command has exited), or on which we have sent EOF: these are reported as `BrokenPipe`, as when writing to a local pipe whose reader is gone. The output received before the close can still be read.
+    fn write_err(&self)->std::io::Error {
+        let code=unsafe { ssh_get_error_code(self.session.session as *const c_void) };
+        let e=err(self.session);
+        if code==SSH_REQUEST_DENIED && self.session.is_connected() {
+            std::io::Error::new(std::io::ErrorKind::BrokenPipe,e)
+        } else {
+            e.into()
+        }
     }
 }
 
+/// Dropping an open channel sends EOF and closes it before freeing it, unless the session has already been disconnected, in which case the channel is only freed.
 impl<'b> Drop for Channel<'b> {
     fn drop(&mut self) {
-        debug!("ssh_channel_free");
-        unsafe { ssh_channel_free(self.channel) };
+        unsafe {
+            if self.session.is_connected() && ssh_channel_is_open(self.channel)!=0 {
+                // ssh_channel_close sends EOF first if we haven't already.
+                self.session.drop_flush();
+                debug!("ssh_channel_close");
+                ssh_channel_close(self.channel);
+            }
+            debug!("ssh_channel_free");
+            ssh_channel_free(self.channel)
+        };
+        self.session.release_child();
     }
 }
 
@@ -548,6 +1309,8 @@ impl <'d,'c> Read for ChannelReader<'d,'c> {
                                         buf.len() as size_t,
                                         self.is_stderr) };
         if e>=0 {
+            count!("ssh_bytes_received_total",e,"transport"=>"channel");
+            self.channel.session.tap(self.channel.channel,tap::Direction::Received,self.is_stderr==1,&buf[..e as usize]);
             Ok(e as usize)
         } else {
             Err(std::io::Error::last_os_error())
@@ -555,6 +1318,28 @@ impl <'d,'c> Read for ChannelReader<'d,'c> {
     }
 }
 
+/// Writing to a channel sends data to the standard input of the remote command.
+impl<'c> Write for Channel<'c> {
+    fn write(&mut self,buf:&[u8])->Result<usize,std::io::Error> {
+        let len=std::cmp::min(buf.len(),u32::MAX as usize) as u32;
+        let e=unsafe { ssh_channel_write(self.channel,
+                                         buf.as_ptr() as *const c_void,
+                                         len) };
+        if e>=0 {
+            count!("ssh_bytes_sent_total",e,"transport"=>"channel");
+            self.session.tap(self.channel,tap::Direction::Sent,false,&buf[..e as usize]);
+            Ok(e as usize)
+        } else {
+            Err(self.write_err())
+        }
+    }
+    /// Wait until the data written so far has been sent to the server (for at most 30 seconds).
+    fn flush(&mut self)->Result<(),std::io::Error> {
+        if self.session.blocking_flush(FLUSH_TIMEOUT)? { Ok(()) } else {
+            Err(std::io::Error::new(std::io::ErrorKind::TimedOut,"timed out sending channel data"))
+        }
+    }
+}
 
 extern "C" {
     // The "SCP subsystem"
@@ -567,11 +1352,11 @@ extern "C" {
     fn ssh_scp_deny_request(s:*mut Scp_)->c_int;
     fn ssh_scp_read(s:*mut Scp_,b:*mut c_char,st:size_t)->c_int;
     //fn ssh_scp_push_file(s:*mut Scp_,b:*const c_char,st:size_t,mode:c_int)->c_int;
-    fn ssh_scp_push_file64(s:*mut Scp_,b:*const c_char,st:uint64_t,mode:c_int)->c_int;
+    fn ssh_scp_push_file64(s:*mut Scp_,b:*const c_char,st:u64,mode:c_int)->c_int;
     fn ssh_scp_push_directory(s:*mut Scp_,b:*const c_char,mode:c_int)->c_int;
     fn ssh_scp_write(s:*mut Scp_,b:*const c_char,st:size_t)->c_int;
     //fn ssh_scp_request_get_size(s:*mut Scp_)->c_int;
-    fn ssh_scp_request_get_size64(s:*mut Scp_)->uint64_t;
+    fn ssh_scp_request_get_size64(s:*mut Scp_)->u64;
     fn ssh_scp_request_get_permissions(s:*mut Scp_)->c_int;
     fn ssh_scp_request_get_filename(s:*mut Scp_)->*const c_char;
     fn ssh_scp_request_get_warning(s:*mut Scp_)->*const c_char;
@@ -588,7 +1373,12 @@ enum Scp_ {}
 pub struct Scp<'b> {
     session:&'b Session,
     scp:*mut Scp_,
-    size:usize
+    /// Bytes remaining in the file being pulled or pushed.
+    size:u64,
+    /// Bytes read or written so far, for instrumentation.
+    transferred:u64,
+    #[cfg_attr(not(feature="tracing"),allow(dead_code))]
+    started:std::time::Instant
 }
 
 
@@ -615,22 +1405,119 @@ pub enum Request {
     WARNING
 }
 
+/// Unix permissions of a remote file (the lower twelve bits of a `mode_t`).
+///
+/// Can be built from an octal literal (`Permissions::from(0o644)`), or parsed from the symbolic notation of `ls -l` (`"rw-r--r--".parse()`).
+#[derive(Clone,Copy,PartialEq,Eq,Hash)]
+pub struct Permissions(u32);
+
+impl Permissions {
+    pub fn from_mode(mode:u32)->Permissions {
+        Permissions(mode)
+    }
+    pub fn mode(&self)->u32 {
+        self.0
+    }
+    fn to_c_int(self)->Result<c_int,Error> {
+        if self.0 <= 0o7777 {
+            Ok(self.0 as c_int)
+        } else {
+            Err(Error::InvalidPermissions(format!("{:o} is not a valid mode", self.0)))
+        }
+    }
+}
+
+impl From<u32> for Permissions {
+    fn from(mode:u32)->Permissions {
+        Permissions(mode)
+    }
+}
+
+impl From<Permissions> for u32 {
+    fn from(p:Permissions)->u32 {
+        p.0
+    }
+}
+
+impl fmt::Debug for Permissions {
+    fn fmt(&self,f:&mut fmt::Formatter)->fmt::Result {
+        write!(f,"Permissions({:#o})",self.0)
+    }
+}
+
+impl fmt::Display for Permissions {
+    fn fmt(&self,f:&mut fmt::Formatter)->fmt::Result {
+        let m=self.0;
+        let special=[(0o4000,'s','S'),(0o2000,'s','S'),(0o1000,'t','T')];
+        for (i,&(bit,set,unset)) in special.iter().enumerate() {
+            let shift=6-3*i;
+            write!(f,"{}",if m&(0o4<<shift)!=0 { 'r' } else { '-' })?;
+            write!(f,"{}",if m&(0o2<<shift)!=0 { 'w' } else { '-' })?;
+            let x=m&(0o1<<shift)!=0;
+            write!(f,"{}",match (m&bit!=0,x) {
+                (true,true)=>set,
+                (true,false)=>unset,
+                (false,true)=>'x',
+                (false,false)=>'-'
+            })?
+        }
+        Ok(())
+    }
+}
+
+impl std::str::FromStr for Permissions {
+    type Err=Error;
+    /// Parse a symbolic mode such as `rw-r--r--`, optionally preceded by the file type character of `ls -l` (as in `-rw-r--r--` or `drwxr-xr-x`).
+    fn from_str(s:&str)->Result<Permissions,Error> {
+        let invalid=|| Error::InvalidPermissions(format!("{:?} is not a symbolic mode", s));
+        let b=s.as_bytes();
+        let b=match b.len() { 9=>b, 10=>&b[1..], _=>return Err(invalid()) };
+        let special=[0o4000,0o2000,0o1000];
+        let mut mode=0;
+        for i in 0..3 {
+            let shift=6-3*i;
+            match b[3*i] { b'r'=>mode|=0o4<<shift, b'-'=>(), _=>return Err(invalid()) }
+            match b[3*i+1] { b'w'=>mode|=0o2<<shift, b'-'=>(), _=>return Err(invalid()) }
+            let (sticky,set)=if i==2 { (b't',b'T') } else { (b's',b'S') };
+            match b[3*i+2] {
+                b'x'=>mode|=0o1<<shift,
+                b'-'=>(),
+                c if c==sticky=>mode|=special[i]|(0o1<<shift),
+                c if c==set=>mode|=special[i],
+                _=>return Err(invalid())
+            }
+        }
+        Ok(Permissions(mode))
+    }
+}
+
 impl <'b>Drop for Scp<'b> {
     fn drop(&mut self) {
         unsafe {
+            trace_event!(bytes=self.transferred,elapsed_us=self.started.elapsed().as_micros() as u64,"scp transfer finished");
+            if self.session.is_connected() {
+                self.session.drop_flush();
+                debug!("ssh_scp_close");
+                ssh_scp_close(self.scp);
+            }
             debug!("ssh_scp_free");
             ssh_scp_free(self.scp);
         }
+        self.session.release_child();
     }
 }
 
 impl <'b>Scp<'b> {
     pub fn init(&mut self)->Result<(),Error> {
-        let e= unsafe {ssh_scp_init(self.scp)};
-        if e==0 { Ok(()) }
-        else { Err(err(self.session)) }
+        traced!("ssh.scp.init",{session=self.session.label()},{
+            let e= unsafe {ssh_scp_init(self.scp)};
+            if e==0 { Ok(()) }
+            else { Err(scp_init_err(self.session)) }
+        })
     }
+    /// End the transfer, after sending the data written (waiting for at most 30 seconds).
     pub fn close(&mut self) {
+        let _=self.session.blocking_flush(FLUSH_TIMEOUT);
         unsafe {
             ssh_scp_close(self.scp);
         }
@@ -646,21 +1533,56 @@ impl <'b>Scp<'b> {
             }
         }
     }
-    pub fn push_file<P:AsRef<Path>>(&mut self,path:P,size:usize,mode:usize)->Result<(),Error> {
-        unsafe {
-            let p=path_as_ptr(path.as_ref());
-            let e=ssh_scp_push_file64(self.scp,p.as_ptr() as *const _,size as uint64_t,mode as c_int);
+    /// Announce a new file of `size` bytes, which must then be written completely with `write`. The size is 64 bits wide on all platforms, so that files larger than 4 GiB can be sent from 32-bit targets.
+    pub fn push_file<P:AsRef<Path>,M:Into<Permissions>>(&mut self,path:P,size:u64,mode:M)->Result<(),Error> {
+        let mode=mode.into().to_c_int()?;
+        traced!("ssh.scp.push_file",{session=self.session.label(),path=?path.as_ref(),size=size},unsafe {
+            let p=path_as_ptr(path.as_ref())?;
+            let e=ssh_scp_push_file64(self.scp,p.as_ptr() as *const _,size,mode);
             if e==0 {
+                self.size=size;
                 Ok(())
             } else {
                 Err(err(self.session))
             }
+        })
+    }
+    /// Push a file of `size` bytes, read from `source`. Short reads are handled, but `source` must provide exactly `size` bytes: if it ends early, the transfer is aborted with an `UnexpectedEof` error (the remote file is then incomplete, and this `Scp` cannot be used anymore), and if it has more data, an `InvalidData` error is returned after sending the first `size` bytes.
+    pub fn push_file_from<P:AsRef<Path>,M:Into<Permissions>,R:Read>(&mut self,path:P,size:u64,mode:M,mut source:R)->Result<(),Error> {
+        self.push_file(path,size,mode)?;
+        let mut buf=vec![0;32768];
+        while self.size>0 {
+            let n=chunk_len(self.size,buf.len());
+            let r=match source.read(&mut buf[..n]) {
+                Ok(0)=>{
+                    self.close();
+                    return Err(Error::IO(std::io::Error::new(std::io::ErrorKind::UnexpectedEof,
+                                                             format!("source ended after {} of {} bytes",size-self.size,size))))
+                },
+                Ok(r)=>r,
+                Err(ref e) if e.kind()==std::io::ErrorKind::Interrupted=>continue,
+                Err(e)=>{
+                    self.close();
+                    return Err(e.into())
+                }
+            };
+            self.write_all(&buf[..r])?;
+        }
+        loop {
+            match source.read(&mut buf[..1]) {
+                Ok(0)=>return Ok(()),
+                Ok(_)=>return Err(Error::IO(std::io::Error::new(std::io::ErrorKind::InvalidData,
+                                                                format!("source is longer than {} bytes",size)))),
+                Err(ref e) if e.kind()==std::io::ErrorKind::Interrupted=>continue,
+                Err(e)=>return Err(e.into())
+            }
         }
     }
-    pub fn push_directory<P:AsRef<Path>>(&mut self,path:P,mode:usize)->Result<(),Error> {
+    pub fn push_directory<P:AsRef<Path>,M:Into<Permissions>>(&mut self,path:P,mode:M)->Result<(),Error> {
+        let mode=mode.into().to_c_int()?;
         unsafe {
-            let p=path_as_ptr(path.as_ref());
-            let e=ssh_scp_push_directory(self.scp,p.as_ptr() as *const _,mode as c_int);
+            let p=path_as_ptr(path.as_ref())?;
+            let e=ssh_scp_push_directory(self.scp,p.as_ptr() as *const _,mode);
             if e==0 {
                 Ok(())
             } else {
@@ -668,12 +1590,13 @@ impl <'b>Scp<'b> {
             }
         }
     }
-    pub fn request_get_size(&mut self)->usize {
-        unsafe { ssh_scp_request_get_size64(self.scp) as usize }
+    /// Size of the file being pulled, in bytes.
+    pub fn request_get_size(&mut self)->u64 {
+        unsafe { ssh_scp_request_get_size64(self.scp) }
     }
-    pub fn request_get_permissions(&mut self)->Result<usize,Error> {
+    pub fn request_get_permissions(&mut self)->Result<Permissions,Error> {
         let e=unsafe { ssh_scp_request_get_permissions(self.scp) };
-        if e>=0 { Ok(e as usize) } else {
+        if e>=0 { Ok(Permissions(e as u32)) } else {
             Err(err(self.session))
         }
     }
@@ -725,6 +1648,11 @@ impl <'b>Scp<'b> {
     }
 }
 
+/// How much of a buffer of `len` bytes can be sent when `remaining` bytes of a file are left to send. `remaining` is 64 bits wide even on 32-bit targets, where it may exceed `usize::MAX`.
+fn chunk_len(remaining:u64,len:usize)->usize {
+    std::cmp::min(remaining,len as u64) as usize
+}
+
 impl<'c> std::io::Read for Scp<'c> {
     fn read(&mut self,buf:&mut [u8])->Result<usize,std::io::Error> {
         if self.size==0 { Ok(0) } else {
@@ -733,7 +1661,9 @@ impl<'c> std::io::Read for Scp<'c> {
                                      buf.as_mut_ptr() as *mut c_char,
                                      buf.len() as size_t) };
             if e>=0 {
-                self.size -= e as usize;
+                self.size=self.size.saturating_sub(e as u64);
+                self.transferred+=e as u64;
+                count!("ssh_bytes_received_total",e,"transport"=>"scp");
                 Ok(e as usize)
             } else {
                 Err(std::io::Error::new(std::io::ErrorKind::Other,
@@ -745,18 +1675,89 @@ impl<'c> std::io::Read for Scp<'c> {
 
 
 impl<'c> std::io::Write for Scp<'c> {
+    /// Write to the file announced by `push_file`. Writing more than its announced size is an error, since it would corrupt the protocol.
     fn write(&mut self,buf:&[u8])->Result<usize,std::io::Error> {
+        if buf.is_empty() {
+            return Ok(0)
+        }
+        // libssh would silently drop the excess.
+        let len=chunk_len(self.size,buf.len());
+        if len==0 {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput,
+                                           "write past the announced size of the file"))
+        }
         let e=unsafe{ ssh_scp_write(self.scp,
                                     buf.as_ptr() as *mut c_char,
-                                    buf.len() as size_t) };
-        if e>=0 {
-            Ok(e as usize)
+                                    len as size_t) };
+        // ssh_scp_write returns SSH_OK after writing the whole buffer, not a number of bytes.
+        if e==SSH_OK {
+            self.size-=len as u64;
+            self.transferred+=len as u64;
+            count!("ssh_bytes_sent_total",len,"transport"=>"scp");
+            Ok(len)
         } else {
             Err(std::io::Error::new(std::io::ErrorKind::Other,
                                     err(self.session)))
         }
     }
+    /// Wait until the data written so far has been sent to the server (for at most 30 seconds).
     fn flush(&mut self)->Result<(),std::io::Error> {
-        Ok(())
+        if self.session.blocking_flush(FLUSH_TIMEOUT)? { Ok(()) } else {
+            Err(std::io::Error::new(std::io::ErrorKind::TimedOut,"timed out sending file data"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{chunk_len,Permissions};
+
+    const FIVE_GIB:u64=5<<30;
+
+    #[test]
+    fn chunk_len_large_files() {
+        assert_eq!(chunk_len(FIVE_GIB,32768),32768);
+        assert_eq!(chunk_len(FIVE_GIB,usize::MAX),std::cmp::min(FIVE_GIB,usize::MAX as u64) as usize);
+        assert_eq!(chunk_len(10,32768),10);
+        assert_eq!(chunk_len(0,32768),0);
+        // Sending a 5 GiB file in chunks of 8 MiB ends exactly at its size.
+        let (mut remaining,mut sent)=(FIVE_GIB,0u64);
+        while remaining>0 {
+            let n=chunk_len(remaining,8<<20) as u64;
+            remaining-=n;
+            sent+=n;
+        }
+        assert_eq!(sent,FIVE_GIB);
+    }
+
+    #[test]
+    fn permissions_symbolic() {
+        let p:Permissions="rwxr-xr-x".parse().unwrap();
+        assert_eq!(u32::from(p),0o755);
+        assert_eq!(p.to_string(),"rwxr-xr-x");
+        assert_eq!(Permissions::from(0o644).to_string(),"rw-r--r--");
+        // The file type character of `ls -l` is ignored.
+        assert_eq!("drwxr-xr-x".parse::<Permissions>().unwrap().mode(),0o755);
+        let p:Permissions="-rwsr-sr-t".parse().unwrap();
+        assert_eq!(p.mode(),0o7755);
+        assert_eq!(p.to_string(),"rwsr-sr-t");
+    }
+
+    #[test]
+    fn permissions_special_bits() {
+        for (s,mode) in [("rwsr-xr-x",0o4755),("rwSr-xr-x",0o4655),("rwxr-sr-x",0o2755),("rwxr-Sr-x",0o2745),("rwxr-xr-t",0o1755),("rwxr-xr-T",0o1754),("--S--S--T",0o7000)] {
+            let p:Permissions=s.parse().unwrap();
+            assert_eq!(p.mode(),mode,"{}",s);
+            assert_eq!(Permissions::from(mode).to_string(),s);
+        }
+    }
+
+    #[test]
+    fn permissions_invalid() {
+        for s in ["","rwxr-xr-","rwxr-xr-x-x","rwxr-xr-s","rwtr-xr-x","xwrr-xr-x","rwxr-xr-é","rw-r--r--\n"] {
+            assert!(s.parse::<Permissions>().is_err(),"{:?}",s)
+        }
+        assert!(Permissions::from(0o10000).to_c_int().is_err());
+        assert_eq!(Permissions::from(0o7777).to_c_int().unwrap(),0o7777);
     }
 }
@@ -0,0 +1,114 @@
+//! Reading the output of commands line by line, in bounded memory, for output that can't be trusted.
+
+use std::io::Read;
+
+use super::Error;
+
+/// The lines (or records) of a reader, such as the output of a channel, without their delimiter. Lines longer than a maximum are not kept in memory: they end the iteration with `Error::LineTooLong`, and reading more than `max_total` bytes in all ends it with `Error::OutputTooLarge`, so that a hostile remote host can't make the reader grow without bounds.
+///
+///```no_run
+/// use ssh::*;
+///
+/// let mut session=Session::new().unwrap();
+/// session.set_host("pijul.org").unwrap();
+/// session.parse_config(None).unwrap();
+/// session.connect().unwrap();
+/// session.userauth_publickey_auto(None).unwrap();
+/// let mut channel=session.channel_new().unwrap();
+/// channel.open_session().unwrap();
+/// channel.request_exec("find / -print0").unwrap();
+/// channel.send_eof().unwrap();
+/// for path in BoundedLines::new(channel.stdout(),4096).delimiter(0).max_total(1<<24) {
+///     println!("{}",String::from_utf8_lossy(&path.unwrap()))
+/// }
+///```
+pub struct BoundedLines<R> {
+    reader:R,
+    delimiter:u8,
+    max_line:usize,
+    max_total:Option<usize>,
+    total:usize,
+    buf:Box<[u8]>,
+    pos:usize,
+    len:usize,
+    line:Vec<u8>,
+    done:bool
+}
+
+impl<R:Read> BoundedLines<R> {
+    /// Lines of `reader`, each of at most `max_line` bytes.
+    pub fn new(reader:R,max_line:usize)->BoundedLines<R> {
+        BoundedLines {
+            reader,
+            delimiter:b'\n',
+            max_line,
+            max_total:None,
+            total:0,
+            buf:vec![0;8192].into_boxed_slice(),
+            pos:0,
+            len:0,
+            line:Vec::new(),
+            done:false
+        }
+    }
+    /// End records with `delimiter` instead of `\n` (for instance 0, for the output of `find -print0`).
+    pub fn delimiter(mut self,delimiter:u8)->BoundedLines<R> {
+        self.delimiter=delimiter;
+        self
+    }
+    /// Stop with `Error::OutputTooLarge` after reading more than `bytes` bytes.
+    pub fn max_total(mut self,bytes:usize)->BoundedLines<R> {
+        self.max_total=Some(bytes);
+        self
+    }
+    /// The underlying reader. Data read from it, but not returned yet, is lost.
+    pub fn into_inner(self)->R {
+        self.reader
+    }
+    fn next_line(&mut self)->Result<Option<Vec<u8>>,Error> {
+        loop {
+            if self.pos==self.len {
+                let n=self.reader.read(&mut self.buf)?;
+                if n==0 {
+                    return Ok(if self.line.is_empty() { None } else { Some(std::mem::take(&mut self.line)) })
+                }
+                self.total+=n;
+                if let Some(max)=self.max_total {
+                    if self.total>max {
+                        return Err(Error::OutputTooLarge(max))
+                    }
+                }
+                self.pos=0;
+                self.len=n;
+            }
+            let data=&self.buf[self.pos..self.len];
+            let (end,found)=match data.iter().position(|&b| b==self.delimiter) {
+                Some(i)=>(i,true),
+                None=>(data.len(),false)
+            };
+            if self.line.len()+end>self.max_line {
+                return Err(Error::LineTooLong(self.max_line))
+            }
+            self.line.extend_from_slice(&data[..end]);
+            self.pos+=end;
+            if found {
+                self.pos+=1;
+                return Ok(Some(std::mem::take(&mut self.line)))
+            }
+        }
+    }
+}
+
+impl<R:Read> Iterator for BoundedLines<R> {
+    type Item=Result<Vec<u8>,Error>;
+    fn next(&mut self)->Option<Self::Item> {
+        if self.done {
+            return None
+        }
+        let r=self.next_line().transpose();
+        if !matches!(r,Some(Ok(_))) {
+            self.done=true
+        }
+        r
+    }
+}
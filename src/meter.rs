@@ -0,0 +1,31 @@
+//! Metrics with the `metrics` crate, enabled by the "metrics" feature, for the recorder (Prometheus exporter or other) installed by the application. Without it, these macros do nothing.
+//!
+//! The metrics are:
+//!
+//! - `ssh_connections_total`, with a `result` label (`ok` or `error`).
+//! - `ssh_auth_attempts_total`, with `method` (`publickey`, `password`…) and `result` (`success`, `partial` or `denied`) labels.
+//! - `ssh_bytes_sent_total` and `ssh_bytes_received_total`, with a `transport` label (`channel`, `scp` or `sftp`).
+//! - `ssh_commands_total`, with a `result` label, and the histogram `ssh_command_duration_seconds`, for the commands run by `Session::exec` and `RemoteCommand`.
+
+/// Add `$value` to the counter `$name`, with labels `$key=>$label`.
+macro_rules! count {
+    ($name:expr, $value:expr $(, $key:expr => $label:expr)*) => {
+        #[cfg(feature="metrics")]
+        ::metrics::counter!($name $(, $key => $label)*).increment($value as u64);
+    }
+}
+
+/// Evaluate `$body` (of type `Result<_,Error>`), record its duration in the histogram `$histogram`, and count its outcome in the counter `$counter`.
+macro_rules! timed {
+    ($histogram:expr, $counter:expr, $body:expr) => {{
+        #[cfg(feature="metrics")]
+        let start=::std::time::Instant::now();
+        let result=$body;
+        #[cfg(feature="metrics")]
+        {
+            ::metrics::histogram!($histogram).record(start.elapsed().as_secs_f64());
+            ::metrics::counter!($counter, "result"=>if result.is_ok() { "ok" } else { "error" }).increment(1);
+        }
+        result
+    }}
+}
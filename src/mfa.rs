@@ -0,0 +1,298 @@
+//! Multi-factor authentication: several methods tried in order, each partial success leading to the next one, for servers configured with OpenSSH's `AuthenticationMethods` (such as `publickey,keyboard-interactive`).
+
+use std::ffi::CString;
+use std::path::PathBuf;
+use std::time::{Duration,Instant};
+
+use super::libc::{c_char,c_int};
+use super::{CredentialsProvider,Error,KbdintChallenge,Session,Session_,err,ssh_userauth_password,ssh_userauth_publickey_auto};
+use super::auth::{AuthMethod,IdentityFailure,ssh_userauth_agent,SSH_AUTH_DENIED,SSH_AUTH_PARTIAL,SSH_AUTH_SUCCESS};
+
+extern "C" {
+    fn ssh_userauth_list(s:*mut Session_,user:*const c_char)->c_int;
+}
+
+const SSH_AUTH_METHOD_PASSWORD:c_int=0x0002;
+const SSH_AUTH_METHOD_PUBLICKEY:c_int=0x0004;
+const SSH_AUTH_METHOD_INTERACTIVE:c_int=0x0010;
+
+/// What happened to a step of an `AuthPipeline`.
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum StepResult {
+    /// The session is authenticated.
+    Success,
+    /// The step was accepted, but the server requires more authentication.
+    Partial,
+    /// The step was refused.
+    Denied,
+    /// The server doesn't allow this method (anymore), or the credentials provider has no answer for it, so the step was not tried or not completed.
+    Skipped
+}
+
+type Answer<'a>=Box<dyn FnMut(&KbdintChallenge)->Result<Vec<String>,Error>+'a>;
+
+enum Step<'a> {
+    PublicKeyAuto(Option<String>),
+    Agent,
+    Identities(Vec<PathBuf>,Option<String>),
+    Password(String),
+    KeyboardInteractive(Answer<'a>),
+    ProvidedPassword,
+    ProvidedKeyboardInteractive
+}
+
+impl<'a> Step<'a> {
+    fn method(&self)->AuthMethod {
+        match *self {
+            Step::PublicKeyAuto(_)|Step::Agent|Step::Identities(..)=>AuthMethod::PublicKey,
+            Step::Password(_)|Step::ProvidedPassword=>AuthMethod::Password,
+            Step::KeyboardInteractive(_)|Step::ProvidedKeyboardInteractive=>AuthMethod::KeyboardInteractive
+        }
+    }
+}
+
+fn method_flag(m:AuthMethod)->c_int {
+    match m {
+        AuthMethod::PublicKey=>SSH_AUTH_METHOD_PUBLICKEY,
+        AuthMethod::Password=>SSH_AUTH_METHOD_PASSWORD,
+        AuthMethod::KeyboardInteractive=>SSH_AUTH_METHOD_INTERACTIVE,
+        AuthMethod::None=>0
+    }
+}
+
+/// Authentication steps tried in order until the session is authenticated. A step that is refused, or only partially accepted, leads to the next one; steps whose method the server doesn't allow anymore are skipped.
+///
+///```no_run
+/// use ssh::*;
+/// use std::time::Duration;
+///
+/// let mut session=Session::new().unwrap();
+/// session.set_host("pijul.org").unwrap();
+/// session.parse_config(None).unwrap();
+/// session.connect().unwrap();
+/// let methods=AuthPipeline::new()
+///     .agent()
+///     .publickey_auto(None)
+///     .kbdint(|challenge| {
+///         println!("{}",challenge.instruction);
+///         Ok(vec!["123456".to_string();challenge.prompts.len()])
+///     })
+///     .timeout(Duration::from_secs(60))
+///     .on_step(|method,result| eprintln!("{}: {:?}",method,result))
+///     .run(&mut session)
+///     .unwrap();
+/// println!("authenticated with {:?}",methods);
+///```
+pub struct AuthPipeline<'a> {
+    steps:Vec<(Step<'a>,Option<Duration>)>,
+    on_step:Option<Box<dyn FnMut(AuthMethod,StepResult)+'a>>,
+    credentials:Option<Box<dyn CredentialsProvider+'a>>
+}
+
+impl<'a> Default for AuthPipeline<'a> {
+    fn default()->Self {
+        AuthPipeline::new()
+    }
+}
+
+impl<'a> AuthPipeline<'a> {
+    pub fn new()->AuthPipeline<'a> {
+        AuthPipeline { steps:Vec::new(),on_step:None,credentials:None }
+    }
+    fn step(mut self,step:Step<'a>)->AuthPipeline<'a> {
+        self.steps.push((step,None));
+        self
+    }
+    /// The default keys and the agent, as `Session::userauth_publickey_auto`.
+    pub fn publickey_auto(self,passphrase:Option<&str>)->AuthPipeline<'a> {
+        self.step(Step::PublicKeyAuto(passphrase.map(|p| p.to_string())))
+    }
+    /// The keys of the agent, as `Session::userauth_agent`.
+    pub fn agent(self)->AuthPipeline<'a> {
+        self.step(Step::Agent)
+    }
+    /// The private keys in `identities`, as `Session::userauth_identities`. Without `passphrase`, the passphrases of encrypted keys are asked to the `credentials` provider.
+    pub fn identities<P:Into<PathBuf>,I:IntoIterator<Item=P>>(self,identities:I,passphrase:Option<&str>)->AuthPipeline<'a> {
+        self.step(Step::Identities(identities.into_iter().map(|p| p.into()).collect(),passphrase.map(|p| p.to_string())))
+    }
+    pub fn password(self,password:&str)->AuthPipeline<'a> {
+        self.step(Step::Password(password.to_string()))
+    }
+    /// Keyboard-interactive authentication, such as one-time passwords, answered by `answer` as in `Session::userauth_kbdint_with`.
+    pub fn kbdint<F:FnMut(&KbdintChallenge)->Result<Vec<String>,Error>+'a>(self,answer:F)->AuthPipeline<'a> {
+        self.step(Step::KeyboardInteractive(Box::new(answer)))
+    }
+    /// A password, asked to the `credentials` provider when the step runs. The step is skipped if the provider has none.
+    pub fn provided_password(self)->AuthPipeline<'a> {
+        self.step(Step::ProvidedPassword)
+    }
+    /// Keyboard-interactive authentication, each prompt being answered by `CredentialsProvider::get_otp`. The step is skipped without a provider.
+    pub fn provided_kbdint(self)->AuthPipeline<'a> {
+        self.step(Step::ProvidedKeyboardInteractive)
+    }
+    /// Ask `provider` for the credentials needed by the steps: passwords, passphrases of the keys of `identities`, and answers to keyboard-interactive prompts.
+    pub fn credentials<C:CredentialsProvider+'a>(mut self,provider:C)->AuthPipeline<'a> {
+        self.credentials=Some(Box::new(provider));
+        self
+    }
+    /// Limit the duration of the step added last: it is the timeout of the session while the step runs, and a keyboard-interactive step fails with a `TimedOut` error if the answers come later than that.
+    pub fn timeout(mut self,timeout:Duration)->AuthPipeline<'a> {
+        if let Some(last)=self.steps.last_mut() {
+            last.1=Some(timeout)
+        }
+        self
+    }
+    /// Call `f` after each step, with its method and result.
+    pub fn on_step<F:FnMut(AuthMethod,StepResult)+'a>(mut self,f:F)->AuthPipeline<'a> {
+        self.on_step=Some(Box::new(f));
+        self
+    }
+    /// Authenticate `session`, and return the methods that were accepted, in order (the last one completing the authentication).
+    pub fn run(mut self,session:&mut Session)->Result<Vec<AuthMethod>,Error> {
+        let mut accepted=Vec::new();
+        for (mut step,timeout) in self.steps.drain(..) {
+            let method=step.method();
+            // Only known after a first refusal.
+            let allowed=unsafe { ssh_userauth_list(session.session,std::ptr::null()) };
+            let result=if allowed!=0 && allowed&method_flag(method)==0 {
+                StepResult::Skipped
+            } else {
+                let previous=session.timeout();
+                if let Some(t)=timeout {
+                    session.set_timeout(t)?
+                }
+                let r=run_step(session,&mut step,self.credentials.as_deref_mut(),timeout.map(|t| Instant::now()+t));
+                if timeout.is_some() {
+                    session.set_timeout(previous.unwrap_or(Duration::from_secs(10)))?
+                }
+                r?
+            };
+            debug!("authentication step {}: {:?}",method,result);
+            if let Some(ref mut f)=self.on_step {
+                f(method,result)
+            }
+            match result {
+                StepResult::Success=>{
+                    accepted.push(method);
+                    return Ok(accepted)
+                },
+                StepResult::Partial=>accepted.push(method),
+                StepResult::Denied|StepResult::Skipped=>()
+            }
+        }
+        let msg=if accepted.is_empty() {
+            "no authentication step was accepted".to_string()
+        } else {
+            format!("partially authenticated with {:?}, but no further step was accepted",accepted)
+        };
+        Err(Error::RequestDenied(msg))
+    }
+}
+
+/// Try the keys of `ids` as `Session::userauth_identities`, then try again the encrypted ones with the passphrases given by `credentials`.
+fn identities<'c>(session:&mut Session,ids:&[PathBuf],passphrase:Option<&str>,credentials:Option<&mut (dyn CredentialsProvider+'c)>)->Result<PathBuf,Error> {
+    let failures=match session.userauth_identities(ids,passphrase) {
+        Err(Error::Identities(failures))=>failures,
+        r=>return r
+    };
+    let c=match credentials {
+        Some(c) if passphrase.is_none() && failures.iter().all(|f| f.1!=IdentityFailure::Partial)=>c,
+        _=>return Err(Error::Identities(failures))
+    };
+    let mut all=Vec::with_capacity(failures.len());
+    for (key,failure) in failures {
+        let p=if failure==IdentityFailure::Encrypted { c.get_key_passphrase(&key) } else { None };
+        match p {
+            Some(p)=>match session.userauth_identities(&[&key],Some(&p)) {
+                Err(Error::Identities(f))=>{
+                    let partial=f.iter().any(|f| f.1==IdentityFailure::Partial);
+                    all.extend(f);
+                    if partial {
+                        break
+                    }
+                },
+                r=>return r
+            },
+            None=>all.push((key,failure))
+        }
+    }
+    Err(Error::Identities(all))
+}
+
+/// Answer keyboard-interactive rounds with `answer`, failing if the answers come after `deadline`.
+fn kbdint(session:&mut Session,answer:&mut dyn FnMut(&KbdintChallenge)->Result<Vec<String>,Error>,deadline:Option<Instant>)->Result<c_int,Error> {
+    let mut timed=|c:&KbdintChallenge| {
+        let answers=answer(c)?;
+        match deadline {
+            Some(d) if Instant::now()>d=>Err(Error::IO(std::io::Error::new(std::io::ErrorKind::TimedOut,"keyboard-interactive answers came too late"))),
+            _=>Ok(answers)
+        }
+    };
+    session.kbdint_rounds(None,&mut timed)
+}
+
+fn password_auth(session:&mut Session,password:&str)->Result<c_int,Error> {
+    let p=CString::new(password)?;
+    let e=unsafe { ssh_userauth_password(session.session,std::ptr::null(),p.as_ptr()) };
+    session.record_auth(AuthMethod::Password,e);
+    Ok(e)
+}
+
+fn run_step<'c>(session:&mut Session,step:&mut Step,credentials:Option<&mut (dyn CredentialsProvider+'c)>,deadline:Option<Instant>)->Result<StepResult,Error> {
+    let e=match *step {
+        Step::PublicKeyAuto(ref passphrase)=>{
+            let p=match *passphrase { Some(ref p)=>Some(CString::new(p.as_str())?), None=>None };
+            let e=unsafe { ssh_userauth_publickey_auto(session.session,std::ptr::null(),p.as_ref().map(|p| p.as_ptr()).unwrap_or(std::ptr::null())) };
+            session.record_auth(AuthMethod::PublicKey,e);
+            e
+        },
+        Step::Agent=>{
+            let e=unsafe { ssh_userauth_agent(session.session,std::ptr::null()) };
+            session.record_auth(AuthMethod::PublicKey,e);
+            e
+        },
+        Step::Identities(ref ids,ref passphrase)=>match identities(session,ids,passphrase.as_deref(),credentials) {
+            Ok(_)=>SSH_AUTH_SUCCESS,
+            Err(Error::Identities(ref failures)) if failures.last().map(|f| &f.1)==Some(&IdentityFailure::Partial)=>SSH_AUTH_PARTIAL,
+            Err(Error::Identities(_))=>SSH_AUTH_DENIED,
+            Err(e)=>return Err(e)
+        },
+        Step::Password(ref password)=>password_auth(session,password)?,
+        Step::ProvidedPassword=>{
+            let (host,user)=(session.host().unwrap_or_default(),session.username().unwrap_or_default());
+            match credentials.and_then(|c| c.get_password(&host,&user)) {
+                Some(password)=>password_auth(session,&password)?,
+                None=>return Ok(StepResult::Skipped)
+            }
+        },
+        Step::KeyboardInteractive(ref mut answer)=>kbdint(session,&mut **answer,deadline)?,
+        Step::ProvidedKeyboardInteractive=>match credentials {
+            Some(c)=>{
+                // A prompt the provider can't answer skips the step, instead of failing the pipeline.
+                let mut missing=false;
+                let r={
+                    let mut answer=|challenge:&KbdintChallenge| {
+                        challenge.prompts.iter().map(|p| {
+                            c.get_otp(&p.text).ok_or_else(|| {
+                                missing=true;
+                                Error::RequestDenied(format!("no answer to {:?}",p.text))
+                            })
+                        }).collect()
+                    };
+                    kbdint(session,&mut answer,deadline)
+                };
+                match r {
+                    Err(_) if missing=>return Ok(StepResult::Skipped),
+                    r=>r?
+                }
+            },
+            None=>return Ok(StepResult::Skipped)
+        }
+    };
+    match e {
+        SSH_AUTH_SUCCESS=>Ok(StepResult::Success),
+        SSH_AUTH_PARTIAL=>Ok(StepResult::Partial),
+        SSH_AUTH_DENIED=>Ok(StepResult::Denied),
+        _=>Err(err(session))
+    }
+}
@@ -0,0 +1,470 @@
+//! Keep a remote directory in sync with a local one, as files are saved: a "remote mirror" for editing locally and building or running remotely.
+//!
+//! Local changes are watched with the `notify` crate (feature `mirror`), and uploaded over SFTP once no change has happened for the debounce delay, so that editors saving several files, or writing a file in several steps, cause a single upload per file.
+//!
+//!```no_run
+//! use ssh::*;
+//! use ssh::mirror::{ConflictPolicy,Mirror};
+//! use std::time::Duration;
+//!
+//! let mut session=Session::new().unwrap();
+//! session.set_host("pijul.org").unwrap();
+//! session.parse_config(None).unwrap();
+//! session.connect().unwrap();
+//! session.userauth_publickey_auto(None).unwrap();
+//! let sftp=session.sftp_new().unwrap();
+//! let mut mirror=Mirror::new("src","/home/me/src")
+//!     .debounce(Duration::from_millis(300))
+//!     .conflict_policy(ConflictPolicy::Backup)
+//!     .exclude("target")
+//!     .delete(true);
+//! for event in mirror.sync(&sftp).unwrap() {
+//!     println!("{:?}",event)
+//! }
+//! let mut watch=mirror.watch().unwrap();
+//! loop {
+//!     for event in watch.wait(&sftp,None).unwrap() {
+//!         println!("{:?}",event)
+//!     }
+//! }
+//!```
+
+use std::collections::{BTreeSet,HashMap};
+use std::fs;
+use std::io::{ErrorKind,Read};
+use std::path::{Path,PathBuf};
+use std::sync::mpsc;
+use std::time::{Duration,Instant,UNIX_EPOCH};
+
+use notify::Watcher;
+use sha1::{Digest,Sha1};
+
+use super::{Error,Permissions};
+use super::sftp::{FileType,Sftp};
+
+/// What to do when a remote file changed since it was last uploaded (or, for files not uploaded yet, when it is newer than the local file).
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Default)]
+pub enum ConflictPolicy {
+    /// Replace the remote file (the default).
+    #[default]
+    Overwrite,
+    /// Leave the remote file alone, and report the conflict.
+    KeepRemote,
+    /// Rename the remote file to `<name>.conflict-<mtime>`, then upload.
+    Backup
+}
+
+/// What the mirror did, with paths relative to the mirrored directories.
+#[derive(Debug)]
+pub enum MirrorEvent {
+    Uploaded { path:PathBuf, size:u64 },
+    CreatedDir(PathBuf),
+    /// The file was removed locally, and then remotely (with `delete(true)` only).
+    Removed(PathBuf),
+    /// The remote file had changed. `backup` is the name it was renamed to, with `ConflictPolicy::Backup`.
+    Conflict { path:PathBuf, policy:ConflictPolicy, backup:Option<PathBuf> },
+    /// A file could not be mirrored. The mirror goes on with other files, and retries this one on its next change.
+    Failed { path:PathBuf, error:Error }
+}
+
+/// A change that `Mirror::sync` would make, returned by `Mirror::plan`. Paths are relative to the mirrored directories.
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub enum Change {
+    CreateDir(PathBuf),
+    /// Upload a file that doesn't exist remotely.
+    Create { path:PathBuf, size:u64 },
+    /// Replace a remote file. `conflict` is set if the remote file changed since it was last uploaded (or is newer than the local file), in which case the conflict policy applies.
+    Update { path:PathBuf, size:u64, remote_size:Option<u64>, conflict:bool },
+    /// Remove a remote file or directory that doesn't exist locally (with `delete(true)` only). `size` is `None` for directories.
+    Delete { path:PathBuf, size:Option<u64> }
+}
+
+/// How `Mirror::verify` compares local and remote files.
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum Verify {
+    /// Sizes only.
+    Size,
+    /// Sizes, and remote files must not be older than local ones.
+    SizeAndMtime,
+    /// Contents, compared by SHA-1. This reads every remote file over SFTP.
+    Hash
+}
+
+/// A difference found by `Mirror::verify`, with paths relative to the mirrored directories.
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub enum Mismatch {
+    Missing(PathBuf),
+    Size { path:PathBuf, local:u64, remote:Option<u64> },
+    /// The remote file is older than the local one.
+    Older(PathBuf),
+    Content(PathBuf)
+}
+
+/// A local directory mirrored to a remote one.
+#[derive(Debug)]
+pub struct Mirror {
+    local:PathBuf,
+    remote:PathBuf,
+    debounce:Duration,
+    conflict:ConflictPolicy,
+    delete:bool,
+    exclude:Vec<String>,
+    /// Size and modification time of the remote files, as of their last upload.
+    uploaded:HashMap<PathBuf,(Option<u64>,Option<u64>)>
+}
+
+impl Mirror {
+    pub fn new<P:AsRef<Path>,Q:AsRef<Path>>(local:P,remote:Q)->Mirror {
+        Mirror {
+            local:local.as_ref().to_path_buf(),
+            remote:remote.as_ref().to_path_buf(),
+            debounce:Duration::from_millis(200),
+            conflict:ConflictPolicy::default(),
+            delete:false,
+            exclude:Vec::new(),
+            uploaded:HashMap::new()
+        }
+    }
+    /// How long to wait after a change before uploading, restarted by every change (200ms by default).
+    pub fn debounce(mut self,delay:Duration)->Mirror {
+        self.debounce=delay;
+        self
+    }
+    pub fn conflict_policy(mut self,policy:ConflictPolicy)->Mirror {
+        self.conflict=policy;
+        self
+    }
+    /// Remove remote files and directories when they are removed locally (off by default).
+    pub fn delete(mut self,delete:bool)->Mirror {
+        self.delete=delete;
+        self
+    }
+    /// Ignore files and directories named `name`, at any depth (such as `.git` or `target`).
+    pub fn exclude<S:Into<String>>(mut self,name:S)->Mirror {
+        self.exclude.push(name.into());
+        self
+    }
+    fn excluded(&self,rel:&Path)->bool {
+        rel.components().any(|c| self.exclude.iter().any(|e| c.as_os_str()==e.as_str()))
+    }
+    /// Upload the whole local directory, skipping remote files that are already up to date (same size, and not older than the local file). Remote files that don't exist locally are left alone, unless `delete(true)` was set, in which case they are removed. See `plan` for what this would do.
+    pub fn sync(&mut self,sftp:&Sftp)->Result<Vec<MirrorEvent>,Error> {
+        let mut events=Vec::new();
+        self.sync_dir(sftp,PathBuf::new(),&mut events)?;
+        if self.delete {
+            let mut extra=Vec::new();
+            self.remote_extras(sftp,PathBuf::new(),&mut extra)?;
+            for (rel,_) in extra {
+                self.remove(sftp,&rel,&mut events)
+            }
+        }
+        Ok(events)
+    }
+    /// The changes `sync` would make, without making them: a dry run, to show or log before synchronising.
+    pub fn plan(&self,sftp:&Sftp)->Result<Vec<Change>,Error> {
+        let mut changes=Vec::new();
+        for (rel,is_dir) in self.local_tree()? {
+            let remote=self.remote.join(&rel);
+            if is_dir {
+                match sftp.stat(&remote) {
+                    Ok(_)=>(),
+                    Err(Error::IO(ref e)) if e.kind()==ErrorKind::NotFound=>changes.push(Change::CreateDir(rel)),
+                    Err(e)=>return Err(e)
+                }
+                continue
+            }
+            let meta=fs::metadata(self.local.join(&rel))?;
+            let mtime=modified(&meta);
+            match sftp.stat(&remote) {
+                Ok(r)=>if !(r.size==Some(meta.len()) && r.mtime>=mtime) {
+                    let conflict=self.conflicts(&rel,r.size,r.mtime,mtime);
+                    changes.push(Change::Update { path:rel,size:meta.len(),remote_size:r.size,conflict })
+                },
+                Err(Error::IO(ref e)) if e.kind()==ErrorKind::NotFound=>changes.push(Change::Create { path:rel,size:meta.len() }),
+                Err(e)=>return Err(e)
+            }
+        }
+        if self.delete {
+            let mut extra=Vec::new();
+            self.remote_extras(sftp,PathBuf::new(),&mut extra)?;
+            changes.extend(extra.into_iter().map(|(path,size)| Change::Delete { path,size }))
+        }
+        Ok(changes)
+    }
+    /// Compare the remote files with the local ones, for instance after `sync`, and return the differences. Remote files that don't exist locally are not reported.
+    pub fn verify(&self,sftp:&Sftp,how:Verify)->Result<Vec<Mismatch>,Error> {
+        let mut mismatches=Vec::new();
+        for (rel,is_dir) in self.local_tree()? {
+            if is_dir {
+                continue
+            }
+            let local=self.local.join(&rel);
+            let meta=fs::metadata(&local)?;
+            let r=match sftp.stat(self.remote.join(&rel)) {
+                Ok(r)=>r,
+                Err(Error::IO(ref e)) if e.kind()==ErrorKind::NotFound=>{
+                    mismatches.push(Mismatch::Missing(rel));
+                    continue
+                },
+                Err(e)=>return Err(e)
+            };
+            if r.size!=Some(meta.len()) {
+                mismatches.push(Mismatch::Size { path:rel,local:meta.len(),remote:r.size })
+            } else if how==Verify::SizeAndMtime && r.mtime<modified(&meta) {
+                mismatches.push(Mismatch::Older(rel))
+            } else if how==Verify::Hash && sha1_of(fs::File::open(&local)?)?!=sha1_of(sftp.open(self.remote.join(&rel))?)? {
+                mismatches.push(Mismatch::Content(rel))
+            }
+        }
+        Ok(mismatches)
+    }
+    /// The local files and directories that are not excluded, relative to the local directory, parents first.
+    fn local_tree(&self)->Result<Vec<(PathBuf,bool)>,Error> {
+        let mut tree=Vec::new();
+        let mut dirs=vec![PathBuf::new()];
+        while let Some(dir)=dirs.pop() {
+            for entry in fs::read_dir(self.local.join(&dir))? {
+                let entry=entry?;
+                let rel=dir.join(entry.file_name());
+                if self.excluded(&rel) {
+                    continue
+                }
+                let is_dir=entry.file_type()?.is_dir();
+                if is_dir {
+                    dirs.push(rel.clone())
+                }
+                tree.push((rel,is_dir))
+            }
+        }
+        Ok(tree)
+    }
+    /// The remote files and directories under `rel` that don't exist locally (and are not excluded), contents before their directories, with the sizes of files.
+    fn remote_extras(&self,sftp:&Sftp,rel:PathBuf,extra:&mut Vec<(PathBuf,Option<u64>)>)->Result<(),Error> {
+        let entries=match sftp.read_dir(self.remote.join(&rel)) {
+            Ok(e)=>e,
+            Err(Error::IO(ref e)) if e.kind()==ErrorKind::NotFound=>return Ok(()),
+            Err(e)=>return Err(e)
+        };
+        for e in entries {
+            let path=rel.join(e.name_os());
+            if self.excluded(&path) {
+                continue
+            }
+            let exists=fs::symlink_metadata(self.local.join(&path)).is_ok();
+            if e.file_type==FileType::Directory {
+                self.remote_extras(sftp,path.clone(),extra)?;
+                if !exists {
+                    extra.push((path,None))
+                }
+            } else if !exists {
+                extra.push((path,e.size))
+            }
+        }
+        Ok(())
+    }
+    /// Whether the remote file at `rel`, of size `size` and modified at `remote_mtime`, changed since it was last uploaded (or, if it wasn't, is newer than the local file).
+    fn conflicts(&self,rel:&Path,size:Option<u64>,remote_mtime:Option<u64>,mtime:Option<u64>)->bool {
+        match self.uploaded.get(rel) {
+            Some(&last)=>last!=(size,remote_mtime),
+            None=>remote_mtime>mtime
+        }
+    }
+    fn sync_dir(&mut self,sftp:&Sftp,start:PathBuf,events:&mut Vec<MirrorEvent>)->Result<(),Error> {
+        let mut dirs=vec![start];
+        while let Some(dir)=dirs.pop() {
+            self.mirror_dir(sftp,&dir,events);
+            for entry in fs::read_dir(self.local.join(&dir))? {
+                let entry=entry?;
+                let rel=dir.join(entry.file_name());
+                if self.excluded(&rel) {
+                    continue
+                }
+                if entry.file_type()?.is_dir() {
+                    dirs.push(rel)
+                } else {
+                    self.mirror_file(sftp,&rel,true,events)
+                }
+            }
+        }
+        Ok(())
+    }
+    /// Start watching the local directory. Changes are uploaded by `Watch::wait`.
+    pub fn watch(&mut self)->Result<Watch<'_>,Error> {
+        let root=fs::canonicalize(&self.local)?;
+        let (tx,rx)=mpsc::channel();
+        let mut watcher=notify::recommended_watcher(tx).map_err(notify_err)?;
+        watcher.watch(&root,notify::RecursiveMode::Recursive).map_err(notify_err)?;
+        Ok(Watch { mirror:self,root,_watcher:watcher,rx })
+    }
+    fn mirror_dir(&mut self,sftp:&Sftp,rel:&Path,events:&mut Vec<MirrorEvent>) {
+        match sftp.create_dir(self.remote.join(rel),dir_mode(&self.local.join(rel))) {
+            Ok(())=>if rel!=Path::new("") { events.push(MirrorEvent::CreatedDir(rel.to_path_buf())) },
+            Err(Error::IO(ref e)) if e.kind()==ErrorKind::AlreadyExists=>(),
+            // Some servers don't tell why mkdir failed.
+            Err(_) if sftp.stat(self.remote.join(rel)).map(|m| m.is_dir()).unwrap_or(false)=>(),
+            Err(error)=>events.push(MirrorEvent::Failed { path:rel.to_path_buf(),error })
+        }
+    }
+    fn mirror_file(&mut self,sftp:&Sftp,rel:&Path,skip_up_to_date:bool,events:&mut Vec<MirrorEvent>) {
+        if let Err(error)=self.upload(sftp,rel,skip_up_to_date,events) {
+            events.push(MirrorEvent::Failed { path:rel.to_path_buf(),error })
+        }
+    }
+    fn upload(&mut self,sftp:&Sftp,rel:&Path,skip_up_to_date:bool,events:&mut Vec<MirrorEvent>)->Result<(),Error> {
+        let local=self.local.join(rel);
+        let remote=self.remote.join(rel);
+        let mut file=fs::File::open(&local)?;
+        let meta=file.metadata()?;
+        let mtime=modified(&meta);
+        match sftp.stat(&remote) {
+            Ok(r)=>{
+                if skip_up_to_date && r.size==Some(meta.len()) && r.mtime>=mtime {
+                    return Ok(())
+                }
+                if self.conflicts(rel,r.size,r.mtime,mtime) {
+                    let backup=match self.conflict {
+                        ConflictPolicy::Overwrite=>None,
+                        ConflictPolicy::KeepRemote=>{
+                            events.push(MirrorEvent::Conflict { path:rel.to_path_buf(),policy:self.conflict,backup:None });
+                            return Ok(())
+                        },
+                        ConflictPolicy::Backup=>{
+                            let mut name=remote.file_name().unwrap_or_default().to_os_string();
+                            name.push(format!(".conflict-{}",r.mtime.unwrap_or(0)));
+                            let backup=remote.with_file_name(name);
+                            sftp.rename(&remote,&backup)?;
+                            Some(backup)
+                        }
+                    };
+                    events.push(MirrorEvent::Conflict { path:rel.to_path_buf(),policy:self.conflict,backup })
+                }
+            },
+            Err(Error::IO(ref e)) if e.kind()==ErrorKind::NotFound=>(),
+            Err(e)=>return Err(e)
+        }
+        let mut f=sftp.create(&remote,file_mode(&meta))?;
+        let size=std::io::copy(&mut file,&mut f)?;
+        f.close()?;
+        let r=sftp.stat(&remote)?;
+        self.uploaded.insert(rel.to_path_buf(),(r.size,r.mtime));
+        events.push(MirrorEvent::Uploaded { path:rel.to_path_buf(),size });
+        Ok(())
+    }
+    fn remove(&mut self,sftp:&Sftp,rel:&Path,events:&mut Vec<MirrorEvent>) {
+        let remote=self.remote.join(rel);
+        self.uploaded.remove(rel);
+        let r=match sftp.lstat(&remote) {
+            Ok(m)=>if m.file_type==FileType::Directory { sftp.remove_dir(&remote) } else { sftp.remove_file(&remote) },
+            Err(Error::IO(ref e)) if e.kind()==ErrorKind::NotFound=>return,
+            Err(e)=>Err(e)
+        };
+        events.push(match r {
+            Ok(())=>MirrorEvent::Removed(rel.to_path_buf()),
+            Err(error)=>MirrorEvent::Failed { path:rel.to_path_buf(),error }
+        })
+    }
+}
+
+/// The modification time of a local file, in seconds since the Unix epoch.
+fn modified(meta:&fs::Metadata)->Option<u64> {
+    meta.modified().ok().and_then(|t| t.duration_since(UNIX_EPOCH).ok()).map(|d| d.as_secs())
+}
+
+fn sha1_of<R:Read>(mut r:R)->Result<Vec<u8>,Error> {
+    let mut hasher=Sha1::new();
+    let mut buf=[0;32768];
+    loop {
+        let n=r.read(&mut buf)?;
+        if n==0 {
+            return Ok(hasher.finalize().to_vec())
+        }
+        hasher.update(&buf[..n])
+    }
+}
+
+#[cfg(unix)]
+fn file_mode(meta:&fs::Metadata)->Permissions {
+    use std::os::unix::fs::PermissionsExt;
+    Permissions::from_mode(meta.permissions().mode()&0o7777)
+}
+
+#[cfg(not(unix))]
+fn file_mode(meta:&fs::Metadata)->Permissions {
+    Permissions::from_mode(if meta.permissions().readonly() { 0o444 } else { 0o644 })
+}
+
+#[cfg(unix)]
+fn dir_mode(local:&Path)->Permissions {
+    fs::metadata(local).map(|m| file_mode(&m)).unwrap_or(Permissions::from_mode(0o755))
+}
+
+#[cfg(not(unix))]
+fn dir_mode(_:&Path)->Permissions {
+    Permissions::from_mode(0o755)
+}
+
+fn notify_err(e:notify::Error)->Error {
+    match e.kind {
+        notify::ErrorKind::Io(e)=>Error::IO(e),
+        _=>Error::IO(std::io::Error::other(e))
+    }
+}
+
+/// Watches the local directory of a `Mirror`, returned by `Mirror::watch`.
+pub struct Watch<'m> {
+    mirror:&'m mut Mirror,
+    root:PathBuf,
+    _watcher:notify::RecommendedWatcher,
+    rx:mpsc::Receiver<notify::Result<notify::Event>>
+}
+
+impl<'m> Watch<'m> {
+    /// Wait for local changes, and mirror them once the debounce delay has passed without new changes. Returns an empty list if nothing changed within `timeout`.
+    pub fn wait(&mut self,sftp:&Sftp,timeout:Option<Duration>)->Result<Vec<MirrorEvent>,Error> {
+        let deadline=timeout.map(|t| Instant::now()+t);
+        let mut changed=BTreeSet::new();
+        loop {
+            let wait=if !changed.is_empty() {
+                Some(self.mirror.debounce)
+            } else {
+                deadline.map(|d| d.saturating_duration_since(Instant::now()))
+            };
+            let event=match wait {
+                Some(w)=>match self.rx.recv_timeout(w) {
+                    Ok(e)=>e,
+                    Err(mpsc::RecvTimeoutError::Timeout)=>break,
+                    Err(mpsc::RecvTimeoutError::Disconnected)=>return Err(Error::Ssh("The file watcher stopped".to_string()))
+                },
+                None=>self.rx.recv().map_err(|_| Error::Ssh("The file watcher stopped".to_string()))?
+            };
+            for path in event.map_err(notify_err)?.paths {
+                if let Ok(rel)=path.strip_prefix(&self.root) {
+                    if !rel.as_os_str().is_empty() && !self.mirror.excluded(rel) {
+                        changed.insert(rel.to_path_buf());
+                    }
+                }
+            }
+        }
+        let mut events=Vec::new();
+        let mut removed=Vec::new();
+        // Parents come before their contents.
+        for rel in changed {
+            match fs::symlink_metadata(self.mirror.local.join(&rel)) {
+                // Files created with the directory may have been written before it was watched.
+                Ok(ref m) if m.is_dir()=>if let Err(error)=self.mirror.sync_dir(sftp,rel.clone(),&mut events) {
+                    events.push(MirrorEvent::Failed { path:rel,error })
+                },
+                Ok(_)=>self.mirror.mirror_file(sftp,&rel,false,&mut events),
+                Err(ref e) if e.kind()==ErrorKind::NotFound=>removed.push(rel),
+                Err(e)=>events.push(MirrorEvent::Failed { path:rel,error:e.into() })
+            }
+        }
+        if self.mirror.delete {
+            // Contents before their parents.
+            for rel in removed.iter().rev() {
+                self.mirror.remove(sftp,rel,&mut events)
+            }
+        }
+        Ok(events)
+    }
+}
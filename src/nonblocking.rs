@@ -0,0 +1,866 @@
+//! Asynchronous sessions, usable from any executor.
+//!
+//! libssh runs in non-blocking mode, and every operation is retried when the socket of the session becomes ready. Waiting for readiness is the only part that depends on the runtime: it is abstracted by the `Reactor` trait, implemented for tokio (feature `tokio`) and for async-io, the reactor of smol and async-std (feature `async-io`).
+//!
+//! A session and its channels are not `Sync`: they must all be driven from the same task. Connecting resolves the host name synchronously, as libssh does not offer asynchronous name resolution.
+//!
+//!# Cancellation
+//!
+//! All futures can be dropped before completion (for instance by `select!` or a timeout):
+//!
+//! - reads and writes are cancellation-safe: no data is consumed or sent unless the future completes.
+//! - `connect` and authentication are resumed by libssh when called again.
+//! - a channel whose opening was cancelled is closed as soon as the server confirms it, during later operations on the session.
+//! - `exec` and `scp_pull` close their channel when dropped, and a channel on which `request_exec` was cancelled is closed: later operations on it fail.
+
+use std::cell::{OnceCell,RefCell};
+use std::ffi::CString;
+use std::future::{poll_fn,Future};
+use std::io;
+use std::os::unix::io::RawFd;
+use std::pin::Pin;
+use std::task::{ready,Context,Poll};
+
+use super::libc::{c_char,c_int,c_void};
+use super::{AuthMethod,Channel,Channel_,Error,Output,ScpEntry,Session,Session_,channel_open_err,err};
+use super::exec::shell_quote;
+use super::scp_pull::{Header,parse_header};
+use super::tap::Direction;
+use super::{ssh_channel_close,ssh_channel_free,ssh_channel_get_exit_status,ssh_channel_is_open,ssh_channel_new,ssh_channel_open_session,ssh_channel_request_exec,ssh_channel_send_eof,ssh_channel_write};
+use super::{ssh_connect,ssh_get_fd,ssh_set_blocking,ssh_userauth_password,ssh_userauth_publickey_auto};
+use super::{SSH_EOF,SSH_OK};
+
+extern "C" {
+    fn ssh_channel_read_nonblocking(c:*mut Channel_,b:*mut c_void,count:u32,is_stderr:c_int)->c_int;
+    fn ssh_get_poll_flags(s:*mut Session_)->c_int;
+}
+
+const SSH_AGAIN:c_int=-2;
+const SSH_AUTH_SUCCESS:c_int=0;
+const SSH_AUTH_AGAIN:c_int=4;
+const SSH_WRITE_PENDING:c_int=2;
+
+/// What an operation waits for.
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum Interest {
+    Readable,
+    Writable
+}
+
+/// Readiness notifications for the socket of a session, provided by the async runtime.
+pub trait Reactor:Sized {
+    /// Start watching `fd`, the socket of a session. Called once the socket exists, from within a task.
+    fn register(fd:RawFd)->io::Result<Self>;
+    /// Complete when the socket may be ready for `interest`. Spurious wakeups are fine, but readiness that occurred since the previous `Ready` must not be lost.
+    fn poll_ready(&self,cx:&mut Context,interest:Interest)->Poll<io::Result<()>>;
+}
+
+/// A session in non-blocking mode, with futures instead of blocking calls.
+pub struct AsyncSession<R:Reactor> {
+    // Declared before the session, so that it stops watching the socket before libssh closes it.
+    reactor:OnceCell<R>,
+    /// Channels whose opening was cancelled, to be closed once the server has answered.
+    abandoned:RefCell<Vec<*mut Channel_>>,
+    session:Session
+}
+
+impl<R:Reactor> std::fmt::Debug for AsyncSession<R> {
+    fn fmt(&self,f:&mut std::fmt::Formatter)->std::fmt::Result {
+        write!(f,"AsyncSession{{ session:{:?} }}",self.session)
+    }
+}
+
+impl<R:Reactor> AsyncSession<R> {
+    /// Take over `session`, which is switched to non-blocking mode. It may be connected already, or only configured, and then connected with `connect`.
+    pub fn new(session:Session)->AsyncSession<R> {
+        unsafe { ssh_set_blocking(session.session,0) };
+        AsyncSession { reactor:OnceCell::new(), abandoned:RefCell::new(Vec::new()), session }
+    }
+    /// The underlying session, to read or change its settings. Its blocking methods must not be called.
+    pub fn session(&self)->&Session {
+        &self.session
+    }
+    pub fn session_mut(&mut self)->&mut Session {
+        &mut self.session
+    }
+    /// Give the session back, in blocking mode.
+    pub fn into_inner(self)->Session {
+        let mut this=std::mem::ManuallyDrop::new(self);
+        this.free_abandoned();
+        unsafe {
+            std::ptr::drop_in_place(&mut this.reactor);
+            std::ptr::drop_in_place(&mut this.abandoned);
+            let session=std::ptr::read(&this.session);
+            ssh_set_blocking(session.session,1);
+            session
+        }
+    }
+    /// Hand over `channel`, whose opening was cancelled.
+    fn abandon(&self,channel:Channel) {
+        let channel=std::mem::ManuallyDrop::new(channel);
+        self.abandoned.borrow_mut().push(channel.channel)
+    }
+    fn free_channel(&self,c:*mut Channel_) {
+        unsafe { ssh_channel_free(c) };
+        self.session.release_child()
+    }
+    /// Close the abandoned channels that the server has answered for.
+    fn sweep(&self) {
+        self.abandoned.borrow_mut().retain(|&c| match unsafe { ssh_channel_open_session(c) } {
+            SSH_AGAIN=>true,
+            SSH_OK=>{
+                unsafe { ssh_channel_close(c) };
+                self.free_channel(c);
+                false
+            },
+            _=>{
+                self.free_channel(c);
+                false
+            }
+        })
+    }
+    fn free_abandoned(&mut self) {
+        for c in std::mem::take(&mut *self.abandoned.borrow_mut()) {
+            self.free_channel(c)
+        }
+    }
+    fn reactor(&self)->Result<&R,Error> {
+        if let Some(r)=self.reactor.get() {
+            return Ok(r)
+        }
+        let fd=unsafe { ssh_get_fd(self.session.session) };
+        if fd<0 {
+            return Err(Error::Ssh("The session has no socket".to_string()))
+        }
+        let _=self.reactor.set(R::register(fd)?);
+        Ok(self.reactor.get().unwrap())
+    }
+    /// Wait until the socket is ready for what libssh is waiting for.
+    fn poll_wait(&self,cx:&mut Context)->Poll<Result<(),Error>> {
+        let interest=if unsafe { ssh_get_poll_flags(self.session.session) }&SSH_WRITE_PENDING!=0 {
+            Interest::Writable
+        } else {
+            Interest::Readable
+        };
+        Poll::Ready(Ok(ready!(self.reactor()?.poll_ready(cx,interest))?))
+    }
+    /// Call `op` until it returns something, waiting for the socket between calls.
+    fn poll_op<T,F:FnMut(&Session)->Option<Result<T,Error>>>(&self,cx:&mut Context,op:&mut F)->Poll<Result<T,Error>> {
+        self.sweep();
+        loop {
+            if let Some(r)=op(&self.session) {
+                return Poll::Ready(r)
+            }
+            ready!(self.poll_wait(cx))?
+        }
+    }
+    /// Connect to the server configured in the session.
+    pub fn connect(&mut self)->impl Future<Output=Result<(),Error>>+'_ {
+        self.session.clear_probe();
+        let this=&*self;
+        let mut op=|s:&Session| match unsafe { ssh_connect(s.session) } {
+            SSH_OK=>{
+                s.watch_socket();
+                Some(s.after_connect())
+            },
+            SSH_AGAIN=>None,
+            _=>Some(Err(err(s).context(format!("connecting to {}:{}",s.host().unwrap_or_default(),s.port()))))
+        };
+        poll_fn(move |cx| this.poll_op(cx,&mut op))
+    }
+    fn auth<'a,F:FnMut(*mut Session_)->Result<c_int,Error>+'a>(&'a mut self,method:AuthMethod,mut f:F)->impl Future<Output=Result<(),Error>>+'a {
+        let this=&*self;
+        let mut op=move |s:&Session| match f(s.session) {
+            Ok(SSH_AUTH_AGAIN)=>None,
+            Ok(e)=>{
+                s.record_auth(method,e);
+                if e==SSH_AUTH_SUCCESS { Some(Ok(())) } else { Some(Err(err(s))) }
+            },
+            Err(e)=>Some(Err(e))
+        };
+        poll_fn(move |cx| this.poll_op(cx,&mut op))
+    }
+    /// Authenticate with the default keys, and then with the agent, as `Session::userauth_publickey_auto`.
+    pub fn userauth_publickey_auto<'a>(&'a mut self,passphrase:Option<&str>)->impl Future<Output=Result<(),Error>>+'a {
+        let p=passphrase.map(CString::new);
+        self.auth(AuthMethod::PublicKey,move |s| match p {
+            None=>Ok(unsafe { ssh_userauth_publickey_auto(s,std::ptr::null(),std::ptr::null()) }),
+            Some(Ok(ref p))=>Ok(unsafe { ssh_userauth_publickey_auto(s,std::ptr::null(),p.as_ptr()) }),
+            Some(Err(ref e))=>Err(Error::Nul(e.clone()))
+        })
+    }
+    pub fn userauth_password<'a>(&'a mut self,password:&str)->impl Future<Output=Result<(),Error>>+'a {
+        let p=CString::new(password);
+        self.auth(AuthMethod::Password,move |s| match p {
+            Ok(ref p)=>Ok(unsafe { ssh_userauth_password(s,std::ptr::null(),p.as_ptr()) }),
+            Err(ref e)=>Err(Error::Nul(e.clone()))
+        })
+    }
+    /// Open a session channel, on which a command can be run.
+    pub fn channel(&self)->OpenChannel<'_,R> {
+        OpenChannel { session:self,channel:None }
+    }
+    fn channel_new(&self)->Result<Channel<'_>,Error> {
+        let c=unsafe { ssh_channel_new(self.session.session) };
+        if c.is_null() {
+            return Err(err(&self.session))
+        }
+        self.session.children.set(self.session.children.get()+1);
+        Ok(Channel { session:&self.session,channel:c })
+    }
+    /// Run `cmd` on a new channel, and collect its output, as `Session::exec`.
+    pub fn exec(&self,cmd:&str)->Exec<'_,R> {
+        Exec {
+            session:self,
+            command:CString::new(cmd),
+            channel:None,
+            state:ExecState::Open,
+            stdout:Vec::new(),
+            stderr:Vec::new(),
+            eof:[false;2]
+        }
+    }
+    /// Download `path` with SCP, as a stream of entries (recursively if `recursive`). libssh's SCP implementation cannot run in non-blocking mode, so this runs `scp -f` on the server and speaks the protocol itself.
+    ///
+    ///```edition2018
+    /// use ssh::*;
+    /// use ssh::nonblocking::{AsyncSession,Reactor};
+    /// use std::future::poll_fn;
+    /// use std::pin::Pin;
+    /// use futures_core::Stream;
+    ///
+    /// async fn sizes<R:Reactor>(session:&AsyncSession<R>)->Result<(),Error> {
+    ///     let mut pull=session.scp_pull("/var/log",true);
+    ///     while let Some(entry)=poll_fn(|cx| Pin::new(&mut pull).poll_next(cx)).await {
+    ///         if let ScpEntry::File { name,.. }=entry? {
+    ///             let (mut buf,mut total)=([0;8192],0);
+    ///             loop {
+    ///                 let n=pull.read(&mut buf).await?;
+    ///                 if n==0 { break }
+    ///                 total+=n
+    ///             }
+    ///             println!("{:?}: {} bytes",name,total)
+    ///         }
+    ///     }
+    ///     Ok(())
+    /// }
+    ///```
+    pub fn scp_pull<P:AsRef<std::path::Path>>(&self,path:P,recursive:bool)->ScpPull<'_,R> {
+        let path=shell_quote(&path.as_ref().to_string_lossy());
+        ScpPull {
+            session:self,
+            command:CString::new(format!("scp -f {}{}",if recursive { "-r " } else { "" },path)),
+            channel:None,
+            state:PullState::Open,
+            buf:Vec::new(),
+            remaining:0,
+            status:false,
+            acks:0
+        }
+    }
+}
+
+impl<R:Reactor> Drop for AsyncSession<R> {
+    fn drop(&mut self) {
+        self.free_abandoned()
+    }
+}
+
+/// The future returned by `AsyncSession::channel`.
+pub struct OpenChannel<'a,R:Reactor+'a> {
+    session:&'a AsyncSession<R>,
+    channel:Option<Channel<'a>>
+}
+
+impl<'a,R:Reactor> Future for OpenChannel<'a,R> {
+    type Output=Result<AsyncChannel<'a,R>,Error>;
+    fn poll(self:Pin<&mut Self>,cx:&mut Context)->Poll<Result<AsyncChannel<'a,R>,Error>> {
+        let this=self.get_mut();
+        if this.channel.is_none() {
+            this.channel=Some(this.session.channel_new()?)
+        }
+        let c=this.channel.as_ref().unwrap().channel;
+        ready!(this.session.poll_op(cx,&mut |s| match unsafe { ssh_channel_open_session(c) } {
+            SSH_OK=>Some(Ok(())),
+            SSH_AGAIN=>None,
+            _=>Some(Err(channel_open_err(s)))
+        }))?;
+        Poll::Ready(Ok(AsyncChannel { session:this.session,channel:this.channel.take().unwrap(),cancelled:false }))
+    }
+}
+
+impl<'a,R:Reactor> Drop for OpenChannel<'a,R> {
+    fn drop(&mut self) {
+        if let Some(c)=self.channel.take() {
+            self.session.abandon(c)
+        }
+    }
+}
+
+/// A channel of an `AsyncSession`.
+pub struct AsyncChannel<'a,R:Reactor+'a> {
+    session:&'a AsyncSession<R>,
+    channel:Channel<'a>,
+    /// Whether a request was cancelled before its reply, which leaves the channel in an unknown state.
+    cancelled:bool
+}
+
+impl<'a,R:Reactor> AsyncChannel<'a,R> {
+    /// Run a command on the remote server. If this future is dropped before completion, the channel is closed.
+    pub fn request_exec<'b>(&'b mut self,cmd:&str)->impl Future<Output=Result<(),Error>>+'b {
+        let cmd=CString::new(cmd);
+        let (session,c):(&'b AsyncSession<R>,_)=(self.session,self.channel.channel);
+        // Dropped with the future, closing the channel if the request is still pending.
+        let mut guard=CancelGuard { c:std::ptr::null_mut(),cancelled:&mut self.cancelled };
+        poll_fn(move |cx| {
+            if *guard.cancelled {
+                return Poll::Ready(Err(cancelled_err()))
+            }
+            let cmd=match cmd { Ok(ref cmd)=>cmd, Err(ref e)=>return Poll::Ready(Err(Error::Nul(e.clone()))) };
+            guard.c=c;
+            let r=ready!(session.poll_op(cx,&mut |s| match unsafe { ssh_channel_request_exec(c,cmd.as_ptr()) } {
+                SSH_OK=>Some(Ok(())),
+                SSH_AGAIN=>None,
+                _=>Some(Err(err(s)))
+            }));
+            guard.c=std::ptr::null_mut();
+            Poll::Ready(r)
+        })
+    }
+    /// Read from the standard output (or error, if `is_stderr`). Returns 0 at the end of the stream.
+    pub fn read<'b>(&'b mut self,buf:&'b mut [u8],is_stderr:bool)->impl Future<Output=Result<usize,Error>>+'b {
+        let (session,c,cancelled):(&'b AsyncSession<R>,_,_)=(self.session,self.channel.channel,self.cancelled);
+        poll_fn(move |cx| if cancelled { Poll::Ready(Err(cancelled_err())) } else { session.poll_op(cx,&mut |s| read_nonblocking(s,c,buf,is_stderr).transpose()) })
+    }
+    /// Write to the standard input of the command, waiting for the remote window to open if needed. Returns the number of bytes written, which may be less than `buf.len()`.
+    pub fn write<'b>(&'b mut self,buf:&'b [u8])->impl Future<Output=Result<usize,Error>>+'b {
+        let (session,channel):(&'b AsyncSession<R>,&'b Channel<'b>)=(self.session,&self.channel);
+        let cancelled=self.cancelled;
+        poll_fn(move |cx| {
+            if cancelled {
+                return Poll::Ready(Err(cancelled_err()))
+            } else if buf.is_empty() {
+                return Poll::Ready(Ok(0))
+            }
+            let len=std::cmp::min(buf.len(),u32::MAX as usize) as u32;
+            session.poll_op(cx,&mut |_| {
+                let e=unsafe { ssh_channel_write(channel.channel,buf.as_ptr() as *const c_void,len) };
+                if e>0 {
+                    channel.session.tap(channel.channel,Direction::Sent,false,&buf[..e as usize]);
+                    Some(Ok(e as usize))
+                } else if e==0 {
+                    None
+                } else {
+                    Some(Err(Error::IO(channel.write_err())))
+                }
+            })
+        })
+    }
+    /// Tell the remote command that its standard input is finished.
+    pub fn send_eof(&mut self)->Result<(),Error> {
+        if self.cancelled {
+            return Err(cancelled_err())
+        }
+        let e=unsafe { ssh_channel_send_eof(self.channel.channel) };
+        if e==SSH_OK { Ok(()) } else { Err(err(&self.session.session)) }
+    }
+    /// Wait for the exit status of the command. Returns `None` if the channel was closed without one, and `Error::ConnectionLost` if the connection was lost first.
+    pub fn exit_status<'b>(&'b mut self)->impl Future<Output=Result<Option<i32>,Error>>+'b {
+        let (session,c):(&'b AsyncSession<R>,_)=(self.session,self.channel.channel);
+        poll_fn(move |cx| session.poll_op(cx,&mut |s| exit_status(s,c)))
+    }
+}
+
+fn cancelled_err()->Error {
+    Error::Ssh("The channel was closed after a cancelled request".to_string())
+}
+
+/// Closes the channel `c` (if not null) when dropped: set while a request waits for its reply.
+struct CancelGuard<'a> {
+    c:*mut Channel_,
+    cancelled:&'a mut bool
+}
+
+impl<'a> Drop for CancelGuard<'a> {
+    fn drop(&mut self) {
+        if !self.c.is_null() {
+            unsafe { ssh_channel_close(self.c) };
+            *self.cancelled=true
+        }
+    }
+}
+
+/// Read what is available on one stream of `c`: `None` if nothing is, `Some(0)` at the end of the stream.
+fn read_nonblocking(s:&Session,c:*mut Channel_,buf:&mut [u8],is_stderr:bool)->Result<Option<usize>,Error> {
+    let len=std::cmp::min(buf.len(),u32::MAX as usize) as u32;
+    match unsafe { ssh_channel_read_nonblocking(c,buf.as_mut_ptr() as *mut c_void,len,is_stderr as c_int) } {
+        SSH_EOF=>Ok(Some(0)),
+        0 if buf.is_empty()=>Ok(Some(0)),
+        0=>Ok(None),
+        n if n>0=>{
+            s.tap(c,Direction::Received,is_stderr,&buf[..n as usize]);
+            Ok(Some(n as usize))
+        },
+        _=>Err(err(s))
+    }
+}
+
+fn exit_status(s:&Session,c:*mut Channel_)->Option<Result<Option<i32>,Error>> {
+    let e=unsafe { ssh_channel_get_exit_status(c) };
+    if e>=0 {
+        Some(Ok(Some(e)))
+    } else if s.connection_lost() {
+        Some(Err(Error::ConnectionLost(err(s).to_string())))
+    } else if unsafe { ssh_channel_is_open(c) }==0 {
+        Some(Ok(None))
+    } else {
+        None
+    }
+}
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+enum ExecState {
+    Open,
+    Exec,
+    Read,
+    Status,
+    Done
+}
+
+/// The future returned by `AsyncSession::exec`.
+pub struct Exec<'a,R:Reactor+'a> {
+    session:&'a AsyncSession<R>,
+    command:Result<CString,std::ffi::NulError>,
+    channel:Option<Channel<'a>>,
+    state:ExecState,
+    stdout:Vec<u8>,
+    stderr:Vec<u8>,
+    eof:[bool;2]
+}
+
+impl<'a,R:Reactor> Exec<'a,R> {
+    /// Make as much progress as possible without waiting: `Ready` when finished, `Pending` when the socket must be waited for.
+    fn step(&mut self)->Poll<Result<Output,Error>> {
+        let s=&self.session.session;
+        loop {
+            match self.state {
+                ExecState::Open=>{
+                    if self.channel.is_none() {
+                        self.channel=Some(self.session.channel_new()?)
+                    }
+                    match unsafe { ssh_channel_open_session(self.channel.as_ref().unwrap().channel) } {
+                        SSH_OK=>self.state=ExecState::Exec,
+                        SSH_AGAIN=>return Poll::Pending,
+                        _=>return Poll::Ready(Err(channel_open_err(s)))
+                    }
+                },
+                ExecState::Exec=>{
+                    let cmd=match self.command { Ok(ref c)=>c.as_ptr(), Err(ref e)=>return Poll::Ready(Err(Error::Nul(e.clone()))) };
+                    let c=self.channel.as_ref().unwrap().channel;
+                    match unsafe { ssh_channel_request_exec(c,cmd as *const c_char) } {
+                        SSH_OK=>{
+                            if unsafe { ssh_channel_send_eof(c) }!=SSH_OK {
+                                return Poll::Ready(Err(err(s)))
+                            }
+                            self.state=ExecState::Read
+                        },
+                        SSH_AGAIN=>return Poll::Pending,
+                        _=>return Poll::Ready(Err(err(s)))
+                    }
+                },
+                ExecState::Read=>{
+                    let c=self.channel.as_ref().unwrap().channel;
+                    let mut buf=[0;8192];
+                    let mut progress=false;
+                    for is_stderr in 0..2 {
+                        if self.eof[is_stderr] {
+                            continue
+                        }
+                        match read_nonblocking(s,c,&mut buf,is_stderr==1)? {
+                            Some(0)=>self.eof[is_stderr]=true,
+                            Some(n)=>{
+                                if is_stderr==1 { &mut self.stderr } else { &mut self.stdout }.extend_from_slice(&buf[..n]);
+                                progress=true
+                            },
+                            None=>()
+                        }
+                    }
+                    if self.eof==[true,true] {
+                        self.state=ExecState::Status
+                    } else if !progress {
+                        return Poll::Pending
+                    }
+                },
+                ExecState::Status=>{
+                    let c=self.channel.as_ref().unwrap().channel;
+                    match exit_status(s,c) {
+                        Some(status)=>{
+                            self.state=ExecState::Done;
+                            self.channel=None;
+                            return Poll::Ready(status.map(|exit_status| Output {
+                                stdout:std::mem::take(&mut self.stdout),
+                                stderr:std::mem::take(&mut self.stderr),
+                                exit_status
+                            }))
+                        },
+                        None=>return Poll::Pending
+                    }
+                },
+                ExecState::Done=>panic!("Exec polled after completion")
+            }
+        }
+    }
+}
+
+impl<'a,R:Reactor> Future for Exec<'a,R> {
+    type Output=Result<Output,Error>;
+    fn poll(self:Pin<&mut Self>,cx:&mut Context)->Poll<Result<Output,Error>> {
+        let this=self.get_mut();
+        this.session.sweep();
+        loop {
+            if let Poll::Ready(r)=this.step() {
+                return Poll::Ready(r)
+            }
+            ready!(this.session.poll_wait(cx))?
+        }
+    }
+}
+
+/// Dropping the future before completion closes its channel.
+impl<'a,R:Reactor> Drop for Exec<'a,R> {
+    fn drop(&mut self) {
+        if self.state==ExecState::Open {
+            if let Some(c)=self.channel.take() {
+                self.session.abandon(c)
+            }
+        }
+    }
+}
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+enum PullState {
+    Open,
+    Exec,
+    /// Waiting for the next header.
+    Headers,
+    /// Receiving the contents of a file.
+    Data,
+    Done
+}
+
+/// A download started by `AsyncSession::scp_pull`: a `Stream` of entries, with the contents of each file read with `read` before polling the next entry. Unread contents are skipped.
+///
+/// Dropping this before the end closes the channel, which stops the remote `scp`.
+pub struct ScpPull<'a,R:Reactor+'a> {
+    session:&'a AsyncSession<R>,
+    command:Result<CString,std::ffi::NulError>,
+    channel:Option<Channel<'a>>,
+    state:PullState,
+    /// Received and not consumed yet.
+    buf:Vec<u8>,
+    /// Bytes of the current file not received yet.
+    remaining:u64,
+    /// Whether the status byte that follows a file has not been received yet.
+    status:bool,
+    /// Number of acknowledgements (NUL bytes) still to be sent.
+    acks:usize
+}
+
+impl<'a,R:Reactor> ScpPull<'a,R> {
+    /// Receive more data into `buf`: `Pending` if nothing is available, `Ready(false)` at the end of the stream.
+    fn fill(&mut self)->Poll<Result<bool,Error>> {
+        let c=self.channel.as_ref().unwrap().channel;
+        let mut buf=[0;16384];
+        match read_nonblocking(&self.session.session,c,&mut buf,false)? {
+            Some(0)=>Poll::Ready(Ok(false)),
+            Some(n)=>{
+                self.buf.extend_from_slice(&buf[..n]);
+                Poll::Ready(Ok(true))
+            },
+            None=>Poll::Pending
+        }
+    }
+    fn send_acks(&mut self)->Poll<Result<(),Error>> {
+        let channel=self.channel.as_ref().unwrap();
+        while self.acks>0 {
+            let e=unsafe { ssh_channel_write(channel.channel,b"\0".as_ptr() as *const c_void,1) };
+            if e==0 {
+                return Poll::Pending
+            } else if e<0 {
+                return Poll::Ready(Err(Error::IO(channel.write_err())))
+            }
+            channel.session.tap(channel.channel,Direction::Sent,false,b"\0");
+            self.acks-=1
+        }
+        Poll::Ready(Ok(()))
+    }
+    /// Make as much progress towards the next entry as possible without waiting.
+    fn step(&mut self)->Poll<Result<Option<ScpEntry>,Error>> {
+        let s=&self.session.session;
+        loop {
+            match self.state {
+                PullState::Open=>{
+                    if self.channel.is_none() {
+                        self.channel=Some(self.session.channel_new()?)
+                    }
+                    match unsafe { ssh_channel_open_session(self.channel.as_ref().unwrap().channel) } {
+                        SSH_OK=>self.state=PullState::Exec,
+                        SSH_AGAIN=>return Poll::Pending,
+                        _=>return Poll::Ready(Err(channel_open_err(s)))
+                    }
+                },
+                PullState::Exec=>{
+                    let cmd=match self.command { Ok(ref c)=>c.as_ptr(), Err(ref e)=>return Poll::Ready(Err(Error::Nul(e.clone()))) };
+                    match unsafe { ssh_channel_request_exec(self.channel.as_ref().unwrap().channel,cmd as *const c_char) } {
+                        SSH_OK=>{
+                            // Tells the remote scp to start.
+                            self.acks=1;
+                            self.state=PullState::Headers
+                        },
+                        SSH_AGAIN=>return Poll::Pending,
+                        _=>return Poll::Ready(Err(err(s)))
+                    }
+                },
+                PullState::Data=>{
+                    // Skip the rest of the file.
+                    if self.remaining>0 {
+                        let n=std::cmp::min(self.remaining,self.buf.len() as u64) as usize;
+                        self.buf.drain(..n);
+                        self.remaining-=n as u64;
+                        if self.remaining>0 && !ready!(self.fill())? {
+                            return Poll::Ready(Err(truncated()))
+                        }
+                    } else {
+                        self.state=PullState::Headers
+                    }
+                },
+                PullState::Headers=>{
+                    ready!(self.send_acks())?;
+                    if self.status {
+                        match self.buf.first() {
+                            Some(&0)=>{
+                                self.buf.remove(0);
+                                self.status=false;
+                                self.acks+=1;
+                                continue
+                            },
+                            // An error line, parsed below.
+                            Some(_)=>(),
+                            None=>{
+                                if !ready!(self.fill())? {
+                                    return Poll::Ready(Err(truncated()))
+                                }
+                                continue
+                            }
+                        }
+                    }
+                    let line=match self.buf.iter().position(|&c| c==b'\n') {
+                        Some(i)=>{
+                            let line:Vec<u8>=self.buf.drain(..=i).collect();
+                            line
+                        },
+                        None=>{
+                            if !ready!(self.fill())? {
+                                self.state=PullState::Done;
+                                return Poll::Ready(if self.buf.is_empty() { Ok(None) } else { Err(truncated()) })
+                            }
+                            continue
+                        }
+                    };
+                    let after_file=std::mem::replace(&mut self.status,false);
+                    match parse_header(&line[..line.len()-1])? {
+                        Header::Times=>self.acks+=1,
+                        Header::Fatal(msg)=>{
+                            self.state=PullState::Done;
+                            return Poll::Ready(Err(Error::Ssh(msg)))
+                        },
+                        Header::Entry(entry)=>{
+                            match entry {
+                                ScpEntry::File { size,.. }=>{
+                                    self.remaining=size;
+                                    self.status=true;
+                                    self.state=PullState::Data;
+                                    self.acks+=1
+                                },
+                                ScpEntry::Directory { .. }|ScpEntry::EndDirectory=>self.acks+=1,
+                                // The remote scp waits for an answer only if the file could not be read completely.
+                                ScpEntry::Warning(_)=>if after_file { self.acks+=1 }
+                            }
+                            return Poll::Ready(Ok(Some(entry)))
+                        }
+                    }
+                },
+                PullState::Done=>return Poll::Ready(Ok(None))
+            }
+        }
+    }
+    fn poll_read(&mut self,cx:&mut Context,buf:&mut [u8])->Poll<Result<usize,Error>> {
+        if self.state!=PullState::Data || self.remaining==0 || buf.is_empty() {
+            return Poll::Ready(Ok(0))
+        }
+        loop {
+            if let Poll::Ready(r)=self.send_acks() {
+                r?;
+                if self.buf.is_empty() {
+                    if let Poll::Ready(more)=self.fill() {
+                        if !more? {
+                            self.state=PullState::Done;
+                            return Poll::Ready(Err(truncated()))
+                        }
+                    }
+                }
+                if !self.buf.is_empty() {
+                    let n=std::cmp::min(std::cmp::min(self.remaining,self.buf.len() as u64) as usize,buf.len());
+                    buf[..n].copy_from_slice(&self.buf[..n]);
+                    self.buf.drain(..n);
+                    self.remaining-=n as u64;
+                    return Poll::Ready(Ok(n))
+                }
+            }
+            ready!(self.session.poll_wait(cx))?
+        }
+    }
+    /// Read the contents of the current file. Returns 0 at the end of the file, or if the last entry is not a file.
+    pub fn read<'b>(&'b mut self,buf:&'b mut [u8])->ScpRead<'b,'a,R> {
+        ScpRead { pull:self,buf }
+    }
+}
+
+/// The future returned by `ScpPull::read`.
+pub struct ScpRead<'b,'a:'b,R:Reactor+'a> {
+    pull:&'b mut ScpPull<'a,R>,
+    buf:&'b mut [u8]
+}
+
+impl<'b,'a:'b,R:Reactor> Future for ScpRead<'b,'a,R> {
+    type Output=Result<usize,Error>;
+    fn poll(self:Pin<&mut Self>,cx:&mut Context)->Poll<Result<usize,Error>> {
+        let this=self.get_mut();
+        this.pull.session.sweep();
+        this.pull.poll_read(cx,this.buf)
+    }
+}
+
+fn truncated()->Error {
+    Error::Ssh("The SCP transfer ended unexpectedly".to_string())
+}
+
+impl<'a,R:Reactor> futures_core::Stream for ScpPull<'a,R> {
+    type Item=Result<ScpEntry,Error>;
+    fn poll_next(self:Pin<&mut Self>,cx:&mut Context)->Poll<Option<Result<ScpEntry,Error>>> {
+        let this=self.get_mut();
+        this.session.sweep();
+        loop {
+            if let Poll::Ready(r)=this.step() {
+                if r.is_err() {
+                    this.state=PullState::Done
+                }
+                return Poll::Ready(r.transpose())
+            }
+            if let Err(e)=ready!(this.session.poll_wait(cx)) {
+                return Poll::Ready(Some(Err(e)))
+            }
+        }
+    }
+}
+
+impl<'a,R:Reactor> Drop for ScpPull<'a,R> {
+    fn drop(&mut self) {
+        if self.state==PullState::Open {
+            if let Some(c)=self.channel.take() {
+                self.session.abandon(c)
+            }
+        }
+    }
+}
+
+/// A socket owned by libssh, registered with a reactor without being closed by it.
+struct Fd(RawFd);
+
+impl std::os::unix::io::AsRawFd for Fd {
+    fn as_raw_fd(&self)->RawFd {
+        self.0
+    }
+}
+
+impl std::os::unix::io::AsFd for Fd {
+    fn as_fd(&self)->std::os::unix::io::BorrowedFd<'_> {
+        unsafe { std::os::unix::io::BorrowedFd::borrow_raw(self.0) }
+    }
+}
+
+/// The tokio reactor. Sessions must be used from within a tokio runtime, with IO enabled.
+///
+///```no_run,edition2018
+/// use ssh::*;
+/// use ssh::nonblocking::{AsyncSession,TokioReactor};
+///
+/// let mut session=Session::new().unwrap();
+/// session.set_host("pijul.org").unwrap();
+/// session.parse_config(None).unwrap();
+/// let mut session:AsyncSession<TokioReactor>=AsyncSession::new(session);
+/// let rt=tokio::runtime::Builder::new_current_thread().enable_io().build().unwrap();
+/// let out=rt.block_on(async {
+///     session.connect().await?;
+///     session.userauth_publickey_auto(None).await?;
+///     session.exec("uname -a").await
+/// }).unwrap();
+/// println!("{}",String::from_utf8_lossy(&out.stdout));
+///```
+#[cfg(feature="tokio")]
+pub struct TokioReactor(tokio::io::unix::AsyncFd<Fd>);
+
+#[cfg(feature="tokio")]
+impl Reactor for TokioReactor {
+    fn register(fd:RawFd)->io::Result<TokioReactor> {
+        Ok(TokioReactor(tokio::io::unix::AsyncFd::new(Fd(fd))?))
+    }
+    fn poll_ready(&self,cx:&mut Context,interest:Interest)->Poll<io::Result<()>> {
+        let mut guard=match interest {
+            Interest::Readable=>ready!(self.0.poll_read_ready(cx))?,
+            Interest::Writable=>ready!(self.0.poll_write_ready(cx))?
+        };
+        // The operation is retried after this, and will see anything that arrived before.
+        guard.clear_ready();
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// The async-io reactor, used by smol and async-std.
+///
+///```no_run,edition2018
+/// use ssh::*;
+/// use ssh::nonblocking::{AsyncSession,AsyncIoReactor};
+///
+/// let mut session=Session::new().unwrap();
+/// session.set_host("pijul.org").unwrap();
+/// session.parse_config(None).unwrap();
+/// let mut session:AsyncSession<AsyncIoReactor>=AsyncSession::new(session);
+/// let out=async_io::block_on(async {
+///     session.connect().await?;
+///     session.userauth_publickey_auto(None).await?;
+///     let mut channel=session.channel().await?;
+///     channel.request_exec("cat").await?;
+///     channel.write(b"hello").await?;
+///     channel.send_eof()?;
+///     let mut buf=[0;5];
+///     let n=channel.read(&mut buf,false).await?;
+///     println!("{:?}",channel.exit_status().await?);
+///     Ok::<_,Error>(buf[..n].to_vec())
+/// }).unwrap();
+///```
+#[cfg(feature="async-io")]
+pub struct AsyncIoReactor(async_io::Async<Fd>);
+
+#[cfg(feature="async-io")]
+impl Reactor for AsyncIoReactor {
+    fn register(fd:RawFd)->io::Result<AsyncIoReactor> {
+        Ok(AsyncIoReactor(async_io::Async::new(Fd(fd))?))
+    }
+    fn poll_ready(&self,cx:&mut Context,interest:Interest)->Poll<io::Result<()>> {
+        match interest {
+            Interest::Readable=>self.0.poll_readable(cx),
+            Interest::Writable=>self.0.poll_writable(cx)
+        }
+    }
+}
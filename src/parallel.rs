@@ -0,0 +1,184 @@
+//! Transfers of single large files over several SFTP connections at once, each copying different ranges of the file.
+
+use std::fs::OpenOptions;
+use std::io::{Read,Seek,SeekFrom};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool,AtomicU64,Ordering};
+use std::thread;
+
+use super::{Error,Permissions,Session};
+use super::fleet;
+
+type Connect=dyn Fn(&str)->Result<Session,Error>+Sync;
+
+/// Copies a file in chunks over several connections to the same host, in parallel. On paths with a high bandwidth and a high latency, a single SFTP stream is limited by the SSH window and the round trips of the protocol, whereas several streams can fill the link.
+///
+/// Each stream is a separate connection (opened with the function given to `connect_with`, by default `fleet::connect`), with its own handle on the remote file. If some of the extra connections fail, for instance because the server limits the number of sessions per user, the transfer goes on with the others.
+///
+///```no_run
+/// use ssh::*;
+///
+/// let n=ParallelTransfer::new("pijul.org")
+///     .streams(8)
+///     .download("/srv/backups/dump.tar","/tmp/dump.tar")
+///     .unwrap();
+/// println!("{} bytes",n);
+///```
+pub struct ParallelTransfer {
+    host:String,
+    streams:usize,
+    chunk_size:u64,
+    connect:Box<Connect>
+}
+
+impl std::fmt::Debug for ParallelTransfer {
+    fn fmt(&self,f:&mut std::fmt::Formatter)->std::fmt::Result {
+        write!(f,"ParallelTransfer{{ host:{:?}, streams:{}, chunk_size:{} }}",self.host,self.streams,self.chunk_size)
+    }
+}
+
+/// The ranges of the file not copied yet, shared by the streams.
+struct Chunks {
+    next:AtomicU64,
+    size:u64,
+    chunk_size:u64,
+    /// Set by the first stream that fails, to stop the others.
+    failed:AtomicBool
+}
+
+impl Chunks {
+    /// The offset and length of the next chunk to copy, or `None` when everything is taken or a stream failed.
+    fn next(&self)->Option<(u64,u64)> {
+        if self.failed.load(Ordering::SeqCst) {
+            return None
+        }
+        let start=self.next.fetch_add(self.chunk_size,Ordering::SeqCst);
+        if start>=self.size {
+            None
+        } else {
+            Some((start,std::cmp::min(self.chunk_size,self.size-start)))
+        }
+    }
+}
+
+fn short_copy(start:u64,n:u64,len:u64)->Error {
+    Error::IO(std::io::Error::new(std::io::ErrorKind::UnexpectedEof,
+                                  format!("file ended at {} instead of {}: was it modified during the transfer?",start+n,start+len)))
+}
+
+impl ParallelTransfer {
+    pub fn new<S:Into<String>>(host:S)->ParallelTransfer {
+        ParallelTransfer {
+            host:host.into(),
+            streams:4,
+            chunk_size:8<<20,
+            connect:Box::new(fleet::connect)
+        }
+    }
+    /// Number of connections used at the same time (at least 1, default 4).
+    pub fn streams(mut self,n:usize)->ParallelTransfer {
+        self.streams=std::cmp::max(n,1);
+        self
+    }
+    /// Size of the ranges handed to the streams, in bytes (default 8 MiB). Smaller chunks balance the load better between streams of different speeds, at the cost of more seeks.
+    pub fn chunk_size(mut self,bytes:u64)->ParallelTransfer {
+        self.chunk_size=std::cmp::max(bytes,1);
+        self
+    }
+    /// Open the connections with `f` (called with the host) instead of `fleet::connect`, for instance to authenticate differently. The sessions must be authenticated.
+    pub fn connect_with<F:Fn(&str)->Result<Session,Error>+Sync+'static>(mut self,f:F)->ParallelTransfer {
+        self.connect=Box::new(f);
+        self
+    }
+
+    /// Run `f` on `session` and on `streams-1` other connections, in parallel, and return the first error.
+    fn run<F:Fn(&mut Session)->Result<(),Error>+Sync>(&self,session:&mut Session,chunks:&Chunks,f:F)->Result<(),Error> {
+        let extra=std::cmp::min(self.streams-1,(chunks.size/chunks.chunk_size) as usize);
+        let run=|session:&mut Session|->Result<(),Error> {
+            let r=f(session);
+            if r.is_err() {
+                chunks.failed.store(true,Ordering::SeqCst)
+            }
+            r
+        };
+        thread::scope(|scope| {
+            let handles:Vec<_>=(0..extra).map(|_| scope.spawn(|| {
+                match (self.connect)(&self.host) {
+                    Ok(mut session)=>{
+                        let r=run(&mut session);
+                        let _=session.close();
+                        r
+                    },
+                    Err(e)=>{
+                        debug!("extra stream to {} failed, continuing without it: {}",self.host,e);
+                        Ok(())
+                    }
+                }
+            })).collect();
+            let mut result=run(session);
+            for h in handles {
+                let r=h.join().unwrap_or_else(|_| Err(Error::Ssh("a transfer thread panicked".to_string())));
+                if result.is_ok() {
+                    result=r
+                }
+            }
+            result
+        })
+    }
+
+    /// Copy the remote file `remote` to `local`, which is created or truncated, and return its size.
+    pub fn download<P:AsRef<Path>,Q:AsRef<Path>>(&self,remote:P,local:Q)->Result<u64,Error> {
+        let (remote,local)=(remote.as_ref(),local.as_ref());
+        let mut session=(self.connect)(&self.host)?;
+        let size={
+            let sftp=session.sftp_new()?;
+            sftp.stat(remote)?.size.unwrap_or(0)
+        };
+        std::fs::File::create(local)?.set_len(size)?;
+        let chunks=Chunks { next:AtomicU64::new(0),size,chunk_size:self.chunk_size,failed:AtomicBool::new(false) };
+        trace_event!(size=size,streams=self.streams,"parallel download");
+        self.run(&mut session,&chunks,|session| {
+            let sftp=session.sftp_new()?;
+            let mut source=sftp.open(remote)?;
+            let mut dest=OpenOptions::new().write(true).open(local)?;
+            while let Some((start,len))=chunks.next() {
+                source.seek(SeekFrom::Start(start))?;
+                dest.seek(SeekFrom::Start(start))?;
+                let n=std::io::copy(&mut (&mut source).take(len),&mut dest)?;
+                if n<len {
+                    return Err(short_copy(start,n,len))
+                }
+            }
+            dest.sync_all()?;
+            source.close()
+        })?;
+        session.close()?;
+        Ok(size)
+    }
+
+    /// Copy the local file `local` to `remote`, which is created (or truncated) with permissions `mode`, and return the size of the file.
+    pub fn upload<P:AsRef<Path>,Q:AsRef<Path>,M:Into<Permissions>>(&self,local:P,remote:Q,mode:M)->Result<u64,Error> {
+        let (local,remote)=(local.as_ref(),remote.as_ref());
+        let size=std::fs::metadata(local)?.len();
+        let mut session=(self.connect)(&self.host)?;
+        session.sftp_new()?.create(remote,mode)?.close()?;
+        let chunks=Chunks { next:AtomicU64::new(0),size,chunk_size:self.chunk_size,failed:AtomicBool::new(false) };
+        trace_event!(size=size,streams=self.streams,"parallel upload");
+        self.run(&mut session,&chunks,|session| {
+            let sftp=session.sftp_new()?;
+            let mut dest=sftp.open_with(remote,super::libc::O_WRONLY,0)?;
+            let mut source=std::fs::File::open(local)?;
+            while let Some((start,len))=chunks.next() {
+                source.seek(SeekFrom::Start(start))?;
+                dest.seek(SeekFrom::Start(start))?;
+                let n=std::io::copy(&mut (&mut source).take(len),&mut dest)?;
+                if n<len {
+                    return Err(short_copy(start,n,len))
+                }
+            }
+            dest.close()
+        })?;
+        session.close()?;
+        Ok(size)
+    }
+}
@@ -0,0 +1,84 @@
+//! Finding out what the remote host is, and what it can do, to choose transfer methods and quoting rules. Results are cached in the session until it is reconnected.
+
+use std::collections::HashMap;
+
+use super::{Error,Session};
+use super::exec::shell_quote;
+
+/// Operating system and architecture of a remote host.
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub struct Platform {
+    /// As given by `uname -s` ("Linux", "Darwin", "FreeBSD"…), or "Windows".
+    pub os:String,
+    /// As given by `uname -m` ("x86_64", "aarch64"…), empty if unknown.
+    pub arch:String
+}
+
+impl Platform {
+    /// Whether the remote commands run in a POSIX shell.
+    pub fn is_unix(&self)->bool {
+        self.os!="Windows"
+    }
+}
+
+#[derive(Debug,Default)]
+pub(crate) struct Cache {
+    platform:Option<Platform>,
+    sftp:Option<bool>,
+    commands:HashMap<String,bool>
+}
+
+impl Session {
+    /// The operating system and architecture of the remote host.
+    pub fn platform(&mut self)->Result<Platform,Error> {
+        if let Some(ref p)=self.probe.platform {
+            return Ok(p.clone())
+        }
+        let out=self.exec("uname -sm")?;
+        let p=if out.exit_status==Some(0) {
+            let s=String::from_utf8_lossy(&out.stdout);
+            let mut it=s.split_whitespace();
+            Platform {
+                os:it.next().unwrap_or("").to_string(),
+                arch:it.next().unwrap_or("").to_string()
+            }
+        } else {
+            let out=self.exec("cmd /c ver")?;
+            let os=if String::from_utf8_lossy(&out.stdout).contains("Windows") { "Windows" } else { "" };
+            Platform { os:os.to_string(),arch:String::new() }
+        };
+        self.probe.platform=Some(p.clone());
+        Ok(p)
+    }
+    /// Whether `command` is available on the remote host (according to `command -v`).
+    pub fn has_command(&mut self,command:&str)->Result<bool,Error> {
+        if let Some(&b)=self.probe.commands.get(command) {
+            return Ok(b)
+        }
+        let out=self.exec(&format!("command -v {}",shell_quote(command)))?;
+        let b=out.exit_status==Some(0);
+        self.probe.commands.insert(command.to_string(),b);
+        Ok(b)
+    }
+    /// Whether the server has an SFTP subsystem.
+    pub fn has_sftp(&mut self)->Result<bool,Error> {
+        if let Some(b)=self.probe.sftp {
+            return Ok(b)
+        }
+        let b={
+            let mut c=self.channel_new()?;
+            c.open_session()?;
+            match c.request_subsystem("sftp") {
+                Ok(())=>true,
+                Err(Error::RequestDenied(_))=>false,
+                Err(e)=>return Err(e)
+            }
+        };
+        self.probe.sftp=Some(b);
+        Ok(b)
+    }
+    /// Forget what was learnt about the remote host.
+    pub(crate) fn clear_probe(&mut self) {
+        self.probe=Cache::default()
+    }
+}
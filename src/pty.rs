@@ -0,0 +1,221 @@
+//! Pseudo-terminal settings: terminal type, size, and the terminal modes of RFC 4254, section 8, sent with PTY requests.
+
+use super::libc::{c_char,c_int,size_t};
+use super::{Channel,Channel_,Error,err,SSH_OK};
+use std::ffi::CString;
+
+extern "C" {
+    fn ssh_channel_request_pty_size_modes(c:*mut Channel_,term:*const c_char,cols:c_int,rows:c_int,modes:*const u8,modes_len:size_t)->c_int;
+}
+
+/// A terminal mode, with the opcode it has in the protocol. Control characters (`V…`) take a character code (255 disables them), flags take 0 or 1.
+///
+/// `VMIN` and `VTIME` have no opcode in the protocol, and can only be set on the remote side, for instance with `stty`.
+#[allow(non_camel_case_types)]
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Hash)]
+pub enum TerminalMode {
+    VINTR=1,
+    VQUIT=2,
+    VERASE=3,
+    VKILL=4,
+    VEOF=5,
+    VEOL=6,
+    VEOL2=7,
+    VSTART=8,
+    VSTOP=9,
+    VSUSP=10,
+    VDSUSP=11,
+    VREPRINT=12,
+    VWERASE=13,
+    VLNEXT=14,
+    VFLUSH=15,
+    VSWTCH=16,
+    VSTATUS=17,
+    VDISCARD=18,
+    IGNPAR=30,
+    PARMRK=31,
+    INPCK=32,
+    ISTRIP=33,
+    INLCR=34,
+    IGNCR=35,
+    ICRNL=36,
+    IUCLC=37,
+    IXON=38,
+    IXANY=39,
+    IXOFF=40,
+    IMAXBEL=41,
+    /// RFC 8160.
+    IUTF8=42,
+    ISIG=50,
+    ICANON=51,
+    XCASE=52,
+    ECHO=53,
+    ECHOE=54,
+    ECHOK=55,
+    ECHONL=56,
+    NOFLSH=57,
+    TOSTOP=58,
+    IEXTEN=59,
+    ECHOCTL=60,
+    ECHOKE=61,
+    PENDIN=62,
+    OPOST=70,
+    OLCUC=71,
+    ONLCR=72,
+    OCRNL=73,
+    ONOCR=74,
+    ONLRET=75,
+    CS7=90,
+    CS8=91,
+    PARENB=92,
+    PARODD=93,
+    /// Input speed, in bits per second.
+    TTY_OP_ISPEED=128,
+    /// Output speed, in bits per second.
+    TTY_OP_OSPEED=129
+}
+
+/// Terminal modes requested for a pseudo-terminal. The modes that are not set keep the defaults of the server.
+///
+///```no_run
+/// use ssh::*;
+///
+/// let mut session=Session::new().unwrap();
+/// session.set_host("pijul.org").unwrap();
+/// session.parse_config(None).unwrap();
+/// session.connect().unwrap();
+/// session.userauth_publickey_auto(None).unwrap();
+/// let mut channel=session.channel_new().unwrap();
+/// channel.open_session().unwrap();
+/// // A terminal for a program reading passwords, without echo.
+/// let modes=TerminalModes::new().flag(TerminalMode::ECHO,false).speed(115200);
+/// channel.request_pty_modes("vt100",80,24,&modes).unwrap();
+///```
+#[derive(Debug,Clone,Default,PartialEq,Eq)]
+pub struct TerminalModes(Vec<(u8,u32)>);
+
+/// The end of the encoded modes.
+const TTY_OP_END:u8=0;
+
+impl TerminalModes {
+    pub fn new()->TerminalModes {
+        TerminalModes(Vec::new())
+    }
+    /// Set `mode` to `value`, replacing a previous value.
+    pub fn set(self,mode:TerminalMode,value:u32)->TerminalModes {
+        self.set_opcode(mode as u8,value)
+    }
+    /// Set a mode by its opcode, for modes not listed in `TerminalMode`. Opcodes 160 to 255 have 64-bit values in the protocol, and are not supported.
+    pub fn set_opcode(mut self,opcode:u8,value:u32)->TerminalModes {
+        match self.0.iter_mut().find(|m| m.0==opcode) {
+            Some(m)=>m.1=value,
+            None=>self.0.push((opcode,value))
+        }
+        self
+    }
+    /// Turn a flag on or off.
+    pub fn flag(self,mode:TerminalMode,on:bool)->TerminalModes {
+        self.set(mode,on as u32)
+    }
+    /// Set both the input and output speeds, in bits per second.
+    pub fn speed(self,bps:u32)->TerminalModes {
+        self.set(TerminalMode::TTY_OP_ISPEED,bps).set(TerminalMode::TTY_OP_OSPEED,bps)
+    }
+    /// The value of `mode`, if set.
+    pub fn get(&self,mode:TerminalMode)->Option<u32> {
+        self.0.iter().find(|m| m.0==mode as u8).map(|m| m.1)
+    }
+    /// The encoding of the modes in the protocol: each mode is an opcode byte followed by a 32-bit big-endian value, and the list ends with a zero byte.
+    pub fn encode(&self)->Vec<u8> {
+        let mut v=Vec::with_capacity(5*self.0.len()+1);
+        for &(opcode,value) in &self.0 {
+            v.push(opcode);
+            v.extend_from_slice(&value.to_be_bytes())
+        }
+        v.push(TTY_OP_END);
+        v
+    }
+}
+
+/// Everything sent with a PTY request.
+///
+///```no_run
+/// use ssh::*;
+///
+/// let mut session=Session::new().unwrap();
+/// session.set_host("pijul.org").unwrap();
+/// session.parse_config(None).unwrap();
+/// session.connect().unwrap();
+/// session.userauth_publickey_auto(None).unwrap();
+/// let mut channel=session.channel_new().unwrap();
+/// channel.open_session().unwrap();
+/// channel.request_pty_with(&PtyOptions { cols:132,..PtyOptions::new() }).unwrap();
+/// channel.request_shell().unwrap();
+///```
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub struct PtyOptions {
+    /// The terminal type, as in `$TERM`.
+    pub term:String,
+    pub cols:u32,
+    pub rows:u32,
+    /// The terminal modes, or `None` to let libssh send those of the local terminal.
+    pub modes:Option<TerminalModes>
+}
+
+impl PtyOptions {
+    /// Options matching the local terminal: the type from `$TERM`, and the size of the terminal on the standard streams. Defaults to an 80×24 "xterm" when they are not set or not terminals.
+    pub fn new()->PtyOptions {
+        let (cols,rows)=local_window_size().unwrap_or((80,24));
+        PtyOptions {
+            term:std::env::var("TERM").ok().filter(|t| !t.is_empty()).unwrap_or_else(|| "xterm".to_string()),
+            cols,
+            rows,
+            modes:None
+        }
+    }
+}
+
+impl Default for PtyOptions {
+    fn default()->PtyOptions {
+        PtyOptions::new()
+    }
+}
+
+/// The size of the terminal `fd`, in columns and rows.
+#[cfg(unix)]
+pub(crate) fn window_size(fd:c_int)->Option<(u32,u32)> {
+    let mut ws:super::libc::winsize=unsafe { std::mem::zeroed() };
+    if unsafe { super::libc::ioctl(fd,super::libc::TIOCGWINSZ,&mut ws) }==0 && ws.ws_col>0 {
+        Some((ws.ws_col as u32,ws.ws_row as u32))
+    } else {
+        None
+    }
+}
+
+/// The size of the first of the standard streams that is a terminal.
+#[cfg(unix)]
+fn local_window_size()->Option<(u32,u32)> {
+    (0..3).filter_map(window_size).next()
+}
+
+#[cfg(not(unix))]
+fn local_window_size()->Option<(u32,u32)> {
+    None
+}
+
+impl<'b> Channel<'b> {
+    /// Ask for a pseudo-terminal with the given options.
+    pub fn request_pty_with(&mut self,options:&PtyOptions)->Result<(),Error> {
+        match options.modes {
+            Some(ref modes)=>self.request_pty_modes(&options.term,options.cols,options.rows,modes),
+            None=>self.request_pty(&options.term,options.cols,options.rows)
+        }
+    }
+    /// Ask for a pseudo-terminal as `request_pty`, with the given terminal modes instead of those of the local terminal. Needs libssh 0.10 or later.
+    pub fn request_pty_modes(&mut self,term:&str,cols:u32,rows:u32,modes:&TerminalModes)->Result<(),Error> {
+        let term=CString::new(term)?;
+        let modes=modes.encode();
+        let e=unsafe { ssh_channel_request_pty_size_modes(self.channel,term.as_ptr(),cols as c_int,rows as c_int,modes.as_ptr(),modes.len() as size_t) };
+        if e==SSH_OK { Ok(()) } else { Err(err(self.session)) }
+    }
+}
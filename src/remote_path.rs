@@ -0,0 +1,184 @@
+//! Paths on the remote host, with the separators and quoting rules of its platform, so that they can be put in command lines safely.
+
+use std::fmt;
+use std::path::PathBuf;
+
+use super::{Error,Session};
+use super::exec::shell_quote;
+
+/// The conventions of the remote host for paths and command lines.
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Hash)]
+pub enum PathStyle {
+    /// `/` separators, and commands run by a POSIX shell.
+    Posix,
+    /// `\` separators (`/` is accepted too), drive letters, and commands run by `cmd.exe`, as with OpenSSH for Windows.
+    Windows
+}
+
+impl PathStyle {
+    fn separator(self)->char {
+        match self {
+            PathStyle::Posix=>'/',
+            PathStyle::Windows=>'\\'
+        }
+    }
+    fn is_separator(self,c:char)->bool {
+        c=='/' || (self==PathStyle::Windows && c=='\\')
+    }
+}
+
+/// A path on the remote host.
+///
+///```
+/// use ssh::*;
+///
+/// let dir=RemotePath::posix("/srv/my files");
+/// let file=dir.join("report (final).txt");
+/// assert_eq!(format!("cat {}",file.quoted()),"cat '/srv/my files/report (final).txt'");
+/// let file=RemotePath::windows(r"C:\Users\me").join("my file.txt");
+/// assert_eq!(file.quoted(),r#""C:\Users\me\my file.txt""#);
+/// assert_eq!(file.sftp_path().to_str(),Some("/C:/Users/me/my file.txt"));
+///```
+#[derive(Debug,Clone,PartialEq,Eq,Hash)]
+pub struct RemotePath {
+    path:String,
+    style:PathStyle
+}
+
+impl RemotePath {
+    pub fn new<S:Into<String>>(path:S,style:PathStyle)->RemotePath {
+        let mut path:String=path.into();
+        if style==PathStyle::Windows {
+            path=path.replace('/',"\\")
+        }
+        RemotePath { path,style }
+    }
+    pub fn posix<S:Into<String>>(path:S)->RemotePath {
+        RemotePath::new(path,PathStyle::Posix)
+    }
+    /// A Windows path. `/` separators are replaced by `\`.
+    pub fn windows<S:Into<String>>(path:S)->RemotePath {
+        RemotePath::new(path,PathStyle::Windows)
+    }
+    pub fn style(&self)->PathStyle {
+        self.style
+    }
+    /// The path, unquoted.
+    pub fn as_str(&self)->&str {
+        &self.path
+    }
+    /// Whether the path starts from the root (or, on Windows, from a drive or a network share) rather than from the home directory of the user.
+    pub fn is_absolute(&self)->bool {
+        let b=self.path.as_bytes();
+        match self.style {
+            PathStyle::Posix=>self.path.starts_with('/'),
+            PathStyle::Windows=>self.path.starts_with('\\') || (b.len()>=2 && b[0].is_ascii_alphabetic() && b[1]==b':')
+        }
+    }
+    /// `self` followed by `path`, or `path` alone if it is absolute.
+    pub fn join(&self,path:&str)->RemotePath {
+        let path=RemotePath::new(path,self.style);
+        if path.is_absolute() || self.path.is_empty() {
+            return path
+        }
+        let sep=self.style.separator();
+        let mut joined=self.path.clone();
+        if !joined.ends_with(sep) {
+            joined.push(sep)
+        }
+        joined.push_str(path.path.trim_start_matches(sep));
+        RemotePath { path:joined,style:self.style }
+    }
+    /// The last component of the path, if any.
+    pub fn file_name(&self)->Option<&str> {
+        let trimmed=self.path.trim_end_matches(|c| self.style.is_separator(c));
+        let name=trimmed.rsplit(|c| self.style.is_separator(c)).next().unwrap_or("");
+        if name.is_empty() || name==".." || (self.style==PathStyle::Windows && name.ends_with(':')) { None } else { Some(name) }
+    }
+    /// The path without its last component, if it has one.
+    pub fn parent(&self)->Option<RemotePath> {
+        let name=self.file_name()?;
+        let trimmed=self.path.trim_end_matches(|c| self.style.is_separator(c));
+        let rest=&trimmed[..trimmed.len()-name.len()];
+        let parent=match rest.trim_end_matches(|c| self.style.is_separator(c)) {
+            // Keep the root, and a drive's root.
+            "" if !rest.is_empty()=>&rest[..1],
+            p if p.ends_with(':') && rest.len()>p.len()=>&rest[..p.len()+1],
+            p=>p
+        };
+        Some(RemotePath { path:parent.to_string(),style:self.style })
+    }
+    /// The path quoted as a single word of a command line: in single quotes for POSIX shells, and in double quotes for `cmd.exe` (which still expands `%VARIABLES%` in them).
+    pub fn quoted(&self)->String {
+        match self.style {
+            PathStyle::Posix=>shell_quote(&self.path),
+            PathStyle::Windows=>format!("\"{}\"",self.path)
+        }
+    }
+    /// The path as given to SFTP, which always uses `/` separators: on Windows, absolute paths look like `/C:/Users`.
+    pub fn sftp_path(&self)->PathBuf {
+        match self.style {
+            PathStyle::Posix=>PathBuf::from(&self.path),
+            PathStyle::Windows=>{
+                let p=self.path.replace('\\',"/");
+                let b=p.as_bytes();
+                if b.len()>=2 && b[0].is_ascii_alphabetic() && b[1]==b':' { PathBuf::from(format!("/{}",p)) } else { PathBuf::from(p) }
+            }
+        }
+    }
+}
+
+impl fmt::Display for RemotePath {
+    /// The path, unquoted.
+    fn fmt(&self,f:&mut fmt::Formatter)->fmt::Result {
+        f.write_str(&self.path)
+    }
+}
+
+impl Session {
+    /// `path` as a path on the remote host, with the style of its platform (see `Session::platform`).
+    pub fn remote_path<S:Into<String>>(&mut self,path:S)->Result<RemotePath,Error> {
+        let style=if self.platform()?.is_unix() { PathStyle::Posix } else { PathStyle::Windows };
+        Ok(RemotePath::new(path,style))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RemotePath;
+
+    fn parent(p:&RemotePath)->Option<String> {
+        p.parent().map(|p| p.as_str().to_string())
+    }
+
+    #[test]
+    fn posix_parent() {
+        assert_eq!(parent(&RemotePath::posix("/a")).as_deref(),Some("/"));
+        assert_eq!(parent(&RemotePath::posix("/")),None);
+        assert_eq!(parent(&RemotePath::posix("/a/b")).as_deref(),Some("/a"));
+        assert_eq!(parent(&RemotePath::posix("/a//b/")).as_deref(),Some("/a"));
+        // A trailing separator doesn't make an empty last component.
+        assert_eq!(parent(&RemotePath::posix("a/")).as_deref(),Some(""));
+        assert_eq!(RemotePath::posix("a/").file_name(),Some("a"));
+        assert_eq!(RemotePath::posix("/a/..").file_name(),None);
+        assert_eq!(RemotePath::posix("").file_name(),None);
+    }
+
+    #[test]
+    fn windows_parent() {
+        assert_eq!(parent(&RemotePath::windows(r"C:\a")).as_deref(),Some(r"C:\"));
+        assert_eq!(parent(&RemotePath::windows(r"C:\")),None);
+        assert_eq!(parent(&RemotePath::windows("C:")),None);
+        assert_eq!(parent(&RemotePath::windows("C:/a/b/")).as_deref(),Some(r"C:\a"));
+        assert_eq!(RemotePath::windows(r"C:\a\").file_name(),Some("a"));
+        assert_eq!(RemotePath::windows(r"C:\").file_name(),None);
+    }
+
+    #[test]
+    fn sftp_path() {
+        assert_eq!(RemotePath::windows(r"C:\").sftp_path().to_str(),Some("/C:/"));
+        assert_eq!(RemotePath::windows(r"C:\a").sftp_path().to_str(),Some("/C:/a"));
+        assert_eq!(RemotePath::windows(r"a\b").sftp_path().to_str(),Some("a/b"));
+        assert_eq!(RemotePath::posix("/a/b").sftp_path().to_str(),Some("/a/b"));
+    }
+}
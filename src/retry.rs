@@ -0,0 +1,114 @@
+//! Retrying operations with exponential backoff.
+
+use std::thread;
+use std::time::Duration;
+
+use super::{Error,Session};
+
+/// A retry policy: how many times an operation is attempted, and how long to wait between attempts.
+///
+/// The delay starts at `initial_delay`, and is multiplied by `factor` after each failed attempt, up to `max_delay`. Only errors for which the classifier returns `true` are retried (by default, `Error::is_transient`).
+///
+///```no_run
+/// use ssh::*;
+/// use std::time::Duration;
+///
+/// let mut session=Session::new().unwrap();
+/// session.set_host("pijul.org").unwrap();
+/// session.parse_config(None).unwrap();
+/// let retry=Retry::new().max_attempts(3).initial_delay(Duration::from_millis(500));
+/// session.connect_with_retry(&retry).unwrap();
+/// retry.run(|_| session.userauth_publickey_auto(None)).unwrap();
+///```
+#[derive(Debug,Clone)]
+pub struct Retry {
+    max_attempts:usize,
+    initial_delay:Duration,
+    max_delay:Duration,
+    factor:u32
+}
+
+impl Default for Retry {
+    fn default()->Retry {
+        Retry {
+            max_attempts:5,
+            initial_delay:Duration::from_secs(1),
+            max_delay:Duration::from_secs(30),
+            factor:2
+        }
+    }
+}
+
+impl Retry {
+    /// The default policy: five attempts, starting with a one-second delay, doubled after each attempt, and capped at thirty seconds.
+    pub fn new()->Retry {
+        Retry::default()
+    }
+    /// Total number of attempts, including the first one (at least 1).
+    pub fn max_attempts(mut self,n:usize)->Retry {
+        self.max_attempts=std::cmp::max(n,1);
+        self
+    }
+    pub fn initial_delay(mut self,d:Duration)->Retry {
+        self.initial_delay=d;
+        self
+    }
+    pub fn max_delay(mut self,d:Duration)->Retry {
+        self.max_delay=d;
+        self
+    }
+    /// Multiplier applied to the delay after each failed attempt (1 gives a constant delay).
+    pub fn factor(mut self,f:u32)->Retry {
+        self.factor=f;
+        self
+    }
+
+    /// Delay before attempt number `attempt` (counting from 0 for the first retry).
+    fn delay(&self,attempt:usize)->Duration {
+        let mut d=self.initial_delay;
+        for _ in 0..attempt {
+            d=match d.checked_mul(self.factor) { Some(d)=>d, None=>return self.max_delay };
+            if d>=self.max_delay {
+                return self.max_delay
+            }
+        }
+        std::cmp::min(d,self.max_delay)
+    }
+
+    /// Run `f` until it succeeds, fails with a non-transient error, or the maximal number of attempts is reached. `f` is given the number of the current attempt, starting at 0.
+    pub fn run<T,F:FnMut(usize)->Result<T,Error>>(&self,f:F)->Result<T,Error> {
+        self.run_if(f,Error::is_transient)
+    }
+
+    /// Like `run`, but with a custom classification of retryable errors.
+    pub fn run_if<T,F,C>(&self,mut f:F,retryable:C)->Result<T,Error>
+        where F:FnMut(usize)->Result<T,Error>, C:Fn(&Error)->bool {
+        let mut attempt=0;
+        loop {
+            match f(attempt) {
+                Ok(x)=>return Ok(x),
+                Err(e)=>{
+                    if attempt+1>=self.max_attempts || !retryable(&e) {
+                        return Err(e)
+                    }
+                    let d=self.delay(attempt);
+                    debug!("attempt {} failed ({}), retrying in {:?}",attempt,e,d);
+                    thread::sleep(d);
+                    attempt+=1
+                }
+            }
+        }
+    }
+}
+
+impl Session {
+    /// Connect, retrying according to `retry`. The session is reset with `disconnect` between attempts, as libssh requires.
+    pub fn connect_with_retry(&mut self,retry:&Retry)->Result<(),Error> {
+        retry.run(|attempt| {
+            if attempt>0 {
+                let _=self.disconnect();
+            }
+            self.connect()
+        })
+    }
+}
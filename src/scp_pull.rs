@@ -0,0 +1,183 @@
+//! SCP downloads as a sequence of entries, so that recursive downloads are a single loop.
+
+use std::io::Read;
+use std::path::PathBuf;
+
+use super::{Error,Permissions,Request,Scp};
+
+/// What the remote `scp` announces during a download.
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub enum ScpEntry {
+    /// The following entries are in directory `name` (recursive downloads only), until the matching `EndDirectory`.
+    Directory { name:PathBuf, mode:Permissions },
+    /// A file of `size` bytes, whose contents are read from the entries before asking for the next one.
+    File { name:PathBuf, size:u64, mode:Permissions },
+    EndDirectory,
+    /// A file could not be sent (for instance because it was not readable). The download goes on with the next one.
+    Warning(String)
+}
+
+#[cfg(unix)]
+pub(crate) fn name_to_path(name:&[u8])->PathBuf {
+    use std::os::unix::ffi::OsStrExt;
+    PathBuf::from(std::ffi::OsStr::from_bytes(name))
+}
+
+#[cfg(not(unix))]
+pub(crate) fn name_to_path(name:&[u8])->PathBuf {
+    PathBuf::from(String::from_utf8_lossy(name).into_owned())
+}
+
+/// A control line of the SCP protocol, as sent by `scp -f`, for the asynchronous implementation.
+#[cfg(all(unix,feature="async"))]
+pub(crate) enum Header {
+    Entry(ScpEntry),
+    /// Modification and access times, sent before an entry with `scp -p`.
+    Times,
+    /// The transfer was aborted.
+    Fatal(String)
+}
+
+/// Parse `line`, without its final newline.
+#[cfg(all(unix,feature="async"))]
+pub(crate) fn parse_header(line:&[u8])->Result<Header,Error> {
+    let invalid=|| Error::Ssh(format!("Invalid SCP header {:?}",String::from_utf8_lossy(line)));
+    let (&kind,rest)=line.split_first().ok_or_else(invalid)?;
+    match kind {
+        b'C'|b'D'=>{
+            let mut fields=rest.splitn(3,|&c| c==b' ');
+            let mut field=|| fields.next().and_then(|f| std::str::from_utf8(f).ok()).ok_or_else(invalid);
+            let mode=u32::from_str_radix(field()?,8).map_err(|_| invalid())?;
+            let size=field()?.parse().map_err(|_| invalid())?;
+            let name=fields.next().filter(|n| !n.is_empty()).ok_or_else(invalid)?;
+            // The name comes from the server: it must not escape the destination.
+            if name==b"." || name==b".." || name.contains(&b'/') {
+                return Err(invalid())
+            }
+            let (name,mode)=(name_to_path(name),Permissions::from_mode(mode));
+            Ok(Header::Entry(if kind==b'C' { ScpEntry::File { name,size,mode } } else { ScpEntry::Directory { name,mode } }))
+        },
+        b'E'=>Ok(Header::Entry(ScpEntry::EndDirectory)),
+        b'T'=>Ok(Header::Times),
+        1=>Ok(Header::Entry(ScpEntry::Warning(String::from_utf8_lossy(rest).into_owned()))),
+        2=>Ok(Header::Fatal(String::from_utf8_lossy(rest).into_owned())),
+        _=>Err(invalid())
+    }
+}
+
+/// The entries of a download, returned by `Scp::entries`. While the last entry is a file, reading from this returns the contents of that file.
+///
+///```no_run
+/// use ssh::*;
+/// use std::path::PathBuf;
+///
+/// let mut session=Session::new().unwrap();
+/// session.set_host("pijul.org").unwrap();
+/// session.parse_config(None).unwrap();
+/// session.connect().unwrap();
+/// session.userauth_publickey_auto(None).unwrap();
+/// let mut scp=session.scp_new(READ|RECURSIVE,"/tmp/dir").unwrap();
+/// scp.init().unwrap();
+/// let mut entries=scp.entries();
+/// let mut dir=PathBuf::from("dir");
+/// while let Some(entry)=entries.next() {
+///     match entry.unwrap() {
+///         ScpEntry::Directory { name,.. }=>{
+///             dir.push(name);
+///             std::fs::create_dir_all(&dir).unwrap()
+///         },
+///         ScpEntry::File { name,.. }=>{
+///             let mut f=std::fs::File::create(dir.join(name)).unwrap();
+///             std::io::copy(&mut entries,&mut f).unwrap();
+///         },
+///         ScpEntry::EndDirectory=>{ dir.pop(); },
+///         ScpEntry::Warning(w)=>eprintln!("{}",w)
+///     }
+/// }
+///```
+pub struct ScpEntries<'s,'b:'s> {
+    scp:&'s mut Scp<'b>,
+    done:bool
+}
+
+impl<'b> Scp<'b> {
+    /// Iterate over what the server sends, on an `Scp` opened in `READ` mode. Files and directories are accepted as they come, and the parts of files that are not read are skipped.
+    pub fn entries<'s>(&'s mut self)->ScpEntries<'s,'b> {
+        ScpEntries { scp:self,done:false }
+    }
+}
+
+impl<'s,'b:'s> ScpEntries<'s,'b> {
+    fn next_entry(&mut self)->Result<Option<ScpEntry>,Error> {
+        // The protocol has no way to skip the rest of a file.
+        if self.scp.size>0 {
+            std::io::copy(&mut *self.scp,&mut std::io::sink())?;
+        }
+        match self.scp.pull_request()? {
+            Request::NEWDIR=>{
+                let name=name_to_path(self.scp.request_get_filename()?);
+                let mode=self.scp.request_get_permissions()?;
+                self.scp.accept_request()?;
+                Ok(Some(ScpEntry::Directory { name,mode }))
+            },
+            Request::NEWFILE=>{
+                let name=name_to_path(self.scp.request_get_filename()?);
+                let mode=self.scp.request_get_permissions()?;
+                let size=self.scp.request_get_size();
+                self.scp.accept_request()?;
+                self.scp.size=size;
+                Ok(Some(ScpEntry::File { name,size,mode }))
+            },
+            Request::ENDDIR=>Ok(Some(ScpEntry::EndDirectory)),
+            Request::WARNING=>Ok(Some(ScpEntry::Warning(String::from_utf8_lossy(self.scp.request_get_warning()?).into_owned()))),
+            Request::EOF=>Ok(None)
+        }
+    }
+}
+
+impl<'s,'b:'s> Iterator for ScpEntries<'s,'b> {
+    type Item=Result<ScpEntry,Error>;
+    fn next(&mut self)->Option<Result<ScpEntry,Error>> {
+        if self.done {
+            return None
+        }
+        let r=self.next_entry();
+        if !matches!(r,Ok(Some(_))) {
+            self.done=true
+        }
+        r.transpose()
+    }
+}
+
+impl<'s,'b:'s> Read for ScpEntries<'s,'b> {
+    fn read(&mut self,buf:&mut [u8])->Result<usize,std::io::Error> {
+        self.scp.read(buf)
+    }
+}
+
+#[cfg(all(test,unix,feature="async"))]
+mod tests {
+    use super::{Header,ScpEntry,parse_header};
+    use std::path::PathBuf;
+
+    #[test]
+    fn parse_large_file() {
+        match parse_header(b"C0644 5368709120 big.iso").unwrap() {
+            Header::Entry(ScpEntry::File { name,size,mode })=>{
+                assert_eq!(name,PathBuf::from("big.iso"));
+                assert_eq!(size,5<<30);
+                assert_eq!(u32::from(mode),0o644);
+            },
+            _=>panic!("not a file")
+        }
+        // Larger than u64.
+        assert!(parse_header(b"C0644 18446744073709551616 big.iso").is_err());
+    }
+
+    #[test]
+    fn parse_invalid_names() {
+        for line in [&b"C0644 1 ../escape"[..],b"C0644 1 a/b",b"D0755 0 ..",b"C0644 1 "] {
+            assert!(parse_header(line).is_err(),"{:?}",String::from_utf8_lossy(line));
+        }
+    }
+}
@@ -0,0 +1,341 @@
+//! Security events, for intrusion detection and security telemetry: changed host keys, weak algorithms and authentication downgrades are reported to `SessionCallbacks::security_event` as they happen, so that they can be sent to a SIEM without parsing logs.
+//!
+//!```no_run
+//! use ssh::*;
+//!
+//! struct Siem;
+//! impl SessionCallbacks for Siem {
+//!     fn security_event(&mut self,event:&SecurityEvent) {
+//!         eprintln!("security: {:?}",event)
+//!     }
+//! }
+//!
+//! let mut session=Session::new().unwrap();
+//! session.set_host("pijul.org").unwrap();
+//! session.set_callbacks(Siem).unwrap();
+//! session.parse_config(None).unwrap();
+//! session.connect().unwrap();
+//! println!("{:?}",session.algorithms().unwrap());
+//!```
+
+use std::ffi::CStr;
+
+use super::libc::{c_char,c_int};
+use super::{AuthMethod,Error,HashType,Session,Session_,SshOptions};
+
+extern "C" {
+    fn ssh_get_kex_algo(s:*mut Session_)->*const c_char;
+    fn ssh_get_cipher_in(s:*mut Session_)->*const c_char;
+    fn ssh_get_cipher_out(s:*mut Session_)->*const c_char;
+    fn ssh_get_hmac_in(s:*mut Session_)->*const c_char;
+    fn ssh_get_hmac_out(s:*mut Session_)->*const c_char;
+    fn ssh_version(req_version:c_int)->*const c_char;
+}
+
+/// The kinds of algorithms negotiated in the protocol.
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Hash)]
+pub enum AlgorithmKind {
+    KeyExchange,
+    HostKey,
+    Cipher,
+    Mac
+}
+
+/// The algorithms negotiated with the server, returned by `Session::algorithms`.
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub struct Algorithms {
+    pub kex:String,
+    /// The type of the host key of the server, such as "ssh-ed25519".
+    pub host_key:String,
+    /// Cipher from the server to the client.
+    pub cipher_in:String,
+    /// Cipher from the client to the server.
+    pub cipher_out:String,
+    /// MAC from the server to the client, "aead-…" for ciphers that include one.
+    pub mac_in:String,
+    pub mac_out:String
+}
+
+impl Algorithms {
+    /// Each algorithm, with its kind.
+    pub fn iter(&self)->impl Iterator<Item=(AlgorithmKind,&str)> {
+        vec![
+            (AlgorithmKind::KeyExchange,&self.kex[..]),
+            (AlgorithmKind::HostKey,&self.host_key[..]),
+            (AlgorithmKind::Cipher,&self.cipher_in[..]),
+            (AlgorithmKind::Cipher,&self.cipher_out[..]),
+            (AlgorithmKind::Mac,&self.mac_in[..]),
+            (AlgorithmKind::Mac,&self.mac_out[..])
+        ].into_iter()
+    }
+}
+
+/// Something a security team may want to know about.
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub enum SecurityEvent {
+    /// The server presented a host key other than the ones in the known hosts files, or given to `Session::expect_host_key`: this is what a man-in-the-middle attack looks like. `old` and `new` are SHA256 fingerprints.
+    HostKeyChanged { host:String, port:u16, old:Vec<String>, new:String },
+    /// An algorithm considered weak (see `is_weak`) was negotiated.
+    WeakAlgorithmNegotiated { kind:AlgorithmKind, name:String },
+    /// Authentication succeeded with a weaker method than one the server refused before, for instance a password after a key was refused.
+    AuthDowngrade { refused:AuthMethod, used:AuthMethod }
+}
+
+/// Whether an algorithm is considered weak: SHA-1 key exchanges, DSA host keys, CBC mode and RC4 ciphers, MD5 and SHA-1 MACs, and no encryption or MAC at all.
+pub fn is_weak(kind:AlgorithmKind,name:&str)->bool {
+    match kind {
+        AlgorithmKind::KeyExchange=>name.ends_with("-sha1"),
+        AlgorithmKind::HostKey=>name=="ssh-dss",
+        AlgorithmKind::Cipher=>name=="none" || name.ends_with("-cbc") || name.starts_with("arcfour") || name.contains("-cbc@"),
+        AlgorithmKind::Mac=>name=="none" || name.starts_with("hmac-md5") || name.starts_with("hmac-sha1")
+    }
+}
+
+/// The algorithms allowed for a session. The presets follow the usual recommendations for OpenSSH servers: `modern` for recent peers only, `intermediate` for the general case, and `legacy` for old appliances. Custom policies are written as structures.
+///
+/// Besides configuring the session, a policy is checked once connected, in case something (such as `parse_config`) changed the configuration in between.
+///
+///```no_run
+/// use ssh::*;
+/// use ssh::security::SecurityPolicy;
+///
+/// let mut session=Session::new().unwrap();
+/// session.set_host("pijul.org").unwrap();
+/// session.parse_config(None).unwrap();
+/// session.set_security_policy(SecurityPolicy::modern()).unwrap();
+/// session.connect().unwrap();
+///```
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub struct SecurityPolicy {
+    pub kex:Vec<String>,
+    /// Host key signature algorithms, such as "rsa-sha2-256" (for RSA keys) or "ssh-ed25519".
+    pub host_key_types:Vec<String>,
+    pub ciphers:Vec<String>,
+    /// MACs, for the ciphers that don't include one.
+    pub macs:Vec<String>
+}
+
+fn strings(v:&[&str])->Vec<String> {
+    v.iter().map(|s| s.to_string()).collect()
+}
+
+impl SecurityPolicy {
+    /// Only current algorithms: Curve25519 key exchanges, authenticated ciphers, and MACs computed on encrypted data.
+    pub fn modern()->SecurityPolicy {
+        SecurityPolicy {
+            kex:strings(&["sntrup761x25519-sha512@openssh.com","curve25519-sha256","curve25519-sha256@libssh.org"]),
+            host_key_types:strings(&["ssh-ed25519","ecdsa-sha2-nistp521","ecdsa-sha2-nistp384","ecdsa-sha2-nistp256","rsa-sha2-512","rsa-sha2-256"]),
+            ciphers:strings(&["chacha20-poly1305@openssh.com","aes256-gcm@openssh.com","aes128-gcm@openssh.com"]),
+            macs:strings(&["hmac-sha2-512-etm@openssh.com","hmac-sha2-256-etm@openssh.com"])
+        }
+    }
+    /// `modern`, plus NIST curves and large Diffie-Hellman groups, CTR mode ciphers, and SHA-2 MACs.
+    pub fn intermediate()->SecurityPolicy {
+        let mut p=SecurityPolicy::modern();
+        p.kex.extend(strings(&["ecdh-sha2-nistp521","ecdh-sha2-nistp384","ecdh-sha2-nistp256","diffie-hellman-group18-sha512","diffie-hellman-group16-sha512","diffie-hellman-group-exchange-sha256","diffie-hellman-group14-sha256"]));
+        p.ciphers.extend(strings(&["aes256-ctr","aes192-ctr","aes128-ctr"]));
+        p.macs.extend(strings(&["hmac-sha2-512","hmac-sha2-256"]));
+        p
+    }
+    /// `intermediate`, plus the SHA-1 key exchanges and signatures, DSA keys, CBC mode ciphers and SHA-1 MACs still needed by old devices. All of these are considered weak (see `is_weak`).
+    pub fn legacy()->SecurityPolicy {
+        let mut p=SecurityPolicy::intermediate();
+        p.kex.extend(strings(&["diffie-hellman-group14-sha1","diffie-hellman-group-exchange-sha1","diffie-hellman-group1-sha1"]));
+        p.host_key_types.extend(strings(&["ssh-rsa","ssh-dss"]));
+        p.ciphers.extend(strings(&["aes256-cbc","aes192-cbc","aes128-cbc","3des-cbc"]));
+        p.macs.extend(strings(&["hmac-sha1-etm@openssh.com","hmac-sha1"]));
+        p
+    }
+    /// Whether this policy allows `name`.
+    pub fn allows(&self,kind:AlgorithmKind,name:&str)->bool {
+        let allowed=|list:&[String]| list.iter().any(|a| a==name);
+        match kind {
+            AlgorithmKind::KeyExchange=>allowed(&self.kex),
+            // The key type of RSA keys is "ssh-rsa", whatever the signature algorithm.
+            AlgorithmKind::HostKey if name=="ssh-rsa"=>self.host_key_types.iter().any(|a| a=="ssh-rsa" || a.starts_with("rsa-sha2-")),
+            AlgorithmKind::HostKey=>allowed(&self.host_key_types),
+            AlgorithmKind::Cipher=>allowed(&self.ciphers),
+            // The MAC of authenticated ciphers.
+            AlgorithmKind::Mac if name.starts_with("aead-")=>true,
+            AlgorithmKind::Mac=>allowed(&self.macs)
+        }
+    }
+    /// Check negotiated algorithms against this policy, failing with `Error::PolicyViolation` for the first one not allowed.
+    pub fn check(&self,algorithms:&Algorithms)->Result<(),Error> {
+        match algorithms.iter().find(|&(kind,name)| !self.allows(kind,name)) {
+            Some((kind,name))=>Err(Error::PolicyViolation(kind,name.to_string())),
+            None=>Ok(())
+        }
+    }
+}
+
+/// What the linked libssh supports, returned by `supported`, to check at startup that a policy can be applied.
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub struct Supported {
+    /// The version of libssh, such as "0.10.6".
+    pub version:String,
+    /// The cryptographic library libssh uses: "openssl", "libgcrypt" or "mbedtls".
+    pub crypto:String,
+    /// Whether compression is available.
+    pub zlib:bool,
+    /// Whether GSSAPI (Kerberos) authentication is available.
+    pub gssapi:bool,
+    pub kex:Vec<String>,
+    pub host_key_types:Vec<String>,
+    pub ciphers:Vec<String>,
+    pub macs:Vec<String>
+}
+
+/// Algorithms looked for by `supported`, on top of those of `SecurityPolicy::legacy`.
+const EXTRA_KEX:[&str;1]=["mlkem768x25519-sha256"];
+const EXTRA_HOST_KEY_TYPES:[&str;2]=["sk-ssh-ed25519@openssh.com","sk-ecdsa-sha2-nistp256@openssh.com"];
+const EXTRA_CIPHERS:[&str;2]=["blowfish-cbc","none"];
+const EXTRA_MACS:[&str;2]=["hmac-md5","none"];
+
+/// List the algorithms and features of the linked libssh. libssh has no such list: each known algorithm is tried in the options of a session, which libssh refuses for algorithms it doesn't support.
+///
+///```
+/// use ssh::security::{SecurityPolicy,supported};
+///
+/// let supported=supported().unwrap();
+/// let policy=SecurityPolicy::modern();
+/// if !policy.kex.iter().any(|k| supported.kex.contains(k)) {
+///     panic!("libssh {} has no modern key exchange",supported.version)
+/// }
+///```
+pub fn supported()->Result<Supported,Error> {
+    let mut session=Session::new().map_err(|_| Error::Ssh("Could not allocate a session".to_string()))?;
+    let legacy=SecurityPolicy::legacy();
+    let mut probe=|option:SshOptions,known:&[String],extra:&[&str]| {
+        known.iter().map(|s| &s[..]).chain(extra.iter().cloned())
+            .filter(|&a| session.set_list(option,&[a]).is_ok())
+            .map(|a| a.to_string())
+            .collect::<Vec<_>>()
+    };
+    let kex=probe(SshOptions::KEY_EXCHANGE,&legacy.kex,&EXTRA_KEX);
+    let host_key_types=probe(SshOptions::HOSTKEYS,&legacy.host_key_types,&EXTRA_HOST_KEY_TYPES);
+    let ciphers=probe(SshOptions::CIPHERS_C_S,&legacy.ciphers,&EXTRA_CIPHERS);
+    let macs=probe(SshOptions::HMAC_C_S,&legacy.macs,&EXTRA_MACS);
+    // Such as "0.10.6/openssl/zlib".
+    let full_version=algorithm(unsafe { ssh_version(0) });
+    let mut parts=full_version.split('/');
+    let version=parts.next().unwrap_or("").to_string();
+    let rest=parts.collect::<Vec<_>>();
+    Ok(Supported {
+        version,
+        crypto:rest.iter().find(|&&p| p!="zlib").map(|p| p.to_string()).unwrap_or_default(),
+        zlib:rest.contains(&"zlib"),
+        gssapi:has_gssapi(),
+        kex,
+        host_key_types,
+        ciphers,
+        macs
+    })
+}
+
+/// libssh only has its GSSAPI functions when compiled with GSSAPI.
+#[cfg(unix)]
+fn has_gssapi()->bool {
+    !unsafe { super::libc::dlsym(super::libc::RTLD_DEFAULT,b"ssh_gssapi_set_creds\0".as_ptr() as *const c_char) }.is_null()
+}
+
+#[cfg(not(unix))]
+fn has_gssapi()->bool {
+    false
+}
+
+/// How much an authentication method proves, for detecting downgrades.
+fn strength(method:AuthMethod)->u8 {
+    match method {
+        AuthMethod::None=>0,
+        AuthMethod::Password=>1,
+        AuthMethod::KeyboardInteractive=>2,
+        AuthMethod::PublicKey=>3
+    }
+}
+
+fn algorithm(name:*const c_char)->String {
+    if name.is_null() {
+        String::new()
+    } else {
+        unsafe { CStr::from_ptr(name) }.to_string_lossy().into_owned()
+    }
+}
+
+impl Session {
+    /// The algorithms negotiated with the server, once connected.
+    pub fn algorithms(&self)->Result<Algorithms,Error> {
+        if !self.is_connected() {
+            return Err(Error::Ssh("Not connected".to_string()))
+        }
+        let host_key=self.get_server_public_key()?;
+        unsafe {
+            Ok(Algorithms {
+                kex:algorithm(ssh_get_kex_algo(self.session)),
+                host_key:host_key.key_type().to_string(),
+                cipher_in:algorithm(ssh_get_cipher_in(self.session)),
+                cipher_out:algorithm(ssh_get_cipher_out(self.session)),
+                mac_in:algorithm(ssh_get_hmac_in(self.session)),
+                mac_out:algorithm(ssh_get_hmac_out(self.session))
+            })
+        }
+    }
+    /// Give `event` to the callbacks of this session, if any.
+    pub(crate) fn security_event(&self,event:SecurityEvent) {
+        trace_event!(event=?event,"security event");
+        if let Some(ref r)=self.callbacks {
+            r.security_event(&event)
+        }
+    }
+    /// Only allow the algorithms of `policy` for this session: the algorithm lists are set now, and checked after `connect`. This must be done before `connect`.
+    pub fn set_security_policy(&mut self,policy:SecurityPolicy)->Result<(),Error> {
+        self.set_key_exchange(&policy.kex)?;
+        self.set_host_key_types(&policy.host_key_types)?;
+        self.set_ciphers(&policy.ciphers)?;
+        self.set_macs(&policy.macs)?;
+        self.policy=Some(policy);
+        Ok(())
+    }
+    /// Checks done once connected: the expected host keys, the security policy, and the strength of the algorithms.
+    pub(crate) fn after_connect(&self)->Result<(),Error> {
+        self.check_expected_host_key()?;
+        if let Some(ref policy)=self.policy {
+            if let Err(e)=self.algorithms().and_then(|a| policy.check(&a)) {
+                self.drop_connection();
+                return Err(e)
+            }
+        }
+        if self.callbacks.is_some() {
+            let algorithms=self.algorithms()?;
+            let mut reported=Vec::new();
+            for (kind,name) in algorithms.iter() {
+                if is_weak(kind,name) && !reported.contains(&name) {
+                    reported.push(name);
+                    self.security_event(SecurityEvent::WeakAlgorithmNegotiated { kind,name:name.to_string() })
+                }
+            }
+        }
+        Ok(())
+    }
+    /// Report a host key that doesn't match the known hosts files.
+    pub(crate) fn report_host_key_changed(&self) {
+        if self.callbacks.is_none() {
+            return
+        }
+        if let Ok(key)=self.get_server_public_key() {
+            let host=self.host().unwrap_or_default();
+            let port=self.port();
+            let old=self.known_keys(&host,port,&key).into_iter().map(|k| k.fingerprint).collect();
+            let new=key.fingerprint(HashType::Sha256).unwrap_or_default();
+            self.security_event(SecurityEvent::HostKeyChanged { host,port,old,new })
+        }
+    }
+    /// Report a successful authentication with `method` if a stronger method was refused before.
+    pub(crate) fn report_auth_downgrade(&self,method:AuthMethod,refused:&[AuthMethod]) {
+        if let Some(&strongest)=refused.iter().max_by_key(|&&m| strength(m)) {
+            if strength(strongest)>strength(method) {
+                self.security_event(SecurityEvent::AuthDowngrade { refused:strongest,used:method })
+            }
+        }
+    }
+}
@@ -0,0 +1,71 @@
+//! Serialization of results, for machine-readable reports. Command output is serialized as text (invalid UTF-8 replaced by U+FFFD), errors as their message, and durations in seconds.
+
+use serde::ser::{Serialize,SerializeStruct,Serializer};
+
+use super::{BatchResult,Error,Output};
+use super::fleet::HostResult;
+
+/// The message of an error, followed by the messages of its causes.
+fn message(e:&Error)->String {
+    let mut msg=e.to_string();
+    let mut source=std::error::Error::source(e);
+    while let Some(s)=source {
+        msg.push_str(": ");
+        msg.push_str(&s.to_string());
+        source=s.source()
+    }
+    msg
+}
+
+impl Serialize for Error {
+    fn serialize<S:Serializer>(&self,s:S)->Result<S::Ok,S::Error> {
+        let mut st=s.serialize_struct("Error",2)?;
+        st.serialize_field("message",&message(self))?;
+        st.serialize_field("transient",&self.is_transient())?;
+        st.end()
+    }
+}
+
+impl Serialize for Output {
+    fn serialize<S:Serializer>(&self,s:S)->Result<S::Ok,S::Error> {
+        let mut st=s.serialize_struct("Output",3)?;
+        st.serialize_field("stdout",&String::from_utf8_lossy(&self.stdout))?;
+        st.serialize_field("stderr",&String::from_utf8_lossy(&self.stderr))?;
+        st.serialize_field("exit_status",&self.exit_status)?;
+        st.end()
+    }
+}
+
+/// Serialize `Ok` results in an `output` field, and errors in an `error` field.
+fn result<T:Serialize,S:SerializeStruct>(st:&mut S,r:&Result<T,Error>)->Result<(),S::Error> {
+    match *r {
+        Ok(ref t)=>{
+            st.serialize_field("output",t)?;
+            st.skip_field("error")
+        },
+        Err(ref e)=>{
+            st.skip_field("output")?;
+            st.serialize_field("error",e)
+        }
+    }
+}
+
+impl Serialize for BatchResult {
+    fn serialize<S:Serializer>(&self,s:S)->Result<S::Ok,S::Error> {
+        let mut st=s.serialize_struct("BatchResult",4)?;
+        st.serialize_field("command",&self.command)?;
+        result(&mut st,&self.result)?;
+        st.serialize_field("duration",&self.duration.as_secs_f64())?;
+        st.end()
+    }
+}
+
+impl<T:Serialize> Serialize for HostResult<T> {
+    fn serialize<S:Serializer>(&self,s:S)->Result<S::Ok,S::Error> {
+        let mut st=s.serialize_struct("HostResult",4)?;
+        st.serialize_field("host",&self.host)?;
+        result(&mut st,&self.result)?;
+        st.serialize_field("duration",&self.duration.as_secs_f64())?;
+        st.end()
+    }
+}
@@ -0,0 +1,748 @@
+//! SFTP, the file transfer protocol of SSH: unlike SCP, it can list directories, read file attributes, and remove or rename files.
+//!
+//!```no_run
+//! use ssh::*;
+//! use std::io::Read;
+//!
+//! let mut session=Session::new().unwrap();
+//! session.set_host("pijul.org").unwrap();
+//! session.parse_config(None).unwrap();
+//! session.connect().unwrap();
+//! session.userauth_publickey_auto(None).unwrap();
+//! let sftp=session.sftp_new().unwrap();
+//! for entry in sftp.read_dir("/tmp").unwrap() {
+//!     println!("{} {:?} {}",entry.permissions,entry.file_type,entry.name)
+//! }
+//! let mut contents=String::new();
+//! sftp.open("/etc/hostname").unwrap().read_to_string(&mut contents).unwrap();
+//!```
+
+use std::ffi::{CStr,CString,OsString};
+use std::io::{Read,Write};
+use std::path::Path;
+
+use super::libc::{c_char,c_int,c_void,size_t,ssize_t};
+use super::{Channel_,Error,Permissions,Session,Session_,err,path_as_ptr};
+use super::key::String_;
+
+#[allow(missing_copy_implementations)]
+enum Sftp_ {}
+#[allow(missing_copy_implementations)]
+enum File_ {}
+#[allow(missing_copy_implementations)]
+enum Dir_ {}
+
+/// libssh's `struct sftp_attributes_struct`.
+#[repr(C)]
+struct Attributes_ {
+    name:*mut c_char,
+    longname:*mut c_char,
+    flags:u32,
+    file_type:u8,
+    size:u64,
+    uid:u32,
+    gid:u32,
+    owner:*mut c_char,
+    group:*mut c_char,
+    permissions:u32,
+    atime64:u64,
+    atime:u32,
+    atime_nseconds:u32,
+    createtime:u64,
+    createtime_nseconds:u32,
+    mtime64:u64,
+    mtime:u32,
+    mtime_nseconds:u32,
+    acl:*mut c_void,
+    extended_count:u32,
+    extended_type:*mut c_void,
+    extended_data:*mut c_void
+}
+
+extern "C" {
+    fn sftp_new(s:*mut Session_)->*mut Sftp_;
+    fn sftp_init(s:*mut Sftp_)->c_int;
+    fn sftp_free(s:*mut Sftp_);
+    fn sftp_get_error(s:*mut Sftp_)->c_int;
+    fn sftp_open(s:*mut Sftp_,file:*const c_char,access:c_int,mode:u32)->*mut File_;
+    fn sftp_close(f:*mut File_)->c_int;
+    fn sftp_read(f:*mut File_,buf:*mut c_void,count:size_t)->ssize_t;
+    fn sftp_write(f:*mut File_,buf:*const c_void,count:size_t)->ssize_t;
+    fn sftp_seek64(f:*mut File_,offset:u64)->c_int;
+    fn sftp_tell64(f:*mut File_)->u64;
+    fn sftp_fstat(f:*mut File_)->*mut Attributes_;
+    fn sftp_opendir(s:*mut Sftp_,path:*const c_char)->*mut Dir_;
+    fn sftp_readdir(s:*mut Sftp_,d:*mut Dir_)->*mut Attributes_;
+    fn sftp_dir_eof(d:*mut Dir_)->c_int;
+    fn sftp_closedir(d:*mut Dir_)->c_int;
+    fn sftp_stat(s:*mut Sftp_,path:*const c_char)->*mut Attributes_;
+    fn sftp_lstat(s:*mut Sftp_,path:*const c_char)->*mut Attributes_;
+    fn sftp_attributes_free(a:*mut Attributes_);
+    fn sftp_unlink(s:*mut Sftp_,path:*const c_char)->c_int;
+    fn sftp_rmdir(s:*mut Sftp_,path:*const c_char)->c_int;
+    fn sftp_mkdir(s:*mut Sftp_,path:*const c_char,mode:u32)->c_int;
+    fn sftp_rename(s:*mut Sftp_,original:*const c_char,newname:*const c_char)->c_int;
+    fn sftp_chmod(s:*mut Sftp_,path:*const c_char,mode:u32)->c_int;
+    fn sftp_setstat(s:*mut Sftp_,path:*const c_char,attr:*mut Attributes_)->c_int;
+    fn sftp_fsync(f:*mut File_)->c_int;
+    fn sftp_chown(s:*mut Sftp_,path:*const c_char,owner:u32,group:u32)->c_int;
+    fn sftp_extension_supported(s:*mut Sftp_,name:*const c_char,data:*const c_char)->c_int;
+    fn sftp_statvfs(s:*mut Sftp_,path:*const c_char)->*mut Statvfs_;
+    fn sftp_statvfs_free(s:*mut Statvfs_);
+    fn ssh_string_len(s:*const String_)->size_t;
+    fn ssh_string_data(s:*const String_)->*const c_void;
+}
+
+const SSH_FXP_STATUS:u8=101;
+const SSH_FXP_EXTENDED:u8=200;
+/// The id of the extended requests sent by this crate, far from those of libssh, which counts from 0.
+const EXTENDED_ID:u32=0xc0de_0000;
+
+fn put_u32(buf:&mut Vec<u8>,n:u32) {
+    buf.extend_from_slice(&n.to_be_bytes())
+}
+
+fn put_string(buf:&mut Vec<u8>,s:&[u8]) {
+    put_u32(buf,s.len() as u32);
+    buf.extend_from_slice(s)
+}
+
+const SSH_FX_EOF:c_int=1;
+const SSH_FX_NO_SUCH_FILE:c_int=2;
+const SSH_FX_PERMISSION_DENIED:c_int=3;
+const SSH_FX_OP_UNSUPPORTED:c_int=8;
+const SSH_FX_NO_SUCH_PATH:c_int=10;
+const SSH_FX_FILE_ALREADY_EXISTS:c_int=11;
+const SSH_FX_WRITE_PROTECT:c_int=12;
+// Status codes of later versions of the protocol, sent by some servers anyway.
+const SSH_FX_NO_SPACE_ON_FILESYSTEM:c_int=14;
+const SSH_FX_QUOTA_EXCEEDED:c_int=15;
+
+/// An error for SFTP status `code`, with an `io::ErrorKind` matching it.
+fn status_error<D:std::fmt::Display>(code:c_int,msg:&D)->Error {
+    use std::io::ErrorKind::*;
+    let (kind,what)=match code {
+        SSH_FX_NO_SPACE_ON_FILESYSTEM|SSH_FX_QUOTA_EXCEEDED=>return Error::NoSpace(format!("SFTP error {}: {}",code,msg)),
+        SSH_FX_EOF=>(UnexpectedEof,"end of file"),
+        SSH_FX_NO_SUCH_FILE|SSH_FX_NO_SUCH_PATH=>(NotFound,"no such file"),
+        SSH_FX_PERMISSION_DENIED|SSH_FX_WRITE_PROTECT=>(PermissionDenied,"permission denied"),
+        SSH_FX_FILE_ALREADY_EXISTS=>(AlreadyExists,"file already exists"),
+        SSH_FX_OP_UNSUPPORTED=>(Unsupported,"operation not supported"),
+        _=>(Other,"failure")
+    };
+    Error::IO(std::io::Error::new(kind,format!("SFTP error {} ({}): {}",code,what,msg)))
+}
+
+const SSH_FILEXFER_ATTR_SIZE:u32=0x1;
+const SSH_FILEXFER_ATTR_ACMODTIME:u32=0x8;
+
+/// The type of a remote file.
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum FileType {
+    File,
+    Directory,
+    Symlink,
+    Special,
+    Unknown
+}
+
+impl FileType {
+    fn from_sftp(t:u8)->FileType {
+        match t {
+            1=>FileType::File,
+            2=>FileType::Directory,
+            3=>FileType::Symlink,
+            4=>FileType::Special,
+            _=>FileType::Unknown
+        }
+    }
+    /// The file type character of `ls -l`.
+    pub(crate) fn from_ls(c:u8)->FileType {
+        match c {
+            b'-'=>FileType::File,
+            b'd'=>FileType::Directory,
+            b'l'=>FileType::Symlink,
+            b'c'|b'b'|b'p'|b's'=>FileType::Special,
+            _=>FileType::Unknown
+        }
+    }
+}
+
+/// Attributes of a remote file.
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub struct Metadata {
+    /// The file name, without its directory (empty for `stat`), decoded with the filename encoding of the SFTP session: invalid UTF-8 sequences are replaced by U+FFFD by default.
+    pub name:String,
+    /// The file name, as sent by the server.
+    pub raw_name:Vec<u8>,
+    pub file_type:FileType,
+    pub size:Option<u64>,
+    pub permissions:Permissions,
+    pub uid:u32,
+    pub gid:u32,
+    /// Last modification, in seconds since the Unix epoch.
+    pub mtime:Option<u64>,
+    /// Last access, in seconds since the Unix epoch.
+    pub atime:Option<u64>
+}
+
+impl Metadata {
+    /// Read, then free, attributes returned by libssh.
+    unsafe fn from_raw(a:*mut Attributes_,encoding:FilenameEncoding)->Metadata {
+        let r=&*a;
+        let raw_name=if r.name.is_null() { Vec::new() } else { CStr::from_ptr(r.name).to_bytes().to_vec() };
+        let time=|t:u64,t32:u32| if r.flags&SSH_FILEXFER_ATTR_ACMODTIME!=0 { Some(if t>0 { t } else { t32 as u64 }) } else { None };
+        let m=Metadata {
+            name:encoding.decode(&raw_name),
+            raw_name,
+            file_type:FileType::from_sftp(r.file_type),
+            size:if r.flags&SSH_FILEXFER_ATTR_SIZE!=0 { Some(r.size) } else { None },
+            permissions:Permissions::from_mode(r.permissions&0o7777),
+            uid:r.uid,
+            gid:r.gid,
+            mtime:time(r.mtime64,r.mtime),
+            atime:time(r.atime64,r.atime)
+        };
+        sftp_attributes_free(a);
+        m
+    }
+    pub fn is_dir(&self)->bool {
+        self.file_type==FileType::Directory
+    }
+    /// The file name, to build paths from: the bytes sent by the server if `name` was decoded as UTF-8 (so that names that aren't valid UTF-8 are kept intact), else the transcoded `name`.
+    pub fn name_os(&self)->OsString {
+        #[cfg(unix)]
+        {
+            use std::os::unix::ffi::OsStringExt;
+            if String::from_utf8_lossy(&self.raw_name)==self.name {
+                return OsString::from_vec(self.raw_name.clone())
+            }
+        }
+        OsString::from(&self.name)
+    }
+}
+
+/// What `Sftp::rename` does when the destination exists.
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Default)]
+pub enum RenameMode {
+    /// Replace it atomically with the `posix-rename@openssh.com` extension, if the server supports it. Otherwise, use a standard SFTP rename, which fails on most servers (the default).
+    #[default]
+    Atomic,
+    /// Like `Atomic`, but without the extension, remove the destination and try again. Readers may briefly find no file.
+    Replace,
+    /// Only use standard SFTP renames.
+    Standard
+}
+
+/// How the file names of an SFTP session are encoded on the server. SFTP version 3, spoken by OpenSSH, sends names as raw bytes, which are usually, but not always, UTF-8.
+#[derive(Debug,Clone,Copy,Default)]
+pub enum FilenameEncoding {
+    /// Names are decoded as UTF-8, replacing invalid sequences by U+FFFD in `Metadata::name`, and paths are sent unchanged (the default).
+    #[default]
+    Utf8,
+    /// ISO 8859-1, as used by old servers in western Europe.
+    Latin1,
+    /// Another encoding: `decode` turns names into strings, and `encode` turns paths into names, returning `None` if the path can't be encoded.
+    Custom { decode:fn(&[u8])->String, encode:fn(&str)->Option<Vec<u8>> }
+}
+
+impl FilenameEncoding {
+    /// `name`, as sent by the server, as a string.
+    pub fn decode(&self,name:&[u8])->String {
+        match *self {
+            FilenameEncoding::Utf8=>String::from_utf8_lossy(name).into_owned(),
+            FilenameEncoding::Latin1=>name.iter().map(|&b| b as char).collect(),
+            FilenameEncoding::Custom { decode,.. }=>decode(name)
+        }
+    }
+    /// `path` as sent to the server. Paths that aren't valid Unicode (such as those built from `Metadata::name_os`) are sent unchanged.
+    pub fn encode(&self,path:&Path)->Result<Vec<u8>,Error> {
+        let invalid=|| Error::IO(std::io::Error::new(std::io::ErrorKind::InvalidInput,format!("{:?} can't be encoded in {:?}",path,self)));
+        let s=match (self,path.to_str()) {
+            (&FilenameEncoding::Utf8,_)|(_,None)=>return Ok(path_as_ptr(path)?.into_bytes()),
+            (_,Some(s))=>s
+        };
+        match *self {
+            FilenameEncoding::Latin1=>s.chars().map(|c| if (c as u32)<256 { Some(c as u8) } else { None }).collect::<Option<Vec<u8>>>().ok_or_else(invalid),
+            FilenameEncoding::Custom { encode,.. }=>encode(s).ok_or_else(invalid),
+            FilenameEncoding::Utf8=>unreachable!()
+        }
+    }
+}
+
+/// The first fields of libssh's `struct sftp_session_struct`.
+#[repr(C)]
+struct SftpSession_ {
+    session:*mut Session_,
+    channel:*mut Channel_
+}
+
+/// The first fields of libssh's `struct sftp_file_struct`.
+#[repr(C)]
+struct SftpFile_ {
+    sftp:*mut Sftp_,
+    name:*mut c_char,
+    offset:u64,
+    handle:*const String_
+}
+
+/// libssh's `struct sftp_statvfs_struct`.
+#[repr(C)]
+struct Statvfs_ {
+    f_bsize:u64,
+    f_frsize:u64,
+    f_blocks:u64,
+    f_bfree:u64,
+    f_bavail:u64,
+    f_files:u64,
+    f_ffree:u64,
+    f_favail:u64,
+    f_fsid:u64,
+    f_flag:u64,
+    f_namemax:u64
+}
+
+/// Size and usage of a remote file system, as `statvfs(2)`, returned by `Sftp::statvfs`. Sizes are in blocks of `block_size` bytes.
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub struct FsStats {
+    pub block_size:u64,
+    pub blocks:u64,
+    /// Free blocks, including those reserved to root.
+    pub free:u64,
+    /// Free blocks available to unprivileged users.
+    pub available:u64,
+    pub files:u64,
+    pub files_available:u64,
+    pub read_only:bool,
+    pub name_max:u64
+}
+
+impl FsStats {
+    /// Bytes available to unprivileged users. Quotas are not taken into account.
+    pub fn available_bytes(&self)->u64 {
+        self.available.saturating_mul(self.block_size)
+    }
+}
+
+/// An SFTP session, opened by `Session::sftp_new`.
+pub struct Sftp<'b> {
+    session:&'b Session,
+    sftp:*mut Sftp_,
+    encoding:FilenameEncoding,
+    rename_mode:RenameMode
+}
+
+impl Session {
+    /// Start an SFTP session. This fails if the server has no SFTP subsystem.
+    pub fn sftp_new<'b>(&'b mut self)->Result<Sftp<'b>,Error> {
+        let sftp=unsafe { sftp_new(self.session) };
+        if sftp.is_null() {
+            return Err(err(self))
+        }
+        self.children.set(self.children.get()+1);
+        let sftp=Sftp { session:self,sftp,encoding:FilenameEncoding::Utf8,rename_mode:RenameMode::Atomic };
+        if unsafe { sftp_init(sftp.sftp) }<0 {
+            return Err(sftp.error())
+        }
+        Ok(sftp)
+    }
+}
+
+impl<'b> Sftp<'b> {
+    /// The error of the last operation, with an `io::ErrorKind` matching the SFTP status code.
+    pub(crate) fn error(&self)->Error {
+        let code=unsafe { sftp_get_error(self.sftp) };
+        if code==0 {
+            return err(self.session)
+        }
+        status_error(code,&err(self.session))
+    }
+    /// Use `encoding` for the paths given to this session, and the names it returns.
+    pub fn set_filename_encoding(&mut self,encoding:FilenameEncoding) {
+        self.encoding=encoding
+    }
+    pub fn filename_encoding(&self)->FilenameEncoding {
+        self.encoding
+    }
+    /// Choose what `rename` does when its destination exists.
+    pub fn set_rename_mode(&mut self,mode:RenameMode) {
+        self.rename_mode=mode
+    }
+    pub fn rename_mode(&self)->RenameMode {
+        self.rename_mode
+    }
+    fn c_path(&self,path:&Path)->Result<CString,Error> {
+        Ok(CString::new(self.encoding.encode(path)?)?)
+    }
+    fn check(&self,e:c_int)->Result<(),Error> {
+        if e<0 { Err(self.error()) } else { Ok(()) }
+    }
+    /// Open a file with the given `open(2)` flags (for instance `libc::O_WRONLY|libc::O_CREAT`), and `mode` if it is created.
+    pub fn open_with<P:AsRef<Path>,M:Into<Permissions>>(&self,path:P,flags:c_int,mode:M)->Result<File<'_>,Error> {
+        let mode=u32::from(mode.into());
+        let p=self.c_path(path.as_ref())?;
+        let f=unsafe { sftp_open(self.sftp,p.as_ptr(),flags,mode) };
+        if f.is_null() {
+            Err(self.error())
+        } else {
+            Ok(File { sftp:self,file:f })
+        }
+    }
+    /// Open a file for reading.
+    pub fn open<P:AsRef<Path>>(&self,path:P)->Result<File<'_>,Error> {
+        self.open_with(path,super::libc::O_RDONLY,0)
+    }
+    /// Create a file for writing (truncating it if it exists).
+    pub fn create<P:AsRef<Path>,M:Into<Permissions>>(&self,path:P,mode:M)->Result<File<'_>,Error> {
+        self.open_with(path,super::libc::O_WRONLY|super::libc::O_CREAT|super::libc::O_TRUNC,mode)
+    }
+    /// The entries of a directory, except `.` and `..`.
+    pub fn read_dir<P:AsRef<Path>>(&self,path:P)->Result<Vec<Metadata>,Error> {
+        let p=self.c_path(path.as_ref())?;
+        let d=unsafe { sftp_opendir(self.sftp,p.as_ptr()) };
+        if d.is_null() {
+            return Err(self.error())
+        }
+        let mut v=Vec::new();
+        loop {
+            let a=unsafe { sftp_readdir(self.sftp,d) };
+            if a.is_null() {
+                break
+            }
+            let m=unsafe { Metadata::from_raw(a,self.encoding) };
+            if m.name!="." && m.name!=".." {
+                v.push(m)
+            }
+        }
+        let eof=unsafe { sftp_dir_eof(d) }!=0;
+        let e=if eof { None } else { Some(self.error()) };
+        unsafe { sftp_closedir(d) };
+        match e {
+            Some(e)=>Err(e),
+            None=>Ok(v)
+        }
+    }
+    /// Attributes of a file, following symbolic links.
+    pub fn stat<P:AsRef<Path>>(&self,path:P)->Result<Metadata,Error> {
+        let p=self.c_path(path.as_ref())?;
+        let a=unsafe { sftp_stat(self.sftp,p.as_ptr()) };
+        if a.is_null() { Err(self.error()) } else { Ok(unsafe { Metadata::from_raw(a,self.encoding) }) }
+    }
+    /// Attributes of a file, without following symbolic links.
+    pub fn lstat<P:AsRef<Path>>(&self,path:P)->Result<Metadata,Error> {
+        let p=self.c_path(path.as_ref())?;
+        let a=unsafe { sftp_lstat(self.sftp,p.as_ptr()) };
+        if a.is_null() { Err(self.error()) } else { Ok(unsafe { Metadata::from_raw(a,self.encoding) }) }
+    }
+    pub fn remove_file<P:AsRef<Path>>(&self,path:P)->Result<(),Error> {
+        let p=self.c_path(path.as_ref())?;
+        self.check(unsafe { sftp_unlink(self.sftp,p.as_ptr()) })
+    }
+    pub fn remove_dir<P:AsRef<Path>>(&self,path:P)->Result<(),Error> {
+        let p=self.c_path(path.as_ref())?;
+        self.check(unsafe { sftp_rmdir(self.sftp,p.as_ptr()) })
+    }
+    pub fn create_dir<P:AsRef<Path>,M:Into<Permissions>>(&self,path:P,mode:M)->Result<(),Error> {
+        let p=self.c_path(path.as_ref())?;
+        self.check(unsafe { sftp_mkdir(self.sftp,p.as_ptr(),u32::from(mode.into())) })
+    }
+    /// Rename a file. If `to` exists, it is replaced atomically on servers supporting `posix-rename@openssh.com` (such as OpenSSH); on others (SFTP version 3), what happens depends on `set_rename_mode`.
+    pub fn rename<P:AsRef<Path>,Q:AsRef<Path>>(&self,from:P,to:Q)->Result<(),Error> {
+        let (from,to)=(from.as_ref(),to.as_ref());
+        let c_from=self.c_path(from)?;
+        let c_to=self.c_path(to)?;
+        if self.rename_mode!=RenameMode::Standard && self.extension_supported("posix-rename@openssh.com","1")? {
+            let mut data=Vec::new();
+            put_string(&mut data,c_from.as_bytes());
+            put_string(&mut data,c_to.as_bytes());
+            return self.extended("posix-rename@openssh.com",&data)
+        }
+        match self.check(unsafe { sftp_rename(self.sftp,c_from.as_ptr(),c_to.as_ptr()) }) {
+            Err(_) if self.rename_mode==RenameMode::Replace && self.lstat(to).is_ok() && self.lstat(from).is_ok()=>{
+                debug!("replacing {:?} non-atomically",to);
+                self.remove_file(to)?;
+                self.check(unsafe { sftp_rename(self.sftp,c_from.as_ptr(),c_to.as_ptr()) })
+            },
+            r=>r
+        }
+    }
+    pub fn set_permissions<P:AsRef<Path>,M:Into<Permissions>>(&self,path:P,mode:M)->Result<(),Error> {
+        let p=self.c_path(path.as_ref())?;
+        self.check(unsafe { sftp_chmod(self.sftp,p.as_ptr(),u32::from(mode.into())) })
+    }
+    /// Change the owner and group of a file, given as numeric ids (see `Session::lookup_owner`). Servers only allow this to root, or to the owner when changing the group to one of their groups.
+    pub fn chown<P:AsRef<Path>>(&self,path:P,uid:u32,gid:u32)->Result<(),Error> {
+        let p=self.c_path(path.as_ref())?;
+        self.check(unsafe { sftp_chown(self.sftp,p.as_ptr(),uid,gid) })
+    }
+    /// Whether the server supports the SFTP extension `name`, in version `version` (such as `"statvfs@openssh.com"` and `"2"`).
+    pub fn extension_supported(&self,name:&str,version:&str)->Result<bool,Error> {
+        let name=std::ffi::CString::new(name)?;
+        let version=std::ffi::CString::new(version)?;
+        Ok(unsafe { sftp_extension_supported(self.sftp,name.as_ptr(),version.as_ptr()) }!=0)
+    }
+    /// Size and usage of the file system containing `path`. This needs the `statvfs@openssh.com` extension of OpenSSH servers, and fails on other servers.
+    pub fn statvfs<P:AsRef<Path>>(&self,path:P)->Result<FsStats,Error> {
+        let p=self.c_path(path.as_ref())?;
+        let s=unsafe { sftp_statvfs(self.sftp,p.as_ptr()) };
+        if s.is_null() {
+            return Err(self.error())
+        }
+        let stats=unsafe {
+            let r=&*s;
+            let stats=FsStats {
+                block_size:if r.f_frsize>0 { r.f_frsize } else { r.f_bsize },
+                blocks:r.f_blocks,
+                free:r.f_bfree,
+                available:r.f_bavail,
+                files:r.f_files,
+                files_available:r.f_favail,
+                // ST_RDONLY
+                read_only:r.f_flag&1!=0,
+                name_max:r.f_namemax
+            };
+            sftp_statvfs_free(s);
+            stats
+        };
+        Ok(stats)
+    }
+    /// Check, before an upload, that the file system containing `path` (an existing directory, or file to replace) has `size` bytes available, and is writable. Fails with `Error::NoSpace` if it doesn't; succeeds if the server can't tell (without `statvfs@openssh.com`). Per-user quotas are not visible this way: uploads may still fail with `Error::NoSpace` if one is exceeded.
+    pub fn check_space<P:AsRef<Path>>(&self,path:P,size:u64)->Result<(),Error> {
+        if !self.extension_supported("statvfs@openssh.com","2")? {
+            return Ok(())
+        }
+        let path=path.as_ref();
+        let stats=self.statvfs(path)?;
+        if stats.read_only {
+            return Err(Error::IO(std::io::Error::new(std::io::ErrorKind::PermissionDenied,format!("{:?} is on a read-only file system",path))))
+        }
+        if stats.available_bytes()<size {
+            return Err(Error::NoSpace(format!("{} bytes needed in {:?}, {} available",size,path,stats.available_bytes())))
+        }
+        Ok(())
+    }
+    /// Send the extended request `name` (an extension advertised by the server) with `data`, and wait for its status. libssh has no function for extensions it doesn't know, so the request is written directly on the channel of the SFTP session, which is only correct since no other request is in flight.
+    fn extended(&self,name:&str,data:&[u8])->Result<(),Error> {
+        let mut packet=Vec::with_capacity(data.len()+name.len()+13);
+        put_u32(&mut packet,0);
+        packet.push(SSH_FXP_EXTENDED);
+        put_u32(&mut packet,EXTENDED_ID);
+        put_string(&mut packet,name.as_bytes());
+        packet.extend_from_slice(data);
+        let len=(packet.len()-4) as u32;
+        packet[..4].copy_from_slice(&len.to_be_bytes());
+        let channel=unsafe { (*(self.sftp as *mut SftpSession_)).channel };
+        let mut sent=0;
+        while sent<packet.len() {
+            let e=unsafe { super::ssh_channel_write(channel,packet[sent..].as_ptr() as *const c_void,(packet.len()-sent) as u32) };
+            if e<0 {
+                return Err(err(self.session))
+            }
+            sent+=e as usize
+        }
+        let mut len=[0;4];
+        self.read_exact(channel,&mut len)?;
+        let mut reply=vec![0;u32::from_be_bytes(len) as usize];
+        self.read_exact(channel,&mut reply)?;
+        let invalid=|| Error::Ssh(format!("Invalid reply to SFTP request {}",name));
+        if reply.len()<9 || reply[0]!=SSH_FXP_STATUS || reply[1..5]!=EXTENDED_ID.to_be_bytes() {
+            return Err(invalid())
+        }
+        let code=u32::from_be_bytes([reply[5],reply[6],reply[7],reply[8]]);
+        if code==0 {
+            return Ok(())
+        }
+        let msg=reply.get(13..).and_then(|m| {
+            let n=u32::from_be_bytes([reply[9],reply[10],reply[11],reply[12]]) as usize;
+            m.get(..n)
+        }).map(|m| String::from_utf8_lossy(m).into_owned()).unwrap_or_default();
+        Err(status_error(code as c_int,&format!("{}: {}",name,msg)))
+    }
+    fn read_exact(&self,channel:*mut Channel_,buf:&mut [u8])->Result<(),Error> {
+        let mut n=0;
+        while n<buf.len() {
+            let e=unsafe { super::ssh_channel_read(channel,buf[n..].as_mut_ptr() as *mut c_char,(buf.len()-n) as size_t,0) };
+            if e<0 {
+                return Err(err(self.session))
+            } else if e==0 {
+                return Err(Error::IO(std::io::Error::new(std::io::ErrorKind::UnexpectedEof,"the SFTP channel was closed")))
+            }
+            n+=e as usize
+        }
+        Ok(())
+    }
+    /// Fail with an `Unsupported` I/O error unless the server advertises extension `name`, in version `version`.
+    fn require_extension(&self,name:&str,version:&str)->Result<(),Error> {
+        if self.extension_supported(name,version)? {
+            Ok(())
+        } else {
+            Err(Error::IO(std::io::Error::new(std::io::ErrorKind::Unsupported,format!("the SFTP server doesn't support {}",name))))
+        }
+    }
+    /// Copy the remote file `from` to `to` on the server, without transferring its contents over the network, with the `copy-data` extension (of OpenSSH 9.0 and later). `to` is created with the permissions of `from`, or truncated. Fails with an `Unsupported` I/O error if the server doesn't support `copy-data`.
+    ///
+    ///```no_run
+    /// use ssh::*;
+    ///
+    /// let mut session=Session::new().unwrap();
+    /// session.set_host("pijul.org").unwrap();
+    /// session.parse_config(None).unwrap();
+    /// session.connect().unwrap();
+    /// session.userauth_publickey_auto(None).unwrap();
+    /// let sftp=session.sftp_new().unwrap();
+    /// sftp.rename("backup.1","backup.2").unwrap();
+    /// sftp.remote_copy("backup","backup.1").unwrap();
+    ///```
+    pub fn remote_copy<P:AsRef<Path>,Q:AsRef<Path>>(&self,from:P,to:Q)->Result<(),Error> {
+        self.require_extension("copy-data","1")?;
+        let source=self.open(from)?;
+        let metadata=source.metadata()?;
+        // Without O_TRUNC, which would empty the source if `to` is the same file: the destination is truncated after the copy instead.
+        let dest=self.open_with(to.as_ref(),super::libc::O_WRONLY|super::libc::O_CREAT,metadata.permissions)?;
+        let mut data=Vec::new();
+        put_string(&mut data,source.handle());
+        // Read from offset 0, until the end of the file.
+        data.extend_from_slice(&0u64.to_be_bytes());
+        data.extend_from_slice(&0u64.to_be_bytes());
+        put_string(&mut data,dest.handle());
+        data.extend_from_slice(&0u64.to_be_bytes());
+        self.extended("copy-data",&data)?;
+        dest.close()?;
+        match metadata.size {
+            Some(size)=>self.set_size(to.as_ref(),size),
+            None=>Ok(())
+        }
+    }
+    /// Truncate (or extend) the file at `path` to `size` bytes.
+    fn set_size(&self,path:&Path,size:u64)->Result<(),Error> {
+        let p=self.c_path(path)?;
+        let mut attr:Attributes_=unsafe { std::mem::zeroed() };
+        attr.flags=SSH_FILEXFER_ATTR_SIZE;
+        attr.size=size;
+        self.check(unsafe { sftp_setstat(self.sftp,p.as_ptr(),&mut attr) })
+    }
+    /// Create a hard link `to` pointing to the same file as `from`, with the `hardlink@openssh.com` extension. Fails with an `Unsupported` I/O error if the server doesn't support it.
+    ///
+    ///```no_run
+    /// use ssh::*;
+    ///
+    /// let mut session=Session::new().unwrap();
+    /// session.set_host("pijul.org").unwrap();
+    /// session.parse_config(None).unwrap();
+    /// session.connect().unwrap();
+    /// session.userauth_publickey_auto(None).unwrap();
+    /// let sftp=session.sftp_new().unwrap();
+    /// // Unchanged files of a new snapshot share the storage of the previous one.
+    /// sftp.hardlink("snapshots/monday/big.iso","snapshots/tuesday/big.iso").unwrap();
+    ///```
+    pub fn hardlink<P:AsRef<Path>,Q:AsRef<Path>>(&self,from:P,to:Q)->Result<(),Error> {
+        self.require_extension("hardlink@openssh.com","1")?;
+        let mut data=Vec::new();
+        put_string(&mut data,self.c_path(from.as_ref())?.as_bytes());
+        put_string(&mut data,self.c_path(to.as_ref())?.as_bytes());
+        self.extended("hardlink@openssh.com",&data)
+    }
+    /// The error of a failed upload to `path`: OpenSSH reports a full disk as a generic failure, which is turned into `Error::NoSpace` if the file system of `path` has no space left.
+    pub(crate) fn upload_error(&self,path:&Path,e:Error)->Error {
+        if let Error::NoSpace(_)=e {
+            return e
+        }
+        let dir=match path.parent() {
+            Some(d) if !d.as_os_str().is_empty()=>d,
+            _=>Path::new(".")
+        };
+        match self.statvfs(dir) {
+            Ok(ref s) if s.available_bytes()<s.block_size=>Error::NoSpace(format!("no space left in {:?} ({})",dir,e)),
+            _=>e
+        }
+    }
+}
+
+impl<'b> Drop for Sftp<'b> {
+    fn drop(&mut self) {
+        unsafe { sftp_free(self.sftp) };
+        self.session.release_child();
+    }
+}
+
+/// A remote file opened with SFTP.
+pub struct File<'a> {
+    sftp:&'a Sftp<'a>,
+    file:*mut File_
+}
+
+impl<'a> File<'a> {
+    pub fn metadata(&self)->Result<Metadata,Error> {
+        let a=unsafe { sftp_fstat(self.file) };
+        if a.is_null() { Err(self.sftp.error()) } else { Ok(unsafe { Metadata::from_raw(a,self.sftp.encoding) }) }
+    }
+    /// The handle of the file in the SFTP protocol.
+    fn handle(&self)->&[u8] {
+        unsafe {
+            let h=(*(self.file as *mut SftpFile_)).handle;
+            std::slice::from_raw_parts(ssh_string_data(h) as *const u8,ssh_string_len(h))
+        }
+    }
+    /// Ask the server to write the file to disk, as `fsync(2)`. This needs the `fsync@openssh.com` extension of OpenSSH servers, and fails on other servers.
+    pub fn sync_all(&self)->Result<(),Error> {
+        self.sftp.check(unsafe { sftp_fsync(self.file) })
+    }
+    /// Close the file, reporting errors (which dropping the file ignores).
+    pub fn close(self)->Result<(),Error> {
+        let e=unsafe { sftp_close(self.file) };
+        let sftp=self.sftp;
+        std::mem::forget(self);
+        sftp.check(e)
+    }
+}
+
+impl<'a> Read for File<'a> {
+    fn read(&mut self,buf:&mut [u8])->Result<usize,std::io::Error> {
+        let e=unsafe { sftp_read(self.file,buf.as_mut_ptr() as *mut c_void,buf.len() as size_t) };
+        if e<0 {
+            return Err(self.sftp.error().into())
+        }
+        count!("ssh_bytes_received_total",e,"transport"=>"sftp");
+        Ok(e as usize)
+    }
+}
+
+impl<'a> Write for File<'a> {
+    fn write(&mut self,buf:&[u8])->Result<usize,std::io::Error> {
+        let e=unsafe { sftp_write(self.file,buf.as_ptr() as *const c_void,buf.len() as size_t) };
+        if e<0 {
+            return Err(self.sftp.error().into())
+        }
+        count!("ssh_bytes_sent_total",e,"transport"=>"sftp");
+        Ok(e as usize)
+    }
+    fn flush(&mut self)->Result<(),std::io::Error> {
+        Ok(())
+    }
+}
+
+impl<'a> std::io::Seek for File<'a> {
+    fn seek(&mut self,pos:std::io::SeekFrom)->Result<u64,std::io::Error> {
+        use std::io::SeekFrom;
+        let target=match pos {
+            SeekFrom::Start(n)=>Some(n),
+            SeekFrom::Current(d)=>unsafe { sftp_tell64(self.file) }.checked_add_signed(d),
+            SeekFrom::End(d)=>self.metadata()?.size.and_then(|s| s.checked_add_signed(d))
+        };
+        match target {
+            Some(t)=>{
+                if unsafe { sftp_seek64(self.file,t) }<0 {
+                    return Err(self.sftp.error().into())
+                }
+                Ok(t)
+            },
+            None=>Err(std::io::Error::new(std::io::ErrorKind::InvalidInput,"invalid seek"))
+        }
+    }
+}
+
+impl<'a> Drop for File<'a> {
+    fn drop(&mut self) {
+        unsafe { sftp_close(self.file) };
+    }
+}
@@ -0,0 +1,190 @@
+//! Interactive terminal sessions, as the `ssh` command without a remote command: the core of an SSH client binary.
+//!
+//!```no_run
+//! use ssh::*;
+//!
+//! let mut session=Session::new().unwrap();
+//! session.set_host("pijul.org").unwrap();
+//! session.parse_config(None).unwrap();
+//! session.connect().unwrap();
+//! session.userauth_publickey_auto(None).unwrap();
+//! let status=shell::interactive(&mut session).unwrap();
+//! std::process::exit(status.unwrap_or(255))
+//!```
+
+use std::io::Write;
+use std::sync::atomic::{AtomicUsize,Ordering};
+
+use super::libc::{self,c_int,c_void,size_t};
+use super::{Channel,Error,PtyOptions,Session,err,ssh_channel_poll,ssh_channel_read,ssh_get_fd};
+use super::tap::Direction;
+use super::pty::window_size;
+use super::{SSH_EOF,SSH_ERROR};
+
+/// Signals received and not handled yet, one bit per signal number.
+static PENDING:AtomicUsize=AtomicUsize::new(0);
+
+/// Signals that end the session, after being forwarded to the remote shell.
+const FORWARDED:[(c_int,&str);3]=[(libc::SIGHUP,"HUP"),(libc::SIGINT,"INT"),(libc::SIGTERM,"TERM")];
+
+/// How long to wait for input or output before looking at signals again, in milliseconds.
+const POLL_INTERVAL:c_int=100;
+
+extern "C" fn on_signal(signal:c_int) {
+    PENDING.fetch_or(1<<signal,Ordering::SeqCst);
+}
+
+/// Puts a terminal in raw mode, and restores its previous mode when dropped.
+struct RawMode {
+    fd:c_int,
+    saved:libc::termios
+}
+
+impl RawMode {
+    fn enable(fd:c_int)->Result<RawMode,Error> {
+        unsafe {
+            let mut saved:libc::termios=std::mem::zeroed();
+            if libc::tcgetattr(fd,&mut saved)!=0 {
+                return Err(std::io::Error::last_os_error().into())
+            }
+            let mut raw=saved;
+            libc::cfmakeraw(&mut raw);
+            if libc::tcsetattr(fd,libc::TCSADRAIN,&raw)!=0 {
+                return Err(std::io::Error::last_os_error().into())
+            }
+            Ok(RawMode { fd,saved })
+        }
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        unsafe { libc::tcsetattr(self.fd,libc::TCSADRAIN,&self.saved) };
+    }
+}
+
+/// Catches signals into `PENDING`, and restores the previous handlers when dropped.
+struct Signals(Vec<(c_int,libc::sigaction)>);
+
+impl Signals {
+    fn catch(signals:&[c_int])->Result<Signals,Error> {
+        let mut saved=Signals(Vec::new());
+        for &signal in signals {
+            unsafe {
+                let mut action:libc::sigaction=std::mem::zeroed();
+                action.sa_sigaction=on_signal as extern "C" fn(c_int) as libc::sighandler_t;
+                libc::sigemptyset(&mut action.sa_mask);
+                let mut old:libc::sigaction=std::mem::zeroed();
+                if libc::sigaction(signal,&action,&mut old)!=0 {
+                    return Err(std::io::Error::last_os_error().into())
+                }
+                saved.0.push((signal,old))
+            }
+        }
+        Ok(saved)
+    }
+}
+
+impl Drop for Signals {
+    fn drop(&mut self) {
+        for &(signal,ref old) in &self.0 {
+            unsafe { libc::sigaction(signal,old,std::ptr::null_mut()) };
+        }
+    }
+}
+
+/// Start the login shell of the remote user, and connect it to the standard input and output of this process until it exits. Returns its exit status.
+///
+/// If the standard input is a terminal, the shell gets a pseudo-terminal of the same size and type (from `$TERM`), resized along with the local one, and the local terminal is in raw mode for the duration of the session, so that keys such as Ctrl-C are sent to the remote side. The terminal is restored before returning, including on errors.
+///
+/// `SIGHUP`, `SIGINT` and `SIGTERM` received by this process are forwarded to the remote shell, and end the session.
+pub fn interactive(session:&mut Session)->Result<Option<i32>,Error> {
+    let tty=unsafe { libc::isatty(0) }==1;
+    let mut channel=session.channel_new()?;
+    channel.open_session()?;
+    if tty {
+        channel.request_pty_with(&PtyOptions::new())?;
+    }
+    channel.request_shell()?;
+    PENDING.store(0,Ordering::SeqCst);
+    let _signals=Signals::catch(&[libc::SIGWINCH,libc::SIGHUP,libc::SIGINT,libc::SIGTERM])?;
+    let _raw=if tty { Some(RawMode::enable(0)?) } else { None };
+    let status=pump(&mut channel)?;
+    channel.close()?;
+    Ok(status)
+}
+
+/// Copy between the standard streams and `channel` until the remote side is done, or a signal ends the session.
+fn pump(channel:&mut Channel)->Result<Option<i32>,Error> {
+    let socket=unsafe { ssh_get_fd(channel.session.session) };
+    let mut stdin_open=true;
+    let mut buf=[0u8;16384];
+    loop {
+        let pending=PENDING.swap(0,Ordering::SeqCst);
+        if pending&(1<<libc::SIGWINCH)!=0 {
+            if let Some((cols,rows))=window_size(0) {
+                channel.change_pty_size(cols,rows)?
+            }
+        }
+        if let Some(&(_,name))=FORWARDED.iter().find(|&&(signal,_)| pending&(1<<signal)!=0) {
+            // Best effort: the session ends anyway.
+            let _=channel.send_signal(name);
+            return Ok(None)
+        }
+        let mut eof=true;
+        for is_stderr in 0..2 {
+            let n=unsafe { ssh_channel_poll(channel.channel,is_stderr) };
+            if n==SSH_ERROR {
+                return Err(err(channel.session))
+            } else if n==SSH_EOF {
+                continue
+            }
+            eof=false;
+            if n>0 {
+                let len=std::cmp::min(n as usize,buf.len());
+                let r=unsafe { ssh_channel_read(channel.channel,buf.as_mut_ptr() as *mut _,len as size_t,is_stderr) };
+                if r<0 {
+                    return Err(err(channel.session))
+                }
+                channel.session.tap(channel.channel,Direction::Received,is_stderr==1,&buf[..r as usize]);
+                if is_stderr==1 {
+                    let mut e=std::io::stderr();
+                    e.write_all(&buf[..r as usize])?;
+                    e.flush()?
+                } else {
+                    let mut o=std::io::stdout();
+                    o.write_all(&buf[..r as usize])?;
+                    o.flush()?
+                }
+            }
+        }
+        if eof {
+            return channel.command_status()
+        }
+        let mut fds=[
+            libc::pollfd { fd:if stdin_open { 0 } else { -1 },events:libc::POLLIN,revents:0 },
+            libc::pollfd { fd:socket,events:libc::POLLIN,revents:0 }
+        ];
+        if unsafe { libc::poll(fds.as_mut_ptr(),2,POLL_INTERVAL) }<0 {
+            let e=std::io::Error::last_os_error();
+            if e.kind()==std::io::ErrorKind::Interrupted {
+                continue
+            }
+            return Err(e.into())
+        }
+        if stdin_open && fds[0].revents&(libc::POLLIN|libc::POLLHUP)!=0 {
+            let n=unsafe { libc::read(0,buf.as_mut_ptr() as *mut c_void,buf.len() as size_t) };
+            if n==0 {
+                channel.send_eof()?;
+                stdin_open=false
+            } else if n>0 {
+                channel.write_all(&buf[..n as usize])?
+            } else {
+                let e=std::io::Error::last_os_error();
+                if e.kind()!=std::io::ErrorKind::Interrupted {
+                    return Err(e.into())
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,103 @@
+//! Downloads that keep files sparse: runs of zeros, as in virtual machine disk images, become holes in the local file instead of being written.
+
+use std::fs::File;
+use std::io::{Read,Seek,SeekFrom,Write};
+use std::path::Path;
+
+use super::{Error,Session};
+use super::exec::shell_quote;
+use super::sftp::Sftp;
+
+/// Zero runs are detected on blocks of this size, the usual size of file system blocks.
+const BLOCK:usize=4096;
+/// Size of the reads from the remote file.
+const BUFFER:usize=64*BLOCK;
+
+/// What a sparse download did.
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Default)]
+pub struct SparseStats {
+    /// Size of the file.
+    pub size:u64,
+    /// Number of bytes left as holes instead of being written.
+    pub holes:u64
+}
+
+/// Fill `buf` from `r`, unless the end of the file comes first. Returns the number of bytes read.
+fn fill<R:Read>(r:&mut R,buf:&mut [u8])->Result<usize,std::io::Error> {
+    let mut n=0;
+    while n<buf.len() {
+        match r.read(&mut buf[n..]) {
+            Ok(0)=>break,
+            Ok(k)=>n+=k,
+            Err(ref e) if e.kind()==std::io::ErrorKind::Interrupted=>(),
+            Err(e)=>return Err(e)
+        }
+    }
+    Ok(n)
+}
+
+impl<'b> Sftp<'b> {
+    /// Copy the remote file `remote` to `local` (created or truncated), skipping the blocks that contain only zeros, so that the local file is sparse on file systems that support it. The contents of the local file are the same as with a plain copy.
+    ///
+    ///```no_run
+    /// use ssh::*;
+    ///
+    /// let mut session=Session::new().unwrap();
+    /// session.set_host("pijul.org").unwrap();
+    /// session.parse_config(None).unwrap();
+    /// session.connect().unwrap();
+    /// session.userauth_publickey_auto(None).unwrap();
+    /// let sftp=session.sftp_new().unwrap();
+    /// let stats=sftp.download_sparse("/var/lib/vm/disk.img","/tmp/disk.img").unwrap();
+    /// println!("{} of {} bytes are holes",stats.holes,stats.size);
+    ///```
+    pub fn download_sparse<P:AsRef<Path>,Q:AsRef<Path>>(&self,remote:P,local:Q)->Result<SparseStats,Error> {
+        let mut source=self.open(remote)?;
+        let mut dest=File::create(local)?;
+        let mut buf=vec![0;BUFFER];
+        let mut stats=SparseStats::default();
+        // Whether `dest` is positioned at `stats.size`.
+        let mut positioned=true;
+        loop {
+            let n=fill(&mut source,&mut buf)?;
+            if n==0 {
+                break
+            }
+            for block in buf[..n].chunks(BLOCK) {
+                if block.iter().all(|&b| b==0) {
+                    stats.holes+=block.len() as u64;
+                    positioned=false
+                } else {
+                    if !positioned {
+                        dest.seek(SeekFrom::Start(stats.size))?;
+                        positioned=true
+                    }
+                    dest.write_all(block)?
+                }
+                stats.size+=block.len() as u64
+            }
+        }
+        // A hole at the end only exists once the size is set.
+        dest.set_len(stats.size)?;
+        dest.sync_all()?;
+        trace_event!(size=stats.size,holes=stats.holes,"sparse download finished");
+        Ok(stats)
+    }
+}
+
+impl Session {
+    /// The number of bytes actually allocated on disk for the remote file `path`, which is less than its size if it is sparse (or compressed by the file system). Returns `None` if the server can't tell: this runs `stat` (GNU or BSD) on the server.
+    pub fn allocated_size<P:AsRef<Path>>(&mut self,path:P)->Result<Option<u64>,Error> {
+        let path=shell_quote(&path.as_ref().to_string_lossy());
+        let out=self.exec(&format!("LC_ALL=C stat -c '%b %B' -- {} 2>/dev/null || LC_ALL=C stat -f '%b 512' -- {}",path,path))?;
+        if out.exit_status!=Some(0) {
+            return Ok(None)
+        }
+        let out=String::from_utf8_lossy(&out.stdout);
+        let mut fields=out.split_whitespace().map(|f| f.parse::<u64>());
+        match (fields.next(),fields.next()) {
+            (Some(Ok(blocks)),Some(Ok(size)))=>Ok(Some(blocks*size)),
+            _=>Ok(None)
+        }
+    }
+}
@@ -0,0 +1,139 @@
+//! A channel as a byte stream, for tunnels and proxies.
+
+use std::io::{Read,Write};
+use std::net::{Shutdown,TcpStream};
+use std::sync::mpsc;
+use std::time::Duration;
+
+use super::libc::{c_char,size_t};
+use super::{Channel,Error,err,ssh_channel_read};
+use super::tap::Direction;
+
+/// Reads the standard output of a channel (or the data of a `direct-tcpip` channel), and writes to its standard input, so that `std::io::copy` can be used in both directions.
+///
+///```no_run
+/// use ssh::*;
+/// use std::net::TcpListener;
+///
+/// let mut session=Session::new().unwrap();
+/// session.set_host("pijul.org").unwrap();
+/// session.parse_config(None).unwrap();
+/// session.connect().unwrap();
+/// session.userauth_publickey_auto(None).unwrap();
+/// // As `ssh -L 8080:localhost:80`, for a single connection.
+/// let (local,addr)=TcpListener::bind("127.0.0.1:8080").unwrap().accept().unwrap();
+/// let mut channel=session.channel_new().unwrap();
+/// channel.open_forward("localhost",80,&addr.ip().to_string(),addr.port()).unwrap();
+/// let (up,down)=channel.stream().tunnel(&local).unwrap();
+/// println!("{} bytes sent, {} bytes received",up,down);
+///```
+pub struct ChannelStream<'d,'c:'d> {
+    channel:&'d mut Channel<'c>
+}
+
+impl<'c> Channel<'c> {
+    pub fn stream<'d>(&'d mut self)->ChannelStream<'d,'c> {
+        ChannelStream { channel:self }
+    }
+}
+
+/// How long the tunnel waits for data from the channel before looking at the local side again.
+const TUNNEL_POLL:Duration=Duration::from_millis(10);
+
+/// Sends what is written to it to the thread running the tunnel.
+struct ChunkSender(mpsc::SyncSender<Vec<u8>>);
+
+impl Write for ChunkSender {
+    fn write(&mut self,buf:&[u8])->Result<usize,std::io::Error> {
+        match self.0.send(buf.to_vec()) {
+            Ok(())=>Ok(buf.len()),
+            Err(_)=>Err(std::io::ErrorKind::BrokenPipe.into())
+        }
+    }
+    fn flush(&mut self)->Result<(),std::io::Error> {
+        Ok(())
+    }
+}
+
+impl<'d,'c:'d> ChannelStream<'d,'c> {
+    /// Copy data between `local` and the channel in both directions, until both sides have sent EOF, and return the number of bytes sent to the channel and received from it.
+    ///
+    /// A session cannot be used from two threads at the same time, so the channel is served from this thread only, while another thread copies from `local`.
+    pub fn tunnel(&mut self,local:&TcpStream)->Result<(u64,u64),Error> {
+        let (tx,rx)=mpsc::sync_channel(4);
+        let mut reader=local.try_clone()?;
+        let thread=std::thread::spawn(move || {
+            std::io::copy(&mut reader,&mut ChunkSender(tx))
+        });
+        let result=self.pump(local,&rx);
+        // Unblock the reading thread if we stopped first.
+        let _=local.shutdown(Shutdown::Read);
+        drop(rx);
+        let copied=thread.join().unwrap_or_else(|_| Err(std::io::Error::other("tunnel thread panicked")));
+        let (up,down)=result?;
+        copied?;
+        Ok((up,down))
+    }
+    fn pump(&mut self,mut local:&TcpStream,rx:&mpsc::Receiver<Vec<u8>>)->Result<(u64,u64),Error> {
+        let (mut up,mut down)=(0,0);
+        let (mut local_open,mut remote_open)=(true,true);
+        let mut buf=[0;16384];
+        while local_open || remote_open {
+            while local_open {
+                let chunk=if remote_open {
+                    rx.try_recv().map_err(|e| e==mpsc::TryRecvError::Disconnected)
+                } else {
+                    // Nothing else to wait for.
+                    rx.recv().map_err(|_| true)
+                };
+                match chunk {
+                    Ok(data)=>{
+                        self.write_all(&data)?;
+                        up+=data.len() as u64
+                    },
+                    Err(false)=>break,
+                    Err(true)=>{
+                        self.channel.send_eof()?;
+                        local_open=false
+                    }
+                }
+            }
+            if remote_open {
+                match self.channel.poll_timeout(TUNNEL_POLL,false)? {
+                    Some(0)=>(),
+                    Some(_)=>{
+                        let n=self.read(&mut buf)?;
+                        local.write_all(&buf[..n])?;
+                        down+=n as u64
+                    },
+                    None=>{
+                        local.shutdown(Shutdown::Write)?;
+                        remote_open=false
+                    }
+                }
+            }
+        }
+        Ok((up,down))
+    }
+}
+
+impl<'d,'c:'d> Read for ChannelStream<'d,'c> {
+    fn read(&mut self,buf:&mut [u8])->Result<usize,std::io::Error> {
+        let e=unsafe { ssh_channel_read(self.channel.channel,buf.as_mut_ptr() as *mut c_char,buf.len() as size_t,0) };
+        if e>=0 {
+            self.channel.session.tap(self.channel.channel,Direction::Received,false,&buf[..e as usize]);
+            Ok(e as usize)
+        } else {
+            Err(err(self.channel.session).into())
+        }
+    }
+}
+
+impl<'d,'c:'d> Write for ChannelStream<'d,'c> {
+    fn write(&mut self,buf:&[u8])->Result<usize,std::io::Error> {
+        self.channel.write(buf)
+    }
+    fn flush(&mut self)->Result<(),std::io::Error> {
+        self.channel.flush()
+    }
+}
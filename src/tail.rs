@@ -0,0 +1,187 @@
+//! Following remote log files, as `tail -F`.
+
+use std::io::{ErrorKind,Read,Seek,SeekFrom};
+use std::path::{Path,PathBuf};
+use std::time::Duration;
+
+use super::{Channel,Error,Session};
+use super::exec::shell_quote;
+use super::sftp::Sftp;
+
+/// Number of lines of the existing file returned before new ones.
+const TAIL_LINES:usize=10;
+/// How often the file is checked for new data when following it over SFTP.
+const SFTP_POLL:Duration=Duration::from_secs(1);
+/// How long to wait for new output from `tail`, between checks of its standard error.
+const EXEC_POLL:Duration=Duration::from_millis(500);
+/// At most this much of the standard error of `tail` is kept, for error messages.
+const MAX_STDERR:usize=4096;
+/// How much of the end of the file is read over SFTP to find its last lines.
+const SFTP_TAIL_BYTES:u64=65536;
+
+/// The lines of a remote file, returned by `Session::tail`: the last ten lines, and then, when following, the lines appended to the file, forever.
+///
+/// Lines are returned without their newline, and decoded as UTF-8, invalid sequences being replaced by U+FFFD.
+///
+///```no_run
+/// use ssh::*;
+///
+/// let mut session=Session::new().unwrap();
+/// session.set_host("pijul.org").unwrap();
+/// session.parse_config(None).unwrap();
+/// session.connect().unwrap();
+/// session.userauth_publickey_auto(None).unwrap();
+/// for line in session.tail("/var/log/nginx/access.log",true).unwrap() {
+///     println!("{}",line.unwrap())
+/// }
+///```
+pub struct Tail<'s> {
+    source:Source<'s>,
+    follow:bool,
+    /// Data received and not returned yet.
+    buf:Vec<u8>,
+    done:bool
+}
+
+enum Source<'s> {
+    /// `tail` running on the server.
+    Exec { channel:Channel<'s>, stderr:Vec<u8> },
+    /// Reading the file over SFTP, for servers that don't run commands. `offset` is where the next read starts, `None` before the first one.
+    Sftp { sftp:Sftp<'s>, path:PathBuf, offset:Option<u64> }
+}
+
+impl Session {
+    /// Return the last lines of the remote file `path`, and if `follow` is set, wait for new lines forever. Following survives log rotation: when `path` is replaced by a new file, or truncated, the new contents are followed.
+    ///
+    /// This runs `tail -F` on the server. If the server refuses to run commands (as SFTP-only accounts do), the file is read over SFTP instead, and checked for new data every second.
+    pub fn tail<P:AsRef<Path>>(&mut self,path:P,follow:bool)->Result<Tail<'_>,Error> {
+        let path=path.as_ref();
+        let cmd=format!("tail -n {}{} -- {}",TAIL_LINES,if follow { " -F" } else { "" },shell_quote(&path.to_string_lossy()));
+        let exec={
+            let mut channel=self.channel_new()?;
+            channel.open_session()?;
+            match channel.request_exec(&cmd) {
+                Ok(())=>{
+                    channel.send_eof()?;
+                    Some(std::mem::ManuallyDrop::new(channel).channel)
+                },
+                Err(Error::RequestDenied(_))=>None,
+                Err(e)=>return Err(e)
+            }
+        };
+        let source=match exec {
+            Some(channel)=>Source::Exec { channel:Channel { session:self,channel },stderr:Vec::new() },
+            None=>{
+                debug!("exec refused, tailing {:?} over SFTP",path);
+                Source::Sftp { sftp:self.sftp_new()?,path:path.to_path_buf(),offset:None }
+            }
+        };
+        Ok(Tail { source,follow,buf:Vec::new(),done:false })
+    }
+}
+
+/// Where the last `n` lines of `data` start. If `data` is not the whole file (`complete` is false), an incomplete first line is skipped.
+fn last_lines(data:&[u8],n:usize,complete:bool)->usize {
+    let end=if data.last()==Some(&b'\n') { data.len()-1 } else { data.len() };
+    let mut newlines=data[..end].iter().enumerate().rev().filter(|&(_,&c)| c==b'\n').map(|(i,_)| i+1);
+    match newlines.nth(n-1) {
+        Some(i)=>i,
+        None if complete=>0,
+        None=>data.iter().position(|&c| c==b'\n').map(|i| i+1).unwrap_or(data.len())
+    }
+}
+
+impl<'s> Tail<'s> {
+    /// Receive more data into `buf`, waiting if there is none yet. Returns `false` at the end.
+    fn fill(&mut self)->Result<bool,Error> {
+        let follow=self.follow;
+        match self.source {
+            Source::Exec { ref mut channel,ref mut stderr }=>{
+                let mut tmp=[0;8192];
+                // Messages about rotation, or errors.
+                while channel.poll_timeout(Duration::from_millis(0),true)?.unwrap_or(0)>0 {
+                    let n=channel.read_timeout(&mut tmp,Duration::from_millis(0),true)?;
+                    if stderr.len()<MAX_STDERR {
+                        stderr.extend_from_slice(&tmp[..n])
+                    }
+                }
+                match channel.poll_timeout(EXEC_POLL,false)? {
+                    Some(0)=>Ok(true),
+                    Some(_)=>{
+                        let n=channel.read_timeout(&mut tmp,Duration::from_millis(0),false)?;
+                        self.buf.extend_from_slice(&tmp[..n]);
+                        Ok(true)
+                    },
+                    None=>{
+                        match channel.get_exit_status() {
+                            Some(0)|None=>Ok(false),
+                            Some(status)=>Err(Error::Ssh(format!("tail exited with status {}: {}",status,String::from_utf8_lossy(stderr).trim())))
+                        }
+                    }
+                }
+            },
+            Source::Sftp { ref sftp,ref path,ref mut offset }=>{
+                let size=match sftp.stat(path) {
+                    Ok(m)=>m.size.unwrap_or(0),
+                    // Between the rotation and the creation of the new file.
+                    Err(Error::IO(ref e)) if e.kind()==ErrorKind::NotFound && follow && offset.is_some()=>0,
+                    Err(e)=>return Err(e)
+                };
+                let start=match *offset {
+                    None=>size.saturating_sub(SFTP_TAIL_BYTES),
+                    // Truncated, or replaced by a new file.
+                    Some(o) if size<o=>0,
+                    Some(o)=>o
+                };
+                if size>start {
+                    let mut f=sftp.open(path)?;
+                    f.seek(SeekFrom::Start(start))?;
+                    let mut data=Vec::new();
+                    f.take(size-start).read_to_end(&mut data)?;
+                    let skip=if offset.is_none() { last_lines(&data,TAIL_LINES,start==0) } else { 0 };
+                    self.buf.extend_from_slice(&data[skip..]);
+                    *offset=Some(start+data.len() as u64);
+                    return Ok(true)
+                }
+                *offset=Some(start);
+                if follow {
+                    std::thread::sleep(SFTP_POLL);
+                    Ok(true)
+                } else {
+                    Ok(false)
+                }
+            }
+        }
+    }
+}
+
+impl<'s> Iterator for Tail<'s> {
+    type Item=Result<String,Error>;
+    fn next(&mut self)->Option<Result<String,Error>> {
+        loop {
+            if let Some(i)=self.buf.iter().position(|&c| c==b'\n') {
+                let line=String::from_utf8_lossy(&self.buf[..i]).into_owned();
+                self.buf.drain(..=i);
+                return Some(Ok(line))
+            }
+            if self.done {
+                if self.buf.is_empty() {
+                    return None
+                }
+                // The last line, without a newline.
+                let line=String::from_utf8_lossy(&self.buf).into_owned();
+                self.buf.clear();
+                return Some(Ok(line))
+            }
+            match self.fill() {
+                Ok(true)=>(),
+                Ok(false)=>self.done=true,
+                Err(e)=>{
+                    self.done=true;
+                    self.buf.clear();
+                    return Some(Err(e))
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,64 @@
+//! Copies of the data sent and received on the channels of a session (commands, shells and forwardings), for session recording on gateways and bastions. SFTP and SCP transfers, which libssh runs on channels of its own, are not included.
+//!
+//! Without a tap, the only cost is checking that none is installed.
+//!
+//!```no_run
+//! use ssh::*;
+//! use ssh::tap::Chunk;
+//!
+//! let mut session=Session::new().unwrap();
+//! session.set_host("pijul.org").unwrap();
+//! session.set_tap(|c:&Chunk| println!("{:?} {:?} {:?} {:?}",c.time,c.direction,c.stream,String::from_utf8_lossy(c.data)));
+//! session.parse_config(None).unwrap();
+//! session.connect().unwrap();
+//! session.userauth_publickey_auto(None).unwrap();
+//! session.exec("ls").unwrap();
+//!```
+
+use std::time::SystemTime;
+
+use super::{Channel_,Session,Stream};
+pub use super::capture::Direction;
+
+/// Data sent or received on a channel.
+#[derive(Debug,Clone,Copy)]
+pub struct Chunk<'a> {
+    /// Identifies the channel among those open at the same time on the session.
+    pub channel:usize,
+    pub direction:Direction,
+    pub stream:Stream,
+    pub time:SystemTime,
+    pub data:&'a [u8]
+}
+
+/// Receives copies of the data of channels, as it is read or written.
+pub trait Tap:Send {
+    fn data(&mut self,chunk:&Chunk);
+}
+
+impl<F:FnMut(&Chunk)+Send> Tap for F {
+    fn data(&mut self,chunk:&Chunk) {
+        self(chunk)
+    }
+}
+
+impl Session {
+    /// Send copies of the data of all channels of this session to `tap`, replacing the previous one.
+    pub fn set_tap<T:Tap+'static>(&mut self,tap:T) {
+        *self.tap.borrow_mut()=Some(Box::new(tap))
+    }
+    /// Remove the tap, and return it.
+    pub fn remove_tap(&mut self)->Option<Box<dyn Tap>> {
+        self.tap.borrow_mut().take()
+    }
+    /// Report `data`, read or written on `channel`, to the tap if there is one.
+    #[inline]
+    pub(crate) fn tap(&self,channel:*mut Channel_,direction:Direction,is_stderr:bool,data:&[u8]) {
+        if let Some(ref mut tap)=*self.tap.borrow_mut() {
+            if !data.is_empty() {
+                let stream=if is_stderr { Stream::Stderr } else { Stream::Stdout };
+                tap.data(&Chunk { channel:channel as usize,direction,stream,time:SystemTime::now(),data })
+            }
+        }
+    }
+}
@@ -0,0 +1,26 @@
+//! Instrumentation with the `tracing` crate, enabled by the "tracing" feature. Without it, these macros only evaluate their body.
+
+/// Evaluate `$body` (of type `Result<_,Error>`) inside a span called `$name`, and record its outcome and duration in an event.
+macro_rules! traced {
+    ($name:expr, { $($fields:tt)* }, $body:expr) => {{
+        #[cfg(feature="tracing")]
+        let _span=::tracing::info_span!(target:"ssh", $name, $($fields)*).entered();
+        #[cfg(feature="tracing")]
+        let start=::std::time::Instant::now();
+        let result=$body;
+        #[cfg(feature="tracing")]
+        match result {
+            Ok(_)=>::tracing::debug!(target:"ssh", elapsed_us=start.elapsed().as_micros() as u64, "ok"),
+            Err(ref e)=>::tracing::warn!(target:"ssh", elapsed_us=start.elapsed().as_micros() as u64, error=%e, "failed")
+        }
+        result
+    }}
+}
+
+/// Emit an informational event.
+macro_rules! trace_event {
+    ($($args:tt)*) => {
+        #[cfg(feature="tracing")]
+        ::tracing::info!(target:"ssh", $($args)*)
+    }
+}
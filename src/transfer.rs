@@ -0,0 +1,242 @@
+//! A common interface to SCP and SFTP, so that applications can use SFTP and fall back to SCP on servers without an SFTP subsystem.
+
+use std::io::{Read,Write};
+use std::path::Path;
+
+use super::{Error,Output,Permissions,Request,Session,READ,WRITE};
+use super::exec::shell_quote;
+use super::sftp::{FileType,Metadata,Sftp};
+
+/// Basic file operations on a remote host.
+pub trait Transfer {
+    /// Copy `size` bytes from `source` to the remote file `remote`, which is created (or truncated) with permissions `mode`.
+    fn upload(&mut self,source:&mut dyn Read,size:u64,remote:&Path,mode:Permissions)->Result<(),Error>;
+    /// Copy the remote file `remote` to `dest`, and return the number of bytes copied.
+    fn download(&mut self,remote:&Path,dest:&mut dyn Write)->Result<u64,Error>;
+    /// The entries of a remote directory, except `.` and `..`.
+    fn list(&mut self,remote:&Path)->Result<Vec<Metadata>,Error>;
+    /// Attributes of a remote file.
+    fn stat(&mut self,remote:&Path)->Result<Metadata,Error>;
+    /// Remove a remote file.
+    fn remove(&mut self,remote:&Path)->Result<(),Error>;
+    /// Create a remote directory with permissions `mode`. Its parent must exist.
+    fn create_dir(&mut self,remote:&Path,mode:Permissions)->Result<(),Error>;
+    /// Copy all of `source`, whose size is not known in advance, to the remote file `remote`, and return the number of bytes copied. By default, `source` is first copied to a temporary local file, to know its size; SFTP streams it instead.
+    fn upload_stream(&mut self,source:&mut dyn Read,remote:&Path,mode:Permissions)->Result<u64,Error> {
+        let (mut f,size)=super::filter::spool(source)?;
+        self.upload(&mut f,size,remote,mode)?;
+        Ok(size)
+    }
+}
+
+impl<T:Transfer+?Sized> Transfer for Box<T> {
+    fn upload(&mut self,source:&mut dyn Read,size:u64,remote:&Path,mode:Permissions)->Result<(),Error> {
+        (**self).upload(source,size,remote,mode)
+    }
+    fn download(&mut self,remote:&Path,dest:&mut dyn Write)->Result<u64,Error> {
+        (**self).download(remote,dest)
+    }
+    fn list(&mut self,remote:&Path)->Result<Vec<Metadata>,Error> {
+        (**self).list(remote)
+    }
+    fn stat(&mut self,remote:&Path)->Result<Metadata,Error> {
+        (**self).stat(remote)
+    }
+    fn remove(&mut self,remote:&Path)->Result<(),Error> {
+        (**self).remove(remote)
+    }
+    fn create_dir(&mut self,remote:&Path,mode:Permissions)->Result<(),Error> {
+        (**self).create_dir(remote,mode)
+    }
+    fn upload_stream(&mut self,source:&mut dyn Read,remote:&Path,mode:Permissions)->Result<u64,Error> {
+        (**self).upload_stream(source,remote,mode)
+    }
+}
+
+impl<'b> Transfer for Sftp<'b> {
+    fn upload(&mut self,source:&mut dyn Read,size:u64,remote:&Path,mode:Permissions)->Result<(),Error> {
+        let n=self.write_file(&mut source.take(size),remote,mode).map_err(|e| self.upload_error(remote,e))?;
+        if n<size {
+            return Err(Error::IO(std::io::Error::new(std::io::ErrorKind::UnexpectedEof,
+                                                     format!("source ended after {} of {} bytes",n,size))))
+        }
+        Ok(())
+    }
+    fn download(&mut self,remote:&Path,dest:&mut dyn Write)->Result<u64,Error> {
+        let mut f=self.open(remote)?;
+        Ok(std::io::copy(&mut f,dest)?)
+    }
+    fn list(&mut self,remote:&Path)->Result<Vec<Metadata>,Error> {
+        self.read_dir(remote)
+    }
+    fn stat(&mut self,remote:&Path)->Result<Metadata,Error> {
+        Sftp::stat(self,remote)
+    }
+    fn remove(&mut self,remote:&Path)->Result<(),Error> {
+        self.remove_file(remote)
+    }
+    fn create_dir(&mut self,remote:&Path,mode:Permissions)->Result<(),Error> {
+        Sftp::create_dir(self,remote,mode)
+    }
+    fn upload_stream(&mut self,source:&mut dyn Read,remote:&Path,mode:Permissions)->Result<u64,Error> {
+        self.write_file(source,remote,mode).map_err(|e| self.upload_error(remote,e))
+    }
+}
+
+impl<'b> Sftp<'b> {
+    fn write_file(&self,source:&mut dyn Read,remote:&Path,mode:Permissions)->Result<u64,Error> {
+        let mut f=self.create(remote,mode)?;
+        let n=std::io::copy(source,&mut f)?;
+        f.close()?;
+        Ok(n)
+    }
+}
+
+/// File operations over SCP. SCP itself can only copy files: listing, `stat` and removal run `ls` and `rm` on the remote host, which must have a POSIX shell.
+pub struct ScpTransfer<'a> {
+    session:&'a mut Session
+}
+
+impl<'a> ScpTransfer<'a> {
+    pub fn new(session:&'a mut Session)->ScpTransfer<'a> {
+        ScpTransfer { session }
+    }
+    fn run(&mut self,cmd:&str,path:&Path)->Result<Output,Error> {
+        let path=path.to_string_lossy();
+        let out=self.session.exec(&format!("LC_ALL=C {} -- {}",cmd,shell_quote(&path)))?;
+        if out.exit_status==Some(0) {
+            return Ok(out)
+        }
+        let msg=String::from_utf8_lossy(&out.stderr).trim().to_string();
+        let kind=if msg.contains("No such file") {
+            std::io::ErrorKind::NotFound
+        } else if msg.contains("Permission denied") {
+            std::io::ErrorKind::PermissionDenied
+        } else {
+            std::io::ErrorKind::Other
+        };
+        Err(Error::IO(std::io::Error::new(kind,msg)))
+    }
+}
+
+/// Parse a line of `ls -ln` output, in the C locale.
+fn parse_ls(line:&str)->Option<Metadata> {
+    let mut rest=line;
+    let mut fields=Vec::with_capacity(8);
+    for _ in 0..8 {
+        rest=rest.trim_start();
+        let end=rest.find(char::is_whitespace)?;
+        fields.push(&rest[..end]);
+        rest=&rest[end..];
+    }
+    let mut name=rest.strip_prefix(' ').unwrap_or(rest);
+    // ls marks files with ACLs or security contexts.
+    let mode=fields[0].trim_end_matches(['+','.','@']);
+    let file_type=FileType::from_ls(*mode.as_bytes().first()?);
+    if file_type==FileType::Symlink {
+        if let Some(i)=name.find(" -> ") {
+            name=&name[..i]
+        }
+    }
+    Some(Metadata {
+        name:name.to_string(),
+        raw_name:name.as_bytes().to_vec(),
+        file_type,
+        size:fields[4].parse().ok(),
+        permissions:mode.parse().ok()?,
+        uid:fields[2].parse().ok()?,
+        gid:fields[3].parse().ok()?,
+        mtime:None,
+        atime:None
+    })
+}
+
+impl<'a> Transfer for ScpTransfer<'a> {
+    fn upload(&mut self,source:&mut dyn Read,size:u64,remote:&Path,mode:Permissions)->Result<(),Error> {
+        let dir=match remote.parent() {
+            Some(d) if !d.as_os_str().is_empty()=>d,
+            _=>Path::new(".")
+        };
+        let name=match remote.file_name() {
+            Some(n)=>n,
+            None=>return Err(Error::Ssh(format!("{:?} is not a file name",remote)))
+        };
+        let mut scp=self.session.scp_new(WRITE,dir)?;
+        scp.init()?;
+        scp.push_file_from(name,size,mode,source)
+    }
+    fn download(&mut self,remote:&Path,dest:&mut dyn Write)->Result<u64,Error> {
+        let mut scp=self.session.scp_new(READ,remote)?;
+        scp.init()?;
+        loop {
+            match scp.pull_request()? {
+                Request::NEWFILE=>{
+                    scp.accept_request()?;
+                    return Ok(std::io::copy(scp.reader(),dest)?)
+                },
+                Request::WARNING=>{
+                    let w=String::from_utf8_lossy(scp.request_get_warning()?).into_owned();
+                    return Err(Error::IO(std::io::Error::other(w)))
+                },
+                Request::EOF=>return Err(Error::Ssh(format!("{:?} is not a file",remote))),
+                _=>scp.deny_request()?
+            }
+        }
+    }
+    fn list(&mut self,remote:&Path)->Result<Vec<Metadata>,Error> {
+        let out=self.run("ls -lna",remote)?;
+        Ok(String::from_utf8_lossy(&out.stdout).lines()
+           .filter_map(parse_ls)
+           .filter(|m| m.name!="." && m.name!="..")
+           .collect())
+    }
+    fn stat(&mut self,remote:&Path)->Result<Metadata,Error> {
+        let out=self.run("ls -lnd",remote)?;
+        match String::from_utf8_lossy(&out.stdout).lines().next().and_then(parse_ls) {
+            Some(m)=>Ok(Metadata { name:String::new(),raw_name:Vec::new(),..m }),
+            None=>Err(Error::Ssh(format!("Could not parse the attributes of {:?}",remote)))
+        }
+    }
+    fn remove(&mut self,remote:&Path)->Result<(),Error> {
+        self.run("rm",remote)?;
+        Ok(())
+    }
+    fn create_dir(&mut self,remote:&Path,mode:Permissions)->Result<(),Error> {
+        self.run(&format!("mkdir -m {:o}",u32::from(mode)),remote)?;
+        Ok(())
+    }
+}
+
+impl Session {
+    /// File operations over SFTP if the server supports it, else over SCP.
+    ///
+    ///```no_run
+    /// use ssh::*;
+    /// use std::path::Path;
+    ///
+    /// let mut session=Session::new().unwrap();
+    /// session.set_host("pijul.org").unwrap();
+    /// session.parse_config(None).unwrap();
+    /// session.connect().unwrap();
+    /// session.userauth_publickey_auto(None).unwrap();
+    /// let mut t=session.transfer().unwrap();
+    /// let data=b"blabla\n";
+    /// t.upload(&mut &data[..],data.len() as u64,Path::new("/tmp/blublu"),0o644.into()).unwrap();
+    /// for f in t.list(Path::new("/tmp")).unwrap() {
+    ///     println!("{}",f.name)
+    /// }
+    ///```
+    pub fn transfer<'b>(&'b mut self)->Result<Box<dyn Transfer+'b>,Error> {
+        // Returning the borrow from one branch only is rejected by the borrow checker, even though the borrow ends when `sftp_new` fails.
+        let this:*mut Session=self;
+        match unsafe { &mut *this }.sftp_new() {
+            Ok(sftp)=>Ok(Box::new(sftp)),
+            Err(e)=>{
+                if !self.is_connected() {
+                    return Err(e)
+                }
+                Ok(Box::new(ScpTransfer::new(self)))
+            }
+        }
+    }
+}
@@ -0,0 +1,179 @@
+//! Copying whole directory trees, either file by file or as a single tar stream.
+
+use std::fs::File;
+use std::path::{Path,PathBuf};
+
+use super::{Error,Permissions,Session};
+use super::copy::local_mode;
+use super::sftp::FileType;
+use super::transfer::Transfer;
+
+/// How `Session::download_tree` and `Session::upload_tree` copy a directory.
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum TreeStrategy {
+    /// One transfer per file, over SFTP if the server supports it, else over SCP (see `Session::transfer`). This works with SFTP-only accounts, but each file costs a few round trips.
+    Files,
+    /// A single `tar` archive, created or extracted by `tar` on the server and streamed over one channel. This is much faster for trees with many small files, but needs a POSIX shell and `tar` on the server. Symbolic links are copied as links.
+    #[cfg(feature="tar")]
+    Tar
+}
+
+/// The entries of the local directory `root`, recursively, as paths relative to it, with whether they are directories. Parents come before their children; symbolic links are included.
+fn local_tree(root:&Path)->Result<Vec<(PathBuf,bool)>,Error> {
+    let mut entries=Vec::new();
+    let mut stack=vec![PathBuf::new()];
+    while let Some(dir)=stack.pop() {
+        for e in std::fs::read_dir(root.join(&dir))? {
+            let e=e?;
+            let rel=dir.join(e.file_name());
+            let is_dir=e.file_type()?.is_dir();
+            if is_dir {
+                stack.push(rel.clone())
+            }
+            entries.push((rel,is_dir))
+        }
+    }
+    Ok(entries)
+}
+
+impl Session {
+    /// Copy the remote directory `remote` into the local directory `local` (created if needed), recursively, and return the number of files copied.
+    ///
+    ///```no_run
+    /// use ssh::*;
+    ///
+    /// let mut session=Session::new().unwrap();
+    /// session.set_host("pijul.org").unwrap();
+    /// session.parse_config(None).unwrap();
+    /// session.connect().unwrap();
+    /// session.userauth_publickey_auto(None).unwrap();
+    /// let n=session.download_tree("/srv/www","/tmp/www",TreeStrategy::Files).unwrap();
+    /// println!("{} files",n);
+    ///```
+    pub fn download_tree<P:AsRef<Path>,Q:AsRef<Path>>(&mut self,remote:P,local:Q,strategy:TreeStrategy)->Result<u64,Error> {
+        let (remote,local)=(remote.as_ref(),local.as_ref());
+        std::fs::create_dir_all(local)?;
+        match strategy {
+            TreeStrategy::Files=>download_files(&mut *self.transfer()?,remote,local),
+            #[cfg(feature="tar")]
+            TreeStrategy::Tar=>self.download_tar(remote,local)
+        }
+    }
+    /// Copy the local directory `local` into the remote directory `remote` (created if needed, but not its parents), recursively, and return the number of files copied. Files keep their permissions.
+    pub fn upload_tree<P:AsRef<Path>,Q:AsRef<Path>>(&mut self,local:P,remote:Q,strategy:TreeStrategy)->Result<u64,Error> {
+        let (local,remote)=(local.as_ref(),remote.as_ref());
+        let entries=local_tree(local)?;
+        match strategy {
+            TreeStrategy::Files=>upload_files(&mut *self.transfer()?,local,remote,&entries),
+            #[cfg(feature="tar")]
+            TreeStrategy::Tar=>self.upload_tar(local,remote,&entries)
+        }
+    }
+}
+
+fn download_files(t:&mut dyn Transfer,remote:&Path,local:&Path)->Result<u64,Error> {
+    let mut n=0;
+    for m in t.list(remote)? {
+        let name=m.name_os();
+        let (remote,local)=(remote.join(&name),local.join(&name));
+        match m.file_type {
+            FileType::Directory=>{
+                std::fs::create_dir_all(&local)?;
+                n+=download_files(t,&remote,&local)?
+            },
+            FileType::File=>{
+                t.download(&remote,&mut File::create(&local)?)?;
+                n+=1
+            },
+            _=>debug!("skipping {:?}, which is not a file or a directory",remote)
+        }
+    }
+    Ok(n)
+}
+
+fn upload_files(t:&mut dyn Transfer,local:&Path,remote:&Path,entries:&[(PathBuf,bool)])->Result<u64,Error> {
+    if t.stat(remote).is_err() {
+        t.create_dir(remote,Permissions::from(0o755))?
+    }
+    let mut n=0;
+    for &(ref rel,is_dir) in entries {
+        let path=local.join(rel);
+        let meta=std::fs::symlink_metadata(&path)?;
+        if is_dir {
+            if t.stat(&remote.join(rel)).is_err() {
+                t.create_dir(&remote.join(rel),local_mode(&meta))?
+            }
+        } else if meta.is_file() {
+            t.upload(&mut File::open(&path)?,meta.len(),&remote.join(rel),local_mode(&meta))?;
+            n+=1
+        } else {
+            debug!("skipping {:?}, which is not a file or a directory",path)
+        }
+    }
+    Ok(n)
+}
+
+#[cfg(feature="tar")]
+impl Session {
+    /// Run `cmd`, call `f` with the channel, and check the exit status of `cmd`.
+    fn with_tar<T,F:FnOnce(&mut super::Channel)->Result<T,Error>>(&mut self,cmd:&str,f:F)->Result<T,Error> {
+        use std::io::Read;
+        let mut channel=self.channel_new()?;
+        channel.open_session()?;
+        channel.request_exec(cmd)?;
+        let r=f(&mut channel)?;
+        let mut stderr=Vec::new();
+        channel.stderr().read_to_end(&mut stderr)?;
+        match channel.command_status()? {
+            Some(0)=>{
+                channel.close()?;
+                Ok(r)
+            },
+            status=>Err(Error::Ssh(format!("{} exited with status {:?}: {}",cmd,status,String::from_utf8_lossy(&stderr).trim())))
+        }
+    }
+    fn download_tar(&mut self,remote:&Path,local:&Path)->Result<u64,Error> {
+        let cmd=format!("tar -cf - -C {} .",super::exec::shell_quote(&remote.to_string_lossy()));
+        self.with_tar(&cmd,|channel| {
+            channel.send_eof()?;
+            let mut archive=tar::Archive::new(channel.stdout());
+            let mut n=0;
+            for entry in archive.entries()? {
+                let mut entry=entry?;
+                if entry.header().entry_type().is_file() {
+                    n+=1
+                }
+                // Refuses paths outside of `local`.
+                entry.unpack_in(local)?;
+            }
+            Ok(n)
+        })
+    }
+    fn upload_tar(&mut self,local:&Path,remote:&Path,entries:&[(PathBuf,bool)])->Result<u64,Error> {
+        let remote=super::exec::shell_quote(&remote.to_string_lossy());
+        let cmd=format!("mkdir -p {} && tar -xf - -C {}",remote,remote);
+        self.with_tar(&cmd,|channel| {
+            let mut n=0;
+            {
+                let mut builder=tar::Builder::new(&mut *channel);
+                builder.follow_symlinks(false);
+                for &(ref rel,is_dir) in entries {
+                    let path=local.join(rel);
+                    if is_dir {
+                        builder.append_dir(rel,&path)?
+                    } else {
+                        if std::fs::symlink_metadata(&path)?.is_file() {
+                            n+=1
+                        }
+                        builder.append_path_with_name(&path,rel)?
+                    }
+                }
+                builder.finish()?;
+            }
+            channel.send_eof()?;
+            // Wait for the end of `tar`.
+            std::io::copy(&mut channel.stdout(),&mut std::io::sink())?;
+            Ok(n)
+        })
+    }
+}
@@ -0,0 +1,49 @@
+//! Labels and application data attached to a session, to tell connections apart in applications managing many of them.
+
+use std::any::{Any,TypeId};
+use std::collections::HashMap;
+
+use super::Session;
+
+/// Values attached to a session, at most one per type.
+#[derive(Default)]
+pub(crate) struct UserData(HashMap<TypeId,Box<dyn Any+Send>>);
+
+impl Session {
+    /// Name this session (for instance "web-3" or "deploy@10.0.0.7"). The label starts the messages of the errors of this session, and is recorded in its tracing spans.
+    pub fn set_label<S:Into<String>>(&mut self,label:S) {
+        self.label=Some(label.into())
+    }
+    pub fn label(&self)->Option<&str> {
+        self.label.as_deref()
+    }
+    /// Attach `data` to this session, and return the value of the same type previously attached, if any.
+    ///
+    ///```
+    /// use ssh::*;
+    ///
+    /// struct Role(&'static str);
+    /// let mut session=Session::new().unwrap();
+    /// session.set_label("db-1");
+    /// session.set_userdata(Role("primary"));
+    /// assert_eq!(session.userdata::<Role>().map(|r| r.0),Some("primary"));
+    ///```
+    pub fn set_userdata<T:Any+Send>(&mut self,data:T)->Option<T> {
+        self.userdata.0.insert(TypeId::of::<T>(),Box::new(data))
+            .and_then(|old| old.downcast().ok())
+            .map(|old| *old)
+    }
+    /// The value of type `T` attached to this session.
+    pub fn userdata<T:Any+Send>(&self)->Option<&T> {
+        self.userdata.0.get(&TypeId::of::<T>()).and_then(|d| d.downcast_ref())
+    }
+    pub fn userdata_mut<T:Any+Send>(&mut self)->Option<&mut T> {
+        self.userdata.0.get_mut(&TypeId::of::<T>()).and_then(|d| d.downcast_mut())
+    }
+    /// Detach the value of type `T` from this session, and return it.
+    pub fn take_userdata<T:Any+Send>(&mut self)->Option<T> {
+        self.userdata.0.remove(&TypeId::of::<T>())
+            .and_then(|d| d.downcast().ok())
+            .map(|d| *d)
+    }
+}
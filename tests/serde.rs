@@ -0,0 +1,44 @@
+//! JSON serialization of results and configuration. Run with `cargo test --features serde`.
+
+#![cfg(feature="serde")]
+
+extern crate ssh;
+extern crate serde_json;
+
+use ssh::*;
+use ssh::fleet::Fleet;
+
+#[test]
+fn output() {
+    let out=Output { stdout:b"hello\n".to_vec(), stderr:vec![b'e',0xff], exit_status:Some(3) };
+    assert_eq!(serde_json::to_string(&out).unwrap(),"{\"stdout\":\"hello\\n\",\"stderr\":\"e\u{fffd}\",\"exit_status\":3}");
+}
+
+#[test]
+fn host_result() {
+    let fleet=Fleet::new(vec!["a"]).connect_with(|_| Err(Error::Ssh("no route".to_string()).context("connecting to a:22")));
+    let r=serde_json::to_value(fleet.exec("true")).unwrap();
+    assert_eq!(r[0]["host"],"a");
+    assert_eq!(r[0]["error"]["message"],"Error while connecting to a:22: SSH error: no route");
+    assert_eq!(r[0]["error"]["transient"],true);
+    assert!(r[0].get("output").is_none());
+    assert!(r[0]["duration"].is_f64());
+}
+
+#[test]
+fn session_config() {
+    let config:SessionConfig=serde_json::from_str(r#"{
+        "host":"build.example.com",
+        "user":"ci",
+        "timeout":2.5,
+        "ciphers":["aes256-gcm@openssh.com"],
+        "host_key_policy":"accept-new"
+    }"#).unwrap();
+    assert_eq!(config.host,"build.example.com");
+    assert_eq!(config.user.as_deref(),Some("ci"));
+    assert_eq!(config.port,None);
+    assert_eq!(config.timeout,Some(std::time::Duration::from_millis(2500)));
+    assert_eq!(config.host_key_policy,HostKeyPolicy::AcceptNew);
+    assert_eq!(serde_json::from_value::<SessionConfig>(serde_json::to_value(&config).unwrap()).unwrap(),config);
+    assert!(serde_json::from_str::<SessionConfig>(r#"{"host":"a","hostname":"b"}"#).is_err());
+}
@@ -0,0 +1,1784 @@
+//! End-to-end tests against an OpenSSH server started locally for each test, so that they run offline (unlike the documentation examples, which connect to pijul.org).
+//!
+//! Run with `cargo test --features sshd-tests`. `sshd` and `ssh-keygen` are looked up in the `PATH` and in `/usr/sbin`, or can be given by the `SSHD` and `SSH_KEYGEN` environment variables.
+
+#![cfg(feature="sshd-tests")]
+
+extern crate ssh;
+#[cfg(feature="tokio")]
+extern crate tokio;
+#[cfg(feature="async-io")]
+extern crate async_io;
+#[cfg(feature="async")]
+extern crate futures_core;
+#[cfg(feature="metrics")]
+extern crate metrics;
+
+use std::fs;
+use std::io::{Read,Write};
+use std::net::{TcpListener,TcpStream};
+use std::path::PathBuf;
+use std::process::{Child,Command,Stdio};
+use std::time::{Duration,Instant};
+
+use ssh::*;
+
+struct Sshd {
+    dir:PathBuf,
+    port:u16,
+    child:Child
+}
+
+fn program(var:&str,name:&str)->String {
+    if let Ok(p)=std::env::var(var) {
+        return p
+    }
+    let sbin=PathBuf::from("/usr/sbin").join(name);
+    if sbin.exists() {
+        sbin.to_string_lossy().into_owned()
+    } else {
+        name.to_string()
+    }
+}
+
+fn keygen(path:&PathBuf) {
+    let status=Command::new(program("SSH_KEYGEN","ssh-keygen"))
+        .args(["-q","-t","ed25519","-N","","-f"]).arg(path)
+        .status().expect("could not run ssh-keygen");
+    assert!(status.success());
+}
+
+impl Sshd {
+    fn start(name:&str)->Sshd {
+        Sshd::start_with(name,"")
+    }
+    /// A server with `extra` lines added to its configuration. They come first, so that they override the defaults (sshd keeps the first value of each option).
+    fn start_with(name:&str,extra:&str)->Sshd {
+        let dir=std::env::temp_dir().join(format!("ssh-tests-{}-{}",std::process::id(),name));
+        let _=fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        keygen(&dir.join("host_ed25519"));
+        keygen(&dir.join("id_ed25519"));
+        fs::copy(dir.join("id_ed25519.pub"),dir.join("authorized_keys")).unwrap();
+        let port=TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let config=dir.join("sshd_config");
+        fs::write(&config,format!(
+            "{extra}Port {port}\nListenAddress 127.0.0.1\nHostKey {dir}/host_ed25519\nAuthorizedKeysFile {dir}/authorized_keys\nPidFile {dir}/sshd.pid\nStrictModes no\nUsePAM no\nPasswordAuthentication no\nKbdInteractiveAuthentication no\nSubsystem sftp internal-sftp\n",
+            port=port,dir=dir.display(),extra=extra)).unwrap();
+        let child=Command::new(program("SSHD","sshd"))
+            .arg("-D").arg("-e").arg("-f").arg(&config)
+            .stderr(Stdio::null())
+            .spawn().expect("could not run sshd");
+        let deadline=Instant::now()+Duration::from_secs(10);
+        while TcpStream::connect(("127.0.0.1",port)).is_err() {
+            assert!(Instant::now()<deadline,"sshd did not start");
+            std::thread::sleep(Duration::from_millis(50))
+        }
+        Sshd { dir,port,child }
+    }
+    /// A session connected to this server, not authenticated yet.
+    fn connect(&self)->Session {
+        let mut session=Session::new().unwrap();
+        session.set_host("127.0.0.1").unwrap();
+        session.set_port(self.port as usize).unwrap();
+        session.set_knownhosts(self.dir.join("known_hosts")).unwrap();
+        session.set_identity(self.dir.join("id_ed25519")).unwrap();
+        session.set_agent_socket(self.dir.join("no-agent")).unwrap();
+        session.connect().unwrap();
+        session
+    }
+    /// A session connected and authenticated to this server.
+    fn session(&self)->Session {
+        let mut session=self.connect();
+        assert!(!session.is_server_known().unwrap().is_known());
+        session.write_knownhost().unwrap();
+        session.userauth_publickey_auto(None).unwrap();
+        session
+    }
+}
+
+impl Drop for Sshd {
+    fn drop(&mut self) {
+        let _=self.child.kill();
+        let _=self.child.wait();
+        let _=fs::remove_dir_all(&self.dir);
+    }
+}
+
+#[test]
+fn exec() {
+    let sshd=Sshd::start("exec");
+    let mut session=sshd.session();
+    let out=session.exec("echo hello; echo oops >&2; exit 3").unwrap();
+    assert_eq!(out.stdout,b"hello\n");
+    assert_eq!(out.stderr,b"oops\n");
+    assert_eq!(out.exit_status,Some(3));
+}
+
+#[test]
+fn exec_login_shell() {
+    let sshd=Sshd::start("exec_login_shell");
+    let mut session=sshd.session();
+    let out=RemoteCommand::new("echo \"it's $0\"").login_shell("sh").output(&mut session).unwrap();
+    assert_eq!(out.stdout,b"it's sh\n");
+}
+
+#[test]
+fn exec_status_closed_or_lost() {
+    let sshd=Sshd::start("exec_status");
+    let mut session=sshd.session();
+    assert_eq!(session.exec("exit 3").unwrap().exit_status,Some(3));
+    // Killed by a signal: the server sends exit-signal, not exit-status.
+    assert_eq!(session.exec("kill -9 $$").unwrap().exit_status,None);
+    // Killing the sshd process of the session drops the connection.
+    match session.exec("kill -9 $PPID; sleep 5") {
+        Err(Error::ConnectionLost(_))=>(),
+        r=>panic!("{:?}",r)
+    }
+}
+
+#[test]
+fn exec_shell() {
+    let sshd=Sshd::start("exec_shell");
+    let mut session=sshd.session();
+    let out=RemoteCommand::new("echo \"it's $0\"").shell("sh").output(&mut session).unwrap();
+    assert_eq!(out.stdout,b"it's sh\n");
+    let mut channel=session.channel_new().unwrap();
+    channel.open_session().unwrap();
+    match channel.request_exec("echo a\0b") {
+        Err(Error::Nul(_))=>(),
+        r=>panic!("{:?}",r)
+    }
+    channel.request_exec_shell("sh","echo \"$0\"").unwrap();
+    let mut out=String::new();
+    channel.stdout().read_to_string(&mut out).unwrap();
+    assert_eq!(out,"sh\n");
+}
+
+#[cfg(feature="tokio")]
+#[test]
+fn tokio_exec() {
+    use ssh::nonblocking::{AsyncSession,TokioReactor};
+    let sshd=Sshd::start("tokio_exec");
+    let session:AsyncSession<TokioReactor>=AsyncSession::new(sshd.session());
+    let rt=tokio::runtime::Builder::new_current_thread().enable_io().build().unwrap();
+    let out=rt.block_on(session.exec("echo hello; echo oops >&2; exit 3")).unwrap();
+    assert_eq!(out.stdout,b"hello\n");
+    assert_eq!(out.stderr,b"oops\n");
+    assert_eq!(out.exit_status,Some(3));
+}
+
+#[cfg(feature="tokio")]
+#[test]
+fn tokio_cancel() {
+    use ssh::nonblocking::{AsyncSession,TokioReactor};
+    use tokio::time::timeout;
+    let sshd=Sshd::start("tokio_cancel");
+    let session:AsyncSession<TokioReactor>=AsyncSession::new(sshd.session());
+    let rt=tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+    // Cancelled while reading the output.
+    assert!(rt.block_on(timeout(Duration::from_millis(200),session.exec("sleep 10"))).is_err());
+    // Cancelled while opening the channel, and while waiting for the reply to the exec request.
+    assert!(rt.block_on(timeout(Duration::from_secs(0),session.channel())).is_err());
+    let mut channel=rt.block_on(session.channel()).unwrap();
+    assert!(rt.block_on(timeout(Duration::from_secs(0),channel.request_exec("echo lost"))).is_err());
+    let mut buf=[0;16];
+    assert!(rt.block_on(channel.read(&mut buf,false)).is_err());
+    drop(channel);
+    // The session is still usable.
+    let out=rt.block_on(session.exec("echo still here")).unwrap();
+    assert_eq!(out.stdout,b"still here\n");
+}
+
+#[cfg(feature="tokio")]
+#[test]
+fn tokio_select_cancel() {
+    use ssh::nonblocking::{AsyncSession,TokioReactor};
+    use std::future::Future;
+    use std::task::Poll;
+    let sshd=Sshd::start("tokio_select_cancel");
+    let session:AsyncSession<TokioReactor>=AsyncSession::new(sshd.session());
+    let rt=tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+    let _rt=rt.enter();
+    let mut channel=rt.block_on(session.channel()).unwrap();
+    rt.block_on(channel.request_exec("echo start; sleep 30")).unwrap();
+    let mut buf=[0;64];
+    let n=rt.block_on(channel.read(&mut buf,false)).unwrap();
+    assert_eq!(&buf[..n],b"start\n");
+    {
+        // What `tokio::select!` does between the read and a timer (the macro needs async blocks, which the 2015 edition doesn't have). The command sends nothing more, so the timer wins, and the read is cancelled by dropping it.
+        let mut read=Box::pin(channel.read(&mut buf,false));
+        let mut timer=Box::pin(tokio::time::sleep(Duration::from_millis(200)));
+        let r=rt.block_on(std::future::poll_fn(|cx| {
+            if let Poll::Ready(r)=read.as_mut().poll(cx) {
+                return Poll::Ready(Some(r))
+            }
+            timer.as_mut().poll(cx).map(|_| None)
+        }));
+        assert!(r.is_none(),"unexpected {:?}",r);
+    }
+    let start=Instant::now();
+    drop(channel);
+    assert!(start.elapsed()<Duration::from_secs(1),"dropping the channel took {:?}",start.elapsed());
+    let out=rt.block_on(session.exec("echo still here")).unwrap();
+    assert_eq!(out.stdout,b"still here\n");
+}
+
+#[cfg(feature="async-io")]
+#[test]
+fn async_io_exec() {
+    use ssh::nonblocking::{AsyncSession,AsyncIoReactor};
+    let sshd=Sshd::start("async_io_exec");
+    let session:AsyncSession<AsyncIoReactor>=AsyncSession::new(sshd.session());
+    let out=async_io::block_on(session.exec("seq 100000")).unwrap();
+    assert_eq!(out.stdout.iter().filter(|&&b| b==b'\n').count(),100000);
+    assert_eq!(out.exit_status,Some(0));
+}
+
+#[test]
+fn channel() {
+    let sshd=Sshd::start("channel");
+    let mut session=sshd.session();
+    let mut s=session.channel_new().unwrap();
+    s.open_session().unwrap();
+    s.request_exec(b"cat").unwrap();
+    s.write_all(b"through the channel").unwrap();
+    s.send_eof().unwrap();
+    let mut buf=Vec::new();
+    s.stdout().read_to_end(&mut buf).unwrap();
+    assert_eq!(buf,b"through the channel");
+}
+
+#[test]
+fn read_write_ext() {
+    let sshd=Sshd::start("read_write_ext");
+    let mut session=sshd.session();
+    let mut s=session.channel_new().unwrap();
+    s.open_session().unwrap();
+    s.request_exec(b"cat >&2").unwrap();
+    assert_eq!(s.write_ext(b"to stderr",Stream::Stdout).unwrap(),9);
+    s.send_eof().unwrap();
+    let mut buf=[0;64];
+    let n=s.read_ext(&mut buf,Stream::Stderr).unwrap();
+    assert_eq!(&buf[..n],b"to stderr");
+    assert_eq!(s.read_ext(&mut buf,Stream::Stdout).unwrap(),0);
+}
+
+#[test]
+fn pty_shell() {
+    let sshd=Sshd::start("pty_shell");
+    let mut session=sshd.session();
+    let mut s=session.channel_new().unwrap();
+    s.open_session().unwrap();
+    s.request_pty("xterm",100,30).unwrap();
+    s.request_shell().unwrap();
+    s.write_all(b"stty size; echo $TERM; exit 4\n").unwrap();
+    let mut buf=Vec::new();
+    s.stdout().read_to_end(&mut buf).unwrap();
+    let out=String::from_utf8_lossy(&buf);
+    assert!(out.contains("30 100\r\nxterm\r\n"),"{}",out);
+    assert_eq!(s.get_exit_status(),Some(4));
+}
+
+#[test]
+fn pty_modes() {
+    let sshd=Sshd::start("pty_modes");
+    let mut session=sshd.session();
+    let mut s=session.channel_new().unwrap();
+    s.open_session().unwrap();
+    let modes=TerminalModes::new().flag(TerminalMode::ECHO,false).flag(TerminalMode::ICANON,false).speed(9600);
+    assert_eq!(modes.encode()[..5],[53,0,0,0,0]);
+    assert_eq!(modes.get(TerminalMode::TTY_OP_OSPEED),Some(9600));
+    s.request_pty_with(&PtyOptions { term:"vt100".to_string(),cols:80,rows:24,modes:Some(modes) }).unwrap();
+    s.request_exec(b"stty -a").unwrap();
+    let mut buf=Vec::new();
+    s.stdout().read_to_end(&mut buf).unwrap();
+    let out=String::from_utf8_lossy(&buf);
+    assert!(out.contains("speed 9600 baud"),"{}",out);
+    assert!(out.contains(" -echo ") && out.contains(" -icanon "),"{}",out);
+}
+
+#[test]
+fn write_after_exit() {
+    let sshd=Sshd::start("write_after_exit");
+    let mut session=sshd.session();
+    {
+        let mut s=session.channel_new().unwrap();
+        s.open_session().unwrap();
+        s.request_exec(b"echo done").unwrap();
+        let mut buf=Vec::new();
+        s.stdout().read_to_end(&mut buf).unwrap();
+        assert_eq!(buf,b"done\n");
+        assert_eq!(s.write_all(b"too late").unwrap_err().kind(),std::io::ErrorKind::BrokenPipe);
+        assert_eq!(s.get_exit_status(),Some(0));
+    }
+    let input=vec![b'x';1<<24];
+    let out=RemoteCommand::new("head -c 5").stdin(&input[..]).output(&mut session).unwrap();
+    assert_eq!(out.stdout,b"xxxxx");
+    assert_eq!(out.exit_status,Some(0));
+}
+
+#[test]
+fn tunnel() {
+    let sshd=Sshd::start("tunnel");
+    let mut session=sshd.session();
+    // An upper-casing server behind the SSH server.
+    let target=TcpListener::bind("127.0.0.1:0").unwrap();
+    let target_port=target.local_addr().unwrap().port();
+    std::thread::spawn(move || {
+        let (mut s,_)=target.accept().unwrap();
+        let mut buf=Vec::new();
+        s.read_to_end(&mut buf).unwrap();
+        s.write_all(&buf.to_ascii_uppercase()).unwrap();
+    });
+    let listener=TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut client=TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (local,_)=listener.accept().unwrap();
+    let client_thread=std::thread::spawn(move || {
+        client.write_all(b"through the tunnel").unwrap();
+        client.shutdown(std::net::Shutdown::Write).unwrap();
+        let mut buf=Vec::new();
+        client.read_to_end(&mut buf).unwrap();
+        buf
+    });
+    let mut channel=session.channel_new().unwrap();
+    channel.open_forward("127.0.0.1",target_port,"127.0.0.1",0).unwrap();
+    assert_eq!(channel.stream().tunnel(&local).unwrap(),(18,18));
+    assert_eq!(client_thread.join().unwrap(),b"THROUGH THE TUNNEL");
+}
+
+#[test]
+fn known_host() {
+    let sshd=Sshd::start("known_host");
+    drop(sshd.session());
+    let mut session=Session::new().unwrap();
+    session.set_host("127.0.0.1").unwrap();
+    session.set_port(sshd.port as usize).unwrap();
+    session.set_knownhosts(sshd.dir.join("known_hosts")).unwrap();
+    session.connect().unwrap();
+    assert!(session.is_server_known().unwrap().is_known());
+}
+
+#[test]
+fn ephemeral_known_hosts() {
+    use ssh::known_hosts::EphemeralKnownHosts;
+    let sshd=Sshd::start("ephemeral_known_hosts");
+    let known_hosts=EphemeralKnownHosts::new().unwrap();
+    let path=known_hosts.path().to_path_buf();
+    for _ in 0..2 {
+        let mut session=Session::new().unwrap();
+        session.set_host("127.0.0.1").unwrap();
+        session.set_port(sshd.port as usize).unwrap();
+        known_hosts.apply(&mut session).unwrap();
+        session.connect().unwrap();
+        known_hosts.accept(&mut session).unwrap();
+        assert!(session.is_server_known().unwrap().is_known());
+    }
+    assert_eq!(fs::read_to_string(&path).unwrap().lines().count(),1);
+    assert!(!sshd.dir.join("known_hosts").exists());
+    drop(known_hosts);
+    assert!(!path.exists());
+}
+
+#[test]
+fn keyscan() {
+    let sshd=Sshd::start("keyscan");
+    let host_key=PublicKey::from_file(sshd.dir.join("host_ed25519.pub")).unwrap();
+    // The server only has an ed25519 key: the other default types are not negotiated.
+    assert_eq!(ssh::known_hosts::keyscan("127.0.0.1",sshd.port,&[]).unwrap(),vec![host_key]);
+    assert!(ssh::known_hosts::keyscan("127.0.0.1",sshd.port,&["ssh-rsa"]).is_err());
+}
+
+#[test]
+fn trust_store() {
+    use ssh::known_hosts::{FileStore,HostKeyStore,MemoryStore,Scoped,TrustStore};
+    let sshd=Sshd::start("trust_store");
+    let host_key=PublicKey::from_file(sshd.dir.join("host_ed25519.pub")).unwrap();
+    let mut store=Scoped::new("work",FileStore::new(sshd.dir.join("client_known_hosts")));
+    {
+        let mut session=sshd.connect();
+        assert!(session.verify_host_key(&mut store,HostKeyPolicy::Strict).is_err());
+        session.verify_host_key(&mut store,HostKeyPolicy::AcceptNew).unwrap();
+    }
+    let contents=fs::read_to_string(sshd.dir.join("client_known_hosts")).unwrap();
+    assert!(contents.starts_with(&format!("[work/127.0.0.1]:{} ssh-ed25519 ",sshd.port)));
+    let mut session=sshd.connect();
+    session.verify_host_key(&mut store,HostKeyPolicy::Strict).unwrap();
+    // Other profiles don't see the decision.
+    let mut other=Scoped::new("home",store.into_inner());
+    assert!(!session.check_host_key(&other).unwrap().is_known());
+    other.forget("127.0.0.1",sshd.port).unwrap();
+    // A changed key replaces the key of the same type.
+    let mut memory=MemoryStore::new();
+    memory.trust("127.0.0.1",sshd.port,&PublicKey::from_file(sshd.dir.join("id_ed25519.pub")).unwrap()).unwrap();
+    assert_eq!(session.check_host_key(&memory).unwrap(),ServerKnown::Changed);
+    session.trust_host_key(&mut memory).unwrap();
+    assert_eq!(memory.host_keys("127.0.0.1",sshd.port).unwrap(),[host_key]);
+}
+
+#[test]
+fn expect_host_key() {
+    let sshd=Sshd::start("expect_host_key");
+    let host_key=PublicKey::from_file(sshd.dir.join("host_ed25519.pub")).unwrap();
+    let connect=|fingerprint:&str| {
+        let mut session=Session::new().unwrap();
+        session.set_host("127.0.0.1").unwrap();
+        session.set_port(sshd.port as usize).unwrap();
+        session.set_knownhosts(sshd.dir.join("known_hosts")).unwrap();
+        session.expect_host_key(fingerprint);
+        session.connect().map(|()| session)
+    };
+    let session=connect(&host_key.fingerprint(HashType::Sha256).unwrap()).unwrap();
+    assert!(session.is_connected());
+    connect(&host_key.fingerprint(HashType::Md5).unwrap()).unwrap();
+    match connect("SHA256:uNiVztksCsDhcc0u9e8BujQXVUpKZIDTMczCvj3tD2s") {
+        Err(Error::HostKeyMismatch(f))=>assert_eq!(f,host_key.fingerprint(HashType::Sha256).unwrap()),
+        r=>panic!("{:?}",r.map(|_| ()))
+    }
+}
+
+#[test]
+fn security_events() {
+    use ssh::known_hosts::KnownHosts;
+    use std::sync::{Arc,Mutex};
+    struct Events(Arc<Mutex<Vec<SecurityEvent>>>);
+    impl SessionCallbacks for Events {
+        fn security_event(&mut self,event:&SecurityEvent) {
+            self.0.lock().unwrap().push(event.clone())
+        }
+    }
+    let sshd=Sshd::start("security_events");
+    keygen(&sshd.dir.join("other_ed25519"));
+    let other=PublicKey::from_file(sshd.dir.join("other_ed25519.pub")).unwrap();
+    let mut kh=KnownHosts::new();
+    kh.add_key("127.0.0.1",sshd.port,&other,false).unwrap();
+    kh.save_to(sshd.dir.join("known_hosts")).unwrap();
+    let events=Arc::new(Mutex::new(Vec::new()));
+    let mut session=Session::new().unwrap();
+    session.set_host("127.0.0.1").unwrap();
+    session.set_port(sshd.port as usize).unwrap();
+    session.set_knownhosts(sshd.dir.join("known_hosts")).unwrap();
+    session.set_callbacks(Events(events.clone())).unwrap();
+    session.connect().unwrap();
+    assert_eq!(session.is_server_known().unwrap(),ServerKnown::Changed);
+    let algorithms=session.algorithms().unwrap();
+    assert_eq!(algorithms.host_key,"ssh-ed25519");
+    let server_key=PublicKey::from_file(sshd.dir.join("host_ed25519.pub")).unwrap();
+    let events=events.lock().unwrap();
+    assert_eq!(*events,vec![SecurityEvent::HostKeyChanged {
+        host:"127.0.0.1".to_string(),
+        port:sshd.port,
+        old:vec![other.fingerprint(HashType::Sha256).unwrap()],
+        new:server_key.fingerprint(HashType::Sha256).unwrap()
+    }]);
+}
+
+#[test]
+fn security_policy() {
+    use ssh::security::{AlgorithmKind,SecurityPolicy};
+    let sshd=Sshd::start("security_policy");
+    let mut session=Session::new().unwrap();
+    session.set_host("127.0.0.1").unwrap();
+    session.set_port(sshd.port as usize).unwrap();
+    session.set_security_policy(SecurityPolicy::modern()).unwrap();
+    session.connect().unwrap();
+    let mut algorithms=session.algorithms().unwrap();
+    SecurityPolicy::modern().check(&algorithms).unwrap();
+    algorithms.cipher_out="aes128-cbc".to_string();
+    match SecurityPolicy::intermediate().check(&algorithms) {
+        Err(Error::PolicyViolation(AlgorithmKind::Cipher,ref c)) if c=="aes128-cbc"=>(),
+        r=>panic!("{:?}",r)
+    }
+    SecurityPolicy::legacy().check(&algorithms).unwrap();
+}
+
+#[test]
+fn supported_algorithms() {
+    let supported=ssh::security::supported().unwrap();
+    assert!(supported.version.starts_with("0."),"{:?}",supported);
+    assert!(supported.kex.iter().any(|k| k=="curve25519-sha256@libssh.org"));
+    assert!(supported.host_key_types.iter().any(|k| k=="ssh-ed25519"));
+    assert!(supported.ciphers.iter().any(|c| c=="aes256-ctr"));
+    assert!(supported.macs.iter().any(|m| m=="hmac-sha2-256"));
+}
+
+#[test]
+fn auth_info() {
+    let sshd=Sshd::start("auth_info");
+    let mut session=sshd.connect();
+    assert_eq!(session.auth_info(),AuthInfo::default());
+    assert!(!session.userauth_none().unwrap());
+    assert!(session.userauth_password("wrong").is_err());
+    session.userauth_publickey_auto(None).unwrap();
+    let info=session.auth_info();
+    assert_eq!(info.method,Some(AuthMethod::PublicKey));
+    assert_eq!(info.attempts,3);
+    assert_eq!(info.refused,vec![AuthMethod::None,AuthMethod::Password]);
+}
+
+/// Sums the counters by name and labels, and counts the values recorded in histograms.
+#[cfg(feature="metrics")]
+#[derive(Default)]
+struct Recorded(std::sync::Mutex<std::collections::HashMap<String,u64>>);
+
+#[cfg(feature="metrics")]
+struct Handle(std::sync::Arc<Recorded>,String);
+
+#[cfg(feature="metrics")]
+impl metrics::CounterFn for Handle {
+    fn increment(&self,value:u64) {
+        *self.0.0.lock().unwrap().entry(self.1.clone()).or_default()+=value
+    }
+    fn absolute(&self,value:u64) {
+        self.0.0.lock().unwrap().insert(self.1.clone(),value);
+    }
+}
+
+#[cfg(feature="metrics")]
+impl metrics::HistogramFn for Handle {
+    fn record(&self,_:f64) {
+        metrics::CounterFn::increment(self,1)
+    }
+}
+
+#[cfg(feature="metrics")]
+struct Recorder(std::sync::Arc<Recorded>);
+
+#[cfg(feature="metrics")]
+impl Recorder {
+    fn handle(&self,key:&metrics::Key)->std::sync::Arc<Handle> {
+        let labels:Vec<String>=key.labels().map(|l| format!("{}={}",l.key(),l.value())).collect();
+        std::sync::Arc::new(Handle(self.0.clone(),format!("{}{{{}}}",key.name(),labels.join(","))))
+    }
+}
+
+#[cfg(feature="metrics")]
+impl metrics::Recorder for Recorder {
+    fn describe_counter(&self,_:metrics::KeyName,_:Option<metrics::Unit>,_:metrics::SharedString) {}
+    fn describe_gauge(&self,_:metrics::KeyName,_:Option<metrics::Unit>,_:metrics::SharedString) {}
+    fn describe_histogram(&self,_:metrics::KeyName,_:Option<metrics::Unit>,_:metrics::SharedString) {}
+    fn register_counter(&self,key:&metrics::Key,_:&metrics::Metadata)->metrics::Counter {
+        metrics::Counter::from_arc(self.handle(key))
+    }
+    fn register_gauge(&self,_:&metrics::Key,_:&metrics::Metadata)->metrics::Gauge {
+        metrics::Gauge::noop()
+    }
+    fn register_histogram(&self,key:&metrics::Key,_:&metrics::Metadata)->metrics::Histogram {
+        metrics::Histogram::from_arc(self.handle(key))
+    }
+}
+
+#[cfg(feature="metrics")]
+#[test]
+fn metrics() {
+    let sshd=Sshd::start("metrics");
+    let recorded=std::sync::Arc::new(Recorded::default());
+    let recorder=Recorder(recorded.clone());
+    metrics::with_local_recorder(&recorder,|| {
+        let mut session=sshd.connect();
+        assert!(session.userauth_password("wrong").is_err());
+        session.userauth_publickey_auto(None).unwrap();
+        session.exec("printf 12345").unwrap();
+    });
+    let recorded=recorded.0.lock().unwrap();
+    assert_eq!(recorded["ssh_connections_total{result=ok}"],1);
+    assert_eq!(recorded["ssh_auth_attempts_total{method=password,result=denied}"],1);
+    assert_eq!(recorded["ssh_auth_attempts_total{method=publickey,result=success}"],1);
+    assert_eq!(recorded["ssh_bytes_received_total{transport=channel}"],5);
+    assert_eq!(recorded["ssh_commands_total{result=ok}"],1);
+    assert_eq!(recorded["ssh_command_duration_seconds{}"],1);
+}
+
+#[test]
+fn session_handle() {
+    let sshd=Sshd::start("session_handle");
+    let handle=SessionHandle::spawn(sshd.session());
+    let workers:Vec<_>=(0..4).map(|i| {
+        let handle=handle.clone();
+        std::thread::spawn(move || handle.exec(&format!("echo {}",i)).wait().unwrap().stdout)
+    }).collect();
+    for (i,w) in workers.into_iter().enumerate() {
+        assert_eq!(w.join().unwrap(),format!("{}\n",i).into_bytes())
+    }
+    let file=sshd.dir.join("from_handle");
+    handle.write_file(&file,b"written".to_vec(),0o600).wait().unwrap();
+    assert_eq!(handle.read_file(&file).wait().unwrap(),b"written");
+    assert!(handle.read_dir(&sshd.dir).wait().unwrap().iter().any(|m| m.name=="from_handle"));
+    let pending=handle.run(|s| s.exec("sleep 0.3; echo late"));
+    assert!(pending.try_get().is_none());
+    assert_eq!(pending.wait().unwrap().stdout,b"late\n");
+    let other=handle.clone();
+    handle.close().wait().unwrap();
+    assert!(other.exec("true").wait().is_err());
+}
+
+#[test]
+fn idle_timeout() {
+    let sshd=Sshd::start("idle_timeout");
+    let mut session=sshd.session();
+    let idle=std::sync::Arc::new(std::sync::Mutex::new(None));
+    let notified=idle.clone();
+    session.set_idle_timeout(Some(Duration::from_millis(300)));
+    session.on_idle(move |d| *notified.lock().unwrap()=Some(d));
+    assert!(!session.check_idle().unwrap());
+    std::thread::sleep(Duration::from_millis(200));
+    // Using the session restarts the idle time.
+    session.exec("true").unwrap();
+    std::thread::sleep(Duration::from_millis(200));
+    assert!(!session.check_idle().unwrap());
+    std::thread::sleep(Duration::from_millis(200));
+    assert!(session.check_idle().unwrap());
+    assert!(!session.is_connected());
+    assert!(idle.lock().unwrap().unwrap()>=Duration::from_millis(300));
+    assert!(!session.check_idle().unwrap());
+}
+
+#[test]
+fn auth_pipeline() {
+    let sshd=Sshd::start("auth_pipeline");
+    let mut steps=Vec::new();
+    {
+        let mut session=sshd.connect();
+        let accepted=AuthPipeline::new()
+            .password("wrong")
+            .kbdint(|_| panic!("keyboard-interactive is not allowed"))
+            .identities([sshd.dir.join("id_ed25519")],None)
+            .timeout(Duration::from_secs(5))
+            .on_step(|method,result| steps.push((method,result)))
+            .run(&mut session)
+            .unwrap();
+        assert_eq!(accepted,[AuthMethod::PublicKey]);
+        assert_eq!(session.timeout(),None);
+        assert_eq!(session.exec("true").unwrap().exit_status,Some(0));
+    }
+    assert_eq!(steps,[
+        (AuthMethod::Password,StepResult::Denied),
+        (AuthMethod::KeyboardInteractive,StepResult::Skipped),
+        (AuthMethod::PublicKey,StepResult::Success)
+    ]);
+    let mut session=sshd.connect();
+    assert!(matches!(AuthPipeline::new().password("wrong").run(&mut session),Err(Error::RequestDenied(_))));
+}
+
+#[test]
+fn userauth_identities() {
+    let sshd=Sshd::start("userauth_identities");
+    keygen(&sshd.dir.join("other_ed25519"));
+    let status=Command::new(program("SSH_KEYGEN","ssh-keygen"))
+        .args(["-q","-t","ed25519","-N","secret","-f"]).arg(sshd.dir.join("encrypted_ed25519"))
+        .status().unwrap();
+    assert!(status.success());
+    fs::write(sshd.dir.join("garbage"),"not a key\n").unwrap();
+    let keys=["missing","garbage","encrypted_ed25519","other_ed25519"].iter().map(|k| sshd.dir.join(k)).collect::<Vec<_>>();
+    let mut session=sshd.connect();
+    match session.userauth_identities(&keys,None) {
+        Err(Error::Identities(failures))=>{
+            let failures=failures.into_iter().map(|(k,f)| (k.file_name().unwrap().to_string_lossy().into_owned(),f)).collect::<Vec<_>>();
+            assert_eq!(failures[0],("missing".to_string(),IdentityFailure::NotFound));
+            assert_eq!(failures[1],("garbage".to_string(),IdentityFailure::Unsupported));
+            assert_eq!(failures[2],("encrypted_ed25519".to_string(),IdentityFailure::Encrypted));
+            assert!(matches!(failures[3].1,IdentityFailure::Denied(_)));
+        },
+        r=>panic!("{:?}",r)
+    }
+    assert!(matches!(session.userauth_identities(&keys[2..3],Some("wrong")),Err(Error::Identities(ref f)) if f[0].1==IdentityFailure::Encrypted));
+    let good=sshd.dir.join("id_ed25519");
+    assert_eq!(session.userauth_identities(&[&keys[3],&good],None).unwrap(),good);
+}
+
+#[test]
+fn security_key_not_in_agent() {
+    let sshd=Sshd::start("security_key_not_in_agent");
+    let key=sshd.dir.join("id_ed25519_sk");
+    // libssh may not read security key files, but the public key is enough to look for it in the agent.
+    fs::write(&key,"not a key libssh can use\n").unwrap();
+    fs::write(sshd.dir.join("id_ed25519_sk.pub"),"sk-ssh-ed25519@openssh.com AAAAGnNrLXNzaC1lZDI1NTE5QG9wZW5zc2guY29tAAAAIAABAgMEBQYHCAkKCwwNDg8QERITFBUWFxgZGhscHR4fAAAABHNzaDo= test\n").unwrap();
+    assert!(PublicKey::from_file(sshd.dir.join("id_ed25519_sk.pub")).unwrap().is_security_key());
+    let mut session=sshd.connect();
+    match session.userauth_identities(&[&key],None) {
+        Err(Error::Identities(failures))=>assert_eq!(failures,vec![(key.clone(),IdentityFailure::NotInAgent)]),
+        r=>panic!("{:?}",r)
+    }
+}
+
+#[test]
+fn copy_urls() {
+    let sshd=Sshd::start("copy_urls");
+    let mut options=CopyOptions::default();
+    options.config.identities=vec![sshd.dir.join("id_ed25519")];
+    options.config.known_hosts=Some(sshd.dir.join("known_hosts"));
+    options.config.host_key_policy=HostKeyPolicy::AcceptNew;
+    let local=sshd.dir.join("local");
+    fs::write(&local,b"copied by URL\n").unwrap();
+    let remote=sshd.dir.join("remote file");
+    let url=|scheme:&str| format!("{}://127.0.0.1:{}{}",scheme,sshd.port,remote.display().to_string().replace(' ',"%20"));
+    assert_eq!(copy(local.to_str().unwrap(),&url("sftp"),&options).unwrap(),14);
+    assert_eq!(fs::read(&remote).unwrap(),b"copied by URL\n");
+    let back=sshd.dir.join("back");
+    assert_eq!(copy(&url("scp"),back.to_str().unwrap(),&options).unwrap(),14);
+    assert_eq!(fs::read(&back).unwrap(),b"copied by URL\n");
+    assert!(copy(local.to_str().unwrap(),back.to_str().unwrap(),&options).is_err());
+}
+
+#[test]
+fn scp_roundtrip() {
+    let sshd=Sshd::start("scp");
+    let mut session=sshd.session();
+    let contents=b"blabla blibli\n";
+    {
+        let mut scp=session.scp_new(WRITE,&sshd.dir).unwrap();
+        scp.init().unwrap();
+        scp.push_file("pushed",contents.len() as u64,0o640).unwrap();
+        scp.write_all(contents).unwrap();
+    }
+    assert_eq!(fs::read(sshd.dir.join("pushed")).unwrap(),contents);
+    {
+        let mut scp=session.scp_new(READ,sshd.dir.join("pushed")).unwrap();
+        scp.init().unwrap();
+        match scp.pull_request().unwrap() {
+            Request::NEWFILE=>{
+                assert_eq!(scp.request_get_size(),contents.len() as u64);
+                assert_eq!(scp.request_get_permissions().unwrap().mode(),0o640);
+                scp.accept_request().unwrap();
+                let mut buf=Vec::new();
+                scp.reader().read_to_end(&mut buf).unwrap();
+                assert_eq!(buf,contents);
+            },
+            r=>panic!("unexpected request {:?}",r)
+        }
+    }
+}
+
+#[test]
+fn tail() {
+    let sshd=Sshd::start("tail");
+    let log=sshd.dir.join("log");
+    fs::write(&log,(1..13).map(|i| format!("line {}\n",i)).collect::<String>()).unwrap();
+    let mut session=sshd.session();
+    let lines:Vec<String>=session.tail(&log,false).unwrap().map(|l| l.unwrap()).collect();
+    assert_eq!(lines.len(),10);
+    assert_eq!(lines[0],"line 3");
+    assert!(session.tail(sshd.dir.join("missing"),false).unwrap().next().unwrap().is_err());
+    let mut tail=session.tail(&log,true).unwrap();
+    assert_eq!(tail.nth(9).unwrap().unwrap(),"line 12");
+    fs::OpenOptions::new().append(true).open(&log).unwrap().write_all(b"appended\n").unwrap();
+    assert_eq!(tail.next().unwrap().unwrap(),"appended");
+    // Rotation.
+    fs::rename(&log,sshd.dir.join("log.1")).unwrap();
+    fs::write(&log,b"rotated\n").unwrap();
+    assert_eq!(tail.next().unwrap().unwrap(),"rotated");
+}
+
+fn scp_tree(dir:&std::path::Path) {
+    fs::create_dir_all(dir.join("tree/sub")).unwrap();
+    fs::write(dir.join("tree/skipped"),vec![b'x';100000]).unwrap();
+    fs::write(dir.join("tree/sub/read"),b"contents").unwrap();
+}
+
+#[test]
+fn scp_entries() {
+    let sshd=Sshd::start("scp_entries");
+    scp_tree(&sshd.dir);
+    let mut session=sshd.session();
+    let mut scp=session.scp_new(READ|RECURSIVE,sshd.dir.join("tree")).unwrap();
+    scp.init().unwrap();
+    let mut entries=scp.entries();
+    let (mut dirs,mut contents)=(Vec::new(),None);
+    while let Some(entry)=entries.next() {
+        match entry.unwrap() {
+            ScpEntry::Directory { name,.. }=>dirs.push(name),
+            ScpEntry::File { ref name,size,.. } if name.to_str()==Some("read")=>{
+                assert_eq!(size,8);
+                let mut buf=Vec::new();
+                entries.read_to_end(&mut buf).unwrap();
+                contents=Some(buf)
+            },
+            ScpEntry::File { size,.. }=>assert_eq!(size,100000),
+            ScpEntry::EndDirectory=>(),
+            w=>panic!("unexpected {:?}",w)
+        }
+    }
+    assert_eq!(dirs,[PathBuf::from("tree"),PathBuf::from("sub")]);
+    assert_eq!(contents.unwrap(),b"contents");
+}
+
+#[cfg(feature="tokio")]
+#[test]
+fn tokio_scp_pull() {
+    use futures_core::Stream;
+    use ssh::nonblocking::{AsyncSession,TokioReactor};
+    use std::future::poll_fn;
+    use std::pin::Pin;
+    let sshd=Sshd::start("tokio_scp_pull");
+    scp_tree(&sshd.dir);
+    let session:AsyncSession<TokioReactor>=AsyncSession::new(sshd.session());
+    let rt=tokio::runtime::Builder::new_current_thread().enable_io().build().unwrap();
+    let mut pull=session.scp_pull(sshd.dir.join("tree"),true);
+    let (mut entries,mut contents)=(Vec::new(),Vec::new());
+    while let Some(entry)=rt.block_on(poll_fn(|cx| Pin::new(&mut pull).poll_next(cx))) {
+        let entry=entry.unwrap();
+        if let ScpEntry::File { ref name,.. }=entry {
+            if name.to_str()==Some("read") {
+                let mut buf=[0;3];
+                loop {
+                    let n=rt.block_on(pull.read(&mut buf)).unwrap();
+                    if n==0 { break }
+                    contents.extend_from_slice(&buf[..n])
+                }
+            }
+        }
+        entries.push(entry)
+    }
+    assert_eq!(contents,b"contents");
+    assert_eq!(entries.len(),6);
+    match entries[0] {
+        ScpEntry::Directory { ref name,.. }=>assert_eq!(name.to_str(),Some("tree")),
+        ref e=>panic!("unexpected {:?}",e)
+    }
+    assert_eq!(entries[5],ScpEntry::EndDirectory);
+    // A missing file is a warning.
+    let mut pull=session.scp_pull(sshd.dir.join("missing"),false);
+    match rt.block_on(poll_fn(|cx| Pin::new(&mut pull).poll_next(cx))) {
+        Some(Ok(ScpEntry::Warning(w)))=>assert!(w.contains("No such file"),"{}",w),
+        r=>panic!("unexpected {:?}",r)
+    }
+    assert!(rt.block_on(poll_fn(|cx| Pin::new(&mut pull).poll_next(cx))).is_none());
+}
+
+#[test]
+fn inetd_mode() {
+    use std::os::fd::OwnedFd;
+    use std::os::unix::net::UnixStream;
+    // Start a first server only to get the keys and configuration.
+    let sshd=Sshd::start("inetd");
+    let (client,server)=UnixStream::pair().unwrap();
+    let mut child=Command::new(program("SSHD","sshd"))
+        .arg("-i").arg("-f").arg(sshd.dir.join("sshd_config"))
+        .stdin(OwnedFd::from(server.try_clone().unwrap()))
+        .stdout(OwnedFd::from(server))
+        .stderr(Stdio::null())
+        .spawn().expect("could not run sshd");
+    {
+        let mut session=Session::new().unwrap();
+        session.set_host("127.0.0.1").unwrap();
+        session.set_knownhosts(sshd.dir.join("known_hosts")).unwrap();
+        session.set_identity(sshd.dir.join("id_ed25519")).unwrap();
+        session.set_agent_socket(sshd.dir.join("no-agent")).unwrap();
+        session.set_stream(client).unwrap();
+        session.connect().unwrap();
+        session.userauth_publickey_auto(None).unwrap();
+        assert_eq!(session.exec("echo loopback").unwrap().stdout,b"loopback\n");
+    }
+    let _=child.kill();
+    let _=child.wait();
+}
+
+#[test]
+fn scp_push_file_from() {
+    let sshd=Sshd::start("scp_from");
+    let mut session=sshd.session();
+    {
+        let mut scp=session.scp_new(WRITE,&sshd.dir).unwrap();
+        scp.init().unwrap();
+        scp.push_file_from("exact",5,0o644,&b"12345"[..]).unwrap();
+        match scp.push_file_from("long",3,0o644,&b"12345"[..]) {
+            Err(Error::IO(ref e)) if e.kind()==std::io::ErrorKind::InvalidData=>{},
+            r=>panic!("unexpected result {:?}",r)
+        }
+        match scp.push_file_from("short",10,0o644,&b"12345"[..]) {
+            Err(Error::IO(ref e)) if e.kind()==std::io::ErrorKind::UnexpectedEof=>{},
+            r=>panic!("unexpected result {:?}",r)
+        }
+    }
+    assert_eq!(fs::read(sshd.dir.join("exact")).unwrap(),b"12345");
+    assert_eq!(fs::read(sshd.dir.join("long")).unwrap(),b"123");
+}
+
+#[test]
+fn close_flushes_upload() {
+    let sshd=Sshd::start("close_flushes");
+    let mut session=sshd.session();
+    let data=vec![7u8;1<<20];
+    {
+        let mut scp=session.scp_new(WRITE,&sshd.dir).unwrap();
+        scp.init().unwrap();
+        scp.push_file("big",data.len() as u64,0o644).unwrap();
+        scp.write_all(&data).unwrap();
+        scp.flush().unwrap();
+    }
+    assert!(session.blocking_flush(Duration::from_secs(10)).unwrap());
+    // Closing right after the upload must not lose its end.
+    session.close().unwrap();
+    let deadline=Instant::now()+Duration::from_secs(10);
+    while fs::metadata(sshd.dir.join("big")).map(|m| m.len()).unwrap_or(0)<data.len() as u64 && Instant::now()<deadline {
+        std::thread::sleep(Duration::from_millis(50))
+    }
+    assert_eq!(fs::read(sshd.dir.join("big")).unwrap(),data);
+}
+
+fn transfer_roundtrip(t:&mut dyn Transfer,dir:&std::path::Path) {
+    let file=dir.join("transferred");
+    let data=b"through the transfer\n";
+    t.upload(&mut &data[..],data.len() as u64,&file,Permissions::from(0o600)).unwrap();
+    let mut back=Vec::new();
+    assert_eq!(t.download(&file,&mut back).unwrap(),data.len() as u64);
+    assert_eq!(back,data);
+    let m=t.stat(&file).unwrap();
+    assert_eq!(m.size,Some(data.len() as u64));
+    assert_eq!(m.permissions.mode(),0o600);
+    assert!(t.list(dir).unwrap().iter().any(|m| m.name=="transferred"));
+    t.remove(&file).unwrap();
+    assert!(!file.exists());
+}
+
+#[test]
+fn sftp_transfer() {
+    let sshd=Sshd::start("sftp");
+    let mut session=sshd.session();
+    let mut sftp=session.sftp_new().unwrap();
+    transfer_roundtrip(&mut sftp,&sshd.dir);
+}
+
+/// Flips all bits, in both directions.
+struct Invert;
+
+struct Inverted<T>(T);
+
+impl<T:Read> Read for Inverted<T> {
+    fn read(&mut self,buf:&mut [u8])->std::io::Result<usize> {
+        let n=self.0.read(buf)?;
+        for b in &mut buf[..n] {
+            *b^=0xff
+        }
+        Ok(n)
+    }
+}
+
+impl<T:Write> Write for Inverted<T> {
+    fn write(&mut self,buf:&[u8])->std::io::Result<usize> {
+        let inverted:Vec<u8>=buf.iter().map(|b| !b).collect();
+        self.0.write(&inverted)
+    }
+    fn flush(&mut self)->std::io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl Filter for Invert {
+    fn upload<'a>(&mut self,source:Box<dyn Read+'a>)->Result<Box<dyn Read+'a>,Error> {
+        Ok(Box::new(Inverted(source)))
+    }
+    fn download<'a>(&mut self,dest:Box<dyn Write+'a>)->Result<Box<dyn Write+'a>,Error> {
+        Ok(Box::new(Inverted(dest)))
+    }
+}
+
+#[test]
+fn filtered_transfer() {
+    let sshd=Sshd::start("filtered");
+    let mut session=sshd.session();
+    let data=b"filtered on the way\n";
+    for sftp in [true,false] {
+        let remote=sshd.dir.join("filtered");
+        let inverted:Vec<u8>=data.iter().map(|b| !b).collect();
+        let mut t=if sftp {
+            Filtered::new(session.transfer().unwrap()).filter(Invert)
+        } else {
+            Filtered::new(Box::new(ScpTransfer::new(&mut session)) as Box<dyn Transfer>).filter(Invert)
+        };
+        t.upload(&mut &data[..],data.len() as u64,&remote,Permissions::from(0o600)).unwrap();
+        assert_eq!(fs::read(&remote).unwrap(),inverted);
+        let mut back=Vec::new();
+        assert_eq!(t.download(&remote,&mut back).unwrap(),data.len() as u64);
+        assert_eq!(back,data);
+        // Two inversions cancel out.
+        let mut t=t.filter(Invert);
+        t.upload(&mut &data[..],data.len() as u64,&remote,Permissions::from(0o600)).unwrap();
+        assert_eq!(fs::read(&remote).unwrap(),data);
+    }
+}
+
+#[test]
+fn sftp_space() {
+    let sshd=Sshd::start("space");
+    let mut session=sshd.session();
+    let sftp=session.sftp_new().unwrap();
+    let stats=sftp.statvfs(&sshd.dir).unwrap();
+    assert!(stats.block_size>0 && stats.available<=stats.free && stats.free<=stats.blocks);
+    sftp.check_space(&sshd.dir,1).unwrap();
+    match sftp.check_space(&sshd.dir,u64::MAX) {
+        Err(Error::NoSpace(_))=>(),
+        r=>panic!("{:?}",r)
+    }
+}
+
+#[test]
+fn parallel_transfer() {
+    let sshd=Sshd::start("parallel");
+    let (port,dir)=(sshd.port,sshd.dir.clone());
+    let transfer=ParallelTransfer::new("127.0.0.1")
+        .streams(3)
+        .chunk_size(100_000)
+        .connect_with(move |host| {
+            let mut session=Session::new().unwrap();
+            session.set_host(host)?;
+            session.set_port(port as usize)?;
+            session.set_identity(dir.join("id_ed25519"))?;
+            session.set_agent_socket(dir.join("no-agent"))?;
+            session.connect()?;
+            session.userauth_publickey_auto(None)?;
+            Ok(session)
+        });
+    let data:Vec<u8>=(0..1_000_003u32).map(|i| (i*7+i/251) as u8).collect();
+    fs::write(sshd.dir.join("local"),&data).unwrap();
+    assert_eq!(transfer.upload(sshd.dir.join("local"),sshd.dir.join("remote"),0o600).unwrap(),data.len() as u64);
+    assert_eq!(fs::read(sshd.dir.join("remote")).unwrap(),data);
+    assert_eq!(transfer.download(sshd.dir.join("remote"),sshd.dir.join("back")).unwrap(),data.len() as u64);
+    assert_eq!(fs::read(sshd.dir.join("back")).unwrap(),data);
+}
+
+#[cfg(feature="gzip")]
+#[test]
+fn compressed_transfer() {
+    let sshd=Sshd::start("compressed");
+    let mut session=sshd.session();
+    let data="a line of a log file\n".repeat(10000);
+    let remote=sshd.dir.join("compressed");
+    assert_eq!(session.upload_compressed(&mut data.as_bytes(),&remote,0o640,Compression::Gzip).unwrap(),data.len() as u64);
+    assert_eq!(fs::read_to_string(&remote).unwrap(),data);
+    let mut back=Vec::new();
+    assert_eq!(session.download_compressed(&remote,&mut back,Compression::Gzip).unwrap(),data.len() as u64);
+    assert_eq!(back,data.as_bytes());
+    match session.download_compressed(sshd.dir.join("missing"),&mut back,Compression::Gzip) {
+        Err(Error::Ssh(_))=>(),
+        r=>panic!("{:?}",r)
+    }
+}
+
+fn tree_roundtrip(session:&mut Session,dir:&std::path::Path,strategy:TreeStrategy) {
+    let local=dir.join("tree");
+    fs::create_dir_all(local.join("a/b")).unwrap();
+    fs::write(local.join("top"),"top\n").unwrap();
+    fs::write(local.join("a/b/deep"),"deep\n").unwrap();
+    assert_eq!(session.upload_tree(&local,dir.join("uploaded"),strategy).unwrap(),2);
+    assert_eq!(fs::read_to_string(dir.join("uploaded/a/b/deep")).unwrap(),"deep\n");
+    assert_eq!(session.download_tree(dir.join("uploaded"),dir.join("downloaded"),strategy).unwrap(),2);
+    assert_eq!(fs::read_to_string(dir.join("downloaded/top")).unwrap(),"top\n");
+    assert_eq!(fs::read_to_string(dir.join("downloaded/a/b/deep")).unwrap(),"deep\n");
+}
+
+#[test]
+fn tree_files() {
+    let sshd=Sshd::start("tree_files");
+    let mut session=sshd.session();
+    tree_roundtrip(&mut session,&sshd.dir,TreeStrategy::Files);
+}
+
+#[cfg(feature="tar")]
+#[test]
+fn tree_tar() {
+    let sshd=Sshd::start("tree_tar");
+    let mut session=sshd.session();
+    tree_roundtrip(&mut session,&sshd.dir,TreeStrategy::Tar);
+}
+
+#[test]
+fn delta_transfer() {
+    let sshd=Sshd::start("delta");
+    let mut session=sshd.session();
+    let (local,remote)=(sshd.dir.join("local"),sshd.dir.join("remote"));
+    let mut data=vec![1u8;10_000];
+    fs::write(&local,&data).unwrap();
+    let stats=session.upload_delta(&local,&remote,1000).unwrap();
+    assert_eq!((stats.blocks,stats.changed),(10,10));
+    data[4500]=2;
+    data.truncate(9500);
+    fs::write(&local,&data).unwrap();
+    let stats=session.upload_delta(&local,&remote,1000).unwrap();
+    assert_eq!((stats.blocks,stats.changed,stats.bytes),(10,2,1500));
+    assert_eq!(fs::read(&remote).unwrap(),data);
+    data[0]=3;
+    fs::write(&remote,&data).unwrap();
+    let stats=session.download_delta(&remote,&local,1000).unwrap();
+    assert_eq!((stats.changed,stats.bytes),(1,1000));
+    assert_eq!(fs::read(&local).unwrap(),data);
+}
+
+#[test]
+fn sparse_download() {
+    let sshd=Sshd::start("sparse");
+    let mut session=sshd.session();
+    let remote=sshd.dir.join("image");
+    {
+        let mut f=fs::File::create(&remote).unwrap();
+        f.write_all(b"header").unwrap();
+        f.set_len(1<<20).unwrap();
+    }
+    let allocated=session.allocated_size(&remote).unwrap();
+    assert!(allocated.map(|a| a<(1<<20)).unwrap_or(true));
+    let sftp=session.sftp_new().unwrap();
+    let stats=sftp.download_sparse(&remote,sshd.dir.join("local")).unwrap();
+    assert_eq!(stats,SparseStats { size:1<<20,holes:(1<<20)-4096 });
+    assert_eq!(fs::read(sshd.dir.join("local")).unwrap(),fs::read(&remote).unwrap());
+}
+
+#[test]
+fn upload_atomic() {
+    let sshd=Sshd::start("upload_atomic");
+    let mut session=sshd.session();
+    let remote=sshd.dir.join("artifact");
+    fs::write(&remote,"old").unwrap();
+    assert_eq!(session.upload_atomic(&mut &b"new version"[..],&remote,0o750,true).unwrap(),11);
+    assert_eq!(fs::read_to_string(&remote).unwrap(),"new version");
+    assert_eq!(std::os::unix::fs::PermissionsExt::mode(&fs::metadata(&remote).unwrap().permissions())&0o777,0o750);
+    assert!(!fs::read_dir(&sshd.dir).unwrap().any(|e| e.unwrap().file_name().to_string_lossy().ends_with(".tmp")));
+    assert!(session.upload_atomic(&mut &b"x"[..],sshd.dir.join("missing/artifact"),0o644,false).is_err());
+}
+
+#[test]
+fn ownership() {
+    let sshd=Sshd::start("ownership");
+    let mut session=sshd.session();
+    let me=String::from_utf8(session.exec("id -un").unwrap().stdout).unwrap();
+    let (uid,gid)=session.lookup_owner(me.trim(),None).unwrap();
+    let file=sshd.dir.join("owned");
+    fs::write(&file,"").unwrap();
+    session.sftp_new().unwrap().chown(&file,uid,gid).unwrap();
+    assert!(session.lookup_owner("no-such-user-here",None).is_err());
+}
+
+#[cfg(target_os="linux")]
+#[test]
+fn xattrs() {
+    let sshd=Sshd::start("xattrs");
+    let file=sshd.dir.join("tagged");
+    fs::write(&file,"").unwrap();
+    let xattrs=vec![("user.origin".to_string(),b"build 42".to_vec())];
+    if ssh::attrs::set_local_xattrs(&file,&xattrs).is_err() {
+        // No user extended attributes on this file system.
+        return
+    }
+    assert_eq!(ssh::attrs::local_xattrs(&file).unwrap(),xattrs);
+    let mut session=sshd.session();
+    if session.exec("command -v setfattr").unwrap().exit_status!=Some(0) {
+        return
+    }
+    let other=sshd.dir.join("other");
+    fs::write(&other,"").unwrap();
+    session.set_xattrs(&other,&xattrs).unwrap();
+    assert_eq!(session.xattrs(&other).unwrap(),xattrs);
+    assert!(session.set_xattrs(&other,&[("security.selinux".to_string(),vec![])]).is_err());
+}
+
+#[test]
+fn sftp_glob() {
+    let sshd=Sshd::start("glob");
+    let mut session=sshd.session();
+    let logs=sshd.dir.join("logs");
+    fs::create_dir_all(logs.join("old")).unwrap();
+    for name in ["a.log.gz","b.log.gz","c.log",".hidden.gz","it's [1].gz","old/d.log.gz"] {
+        fs::write(logs.join(name),"").unwrap();
+    }
+    let sftp=session.sftp_new().unwrap();
+    let names=|pattern:&str| -> Vec<String> {
+        sftp.glob(logs.join(pattern)).unwrap().into_iter()
+            .map(|(p,_)| p.strip_prefix(&logs).unwrap().to_string_lossy().into_owned())
+            .collect()
+    };
+    assert_eq!(names("*.gz"),["a.log.gz","b.log.gz","it's [1].gz"]);
+    assert_eq!(names("[ab].log.*"),["a.log.gz","b.log.gz"]);
+    assert_eq!(names("[!a]*.log*"),["b.log.gz","c.log"]);
+    assert_eq!(names("?.log"),["c.log"]);
+    assert_eq!(names(".*"),[".hidden.gz"]);
+    assert_eq!(names("*/*.gz"),["old/d.log.gz"]);
+    assert_eq!(names("it's \\[1\\].gz"),["it's [1].gz"]);
+    assert_eq!(names("old/d.log.gz"),["old/d.log.gz"]);
+    assert!(names("*.zip").is_empty());
+    assert!(names("missing/*.gz").is_empty());
+}
+
+#[test]
+fn scp_transfer() {
+    let sshd=Sshd::start("scp_transfer");
+    let mut session=sshd.session();
+    transfer_roundtrip(&mut ScpTransfer::new(&mut session),&sshd.dir);
+}
+
+#[cfg(feature="mirror")]
+#[test]
+fn mirror() {
+    use ssh::mirror::{Mirror,MirrorEvent};
+    let sshd=Sshd::start("mirror");
+    let (local,remote)=(sshd.dir.join("local"),sshd.dir.join("remote"));
+    fs::create_dir_all(local.join("target")).unwrap();
+    fs::write(local.join("a"),b"first").unwrap();
+    fs::write(local.join("target/skipped"),b"").unwrap();
+    let mut session=sshd.session();
+    let sftp=session.sftp_new().unwrap();
+    let mut mirror=Mirror::new(&local,&remote).exclude("target").delete(true);
+    let events=mirror.sync(&sftp).unwrap();
+    assert!(matches!(events[..],[MirrorEvent::Uploaded { size:5,.. }]),"{:?}",events);
+    assert_eq!(fs::read(remote.join("a")).unwrap(),b"first");
+    assert!(!remote.join("target").exists());
+    // Up to date.
+    assert!(mirror.sync(&sftp).unwrap().is_empty());
+    let mut watch=mirror.watch().unwrap();
+    fs::create_dir(local.join("dir")).unwrap();
+    fs::write(local.join("dir/b"),b"second").unwrap();
+    fs::remove_file(local.join("a")).unwrap();
+    let mut events=Vec::new();
+    while events.len()<3 {
+        let new=watch.wait(&sftp,Some(Duration::from_secs(5))).unwrap();
+        assert!(!new.is_empty(),"{:?}",events);
+        events.extend(new)
+    }
+    assert_eq!(fs::read(remote.join("dir/b")).unwrap(),b"second");
+    assert!(!remote.join("a").exists());
+}
+
+#[cfg(feature="mirror")]
+#[test]
+fn mirror_plan_and_verify() {
+    use ssh::mirror::{Change,Mirror,Mismatch,Verify};
+    let sshd=Sshd::start("mirror_plan");
+    let (local,remote)=(sshd.dir.join("local"),sshd.dir.join("remote"));
+    fs::create_dir_all(local.join("dir")).unwrap();
+    fs::create_dir_all(remote.join("old")).unwrap();
+    fs::write(local.join("dir/new"),b"12345").unwrap();
+    fs::write(remote.join("old/stale"),b"123").unwrap();
+    let mut session=sshd.session();
+    let sftp=session.sftp_new().unwrap();
+    let mut mirror=Mirror::new(&local,&remote).delete(true);
+    let plan=mirror.plan(&sftp).unwrap();
+    assert_eq!(plan,[
+        Change::CreateDir("dir".into()),
+        Change::Create { path:"dir/new".into(),size:5 },
+        Change::Delete { path:"old/stale".into(),size:Some(3) },
+        Change::Delete { path:"old".into(),size:None }
+    ]);
+    // A dry run changes nothing.
+    assert!(remote.join("old/stale").exists());
+    assert_eq!(mirror.verify(&sftp,Verify::Size).unwrap(),[Mismatch::Missing("dir/new".into())]);
+    mirror.sync(&sftp).unwrap();
+    assert!(!remote.join("old").exists());
+    assert!(mirror.plan(&sftp).unwrap().is_empty());
+    fs::write(remote.join("dir/new"),b"54321").unwrap();
+    assert!(mirror.verify(&sftp,Verify::Size).unwrap().is_empty());
+    assert_eq!(mirror.verify(&sftp,Verify::Hash).unwrap(),[Mismatch::Content("dir/new".into())]);
+}
+
+#[test]
+fn audit_log() {
+    let sshd=Sshd::start("audit_log");
+    let path=sshd.dir.join("audit.log");
+    let records=std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let seen=records.clone();
+    let log=ssh::audit::AuditLog::open(&path).unwrap().key(b"secret").on_record(move |r| seen.lock().unwrap().push(r.clone()));
+    let mut session=sshd.session();
+    session.set_audit_log(Some(log));
+    session.exec("echo 'a\tb'").unwrap();
+    session.exec("exit 3").unwrap();
+    {
+        let records=records.lock().unwrap();
+        assert_eq!(records.len(),2);
+        assert_eq!(records[0].command,"echo 'a\tb'");
+        assert_eq!(records[0].exit_status,Some(0));
+        assert_eq!(records[1].exit_status,Some(3));
+        assert_eq!(records[1].prev,records[0].hash);
+        assert!(records[1].start>=records[0].end);
+        assert_eq!(records[1].port,session.port());
+    }
+    session.set_audit_log(None);
+    session.exec("true").unwrap();
+    // Reopening the file continues the chain.
+    session.set_audit_log(Some(ssh::audit::AuditLog::open(&path).unwrap().key(b"secret")));
+    session.exec("true").unwrap();
+    let read=|| std::io::BufReader::new(std::fs::File::open(&path).unwrap());
+    assert_eq!(ssh::audit::verify(read(),Some(b"secret")).unwrap(),3);
+    assert!(ssh::audit::verify(read(),Some(b"other")).is_err());
+    let contents=std::fs::read_to_string(&path).unwrap();
+    std::fs::write(&path,contents.replace("exit 3","exit 0")).unwrap();
+    assert!(ssh::audit::verify(read(),Some(b"secret")).is_err());
+    let lines:Vec<&str>=contents.lines().collect();
+    std::fs::write(&path,format!("{}\n{}\n",lines[0],lines[2])).unwrap();
+    assert!(ssh::audit::verify(read(),Some(b"secret")).is_err());
+}
+
+#[test]
+fn check_remote_port() {
+    let sshd=Sshd::start("check_remote_port");
+    let mut session=sshd.session();
+    let listener=TcpListener::bind("127.0.0.1:0").unwrap();
+    let open=listener.local_addr().unwrap().port();
+    session.check_remote_port("127.0.0.1",open,Duration::from_secs(5)).unwrap();
+    drop(listener);
+    match session.check_remote_port("127.0.0.1",open,Duration::from_secs(5)) {
+        Err(Error::ChannelOpen(reason,_))=>assert_eq!(reason,ssh::ChannelOpenFailure::ConnectFailed),
+        r=>panic!("unexpected {:?}",r)
+    }
+    // The session is still usable.
+    assert_eq!(session.exec("echo ok").unwrap().stdout,b"ok\n");
+}
+
+#[test]
+fn tap() {
+    use ssh::tap::{Chunk,Direction};
+    let sshd=Sshd::start("tap");
+    let mut session=sshd.session();
+    let chunks=std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let recorded=chunks.clone();
+    session.set_tap(move |c:&Chunk| recorded.lock().unwrap().push((c.direction,c.stream,c.data.to_vec())));
+    let out=RemoteCommand::new("cat; echo err >&2").stdin(&b"hello"[..]).output(&mut session).unwrap();
+    assert_eq!(out.stdout,b"hello");
+    let joined=|direction:Direction,stream:Stream| -> Vec<u8> {
+        chunks.lock().unwrap().iter().filter(|c| c.0==direction && c.1==stream).flat_map(|c| c.2.clone()).collect()
+    };
+    assert_eq!(joined(Direction::Sent,Stream::Stdout),b"hello");
+    assert_eq!(joined(Direction::Received,Stream::Stdout),b"hello");
+    assert_eq!(joined(Direction::Received,Stream::Stderr),b"err\n");
+    assert!(session.remove_tap().is_some());
+    let n=chunks.lock().unwrap().len();
+    session.exec("echo untapped").unwrap();
+    assert_eq!(chunks.lock().unwrap().len(),n);
+}
+
+#[test]
+fn remote_path() {
+    let sshd=Sshd::start("remote_path");
+    let mut session=sshd.session();
+    let dir=session.remote_path(sshd.dir.to_str().unwrap()).unwrap().join("with spaces & 'quotes'");
+    assert_eq!(dir.style(),PathStyle::Posix);
+    let file=dir.join("$HOME;.txt");
+    assert_eq!(file.parent().as_ref(),Some(&dir));
+    assert_eq!(file.file_name(),Some("$HOME;.txt"));
+    let out=session.exec(&format!("mkdir {} && echo hi > {}",dir.quoted(),file.quoted())).unwrap();
+    assert_eq!(out.exit_status,Some(0));
+    let sftp=session.sftp_new().unwrap();
+    let mut contents=String::new();
+    sftp.open(file.sftp_path()).unwrap().read_to_string(&mut contents).unwrap();
+    assert_eq!(contents,"hi\n");
+}
+
+#[test]
+fn filename_encoding() {
+    use ssh::sftp::FilenameEncoding;
+    let sshd=Sshd::start("filename_encoding");
+    let mut session=sshd.session();
+    let dir=sshd.dir.join("latin1");
+    // "café" in ISO 8859-1, which isn't valid UTF-8.
+    let out=session.exec(&format!("mkdir {0} && touch {0}/\"$(printf 'caf\\351')\"",dir.display())).unwrap();
+    assert_eq!(out.exit_status,Some(0));
+    let mut sftp=session.sftp_new().unwrap();
+    let entries=sftp.read_dir(&dir).unwrap();
+    assert_eq!(entries[0].raw_name,b"caf\xe9");
+    assert_eq!(entries[0].name,"caf\u{fffd}");
+    sftp.stat(dir.join(entries[0].name_os())).unwrap();
+    sftp.set_filename_encoding(FilenameEncoding::Latin1);
+    let entries=sftp.read_dir(&dir).unwrap();
+    assert_eq!(entries[0].name,"café");
+    sftp.stat(dir.join(entries[0].name_os())).unwrap();
+    sftp.stat(dir.join("café")).unwrap();
+    assert!(sftp.stat(dir.join("日本")).is_err());
+}
+
+#[test]
+fn bounded_lines() {
+    let sshd=Sshd::start("bounded_lines");
+    let mut session=sshd.session();
+    let mut run=|cmd:&str,max_line:usize,max_total:usize| -> Vec<Result<Vec<u8>,Error>> {
+        let mut channel=session.channel_new().unwrap();
+        channel.open_session().unwrap();
+        channel.request_exec(cmd).unwrap();
+        channel.send_eof().unwrap();
+        let lines=BoundedLines::new(channel.stdout(),max_line).max_total(max_total).collect();
+        channel.close().unwrap();
+        lines
+    };
+    let lines=run("printf 'a\\nbb\\n\\nlast'",10,100);
+    let lines:Vec<Vec<u8>>=lines.into_iter().map(|l| l.unwrap()).collect();
+    assert_eq!(lines,vec![b"a".to_vec(),b"bb".to_vec(),Vec::new(),b"last".to_vec()]);
+    let lines=run("echo short; head -c 100000 /dev/zero",1000,1<<20);
+    assert_eq!(lines.len(),2);
+    assert_eq!(lines[0].as_ref().unwrap(),b"short");
+    assert!(matches!(lines[1],Err(Error::LineTooLong(1000))));
+    let lines=run("yes",1000,50000);
+    assert!(matches!(lines.last(),Some(Err(Error::OutputTooLarge(50000)))));
+}
+
+#[test]
+fn custom_transport() {
+    let sshd=Sshd::start("custom_transport");
+    // The standard input and output of `sshd -i`, which are pipes, not a socket.
+    let mut child=Command::new(program("SSHD","sshd"))
+        .arg("-i").arg("-f").arg(sshd.dir.join("sshd_config"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn().expect("could not run sshd");
+    {
+        let mut session=Session::new().unwrap();
+        session.set_host("127.0.0.1").unwrap();
+        session.set_knownhosts(sshd.dir.join("known_hosts")).unwrap();
+        session.set_identity(sshd.dir.join("id_ed25519")).unwrap();
+        session.set_agent_socket(sshd.dir.join("no-agent")).unwrap();
+        session.set_transport((child.stdout.take().unwrap(),child.stdin.take().unwrap())).unwrap();
+        session.connect().unwrap();
+        session.userauth_publickey_auto(None).unwrap();
+        assert_eq!(session.exec("echo piped").unwrap().stdout,b"piped\n");
+    }
+    let _=child.kill();
+    let _=child.wait();
+}
+
+#[test]
+fn proxy_fdpass() {
+    let sshd=Sshd::start("proxy_fdpass");
+    let mut session=Session::new().unwrap();
+    session.set_host("127.0.0.1").unwrap();
+    session.set_port(sshd.port as usize).unwrap();
+    session.set_knownhosts(sshd.dir.join("known_hosts")).unwrap();
+    session.set_identity(sshd.dir.join("id_ed25519")).unwrap();
+    session.set_agent_socket(sshd.dir.join("no-agent")).unwrap();
+    // Connects, and passes the socket back on its standard output, as connection brokers do.
+    session.set_proxy_fdpass("python3 -c 'import socket; s=socket.create_connection((\"%h\",%p)); socket.send_fds(socket.socket(fileno=1),[b\"\\0\"],[s.fileno()])'").unwrap();
+    session.connect().unwrap();
+    session.userauth_publickey_auto(None).unwrap();
+    assert_eq!(session.exec("echo passed").unwrap().stdout,b"passed\n");
+    let mut session=Session::new().unwrap();
+    assert!(session.set_proxy_fdpass("true").is_err());
+}
+
+#[test]
+fn config_layers() {
+    let sshd=Sshd::start("config_layers");
+    let config=sshd.dir.join("ssh_config");
+    fs::write(&config,format!("Host build\n  HostName 127.0.0.1\n  Port {}\n  Ciphers aes128-ctr,aes256-ctr\n  KexAlgorithms curve25519-sha256\n",sshd.port)).unwrap();
+    let from_file=SessionConfig::from_ssh_config("build",Some(&config)).unwrap();
+    assert_eq!(from_file.host,"127.0.0.1");
+    assert_eq!(from_file.port,Some(sshd.port));
+    assert_eq!(from_file.ciphers,vec!["aes128-ctr","aes256-ctr"]);
+    assert_eq!(from_file.key_exchange,vec!["curve25519-sha256"]);
+    assert!(from_file.macs.is_empty() && from_file.user.is_none());
+    let from_code=SessionConfig {
+        ciphers:vec!["aes256-ctr".to_string()],
+        known_hosts:Some(sshd.dir.join("known_hosts")),
+        identities:vec![sshd.dir.join("id_ed25519")],
+        ..SessionConfig::default()
+    };
+    let from_command_line=SessionConfig { host_key_policy:HostKeyPolicy::AcceptNew,..SessionConfig::default() };
+    let merged=from_file.merge(&from_code).merge(&from_command_line);
+    assert_eq!(merged.ciphers,vec!["aes256-ctr"]);
+    assert_eq!(merged.key_exchange,vec!["curve25519-sha256"]);
+    assert_eq!(merged.port,Some(sshd.port));
+    let mut session=merged.connect().unwrap();
+    session.userauth_publickey_auto(None).unwrap();
+    assert_eq!(session.algorithms().unwrap().cipher_out,"aes256-ctr");
+}
+
+#[test]
+fn deadline() {
+    let sshd=Sshd::start("deadline");
+    let mut session=sshd.session();
+    session.set_deadline(Some(Instant::now()+Duration::from_millis(300)));
+    let start=Instant::now();
+    match session.exec("sleep 5") {
+        Err(Error::DeadlineExceeded)=>(),
+        r=>panic!("unexpected {:?}",r)
+    }
+    assert!(start.elapsed()<Duration::from_secs(3));
+    // Later operations fail at once.
+    assert!(session.exec("true").is_err());
+    // Without a deadline, nothing changes.
+    let mut session=sshd.session();
+    session.set_deadline(Some(Instant::now()+Duration::from_secs(60)));
+    session.set_deadline(None);
+    assert_eq!(session.exec("echo ok").unwrap().stdout,b"ok\n");
+}
+
+#[test]
+fn interrupt() {
+    let sshd=Sshd::start("interrupt");
+    let mut session=sshd.session();
+    let interrupter=session.interrupter().unwrap();
+    let start=Instant::now();
+    std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(300));
+        interrupter.interrupt()
+    });
+    match session.exec("sleep 5") {
+        Err(Error::Interrupted)=>(),
+        r=>panic!("unexpected {:?}",r)
+    }
+    assert!(start.elapsed()<Duration::from_secs(3));
+    assert!(session.is_interrupted());
+    assert!(session.exec("true").is_err());
+    // Interrupting a dropped session does nothing.
+    let mut session=sshd.session();
+    let interrupter=session.interrupter().unwrap();
+    drop(session);
+    interrupter.interrupt();
+}
+
+#[test]
+fn interrupt_retry() {
+    // Nothing listens on this port, so each attempt fails with a transient error.
+    let port=TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let mut session=Session::new().unwrap();
+    session.set_host("127.0.0.1").unwrap();
+    session.set_port(port as usize).unwrap();
+    let interrupter=session.interrupter().unwrap();
+    std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(300));
+        interrupter.interrupt()
+    });
+    let retry=Retry::new().max_attempts(100).initial_delay(Duration::from_millis(500)).factor(1);
+    let start=Instant::now();
+    match session.connect_with_retry(&retry) {
+        Err(Error::Interrupted)=>(),
+        r=>panic!("unexpected {:?}",r)
+    }
+    assert!(start.elapsed()<Duration::from_secs(3));
+    assert!(!Error::Interrupted.is_transient());
+    assert!(!Error::DeadlineExceeded.is_transient());
+}
+
+#[test]
+fn scp_unavailable() {
+    // Without `scp` in the PATH of the server.
+    let sshd=Sshd::start_with("scp_unavailable","SetEnv PATH=/nonexistent\n");
+    let mut session=sshd.session();
+    let remote=sshd.dir.join("remote");
+    {
+        let mut scp=session.scp_new(WRITE,&sshd.dir).unwrap();
+        match scp.init() {
+            Err(Error::ScpUnavailable(_))=>(),
+            r=>panic!("unexpected {:?}",r)
+        }
+    }
+    drop(session);
+    let mut options=CopyOptions::default();
+    options.config.identities=vec![sshd.dir.join("id_ed25519")];
+    options.config.known_hosts=Some(sshd.dir.join("known_hosts"));
+    let local=sshd.dir.join("local");
+    fs::write(&local,b"hello").unwrap();
+    let url=format!("scp://127.0.0.1:{}{}",sshd.port,remote.display());
+    assert_eq!(copy(local.to_str().unwrap(),&url,&options).unwrap(),5);
+    assert_eq!(fs::read(&remote).unwrap(),b"hello");
+}
+
+#[test]
+fn sftp_remote_copy() {
+    let sshd=Sshd::start("sftp_remote_copy");
+    let mut session=sshd.session();
+    let sftp=session.sftp_new().unwrap();
+    let (from,to)=(sshd.dir.join("backup"),sshd.dir.join("backup.1"));
+    fs::write(&from,b"day 1\n").unwrap();
+    if !sftp.extension_supported("copy-data","1").unwrap() {
+        match sftp.remote_copy(&from,&to) {
+            Err(Error::IO(e))=>assert_eq!(e.kind(),std::io::ErrorKind::Unsupported),
+            r=>panic!("unexpected {:?}",r)
+        }
+        return
+    }
+    sftp.set_permissions(&from,0o640).unwrap();
+    sftp.remote_copy(&from,&to).unwrap();
+    assert_eq!(fs::read(&to).unwrap(),b"day 1\n");
+    assert_eq!(sftp.stat(&to).unwrap().permissions,Permissions::from_mode(0o640));
+    // The session is still usable.
+    assert_eq!(sftp.stat(&from).unwrap().size,Some(6));
+    // A longer destination is truncated.
+    fs::write(&to,b"day 1\nday 2\n").unwrap();
+    sftp.remote_copy(&from,&to).unwrap();
+    assert_eq!(fs::read(&to).unwrap(),b"day 1\n");
+    // Copying a file onto itself leaves it unchanged.
+    sftp.remote_copy(&from,&from).unwrap();
+    assert_eq!(fs::read(&from).unwrap(),b"day 1\n");
+    match sftp.remote_copy(sshd.dir.join("missing"),&to) {
+        Err(Error::IO(e))=>assert_eq!(e.kind(),std::io::ErrorKind::NotFound),
+        r=>panic!("unexpected {:?}",r)
+    }
+}
+
+#[test]
+fn sftp_hardlink() {
+    use std::os::unix::fs::MetadataExt;
+    let sshd=Sshd::start("sftp_hardlink");
+    let mut session=sshd.session();
+    let sftp=session.sftp_new().unwrap();
+    let (from,to)=(sshd.dir.join("monday"),sshd.dir.join("tuesday"));
+    fs::write(&from,b"unchanged\n").unwrap();
+    sftp.hardlink(&from,&to).unwrap();
+    assert_eq!(fs::metadata(&to).unwrap().ino(),fs::metadata(&from).unwrap().ino());
+    assert_eq!(fs::metadata(&from).unwrap().nlink(),2);
+    match sftp.hardlink(&from,&to) {
+        Err(Error::IO(e))=>assert_ne!(e.kind(),std::io::ErrorKind::Unsupported),
+        r=>panic!("unexpected {:?}",r)
+    }
+}
+
+#[test]
+fn sftp_rename_modes() {
+    use ssh::sftp::RenameMode;
+    let sshd=Sshd::start("sftp_rename_modes");
+    let mut session=sshd.session();
+    let mut sftp=session.sftp_new().unwrap();
+    let (new,current)=(sshd.dir.join("new"),sshd.dir.join("current"));
+    fs::write(&current,b"v1").unwrap();
+    fs::write(&new,b"v2").unwrap();
+    assert_eq!(sftp.rename_mode(),RenameMode::Atomic);
+    sftp.rename(&new,&current).unwrap();
+    assert_eq!(fs::read(&current).unwrap(),b"v2");
+    assert!(!new.exists());
+    // A standard rename refuses to replace the file.
+    fs::write(&new,b"v3").unwrap();
+    sftp.set_rename_mode(RenameMode::Standard);
+    assert!(sftp.rename(&new,&current).is_err());
+    assert_eq!(fs::read(&current).unwrap(),b"v2");
+    sftp.set_rename_mode(RenameMode::Replace);
+    sftp.rename(&new,&current).unwrap();
+    assert_eq!(fs::read(&current).unwrap(),b"v3");
+}
+
+#[test]
+#[cfg(feature="gzip")]
+fn compression_advice() {
+    let sshd=Sshd::start("compression_advice");
+    let mut session=sshd.session();
+    assert_eq!(session.compression(),None);
+    // Compressed transfers run `gzip` on a channel, which the tap sees, unlike SFTP.
+    let tapped=std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let t=tapped.clone();
+    session.set_tap(move |_:&ssh::tap::Chunk| { t.fetch_add(1,std::sync::atomic::Ordering::SeqCst); });
+    let text=b"hello hello hello hello\n".repeat(100);
+    let n=session.upload_advised(&mut &text[..],sshd.dir.join("text"),0o644,CompressionAdvice::Auto(Compression::Gzip)).unwrap();
+    assert_eq!(n,text.len() as u64);
+    assert_eq!(fs::read(sshd.dir.join("text")).unwrap(),text);
+    let compressed=tapped.swap(0,std::sync::atomic::Ordering::SeqCst);
+    assert!(compressed>0);
+    let gz=[0x1f,0x8b,8,0,0,0,0,0];
+    session.upload_advised(&mut &gz[..],sshd.dir.join("data"),0o644,CompressionAdvice::Auto(Compression::Gzip)).unwrap();
+    assert_eq!(fs::read(sshd.dir.join("data")).unwrap(),gz);
+    let mut out=Vec::new();
+    session.download_advised(sshd.dir.join("data"),&mut out,CompressionAdvice::Auto(Compression::Gzip)).unwrap();
+    assert_eq!(out,gz);
+    session.download_advised(sshd.dir.join("text"),&mut out,CompressionAdvice::Never).unwrap();
+    assert_eq!(tapped.load(std::sync::atomic::Ordering::SeqCst),0);
+}
+
+#[test]
+fn credentials_provider() {
+    struct Keychain(Vec<PathBuf>);
+    impl CredentialsProvider for Keychain {
+        fn get_key_passphrase(&mut self,key:&std::path::Path)->Option<String> {
+            self.0.push(key.to_path_buf());
+            Some("secret".to_string())
+        }
+    }
+    let sshd=Sshd::start("credentials_provider");
+    let key=sshd.dir.join("id_encrypted");
+    let status=Command::new(program("SSH_KEYGEN","ssh-keygen"))
+        .args(["-q","-t","ed25519","-N","secret","-f"]).arg(&key)
+        .status().unwrap();
+    assert!(status.success());
+    fs::copy(sshd.dir.join("id_encrypted.pub"),sshd.dir.join("authorized_keys")).unwrap();
+    let mut session=sshd.connect();
+    let steps=std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+    let s=steps.clone();
+    let mut keychain=Keychain(Vec::new());
+    let methods=AuthPipeline::new()
+        .credentials(&mut keychain)
+        .provided_password()
+        .identities([key.clone()],None)
+        .on_step(move |m,r| s.borrow_mut().push((m,r)))
+        .run(&mut session)
+        .unwrap();
+    assert_eq!(methods,vec![AuthMethod::PublicKey]);
+    assert_eq!(keychain.0,vec![key]);
+    assert_eq!(*steps.borrow(),vec![(AuthMethod::Password,StepResult::Skipped),(AuthMethod::PublicKey,StepResult::Success)]);
+}
+
+#[test]
+fn credentials_provider_no_otp() {
+    struct NoOtp(Vec<String>);
+    impl CredentialsProvider for NoOtp {
+        fn get_otp(&mut self,prompt:&str)->Option<String> {
+            self.0.push(prompt.to_string());
+            None
+        }
+    }
+    // With PAM, the server prompts for a password.
+    let sshd=Sshd::start_with("credentials_provider_no_otp","UsePAM yes\nKbdInteractiveAuthentication yes\n");
+    let mut session=sshd.connect();
+    let steps=std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+    let s=steps.clone();
+    let mut provider=NoOtp(Vec::new());
+    let methods=AuthPipeline::new()
+        .credentials(&mut provider)
+        .provided_kbdint()
+        .identities([sshd.dir.join("id_ed25519")],None)
+        .on_step(move |m,r| s.borrow_mut().push((m,r)))
+        .run(&mut session)
+        .unwrap();
+    // The pipeline goes on after the unanswered prompt (or the refusal, if sshd has no PAM).
+    assert_eq!(methods,vec![AuthMethod::PublicKey]);
+    let steps=steps.borrow();
+    let expected=if provider.0.is_empty() { StepResult::Denied } else { StepResult::Skipped };
+    assert_eq!(*steps,vec![(AuthMethod::KeyboardInteractive,expected),(AuthMethod::PublicKey,StepResult::Success)]);
+}
+
+#[test]
+fn transient_errors() {
+    // A refused connection, reported by libssh, may work later.
+    let port=TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let mut session=Session::new().unwrap();
+    session.set_host("127.0.0.1").unwrap();
+    session.set_port(port as usize).unwrap();
+    match session.connect() {
+        Err(e)=>assert!(e.is_transient(),"{:?}",e),
+        Ok(())=>panic!("connected to a closed port")
+    }
+    // Errors detected by the crate don't go away by retrying.
+    let mut session=Session::new().unwrap();
+    let e=session.connect_happy_eyeballs(Duration::from_secs(1)).unwrap_err();
+    assert!(!e.is_transient(),"{:?}",e);
+}
+
+#[test]
+fn agent_identity() {
+    // The server allows a single refused key.
+    let sshd=Sshd::start_with("agent_identity","MaxAuthTries 1\n");
+    keygen(&sshd.dir.join("other"));
+    let socket=sshd.dir.join("agent.sock");
+    let mut agent=Command::new(program("SSH_AGENT","ssh-agent")).arg("-D").arg("-a").arg(&socket)
+        .stdout(Stdio::null())
+        .spawn().expect("could not run ssh-agent");
+    let deadline=Instant::now()+Duration::from_secs(10);
+    while !socket.exists() {
+        assert!(Instant::now()<deadline,"ssh-agent did not start");
+        std::thread::sleep(Duration::from_millis(50))
+    }
+    // The agent offers the other key first.
+    for key in ["other","id_ed25519"] {
+        let status=Command::new(program("SSH_ADD","ssh-add")).arg(sshd.dir.join(key))
+            .env("SSH_AUTH_SOCK",&socket).stderr(Stdio::null())
+            .status().unwrap();
+        assert!(status.success());
+    }
+    let key=PublicKey::from_file(sshd.dir.join("id_ed25519.pub")).unwrap();
+    let mut session=sshd.connect();
+    session.set_agent_socket(&socket).unwrap();
+    assert!(session.userauth_agent().is_err());
+    let mut session=sshd.connect();
+    session.set_agent_socket(&socket).unwrap();
+    assert!(session.userauth_agent_identity(&key).unwrap());
+    assert_eq!(session.exec("echo ok").unwrap().stdout,b"ok\n");
+    // A key the agent doesn't hold.
+    let mut session=sshd.connect();
+    session.set_agent_socket(&socket).unwrap();
+    match session.userauth_agent_identity(&PublicKey::from_file(sshd.dir.join("host_ed25519.pub")).unwrap()) {
+        Err(Error::RequestDenied(_))=>(),
+        r=>panic!("unexpected {:?}",r)
+    }
+    let _=agent.kill();
+    let _=agent.wait();
+}